@@ -200,6 +200,8 @@ pub fn window_event(
             layershellev::Ime::Preedit(content, size) => {
                 input_method::Event::Preedit(content.clone(), size.map(|(start, end)| start..end))
             }
+            // iced reads the visibility from the cursor of the preedit
+            layershellev::Ime::PreeditCursorVisible(_) => return None,
             layershellev::Ime::Commit(content) => input_method::Event::Commit(content.clone()),
             layershellev::Ime::Disabled => input_method::Event::Closed,
         })),
@@ -4,7 +4,8 @@ use layershellev::DispatchMessage;
 #[cfg(feature = "foreign-toplevel")]
 use layershellev::foreign_toplevel::ForeignToplevelEvent;
 use layershellev::keyboard::ModifiersState;
-use layershellev::reexport::wayland_client::{ButtonState, KeyState, WEnum, WlRegion};
+use layershellev::reexport::wayland_client::{ButtonState, KeyState, Transform, WEnum, WlRegion};
+use layershellev::reexport::zxdg_toplevel_decoration_v1::Mode as DecorationMode;
 #[cfg(feature = "screencopy")]
 pub use layershellev::screencopy::{CapturedFrame, ScreencopyEvent};
 pub use layershellev::voice_mode::VoiceModeEvent;
@@ -455,6 +456,13 @@ pub enum WindowEvent {
     Unfocus,
     Focused,
     ModifiersChanged(ModifiersState),
+    RepeatInfoChanged {
+        rate: i32,
+        delay: i32,
+    },
+    KeymapChanged {
+        layout: Option<String>,
+    },
     Axis {
         x: f32,
         y: f32,
@@ -503,6 +511,11 @@ pub enum WindowEvent {
     SurfaceVisibilityChanged {
         visible: bool,
     },
+    /// The visibility combining the hide/show of the surface, its home visibility mode and the
+    /// home state flipped
+    EffectiveVisibilityChanged {
+        visibility: layershellev::EffectiveVisibility,
+    },
     /// Voice mode event from compositor (started, stopped, cancelled, orb attached/detached)
     VoiceMode(VoiceModeEvent),
     /// Foreign toplevel event (window created, changed, or closed)
@@ -545,6 +558,11 @@ pub enum WindowEvent {
     DndSourceAction(u32),
     /// A file was dropped onto the surface (one event per dropped file).
     FileDropped(std::path::PathBuf),
+    /// The clipboard selection read with `request_selection`.
+    SelectionData {
+        mime: String,
+        data: Vec<u8>,
+    },
     /// The output the surface is shown on reported its logical size (logical px).
     /// Delivered to the app through [`output_info_subscription`] so it can position
     /// per-display layer surfaces correctly.
@@ -569,6 +587,43 @@ pub enum WindowEvent {
     /// The full logical layout of every output (startup + hotplug). Delivered to
     /// the app through [`output_layout_subscription`].
     OutputLayout(Vec<layershellev::OutputLayoutItem>),
+    /// The surface got its first configure, with its first valid size.
+    Configured {
+        width: u32,
+        height: u32,
+    },
+    /// The popup was repositioned by the compositor.
+    PopupRepositioned {
+        token: u32,
+    },
+    /// The wl_surface of the window was created again, see
+    /// [`layershellev::WindowState::recreate_all`].
+    Recreated,
+    /// The compositor picked the decoration mode of the toplevel window.
+    DecorationMode(DecorationMode),
+    /// The compositor would like the window drawn in this transform, like for a rotated output.
+    PreferredTransform(Transform),
+    /// The window is now shown on the output with this name, None when the name is unknown.
+    SurfaceEnterOutput {
+        name: Option<String>,
+    },
+    /// The window is no longer shown on the output with this name.
+    SurfaceLeaveOutput {
+        name: Option<String>,
+    },
+    /// The output the window was created on, with this name, was removed while the window
+    /// stayed alive.
+    OutputLost {
+        name: Option<String>,
+    },
+    /// A frame of the surface was presented on screen.
+    #[cfg(feature = "presentation-time")]
+    Presented {
+        tv_sec: u64,
+        tv_nsec: u32,
+        refresh: u32,
+        flags: u32,
+    },
 }
 
 /// The logical size (logical px) of the output a layer surface is shown on.
@@ -686,6 +741,13 @@ impl From<&DispatchMessage> for WindowEvent {
             DispatchMessage::ModifiersChanged(modifiers) => {
                 WindowEvent::ModifiersChanged(*modifiers)
             }
+            DispatchMessage::RepeatInfoChanged { rate, delay } => WindowEvent::RepeatInfoChanged {
+                rate: *rate,
+                delay: *delay,
+            },
+            DispatchMessage::KeymapChanged { layout } => WindowEvent::KeymapChanged {
+                layout: layout.clone(),
+            },
             DispatchMessage::Axis {
                 horizontal,
                 vertical,
@@ -716,6 +778,11 @@ impl From<&DispatchMessage> for WindowEvent {
             DispatchMessage::SurfaceVisibilityChanged { visible } => {
                 WindowEvent::SurfaceVisibilityChanged { visible: *visible }
             }
+            DispatchMessage::EffectiveVisibilityChanged { visibility } => {
+                WindowEvent::EffectiveVisibilityChanged {
+                    visibility: *visibility,
+                }
+            }
             DispatchMessage::VoiceMode(event) => WindowEvent::VoiceMode(event.clone()),
             #[cfg(feature = "foreign-toplevel")]
             DispatchMessage::ForeignToplevel(event) => WindowEvent::ForeignToplevel(event.clone()),
@@ -740,12 +807,17 @@ impl From<&DispatchMessage> for WindowEvent {
             DispatchMessage::DndSourceFinished => WindowEvent::DndSourceFinished,
             DispatchMessage::DndSourceAction(a) => WindowEvent::DndSourceAction(*a),
             DispatchMessage::FileDropped(path) => WindowEvent::FileDropped(path.clone()),
+            DispatchMessage::SelectionData { mime, data } => WindowEvent::SelectionData {
+                mime: mime.clone(),
+                data: data.clone(),
+            },
             DispatchMessage::XdgInfoChanged {
                 width,
                 height,
                 output_name,
                 output_x,
                 output_y,
+                ..
             } => WindowEvent::OutputLogicalSize {
                 width: *width,
                 height: *height,
@@ -756,6 +828,39 @@ impl From<&DispatchMessage> for WindowEvent {
             DispatchMessage::OutputLayoutChanged(layout) => {
                 WindowEvent::OutputLayout(layout.clone())
             }
+            DispatchMessage::Configured {
+                size: (width, height),
+            } => WindowEvent::Configured {
+                width: *width,
+                height: *height,
+            },
+            DispatchMessage::PopupRepositioned { token } => {
+                WindowEvent::PopupRepositioned { token: *token }
+            }
+            DispatchMessage::Recreated => WindowEvent::Recreated,
+            DispatchMessage::DecorationMode(mode) => WindowEvent::DecorationMode(*mode),
+            DispatchMessage::PreferredTransform(transform) => {
+                WindowEvent::PreferredTransform(*transform)
+            }
+            DispatchMessage::SurfaceEnterOutput { name, .. } => {
+                WindowEvent::SurfaceEnterOutput { name: name.clone() }
+            }
+            DispatchMessage::SurfaceLeaveOutput { name, .. } => {
+                WindowEvent::SurfaceLeaveOutput { name: name.clone() }
+            }
+            DispatchMessage::OutputLost { name } => WindowEvent::OutputLost { name: name.clone() },
+            #[cfg(feature = "presentation-time")]
+            DispatchMessage::Presented {
+                tv_sec,
+                tv_nsec,
+                refresh,
+                flags,
+            } => WindowEvent::Presented {
+                tv_sec: *tv_sec,
+                tv_nsec: *tv_nsec,
+                refresh: *refresh,
+                flags: *flags,
+            },
             DispatchMessage::UsableAreaChanged {
                 x,
                 y,
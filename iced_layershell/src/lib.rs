@@ -12,6 +12,7 @@ mod user_interface;
 pub mod settings;
 
 pub mod reexport {
+    pub use layershellev::InputPanelPosition;
     pub use layershellev::NewInputPanelSettings;
     pub use layershellev::NewLayerShellSettings;
     pub use layershellev::OutputOption;
@@ -497,6 +497,9 @@ where
             IcedLayerShellEvent::Window(LayerShellWindowEvent::Closed) => {
                 self.handle_closed_event(ev, layer_shell_id)
             }
+            IcedLayerShellEvent::Window(LayerShellWindowEvent::Recreated) => {
+                self.handle_recreated_event(ev, layer_shell_id)
+            }
             IcedLayerShellEvent::Window(window_event) => {
                 // Voice mode events need to trigger a refresh to process the subscription message
                 let needs_refresh = self.handle_window_event(layer_shell_id, window_event);
@@ -1147,6 +1150,33 @@ where
         }
     }
 
+    /// The wl_surface behind a window was destroyed and created again: keep the iced window and
+    /// its user interface, only the renderer surface is bound to the old wl_surface.
+    fn handle_recreated_event(
+        &mut self,
+        ev: &mut WindowState<IcedId>,
+        layer_shell_id: Option<LayerShellId>,
+    ) {
+        let Some(layer_shell_window) = layer_shell_id.and_then(|lid| ev.get_unit_with_id(lid))
+        else {
+            return;
+        };
+        let Some(compositor) = self.compositor.as_mut() else {
+            return;
+        };
+        let Some((iced_id, window)) = self.window_manager.get_mut_alias(layer_shell_window.id())
+        else {
+            return;
+        };
+        tracing::debug!("surface recreated: iced_id={:?}", iced_id);
+        let physical_size = window.state.viewport().physical_size();
+        window.surface = compositor.create_surface(
+            Arc::new(layer_shell_window.gen_wrapper()),
+            physical_size.width,
+            physical_size.height,
+        );
+    }
+
     /// Handle window events. Returns true if a refresh should be requested
     /// (for events that go through subscription channels like voice mode).
     fn handle_window_event(
@@ -1288,7 +1318,13 @@ where
             } else {
                 iced_surface_visibility::Event::Hidden
             });
-            if let Some((iced_id, _)) = self.window_manager.iter_mut().next() {
+            // Route the event to the surface that was shown/hidden, like auto-hide.
+            let mut target =
+                layer_shell_id.and_then(|lid| self.window_manager.get_alias(lid).map(|(id, _)| id));
+            if target.is_none() {
+                target = self.window_manager.iter_mut().next().map(|(id, _)| id);
+            }
+            if let Some(iced_id) = target {
                 self.iced_events.push((iced_id, iced_event));
             }
             return true;
@@ -1704,6 +1740,7 @@ where
                     tooltip_offset: None,
                     tooltip_anchor: None,
                     tooltip_delay_ms: None,
+                    auto_dismiss: None,
                 };
                 let layer_shell_id = layershellev::id::Id::unique();
                 ev.append_return_data(ReturnData::NewPopUp((
@@ -1749,6 +1786,7 @@ where
                     tooltip_offset: None,
                     tooltip_anchor: None,
                     tooltip_delay_ms: None,
+                    auto_dismiss: None,
                 };
                 let layer_shell_id = layershellev::id::Id::unique();
                 ev.append_return_data(ReturnData::NewPopUp((
@@ -2633,6 +2671,7 @@ pub(crate) fn run_action<P, C, E: Executor>(
                                     tooltip_offset: settings.tooltip_offset,
                                     tooltip_anchor: settings.tooltip_anchor,
                                     tooltip_delay_ms: settings.tooltip_delay_ms,
+                                    auto_dismiss: None,
                                 };
 
                                 let layer_shell_id = layershellev::id::Id::unique();
@@ -3,6 +3,12 @@ use wayland_protocols_wlr::layer_shell::v1::client::{
     zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
 };
 
+use wayland_protocols::xdg::{
+    decoration::zv1::client::zxdg_toplevel_decoration_v1, shell::client::xdg_positioner,
+};
+
+use wayland_protocols::wp::input_method::zv1::client::zwp_input_panel_surface_v1;
+
 use wayland_client::{
     QueueHandle, WEnum,
     globals::GlobalList,
@@ -30,6 +36,8 @@ use crate::id::Id;
 
 use std::{fmt::Debug, fs::File};
 
+use wayland_protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::Shape;
+
 /// tell program what event is happened
 ///
 /// InitRequest will tell the program is inited, you can request to Bind other wayland-protocols
@@ -71,6 +79,23 @@ pub enum LayerShellEvent<'a, T, Message> {
     NormalDispatch,
     /// It return the event you passed with message_receiver, and return it back.
     UserEvent(Message),
+    /// A new output was connected, sent whatever the [crate::StartMode] is. The name is the
+    /// xdg_output name, or the wl_output name, if the compositor already sent it.
+    OutputAdded {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    /// An output was disconnected, sent whatever the [crate::StartMode] is. The surfaces on it
+    /// are closed by the compositor.
+    OutputRemoved {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    /// The connection was lost and opened again, see [crate::WindowState::with_auto_reconnect].
+    /// The init requests were sent again before it, and every layer shell surface gets a
+    /// [DispatchMessage::Recreated] after it. The returned [ReturnData] is handled on the first
+    /// tick of the new connection, like the one of any other event
+    Reconnected,
 }
 
 /// Define the output for new layershell
@@ -165,6 +190,10 @@ pub struct NewLayerShellSettings {
     /// `ShowWindow` is sent.  Useful for daemon-mode GPU warm-up where the
     /// first frame should never be visible.
     pub start_hidden: bool,
+    /// The surface this one belongs to, like the tooltip of a panel. Layer surfaces have no
+    /// parent in the protocol, but the surface is closed together with its parent, and gets
+    /// [DispatchMessage::Closed] then.
+    pub parent: Option<id::Id>,
 }
 
 /// be used to create a new popup
@@ -198,7 +227,12 @@ pub struct NewPopUpSettings {
     pub offset: Option<(i32, i32)>,
     /// Whether the popup should reposition when parent moves
     pub reactive: bool,
-    /// Whether to grab keyboard/pointer focus
+    /// Whether to grab keyboard/pointer focus with `xdg_popup.grab`, so the compositor
+    /// dismisses the popup (see [DispatchMessage::Closed]) on a click outside of it.
+    ///
+    /// NOTE: the grab uses the serial of the latest pointer press, touch down or key press,
+    /// so the popup should be created in response to such an input event. The compositor
+    /// may dismiss the popup immediately if the serial is too old.
     pub grab: bool,
     /// When true, the popup surface gets an empty input region so pointer
     /// events pass through to the surface below (e.g. for tooltips).
@@ -215,6 +249,45 @@ pub struct NewPopUpSettings {
     /// appears only after the pointer has hovered over the parent surface for
     /// this duration. 0 or None = immediate (follows pointer).
     pub tooltip_delay_ms: Option<u32>,
+    /// Close the popup after it has been this long without pointer motion over it, like a
+    /// tooltip. The popup gets the usual [DispatchMessage::Closed].
+    pub auto_dismiss: Option<std::time::Duration>,
+}
+
+impl NewPopUpSettings {
+    /// set the edge of the anchor rect the popup is anchored to
+    pub fn with_anchor(mut self, anchor: xdg_positioner::Anchor) -> Self {
+        self.anchor = anchor.into();
+        self
+    }
+
+    /// set the direction the popup extends to from the anchor point
+    pub fn with_gravity(mut self, gravity: xdg_positioner::Gravity) -> Self {
+        self.gravity = gravity.into();
+        self
+    }
+
+    /// set how the compositor adjusts the popup when it would be constrained, like be clipped
+    /// by the edge of the output
+    pub fn with_constraint_adjustment(
+        mut self,
+        constraint_adjustment: xdg_positioner::ConstraintAdjustment,
+    ) -> Self {
+        self.constraint_adjustment = constraint_adjustment.bits();
+        self
+    }
+
+    /// flip the popup to the other side of the anchor rect when it overflows the output, and
+    /// slide it when flipping is not enough. This is what a dropdown menu usually wants.
+    pub fn flip_on_overflow(self) -> Self {
+        use xdg_positioner::ConstraintAdjustment;
+        self.with_constraint_adjustment(
+            ConstraintAdjustment::FlipX
+                | ConstraintAdjustment::FlipY
+                | ConstraintAdjustment::SlideX
+                | ConstraintAdjustment::SlideY,
+        )
+    }
 }
 
 /// Settings to reposition an existing popup via xdg_popup.reposition (v3).
@@ -253,12 +326,35 @@ pub struct NewXdgWindowSettings {
     pub app_id: Option<String>,
 }
 
+/// The role of an input panel surface, see [NewInputPanelSettings]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputPanelPosition {
+    /// a keyboard on the output, at the given position (`set_toplevel`). The protocol only
+    /// defines the bottom center for now
+    Toplevel(zwp_input_panel_surface_v1::Position),
+    /// an overlay panel, like the candidates of an input method, placed near the text input by
+    /// the compositor (`set_overlay_panel`)
+    Overlay,
+}
+
+impl Default for InputPanelPosition {
+    fn default() -> Self {
+        Self::Toplevel(zwp_input_panel_surface_v1::Position::CenterBottom)
+    }
+}
+
 /// input panel settings to create a new input panel surface
+///
+/// NOTE: `zwp_input_panel_surface_v1` only supports the positions of [InputPanelPosition].
+/// For a floating keyboard near the text cursor, create a layer surface with
+/// [crate::WindowState::osk_settings_near_ime_cursor] instead
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct NewInputPanelSettings {
+    /// with [InputPanelPosition::Toplevel], the width follows the logical width of the output
+    /// once it is known, the panel is refreshed with the new size when the output changes
     pub size: (u32, u32),
-    /// set the surface type as a keyboard
-    pub keyboard: bool,
+    /// where the panel is placed
+    pub position: InputPanelPosition,
     /// follow the last output of the activated surface, used to create some thing like mako, who
     /// will show on the same window, only when the notifications is cleared, it will change the
     /// wl_output.
@@ -287,6 +383,7 @@ impl Default for NewLayerShellSettings {
             transition: None,
             auto_size: false,
             start_hidden: false,
+            parent: None,
         }
     }
 }
@@ -306,6 +403,10 @@ impl Eq for NewLayerShellSettings {}
 ///
 /// When send RequestSetCursorShape, you can set current pointer shape. please take
 /// [cursor-shape](https://wayland.app/protocols/cursor-shape-v1#wp_cursor_shape_device_v1:enum:shape) as reference.
+/// RequestSetCursorShapeTyped does the same with the [Shape] itself, without the name lookup.
+/// RequestSetCursorBuffer sets a custom cursor image from your own buffer, like a color picker
+/// cursor, the hotspot is the point of the image under the pointer.
+/// RequestHideCursor hides the cursor, setting a shape brings it back.
 ///
 /// None means nothing will happened, no request, and no return data
 #[derive(Debug, PartialEq, Eq)]
@@ -317,6 +418,13 @@ pub enum ReturnData<INFO> {
     RedrawAllRequest,
     RedrawIndexRequest(Id),
     RequestSetCursorShape((String, WlPointer)),
+    RequestSetCursorShapeTyped((Shape, WlPointer)),
+    RequestSetCursorBuffer {
+        buffer: WlBuffer,
+        hotspot: (i32, i32),
+        pointer: WlPointer,
+    },
+    RequestHideCursor(WlPointer),
     NewLayerShell((NewLayerShellSettings, id::Id, Option<INFO>)),
     NewPopUp((NewPopUpSettings, id::Id, Option<INFO>)),
     RepositionPopUp(RepositionPopUpSettings),
@@ -325,6 +433,13 @@ pub enum ReturnData<INFO> {
     None,
 }
 
+/// If a surface can be seen, see [crate::WindowStateUnit::effective_visibility]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EffectiveVisibility {
+    Visible,
+    Hidden,
+}
+
 /// this tell the what kind of information passed by [LayerShellEvent::XdgInfoChanged]
 #[derive(Debug, Clone, Copy)]
 pub enum XdgInfoChangedType {
@@ -375,12 +490,18 @@ pub enum Ime {
     /// Notifies when a new composing text should be set at the cursor position.
     ///
     /// The value represents a pair of the preedit string and the cursor begin position and end
-    /// position. When it's `None`, the cursor should be hidden. When `String` is an empty string
-    /// this indicates that preedit was cleared.
+    /// position. When it's `None`, the cursor should be hidden: the input method sent `-1` as
+    /// the cursor, or a position which is not on a char boundary. When `String` is an empty
+    /// string this indicates that preedit was cleared, and it should not be shown at all.
     ///
     /// The cursor position is byte-wise indexed.
     Preedit(String, Option<(usize, usize)>),
 
+    /// Notifies when the cursor of the preedit is shown or hidden, sent right before the
+    /// [`Preedit`][Self::Preedit] which changes it. The first preedit after a clear always
+    /// comes with it.
+    PreeditCursorVisible(bool),
+
     /// Notifies when text should be inserted into the editor widget.
     ///
     /// Right before this event winit will send empty [`Self::Preedit`] event.
@@ -395,10 +516,32 @@ pub enum Ime {
     Disabled,
 }
 
+/// An output hotplug, kept out of the messages as it is sent as [LayerShellEvent::OutputAdded]
+/// or [LayerShellEvent::OutputRemoved]
+#[derive(Debug, Clone)]
+pub(crate) enum OutputChange {
+    Added(WlOutput),
+    Removed {
+        output: WlOutput,
+        name: Option<String>,
+    },
+}
+
 #[allow(unused)]
 #[derive(Debug, Clone)]
 pub(crate) enum DispatchMessageInner {
     NewDisplay(WlOutput),
+    SurfaceEnterOutput {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    SurfaceLeaveOutput {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    OutputLost {
+        name: Option<String>,
+    },
     MouseButton {
         state: WEnum<ButtonState>,
         serial: u32,
@@ -451,6 +594,13 @@ pub(crate) enum DispatchMessageInner {
     },
 
     ModifiersChanged(ModifiersState),
+    RepeatInfoChanged {
+        rate: i32,
+        delay: i32,
+    },
+    KeymapChanged {
+        layout: Option<String>,
+    },
     Focused(Id),
     Unfocus,
     KeyboardInput {
@@ -483,6 +633,27 @@ pub(crate) enum DispatchMessageInner {
         output_x: i32,
         output_y: i32,
     },
+    /// The surface got its first configure, carries the configured size
+    Configured {
+        size: (u32, u32),
+    },
+    /// The popup was repositioned, carries the token passed to `xdg_popup.reposition`
+    PopupRepositioned {
+        token: u32,
+    },
+    /// The surface was destroyed and created again by `recreate_all`
+    Recreated,
+    /// The compositor configured the decoration mode of a xdg toplevel
+    DecorationMode(zxdg_toplevel_decoration_v1::Mode),
+    PreferredTransform(wl_output::Transform),
+    /// A frame of the surface was presented, from `wp_presentation_feedback.presented`
+    #[cfg(feature = "presentation-time")]
+    Presented {
+        tv_sec: u64,
+        tv_nsec: u32,
+        refresh: u32,
+        flags: u32,
+    },
     /// The full logical layout of every output changed (at startup and on
     /// hotplug). Carries every monitor's name + global logical geometry.
     OutputLayoutChanged(Vec<OutputLayoutItem>),
@@ -502,6 +673,7 @@ pub(crate) enum DispatchMessageInner {
     AutoHideVisibilityChanged(bool),
     /// Layer-surface visibility changed via hide/show protocol (true = visible, false = hidden)
     SurfaceVisibilityChanged(bool),
+    EffectiveVisibilityChanged(EffectiveVisibility),
     /// Voice mode event from compositor
     VoiceMode(VoiceModeEvent),
     /// Foreign toplevel event
@@ -546,9 +718,20 @@ pub(crate) enum DispatchMessageInner {
     DndSourceAction(u32),
     /// A file was dropped onto the surface (one message per dropped file).
     FileDropped(std::path::PathBuf),
+    /// The clipboard selection read by `request_selection`
+    SelectionData {
+        mime: String,
+        data: Vec<u8>,
+    },
 }
 
 /// This tell the DispatchMessage by dispatch
+///
+/// It carries everything of the event the loop received, nothing is dropped on the way: like
+/// [DispatchMessage::Axis] has the scale of the surface and the source of the scroll, and
+/// [DispatchMessage::XdgInfoChanged] the kind of the change. Only the output hotplug
+/// notifications are not messages, they come as [LayerShellEvent::OutputAdded] and
+/// [LayerShellEvent::OutputRemoved]
 #[derive(Debug)]
 pub enum DispatchMessage {
     /// forward the event of wayland-mouse
@@ -576,9 +759,11 @@ pub enum DispatchMessage {
     /// About the scroll
     Axis {
         time: u32,
+        /// the scale of the surface, to turn the scroll into physical pixels
         scale: f64,
         horizontal: AxisScroll,
         vertical: AxisScroll,
+        /// the device of the scroll, like a wheel or a finger, when the compositor sent it
         source: Option<wl_pointer::AxisSource>,
     },
     /// forward the event of wayland-touch
@@ -614,6 +799,17 @@ pub enum DispatchMessage {
     Unfocus,
     /// Keyboard ModifiersChanged.
     ModifiersChanged(ModifiersState),
+    /// The compositor changed the key repeat. `rate` is in keys per second, 0 when the
+    /// repeat is disabled, and `delay` in milliseconds before the repeat starts.
+    RepeatInfoChanged {
+        rate: i32,
+        delay: i32,
+    },
+    /// The compositor sent a new keymap, or switched to another layout of the keymap. `layout`
+    /// is the name of the active layout, like `English (US)`, when the keymap has one
+    KeymapChanged {
+        layout: Option<String>,
+    },
     /// Keyboard Event about input.
     KeyboardInput {
         event: KeyEvent,
@@ -654,11 +850,19 @@ pub enum DispatchMessage {
     AutoHideVisibilityChanged {
         visible: bool,
     },
-    /// Layer-surface visibility changed via hide/show protocol
+    /// Layer-surface visibility changed via hide/show protocol, requested by the client or
+    /// done by the compositor itself (auto-hide, workspace switch). It is sent to the unit of
+    /// the surface, see [crate::WindowStateUnit::is_visible]
     /// visible: true = surface visible, false = surface hidden
     SurfaceVisibilityChanged {
         visible: bool,
     },
+    /// The visibility combining the hide/show of the surface, its home visibility mode and the
+    /// home state flipped, see [crate::WindowStateUnit::effective_visibility]. It is sent to
+    /// the unit of the surface
+    EffectiveVisibilityChanged {
+        visibility: EffectiveVisibility,
+    },
     /// Voice mode event from compositor (enabled/disabled, partial result, final result)
     VoiceMode(VoiceModeEvent),
     /// Foreign toplevel event (new window, window changed, window closed)
@@ -702,12 +906,20 @@ pub enum DispatchMessage {
     DndSourceAction(u32),
     /// A file was dropped onto the surface (one message per dropped file).
     FileDropped(std::path::PathBuf),
+    /// The clipboard selection requested with [crate::WindowState::request_selection],
+    /// with the MIME type it was read as.
+    SelectionData {
+        mime: String,
+        data: Vec<u8>,
+    },
     /// The xdg_output info of the output the surface is shown on changed.
     /// Carries that output's current logical size (logical px) — used to
     /// position centered/anchored layer surfaces correctly per-display,
     /// especially for `StartMode::Active` surfaces created with no output
     /// binding (whose output is only known once the compositor maps them).
     XdgInfoChanged {
+        /// which xdg_output info changed
+        change_type: XdgInfoChangedType,
         width: i32,
         height: i32,
         /// The surface's output name + global logical position (for positioning
@@ -728,6 +940,57 @@ pub enum DispatchMessage {
     },
     /// The full logical layout of every output (startup + hotplug).
     OutputLayoutChanged(Vec<OutputLayoutItem>),
+    /// The surface got its first configure from the compositor, so `size` is the first valid
+    /// size of it. It is sent once per surface, before the first [DispatchMessage::RequestRefresh],
+    /// drawing should not begin before it. Later configures only trigger refreshes.
+    Configured {
+        size: (u32, u32),
+    },
+    /// The compositor applied a reposition of the popup, `token` is the one passed to
+    /// `xdg_popup.reposition`, so it can be matched with the request.
+    PopupRepositioned {
+        token: u32,
+    },
+    /// The wl_surface of the unit was destroyed and created again by
+    /// [crate::WindowState::recreate_all], with the same id. Anything bound to the old
+    /// surface, like a renderer surface, must be created again.
+    Recreated,
+    /// The compositor picked the decoration mode of a xdg toplevel, from
+    /// `zxdg_toplevel_decoration_v1.configure`. With `ClientSide` the app draws its own
+    /// decorations, with `ServerSide` the compositor does.
+    DecorationMode(zxdg_toplevel_decoration_v1::Mode),
+    /// The compositor would like the buffers of the surface in this transform, like rotated
+    /// for a rotated output, from `wl_surface.preferred_buffer_transform`. Drawing in it and
+    /// telling it with `wl_surface.set_buffer_transform` spares the compositor a rotation.
+    /// See [crate::WindowStateUnit::preferred_transform]
+    PreferredTransform(wl_output::Transform),
+    /// The surface is now shown on `output`, from `wl_surface.enter`. `name` is the name of
+    /// the output when it is known. See [crate::WindowStateUnit::current_outputs]
+    SurfaceEnterOutput {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    /// The surface is no longer shown on `output`, from `wl_surface.leave`
+    SurfaceLeaveOutput {
+        output: WlOutput,
+        name: Option<String>,
+    },
+    /// The output the surface was created on was removed, but the surface is still alive. It
+    /// is not bound to an output anymore. A layer surface cannot change its output, close it
+    /// and create it again on another output when it should be moved
+    OutputLost {
+        name: Option<String>,
+    },
+    /// A frame of the surface hit the screen. `tv_sec`/`tv_nsec` is the presentation time in
+    /// the clock of [crate::WindowState::presentation_clock_id], `refresh` the nanoseconds until
+    /// the next refresh (0 if unknown), and `flags` the `wp_presentation_feedback.kind` bits.
+    #[cfg(feature = "presentation-time")]
+    Presented {
+        tv_sec: u64,
+        tv_nsec: u32,
+        refresh: u32,
+        flags: u32,
+    },
 }
 
 impl From<DispatchMessageInner> for DispatchMessage {
@@ -816,6 +1079,12 @@ impl From<DispatchMessageInner> for DispatchMessage {
             DispatchMessageInner::ModifiersChanged(modifier) => {
                 DispatchMessage::ModifiersChanged(modifier)
             }
+            DispatchMessageInner::RepeatInfoChanged { rate, delay } => {
+                DispatchMessage::RepeatInfoChanged { rate, delay }
+            }
+            DispatchMessageInner::KeymapChanged { layout } => {
+                DispatchMessage::KeymapChanged { layout }
+            }
             DispatchMessageInner::KeyboardInput {
                 event,
                 is_synthetic,
@@ -832,13 +1101,14 @@ impl From<DispatchMessageInner> for DispatchMessage {
             },
             DispatchMessageInner::Ime(ime) => DispatchMessage::Ime(ime),
             DispatchMessageInner::XdgInfoChanged {
+                change_type,
                 logical_width,
                 logical_height,
                 output_name,
                 output_x,
                 output_y,
-                ..
             } => DispatchMessage::XdgInfoChanged {
+                change_type,
                 width: logical_width,
                 height: logical_height,
                 output_name,
@@ -848,6 +1118,34 @@ impl From<DispatchMessageInner> for DispatchMessage {
             DispatchMessageInner::OutputLayoutChanged(layout) => {
                 DispatchMessage::OutputLayoutChanged(layout)
             }
+            DispatchMessageInner::Configured { size } => DispatchMessage::Configured { size },
+            DispatchMessageInner::PopupRepositioned { token } => {
+                DispatchMessage::PopupRepositioned { token }
+            }
+            DispatchMessageInner::Recreated => DispatchMessage::Recreated,
+            DispatchMessageInner::DecorationMode(mode) => DispatchMessage::DecorationMode(mode),
+            DispatchMessageInner::PreferredTransform(transform) => {
+                DispatchMessage::PreferredTransform(transform)
+            }
+            DispatchMessageInner::SurfaceEnterOutput { output, name } => {
+                DispatchMessage::SurfaceEnterOutput { output, name }
+            }
+            DispatchMessageInner::SurfaceLeaveOutput { output, name } => {
+                DispatchMessage::SurfaceLeaveOutput { output, name }
+            }
+            DispatchMessageInner::OutputLost { name } => DispatchMessage::OutputLost { name },
+            #[cfg(feature = "presentation-time")]
+            DispatchMessageInner::Presented {
+                tv_sec,
+                tv_nsec,
+                refresh,
+                flags,
+            } => DispatchMessage::Presented {
+                tv_sec,
+                tv_nsec,
+                refresh,
+                flags,
+            },
             DispatchMessageInner::UsableAreaChanged {
                 x,
                 y,
@@ -868,6 +1166,9 @@ impl From<DispatchMessageInner> for DispatchMessage {
             DispatchMessageInner::SurfaceVisibilityChanged(visible) => {
                 DispatchMessage::SurfaceVisibilityChanged { visible }
             }
+            DispatchMessageInner::EffectiveVisibilityChanged(visibility) => {
+                DispatchMessage::EffectiveVisibilityChanged { visibility }
+            }
             DispatchMessageInner::VoiceMode(event) => DispatchMessage::VoiceMode(event),
             #[cfg(feature = "foreign-toplevel")]
             DispatchMessageInner::ForeignToplevel(event) => DispatchMessage::ForeignToplevel(event),
@@ -889,6 +1190,9 @@ impl From<DispatchMessageInner> for DispatchMessage {
             DispatchMessageInner::DndSourceFinished => DispatchMessage::DndSourceFinished,
             DispatchMessageInner::DndSourceAction(a) => DispatchMessage::DndSourceAction(a),
             DispatchMessageInner::FileDropped(path) => DispatchMessage::FileDropped(path),
+            DispatchMessageInner::SelectionData { mime, data } => {
+                DispatchMessage::SelectionData { mime, data }
+            }
         }
     }
 }
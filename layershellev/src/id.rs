@@ -18,4 +18,27 @@ impl Id {
     pub fn unique() -> Id {
         Id(COUNT.fetch_add(1, atomic::Ordering::Relaxed))
     }
+
+    /// Get the numeric value of the [`Id`], to pass it over FFI or store it.
+    ///
+    /// The value is unique among the ids created by this process, but it is only stable
+    /// for the lifetime of the process: ids are counted from 0 at every start.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+
+    /// Rebuild an [`Id`] from the value of [`Id::as_u64`].
+    ///
+    /// Returns `None` if no [`Id`] with this value has been created in this process yet.
+    /// It does not check that the window of the id still exists, so a value from a previous
+    /// run may point to another window.
+    pub fn from_u64(raw: u64) -> Option<Id> {
+        (raw < COUNT.load(atomic::Ordering::Relaxed) || raw == Self::MAIN.0).then_some(Id(raw))
+    }
+}
+
+impl From<Id> for u64 {
+    fn from(id: Id) -> Self {
+        id.as_u64()
+    }
 }
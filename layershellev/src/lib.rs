@@ -108,6 +108,7 @@
 //! ```
 //!
 use calloop::channel::Channel;
+pub use events::InputPanelPosition;
 pub use events::LayerTransition;
 pub use events::NewInputPanelSettings;
 pub use events::NewLayerShellSettings;
@@ -133,21 +134,23 @@ pub mod layer_surface_visibility;
 pub mod layer_usable_area;
 #[cfg(feature = "screencopy")]
 pub mod screencopy;
+#[cfg(feature = "serde")]
+mod serde_layer;
 pub mod shadow;
 mod strtoshape;
 pub mod tooltip;
 pub mod voice_mode;
 
-use events::DispatchMessageInner;
+use events::{DispatchMessageInner, OutputChange};
 
 pub mod id;
 
 pub use events::{
-    AxisScroll, DispatchMessage, Ime, LayerShellEvent, OutputLayoutItem, ReturnData,
-    XdgInfoChangedType,
+    AxisScroll, DispatchMessage, EffectiveVisibility, Ime, LayerShellEvent, OutputLayoutItem,
+    ReturnData, XdgInfoChangedType,
 };
 
-use strtoshape::str_to_shape;
+use strtoshape::{ShapeName, shape_since_v2, str_to_shape};
 
 use waycrate_xkbkeycode::xkb_keyboard::ElementState;
 use waycrate_xkbkeycode::xkb_keyboard::RepeatInfo;
@@ -205,8 +208,7 @@ use wayland_protocols::{
 };
 
 use wayland_protocols::wp::input_method::zv1::client::{
-    zwp_input_panel_surface_v1::{Position as ZwpInputPanelPosition, ZwpInputPanelSurfaceV1},
-    zwp_input_panel_v1::ZwpInputPanelV1,
+    zwp_input_panel_surface_v1::ZwpInputPanelSurfaceV1, zwp_input_panel_v1::ZwpInputPanelV1,
 };
 
 use wayland_protocols::wp::keyboard_shortcuts_inhibit::zv1::client::{
@@ -218,8 +220,30 @@ use wayland_protocols::wp::viewporter::client::{
     wp_viewport::WpViewport, wp_viewporter::WpViewporter,
 };
 
+use wayland_protocols::wp::fifo::v1::client::{
+    wp_fifo_manager_v1::WpFifoManagerV1, wp_fifo_v1::WpFifoV1,
+};
+
+use wayland_protocols::wp::commit_timing::v1::client::{
+    wp_commit_timer_v1::WpCommitTimerV1, wp_commit_timing_manager_v1::WpCommitTimingManagerV1,
+};
+
+use wayland_protocols::wp::alpha_modifier::v1::client::{
+    wp_alpha_modifier_surface_v1::WpAlphaModifierSurfaceV1, wp_alpha_modifier_v1::WpAlphaModifierV1,
+};
+
+use wayland_protocols::wp::idle_inhibit::zv1::client::{
+    zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1, zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+};
+
+#[cfg(feature = "presentation-time")]
+use wayland_protocols::wp::presentation_time::client::{
+    wp_presentation::{self, WpPresentation},
+    wp_presentation_feedback::{self, WpPresentationFeedback},
+};
+
 use wayland_protocols::wp::cursor_shape::v1::client::{
-    wp_cursor_shape_device_v1::WpCursorShapeDeviceV1,
+    wp_cursor_shape_device_v1::{Shape, WpCursorShapeDeviceV1},
     wp_cursor_shape_manager_v1::WpCursorShapeManagerV1,
 };
 
@@ -239,12 +263,16 @@ use wayland_protocols::xdg::decoration::zv1::client::{
 
 pub use calloop;
 use calloop::{
-    Error as CallLoopError, EventLoop, LoopHandle, RegistrationToken, channel,
+    Dispatcher, Error as CallLoopError, EventLoop, Interest, LoopHandle, Mode, PostAction,
+    RegistrationToken, channel,
+    generic::Generic,
     timer::{TimeoutAction, Timer},
 };
 use calloop_wayland_source::WaylandSource;
+use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::fmt::Debug;
+use std::rc::Rc;
 use std::time::Duration;
 use std::time::Instant;
 
@@ -262,6 +290,10 @@ pub enum LayerEventError {
     TempFileCreateFailed(#[from] std::io::Error),
     #[error("Event Loop Error")]
     EventLoopInitError(#[from] CallLoopError),
+    #[error("failed to insert an event source into the event loop")]
+    SourceInsertError(#[source] CallLoopError),
+    #[error("the event handler answered {request} with an unexpected ReturnData")]
+    UnexpectedReturnData { request: &'static str },
 }
 
 pub mod reexport {
@@ -292,6 +324,7 @@ pub mod reexport {
             protocol::{
                 wl_compositor::WlCompositor,
                 wl_keyboard::{self, KeyState},
+                wl_output::Transform,
                 wl_pointer::{self, ButtonState},
                 wl_region::WlRegion,
                 wl_seat::WlSeat,
@@ -299,15 +332,31 @@ pub mod reexport {
         };
     }
     pub mod wp_cursor_shape_device_v1 {
-        pub use crate::strtoshape::ShapeName;
+        pub use crate::strtoshape::{ShapeName, all_shape_names};
         pub use wayland_protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::Shape;
     }
     pub mod xdg_toplevel {
-        pub use wayland_protocols::xdg::shell::client::xdg_toplevel::XdgToplevel;
+        pub use wayland_protocols::xdg::shell::client::xdg_toplevel::{ResizeEdge, XdgToplevel};
     }
     pub mod wp_viewport {
         pub use wayland_protocols::wp::viewporter::client::wp_viewport::WpViewport;
     }
+    pub mod xdg_positioner {
+        pub use wayland_protocols::xdg::shell::client::xdg_positioner::{
+            Anchor, ConstraintAdjustment, Gravity,
+        };
+    }
+    pub mod zwp_text_input_v3 {
+        pub use wayland_protocols::wp::text_input::zv3::client::zwp_text_input_v3::{
+            ContentHint, ContentPurpose,
+        };
+    }
+    pub mod zxdg_toplevel_decoration_v1 {
+        pub use wayland_protocols::xdg::decoration::zv1::client::zxdg_toplevel_decoration_v1::Mode;
+    }
+    pub mod zwp_input_panel_surface_v1 {
+        pub use wayland_protocols::wp::input_method::zv1::client::zwp_input_panel_surface_v1::Position;
+    }
 }
 
 #[derive(Debug)]
@@ -347,6 +396,11 @@ impl ZxdgOutputInfo {
         }
     }
 
+    /// the name of the screen, like `DP-1`
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
     /// you can get the Logic position of the screen current surface in
     pub fn get_position(&self) -> (i32, i32) {
         self.position
@@ -356,6 +410,13 @@ impl ZxdgOutputInfo {
     pub fn get_logical_size(&self) -> (i32, i32) {
         self.logical_size
     }
+
+    /// the logical position and size of the screen in one call, as `(x, y, width, height)`
+    pub fn logical_geometry(&self) -> (i32, i32, i32, i32) {
+        let (x, y) = self.position;
+        let (width, height) = self.logical_size;
+        (x, y, width, height)
+    }
 }
 
 /// This is the unit, binding to per screen.
@@ -408,6 +469,14 @@ impl PartialEq<XdgToplevel> for Shell {
         }
     }
 }
+impl PartialEq<ZxdgToplevelDecorationV1> for Shell {
+    fn eq(&self, other: &ZxdgToplevelDecorationV1) -> bool {
+        match self {
+            Self::XdgTopLevel((_, _, Some(decoration))) => decoration == other,
+            _ => false,
+        }
+    }
+}
 impl Shell {
     fn destroy(&self) {
         match self {
@@ -484,6 +553,40 @@ impl<T> WindowStateUnitBuilder<T> {
                 scale: 120,
                 request_flag: Default::default(),
                 present_available_state: Default::default(),
+                keyboard_interactivity: Cell::new(
+                    zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand,
+                ),
+                keyboard_grab_restore: Cell::new(None),
+                keyboard_entered: Cell::new(false),
+                keyboard_entered_now: Cell::new(false),
+                pending_resize: PendingResize::default(),
+                defer_commit: Cell::new(false),
+                commit_pending: Cell::new(false),
+                min_present_interval: None,
+                last_present: None,
+                configured: false,
+                configured_size: None,
+                reposition_token: None,
+                output_refresh: None,
+                entered_outputs: Vec::new(),
+                layer_placement: Cell::new((Anchor::empty(), (0, 0, 0, 0))),
+                visible: true,
+                layer_config: RefCell::new(None),
+                frame_count: Cell::new(0),
+                buffer_scale: Cell::new(None),
+                seat: None,
+                decoration_mode: None,
+                preferred_transform: None,
+                fifo: None,
+                commit_timer: None,
+                commit_time: Cell::new(None),
+                fifo_barrier: Cell::new(false),
+                alpha_modifier: None,
+                idle_inhibitor: None,
+                auto_dismiss: None,
+                follows_output_width: false,
+                home_hidden: false,
+                reported_visibility: EffectiveVisibility::Visible,
             },
         }
     }
@@ -517,15 +620,45 @@ impl<T> WindowStateUnitBuilder<T> {
         self
     }
 
+    fn layer_placement(self, anchor: Anchor, margin: Option<(i32, i32, i32, i32)>) -> Self {
+        self.inner
+            .layer_placement
+            .set((anchor, margin.unwrap_or_default()));
+        self
+    }
+
+    fn layer_config(mut self, layer_config: LayerSurfaceConfig) -> Self {
+        self.inner.layer_config = RefCell::new(Some(layer_config));
+        self
+    }
+
     fn binding(mut self, binding: Option<T>) -> Self {
         self.inner.binding = binding;
         self
     }
 
+    fn auto_dismiss(mut self, auto_dismiss: Option<Duration>) -> Self {
+        self.inner.auto_dismiss = auto_dismiss.map(|after| (after, Instant::now() + after));
+        self
+    }
+
+    fn follows_output_width(mut self, follows: bool) -> Self {
+        self.inner.follows_output_width = follows;
+        self
+    }
+
     fn becreated(mut self, becreated: bool) -> Self {
         self.inner.becreated = becreated;
         self
     }
+
+    fn keyboard_interactivity(
+        self,
+        interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
+    ) -> Self {
+        self.inner.keyboard_interactivity.set(interactivity);
+        self
+    }
 }
 
 #[derive(Default, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
@@ -541,6 +674,218 @@ pub enum RefreshRequest {
     Wait,
 }
 
+/// `(x, y, width, height)`, in logical coordinates
+type LogicalRect = (i32, i32, i32, i32);
+
+/// the settings a layer surface is created with, kept to recreate it, see
+/// [WindowState::recreate_all]. The setters of the unit keep it up to date. The anchor, margin
+/// and keyboard interactivity are kept on the unit itself
+#[derive(Debug, Clone)]
+struct LayerSurfaceConfig {
+    layer: Layer,
+    namespace: String,
+    size: Option<(u32, u32)>,
+    exclusive_zone: Option<i32>,
+    events_transparent: bool,
+}
+
+/// the last configure of a unit with a new size, waiting to settle, see
+/// [WindowState::with_configure_debounce]
+#[derive(Debug, Default, Clone, Copy)]
+struct PendingResize(Option<(Instant, (u32, u32))>);
+
+impl PendingResize {
+    fn configure(&mut self, now: Instant, size: (u32, u32)) {
+        self.0 = Some((now, size));
+    }
+
+    /// the size of the last configure, once no configure came for `debounce`
+    fn take_settled(&mut self, now: Instant, debounce: Duration) -> Option<(u32, u32)> {
+        let (last, size) = self.0?;
+        if now.duration_since(last) < debounce {
+            return None;
+        }
+        self.0 = None;
+        Some(size)
+    }
+
+    fn is_pending(&self) -> bool {
+        self.0.is_some()
+    }
+
+    /// take a configure of `size` for a unit of size `current`, returns if the size is applied
+    /// now. The resize of a configured unit is held back when there is a debounce
+    fn configure_size(
+        &mut self,
+        configured: bool,
+        current: (u32, u32),
+        size: (u32, u32),
+        debounce: Option<Duration>,
+        now: Instant,
+    ) -> bool {
+        if configured && current != size && debounce.is_some() {
+            self.configure(now, size);
+            return false;
+        }
+        self.0 = None;
+        true
+    }
+
+    /// when the held back resize settles, if no configure comes before
+    fn deadline(&self, debounce: Duration) -> Option<Instant> {
+        self.0.map(|(last, _)| last + debounce)
+    }
+}
+
+/// the surface with the focus, the only place `Focused` and `Unfocus` are emitted from, so
+/// every `Focused` of a surface is followed by exactly one `Unfocus` of it
+#[derive(Debug, Default, Clone, Copy)]
+struct FocusState(Option<id::Id>);
+
+impl FocusState {
+    /// move the focus to `id`, unfocusing the surface that had it
+    fn focus(&mut self, id: Option<id::Id>) -> Vec<(Option<id::Id>, DispatchMessageInner)> {
+        if self.0 == id {
+            return Vec::new();
+        }
+        let mut messages = Vec::new();
+        if let Some(old) = self.0.take() {
+            messages.push((Some(old), DispatchMessageInner::Unfocus));
+        }
+        if let Some(new) = id {
+            messages.push((Some(new), DispatchMessageInner::Focused(new)));
+        }
+        self.0 = id;
+        messages
+    }
+
+    /// `id` lost the focus, a leave of a surface the focus already moved away from is stale
+    fn leave(&mut self, id: Option<id::Id>) -> Vec<(Option<id::Id>, DispatchMessageInner)> {
+        if id.is_none() || self.0 != id {
+            return Vec::new();
+        }
+        self.focus(None)
+    }
+
+    /// drop the focus of a destroyed surface, without an `Unfocus` for it
+    fn forget(&mut self, id: id::Id) {
+        if self.0 == Some(id) {
+            self.0 = None;
+        }
+    }
+}
+
+/// where the messages of the app come from
+enum MessageSource<Message> {
+    Channel(Channel<Message>),
+    #[cfg(feature = "stream")]
+    Stream(calloop::stream::StreamSource<MessageStream<Message>>),
+}
+
+#[cfg(feature = "stream")]
+type MessageStream<Message> = std::pin::Pin<Box<dyn futures::Stream<Item = Message>>>;
+
+/// a [MessageSource] registered in the event loop, it gives the source back once the loop is
+/// dropped
+enum MessageDispatcher<Message, Data> {
+    Channel(Dispatcher<'static, Channel<Message>, Data>),
+    #[cfg(feature = "stream")]
+    Stream(Dispatcher<'static, calloop::stream::StreamSource<MessageStream<Message>>, Data>),
+}
+
+impl<Message: 'static, Data> MessageDispatcher<Message, Data> {
+    fn into_source(self) -> MessageSource<Message> {
+        match self {
+            Self::Channel(dispatcher) => MessageSource::Channel(dispatcher.into_source_inner()),
+            #[cfg(feature = "stream")]
+            Self::Stream(dispatcher) => MessageSource::Stream(dispatcher.into_source_inner()),
+        }
+    }
+}
+
+/// what the event loop gives back when it stops because the connection is lost, to run again
+/// on a new one, see [WindowState::with_auto_reconnect]
+struct ConnectionLost<State: 'static, F, Message> {
+    state: State,
+    event_handler: F,
+    message_receiver: Option<MessageSource<Message>>,
+    user_loop: Option<EventLoop<'static, State>>,
+}
+
+/// a closure given to [WindowState::with_calloop_source]
+struct CalloopSource<T>(CalloopSourceFn<T>);
+
+type CalloopSourceFn<T> = Box<dyn FnOnce(&LoopHandle<'static, WindowState<T>>)>;
+
+impl<T> std::fmt::Debug for CalloopSource<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("CalloopSource")
+    }
+}
+
+/// the closure set by [WindowState::with_idle_callback]
+struct IdleCallback<T>(IdleCallbackFn<T>);
+
+type IdleCallbackFn<T> = Box<dyn FnMut(&mut WindowState<T>)>;
+
+impl<T> std::fmt::Debug for IdleCallback<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("IdleCallback")
+    }
+}
+
+/// the closure set by [WindowState::with_per_output_config]
+struct PerOutputConfig(Box<dyn Fn(&ZxdgOutputInfo) -> OutputLayerConfig>);
+
+impl std::fmt::Debug for PerOutputConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PerOutputConfig")
+    }
+}
+
+/// the closure set by [WindowState::with_output_filter]
+struct OutputFilter(Box<dyn Fn(&ZxdgOutputInfo) -> bool>);
+
+impl std::fmt::Debug for OutputFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("OutputFilter")
+    }
+}
+
+/// the closure set by [WindowState::set_input_filter_global]
+struct InputFilter(Box<dyn FnMut(&DispatchMessage) -> bool>);
+
+impl std::fmt::Debug for InputFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("InputFilter")
+    }
+}
+
+/// the closure set by [WindowState::with_foreign_toplevel_filter]
+#[cfg(feature = "foreign-toplevel")]
+struct ForeignToplevelFilter(Box<dyn Fn(&foreign_toplevel::ToplevelInfo) -> bool>);
+
+#[cfg(feature = "foreign-toplevel")]
+impl std::fmt::Debug for ForeignToplevelFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ForeignToplevelFilter")
+    }
+}
+
+/// Define what drives the redraw of the surfaces
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderMode {
+    /// surfaces are presented by both the frame callbacks and a 50ms timer
+    #[default]
+    Polling,
+    /// surfaces are only presented in response to `wl_surface.frame` callbacks and to
+    /// refresh requests. The timer only dispatches the protocol messages and sleeps when
+    /// there are none, like with [WindowState::with_on_demand_presentation]. Use it with
+    /// [WindowState::request_next_present] for smooth animations at the refresh rate of
+    /// the display
+    FrameCallback,
+}
+
 #[derive(Debug, Default)]
 struct WindowStateUnitRequestFlag {
     /// The flag of if this window has been requested to be closed.
@@ -562,6 +907,29 @@ pub struct WindowStateUnit<T> {
     fractional_scale: Option<WpFractionalScaleV1>,
     viewport: Option<WpViewport>,
     wl_output: Option<WlOutput>,
+    /// the outputs the surface is on, from `wl_surface.enter`/`leave`
+    entered_outputs: Vec<WlOutput>,
+    /// the anchor and margin of the layer surface, used to find where it is on the output
+    layer_placement: Cell<(Anchor, (i32, i32, i32, i32))>,
+    /// false when the surface is hidden with the layer_surface_visibility protocol, by
+    /// [WindowState::hide_surface] or by the compositor
+    visible: bool,
+    /// the settings of the layer surface, None for other shells
+    layer_config: RefCell<Option<LayerSurfaceConfig>>,
+    /// the size of the latest layer surface configure, even when the resize is still debounced
+    configured_size: Option<(u32, u32)>,
+    /// see [WindowStateUnit::frame_count]
+    frame_count: Cell<u64>,
+    /// the buffer scale set by [WindowStateUnit::set_buffer_scale], which turns off the one
+    /// following the outputs
+    buffer_scale: Cell<Option<i32>>,
+    /// the seat of the [WindowState], used by the interactive move and resize
+    seat: Option<WlSeat>,
+    /// the decoration mode last configured by the compositor, None for other shells or
+    /// before the first `zxdg_toplevel_decoration_v1.configure`
+    decoration_mode: Option<zxdg_toplevel_decoration_v1::Mode>,
+    /// see [WindowStateUnit::preferred_transform]
+    preferred_transform: Option<wl_output::Transform>,
     binding: Option<T>,
     becreated: bool,
     /// Whether this unit has had its initial Refresh event dispatched.
@@ -573,6 +941,53 @@ pub struct WindowStateUnit<T> {
     scale: u32,
     request_flag: WindowStateUnitRequestFlag,
     present_available_state: PresentAvailableState,
+    /// the keyboard interactivity last set on the layer surface
+    keyboard_interactivity: Cell<zwlr_layer_surface_v1::KeyboardInteractivity>,
+    /// the keyboard interactivity before [WindowStateUnit::grab_keyboard], restored by
+    /// [WindowStateUnit::release_keyboard]
+    keyboard_grab_restore: Cell<Option<zwlr_layer_surface_v1::KeyboardInteractivity>>,
+    /// if a keyboard Enter arrived since the keyboard interactivity was last set
+    keyboard_entered: Cell<bool>,
+    /// if the keyboard is on the surface, between a keyboard Enter and the following Leave
+    keyboard_entered_now: Cell<bool>,
+    /// the resize held back by [WindowState::with_configure_debounce]
+    pending_resize: PendingResize,
+    /// true inside [WindowState::batch], the setters don't commit then
+    defer_commit: Cell<bool>,
+    /// a setter skipped its commit because of `defer_commit`
+    commit_pending: Cell<bool>,
+    /// the min interval between two presents, set by [WindowState::with_max_fps]
+    min_present_interval: Option<Duration>,
+    last_present: Option<Instant>,
+    /// if the first configure of the surface has been received
+    configured: bool,
+    /// the token of the last xdg_popup.reposition not yet applied by the compositor
+    reposition_token: Option<u32>,
+    /// the refresh rate of the current mode of `wl_output`, in mHz
+    output_refresh: Option<i32>,
+    /// the wp_fifo_v1 object of the surface, created by [WindowState::set_fifo_barrier]
+    fifo: Option<WpFifoV1>,
+    /// if the fifo barrier is set and waited before every frame commit
+    fifo_barrier: Cell<bool>,
+    /// the wp_commit_timer_v1 object of the surface, created by [WindowState::set_commit_time]
+    commit_timer: Option<WpCommitTimerV1>,
+    /// the `(tv_sec, tv_nsec)` of [WindowState::set_commit_time] for the next frame commit,
+    /// a second call before it overwrites it
+    commit_time: Cell<Option<(u64, u32)>>,
+    /// the wp_alpha_modifier_surface_v1 of the surface, created by [WindowState::set_opacity]
+    alpha_modifier: Option<WpAlphaModifierSurfaceV1>,
+    /// the zwp_idle_inhibitor_v1 of the surface, while idle is inhibited
+    idle_inhibitor: Option<ZwpIdleInhibitorV1>,
+    /// the delay of [NewPopUpSettings::auto_dismiss], and when the popup is closed without
+    /// pointer motion over it
+    auto_dismiss: Option<(Duration, Instant)>,
+    /// the width follows the logical width of the output, for the keyboard input panels which
+    /// have no configure
+    follows_output_width: bool,
+    /// the home visibility mode of the surface hides it in the current home state
+    home_hidden: bool,
+    /// the effective visibility last sent with [DispatchMessage::EffectiveVisibilityChanged]
+    reported_visibility: EffectiveVisibility,
 }
 
 impl<T> WindowStateUnit<T> {
@@ -679,24 +1094,120 @@ impl<T> WindowStateUnit<T> {
         &self.wl_surface
     }
 
+    /// scoped access to the wl_surface and the queue handle of the unit, so requests the crate
+    /// does not wrap can be sent on the surface, for example to attach an object of an
+    /// experimental protocol to it.
+    ///
+    /// NOTE: objects created with the queue handle need a [Dispatch] implementation on
+    /// [WindowState], and they must be destroyed before the surface is removed
+    pub fn with_surface<R>(
+        &self,
+        f: impl FnOnce(&WlSurface, &QueueHandle<WindowState<T>>) -> R,
+    ) -> R {
+        f(&self.wl_surface, &self.qh)
+    }
+
+    /// if the surface is visible. It is false when hidden with the layer_surface_visibility
+    /// protocol, by [WindowState::hide_surface] or by the compositor (auto-hide, workspace
+    /// switch), hidden surfaces are not presented
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// if the surface can be seen: it is not hidden as in [WindowStateUnit::is_visible], and its
+    /// home visibility mode ([WindowState::with_home_only], [WindowState::with_hide_on_home] or
+    /// [WindowState::set_visibility_mode_for_surface]) does not hide it in the current home state
+    pub fn effective_visibility(&self) -> EffectiveVisibility {
+        if self.visible && !self.home_hidden {
+            EffectiveVisibility::Visible
+        } else {
+            EffectiveVisibility::Hidden
+        }
+    }
+
     /// get the xdg_output info related to this unit
     pub fn get_xdgoutput_info(&self) -> Option<&ZxdgOutputInfo> {
         self.zxdgoutput.as_ref()
     }
 
+    /// the logical geometry of the output of the unit, as `(x, y, width, height)` in the
+    /// global logical space, see [ZxdgOutputInfo::logical_geometry]
+    pub fn output_logical_geometry(&self) -> Option<(i32, i32, i32, i32)> {
+        self.zxdgoutput
+            .as_ref()
+            .map(ZxdgOutputInfo::logical_geometry)
+    }
+
+    /// the logical position of the layer surface on its output, computed from its anchor,
+    /// margin and size, like the compositor places it.
+    ///
+    /// NOTE: the exclusive zones of other surfaces are not known, so when the surface does not
+    /// ignore them (exclusive zone -1), the real position may be shifted by them. None if it is
+    /// not a layer surface, or the size of the output is not known yet
+    pub fn layer_origin(&self) -> Option<(i32, i32)> {
+        if !matches!(self.shell, Shell::LayerShell(_)) {
+            return None;
+        }
+        let (output_width, output_height) = self.zxdgoutput.as_ref()?.get_logical_size();
+        if output_width <= 0 || output_height <= 0 {
+            return None;
+        }
+        let (anchor, (top, right, bottom, left)) = self.layer_placement.get();
+        let (width, height) = (self.size.0 as i32, self.size.1 as i32);
+        Some((
+            layer_axis_origin(
+                anchor.contains(Anchor::Left),
+                anchor.contains(Anchor::Right),
+                left,
+                right,
+                width,
+                output_width,
+            ),
+            layer_axis_origin(
+                anchor.contains(Anchor::Top),
+                anchor.contains(Anchor::Bottom),
+                top,
+                bottom,
+                height,
+                output_height,
+            ),
+        ))
+    }
+
     /// set the anchor of the current unit. please take the simple.rs as reference
     pub fn set_anchor(&self, anchor: Anchor) {
+        if let Shell::LayerShell(_) = &self.shell {
+            self.set_anchor_no_commit(anchor);
+            self.commit_or_defer();
+        }
+    }
+
+    /// like [WindowStateUnit::set_anchor], without the commit. The change is applied with
+    /// the next [WindowStateUnit::commit]
+    pub fn set_anchor_no_commit(&self, anchor: Anchor) {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_anchor(anchor);
-            self.wl_surface.commit();
+            let (_, margin) = self.layer_placement.get();
+            self.layer_placement.set((anchor, margin));
         }
     }
 
     /// you can reset the margin which bind to the surface
-    pub fn set_margin(&self, (top, right, bottom, left): (i32, i32, i32, i32)) {
+    pub fn set_margin(&self, margin: (i32, i32, i32, i32)) {
+        if let Shell::LayerShell(_) = &self.shell {
+            self.set_margin_no_commit(margin);
+            self.commit_or_defer();
+        }
+    }
+
+    /// like [WindowStateUnit::set_margin], without the commit. The change is applied with
+    /// the next [WindowStateUnit::commit]
+    pub fn set_margin_no_commit(&self, (top, right, bottom, left): (i32, i32, i32, i32)) {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_margin(top, right, bottom, left);
-            self.wl_surface.commit();
+            let (anchor, _) = self.layer_placement.get();
+            self.layer_placement
+                .set((anchor, (top, right, bottom, left)));
         }
     }
 
@@ -704,7 +1215,8 @@ impl<T> WindowStateUnit<T> {
     pub fn set_layer(&self, layer: Layer) {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_layer(layer);
-            self.wl_surface.commit();
+            self.update_layer_config(|config| config.layer = layer);
+            self.commit_or_defer();
         }
     }
 
@@ -714,15 +1226,68 @@ impl<T> WindowStateUnit<T> {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_anchor(anchor);
             layer_shell.set_size(width, height);
-            self.wl_surface.commit();
+            self.update_layer_config(|config| config.size = Some((width, height)));
+            self.commit_or_defer();
+            let (_, margin) = self.layer_placement.get();
+            self.layer_placement.set((anchor, margin));
         }
     }
 
+    /// change the layer, anchor, size, margin and exclusive zone together, the ones which are
+    /// Some, in a single commit. Setting them one by one commits each change, so moving a panel
+    /// from the bottom to the top while resizing it goes through several configures and jumps
+    /// on the screen
+    pub fn reconfigure(
+        &self,
+        layer: Option<Layer>,
+        anchor: Option<Anchor>,
+        size: Option<(u32, u32)>,
+        margin: Option<(i32, i32, i32, i32)>,
+        exclusive_zone: Option<i32>,
+    ) {
+        let Shell::LayerShell(layer_shell) = &self.shell else {
+            return;
+        };
+        if let Some(layer) = layer {
+            layer_shell.set_layer(layer);
+            self.update_layer_config(|config| config.layer = layer);
+        }
+        let (mut placement_anchor, mut placement_margin) = self.layer_placement.get();
+        if let Some(anchor) = anchor {
+            layer_shell.set_anchor(anchor);
+            placement_anchor = anchor;
+        }
+        if let Some((width, height)) = size {
+            layer_shell.set_size(width, height);
+            self.update_layer_config(|config| config.size = Some((width, height)));
+        }
+        if let Some((top, right, bottom, left)) = margin {
+            layer_shell.set_margin(top, right, bottom, left);
+            placement_margin = (top, right, bottom, left);
+        }
+        if let Some(zone) = exclusive_zone {
+            layer_shell.set_exclusive_zone(zone);
+            self.update_layer_config(|config| config.exclusive_zone = Some(zone));
+        }
+        self.commit_or_defer();
+        self.layer_placement
+            .set((placement_anchor, placement_margin));
+    }
+
     /// set the layer size of current unit
-    pub fn set_size(&self, (width, height): (u32, u32)) {
+    pub fn set_size(&self, size: (u32, u32)) {
+        if let Shell::LayerShell(_) = &self.shell {
+            self.set_size_no_commit(size);
+            self.commit_or_defer();
+        }
+    }
+
+    /// like [WindowStateUnit::set_size], without the commit. The change is applied with
+    /// the next [WindowStateUnit::commit]
+    pub fn set_size_no_commit(&self, (width, height): (u32, u32)) {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_size(width, height);
-            self.wl_surface.commit();
+            self.update_layer_config(|config| config.size = Some((width, height)));
         }
     }
 
@@ -730,79 +1295,454 @@ impl<T> WindowStateUnit<T> {
     pub fn set_exclusive_zone(&self, zone: i32) {
         if let Shell::LayerShell(layer_shell) = &self.shell {
             layer_shell.set_exclusive_zone(zone);
-            self.wl_surface.commit();
+            self.update_layer_config(|config| config.exclusive_zone = Some(zone));
+            self.commit_or_defer();
         }
     }
 
-    /// set keyboard interactivity for the layer surface
-    pub fn set_keyboard_interactivity(
-        &self,
-        interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
-    ) {
-        if let Shell::LayerShell(layer_shell) = &self.shell {
-            layer_shell.set_keyboard_interactivity(interactivity);
-            self.wl_surface.commit();
+    /// the current layer, anchor, size, margin, exclusive zone and keyboard interactivity of the
+    /// layer surface, to give back to [WindowStateUnit::apply_config] later. None for other
+    /// shells, which have no layer settings. Every layer surface is built with its settings, so
+    /// it is always Some for them
+    pub fn config_snapshot(&self) -> Option<LayerConfig> {
+        if !matches!(self.shell, Shell::LayerShell(_)) {
+            return None;
         }
+        let (anchor, margin) = self.layer_placement.get();
+        let layer_config = self.layer_config.borrow();
+        let layer_config = layer_config.as_ref()?;
+        Some(LayerConfig {
+            layer: layer_config.layer,
+            anchor,
+            size: layer_config.size,
+            margin,
+            exclusive_zone: layer_config.exclusive_zone,
+            keyboard_interactivity: self.keyboard_interactivity.get(),
+        })
     }
 
-    /// you can use this function to set a binding data. the message passed back contain
-    /// a index, you can use that to get the unit. It will be very useful, because you can
-    /// use the binding data to operate the file binding to the buffer. you can take
-    /// startcolorkeyboard as reference.
-    pub fn set_binding(&mut self, binding: T) {
-        self.binding = Some(binding);
+    /// apply every setting of the [LayerConfig] in a single commit, like
+    /// [WindowStateUnit::reconfigure]
+    pub fn apply_config(&self, config: LayerConfig) {
+        let Shell::LayerShell(layer_shell) = &self.shell else {
+            return;
+        };
+        if self.keyboard_interactivity.get() != config.keyboard_interactivity {
+            layer_shell.set_keyboard_interactivity(config.keyboard_interactivity);
+            self.keyboard_interactivity
+                .set(config.keyboard_interactivity);
+            self.keyboard_entered.set(false);
+        }
+        self.reconfigure(
+            Some(config.layer),
+            Some(config.anchor),
+            config.size,
+            Some(config.margin),
+            config.exclusive_zone,
+        );
     }
 
-    /// return the binding data, with mut reference
-    pub fn get_binding_mut(&mut self) -> Option<&mut T> {
-        self.binding.as_mut()
+    /// reserve space on the output for the layer surface, and commit. With
+    /// [ExclusiveZone::Auto] the exclusive zone follows the current anchor: a surface anchored
+    /// to the top or bottom reserves along its height, one anchored to the left or right along
+    /// its width. When the anchor has no single edge, like a surface anchored to all edges,
+    /// the compositor ignores the zone and a warning is logged
+    pub fn reserve_space(&self, zone: ExclusiveZone) {
+        if !matches!(self.shell, Shell::LayerShell(_)) {
+            return;
+        }
+        let zone = match zone {
+            ExclusiveZone::Ignore => -1,
+            ExclusiveZone::None => 0,
+            ExclusiveZone::Auto(pixels) => {
+                let (anchor, _) = self.layer_placement.get();
+                let Some(edge) = exclusive_edge(anchor) else {
+                    log::warn!(
+                        "anchor {anchor:?} has no single edge, the exclusive zone is ignored"
+                    );
+                    return;
+                };
+                if pixels != 0 {
+                    pixels as i32
+                } else {
+                    // before the first configure only the requested size is known
+                    let (width, height) = if self.size != (0, 0) {
+                        self.size
+                    } else {
+                        self.requested_size().unwrap_or_default()
+                    };
+                    if edge.intersects(Anchor::Top | Anchor::Bottom) {
+                        height as i32
+                    } else {
+                        width as i32
+                    }
+                }
+            }
+        };
+        self.set_exclusive_zone(zone);
     }
 
-    /// get the binding data
-    pub fn get_binding(&self) -> Option<&T> {
-        self.binding.as_ref()
+    fn update_layer_config(&self, update: impl FnOnce(&mut LayerSurfaceConfig)) {
+        if let Some(config) = self.layer_config.borrow_mut().as_mut() {
+            update(config);
+        }
     }
 
-    /// get the size of the surface
-    pub fn get_size(&self) -> (u32, u32) {
-        self.size
+    /// the anchor of the layer surface, None for other shells
+    pub fn anchor(&self) -> Option<Anchor> {
+        matches!(self.shell, Shell::LayerShell(_)).then(|| self.layer_placement.get().0)
     }
 
-    /// this function will refresh whole surface. it will reattach the buffer, and damage whole,
-    /// and final commit
-    pub fn refresh(&self) {
-        self.wl_surface.attach(self.buffer.as_ref(), 0, 0);
-        self.wl_surface
-            .damage(0, 0, self.size.0 as i32, self.size.1 as i32);
-        self.wl_surface.commit();
+    /// the margin of the layer surface, as `(top, right, bottom, left)`. None for other shells
+    pub fn margin(&self) -> Option<(i32, i32, i32, i32)> {
+        matches!(self.shell, Shell::LayerShell(_)).then(|| self.layer_placement.get().1)
     }
 
-    pub fn scale_u32(&self) -> u32 {
-        self.scale
+    /// the layer of the layer surface, None for other shells
+    pub fn layer(&self) -> Option<Layer> {
+        self.layer_config
+            .borrow()
+            .as_ref()
+            .map(|config| config.layer)
     }
 
-    pub fn scale_float(&self) -> f64 {
-        self.scale as f64 / 120.
+    /// the size requested for the layer surface, where 0 means it is stretched between the
+    /// anchors. Unlike [WindowStateUnit::get_size] it is not the size given by the compositor.
+    /// None for other shells, or when no size was requested
+    pub fn requested_size(&self) -> Option<(u32, u32)> {
+        self.layer_config.borrow().as_ref()?.size
     }
 
-    pub fn request_close(&mut self) {
-        self.request_flag.close = true;
+    /// the size the compositor gave in the latest configure of the layer surface, compare it
+    /// with [WindowStateUnit::requested_size] to see if the compositor overrode the request.
+    /// None for other shells, or before the first configure
+    pub fn configured_size(&self) -> Option<(u32, u32)> {
+        self.configured_size
     }
 
-    pub fn request_refresh(&mut self, request: RefreshRequest) {
-        // refresh request in nearest future has the highest priority.
-        match self.request_flag.refresh {
-            RefreshRequest::NextFrame => {}
-            RefreshRequest::At(instant) => match request {
-                RefreshRequest::NextFrame => self.request_flag.refresh = request,
-                RefreshRequest::At(other_instant) => {
-                    if other_instant < instant {
-                        self.request_flag.refresh = request;
-                    }
-                }
-                RefreshRequest::Wait => {}
-            },
-            RefreshRequest::Wait => self.request_flag.refresh = request,
+    /// if the compositor configured another size than the requested one, the dimensions
+    /// requested as 0 are left to the compositor and never count as overridden, nor do the
+    /// dimensions configured as 0, which leave the requested size to the surface
+    pub fn size_overridden(&self) -> bool {
+        let (Some((width, height)), Some((configured_width, configured_height))) =
+            (self.requested_size(), self.configured_size)
+        else {
+            return false;
+        };
+        let overridden = |requested: u32, configured: u32| {
+            requested != 0 && configured != 0 && requested != configured
+        };
+        overridden(width, configured_width) || overridden(height, configured_height)
+    }
+
+    /// the exclusive zone of the layer surface, None for other shells, or when it was never set
+    pub fn exclusive_zone(&self) -> Option<i32> {
+        self.layer_config.borrow().as_ref()?.exclusive_zone
+    }
+
+    /// set the title of a xdg toplevel window, shown in its titlebar and in the taskbars
+    pub fn set_title(&self, title: String) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            toplevel.set_title(title);
+        }
+    }
+
+    /// set the app_id of a xdg toplevel window, used by the compositor and the docks to group
+    /// the windows and find the icon from the `.desktop` file of the app
+    pub fn set_app_id(&self, app_id: String) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            toplevel.set_app_id(app_id);
+        }
+    }
+
+    /// maximize a xdg toplevel window, or restore it
+    pub fn set_maximized(&self, maximized: bool) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            if maximized {
+                toplevel.set_maximized();
+            } else {
+                toplevel.unset_maximized();
+            }
+            self.commit_or_defer();
+        }
+    }
+
+    /// make a xdg toplevel window fullscreen, on the given output or on the one the compositor
+    /// chooses
+    pub fn set_fullscreen(&self, output: Option<&WlOutput>) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            toplevel.set_fullscreen(output);
+            self.commit_or_defer();
+        }
+    }
+
+    /// leave the fullscreen set by [WindowStateUnit::set_fullscreen]
+    pub fn unset_fullscreen(&self) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            toplevel.unset_fullscreen();
+            self.commit_or_defer();
+        }
+    }
+
+    /// minimize a xdg toplevel window. There is no way to restore it from the client, the user
+    /// does it from the taskbar
+    pub fn set_minimized(&self) {
+        if let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell {
+            toplevel.set_minimized();
+            self.commit_or_defer();
+        }
+    }
+
+    /// start an interactive move of a xdg toplevel window, like when dragging a custom
+    /// titlebar. `serial` is the serial of the press that starts it, see
+    /// [WindowState::last_button_serial]
+    pub fn start_move(&self, serial: u32) {
+        let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell else {
+            log::warn!("start_move: {:?} is not a xdg toplevel", self.id);
+            return;
+        };
+        let Some(seat) = &self.seat else {
+            log::warn!("start_move: no seat");
+            return;
+        };
+        toplevel._move(seat, serial);
+    }
+
+    /// start an interactive resize of a xdg toplevel window from the given edges. `serial`
+    /// is the serial of the press that starts it, see [WindowState::last_button_serial]
+    pub fn start_resize(&self, edges: xdg_toplevel::ResizeEdge, serial: u32) {
+        let Shell::XdgTopLevel((toplevel, _, _)) = &self.shell else {
+            log::warn!("start_resize: {:?} is not a xdg toplevel", self.id);
+            return;
+        };
+        let Some(seat) = &self.seat else {
+            log::warn!("start_resize: no seat");
+            return;
+        };
+        toplevel.resize(seat, serial, edges);
+    }
+
+    /// set keyboard interactivity for the layer surface
+    pub fn set_keyboard_interactivity(
+        &self,
+        interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
+    ) {
+        if let Shell::LayerShell(layer_shell) = &self.shell {
+            layer_shell.set_keyboard_interactivity(interactivity);
+            self.keyboard_interactivity.set(interactivity);
+            self.keyboard_entered.set(false);
+            self.commit_or_defer();
+        }
+    }
+
+    /// grab the keyboard, by set the keyboard interactivity to Exclusive, useful for modal
+    /// things like search bars. The current interactivity is remembered, and will be restored
+    /// by [WindowStateUnit::release_keyboard]
+    ///
+    /// NOTE: on most compositors, Exclusive only takes effect when the surface is on the
+    /// [Layer::Top] or [Layer::Overlay] layer
+    pub fn grab_keyboard(&self) {
+        if !matches!(self.shell, Shell::LayerShell(_)) {
+            return;
+        }
+        if self.keyboard_grab_restore.get().is_none() {
+            self.keyboard_grab_restore
+                .set(Some(self.keyboard_interactivity.get()));
+        }
+        self.set_keyboard_interactivity(zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive);
+    }
+
+    /// release the keyboard grabbed by [WindowStateUnit::grab_keyboard], and restore the
+    /// keyboard interactivity set before. Do nothing if the keyboard is not grabbed
+    pub fn release_keyboard(&self) {
+        if let Some(interactivity) = self.keyboard_grab_restore.take() {
+            self.set_keyboard_interactivity(interactivity);
+        }
+    }
+
+    /// if the keyboard is grabbed by [WindowStateUnit::grab_keyboard]
+    pub fn is_keyboard_grabbed(&self) -> bool {
+        self.keyboard_grab_restore.get().is_some()
+    }
+
+    /// the outputs the surface is shown on, from `wl_surface.enter` and `wl_surface.leave`.
+    /// A surface spanning the edge between two outputs is on both
+    pub fn current_outputs(&self) -> &[WlOutput] {
+        &self.entered_outputs
+    }
+
+    /// the decoration mode the compositor chose for the xdg toplevel, from the last
+    /// `zxdg_toplevel_decoration_v1.configure`. None on layer surfaces, and when the
+    /// compositor has no xdg decoration manager
+    pub fn decoration_mode(&self) -> Option<zxdg_toplevel_decoration_v1::Mode> {
+        self.decoration_mode
+    }
+
+    /// the buffer transform the compositor prefers for the surface, None before it is sent,
+    /// which never happens when the compositor only has a wl_surface older than v6
+    pub fn preferred_transform(&self) -> Option<wl_output::Transform> {
+        self.preferred_transform
+    }
+
+    /// if the keyboard interactivity is Exclusive or OnDemand, and the compositor did give
+    /// the keyboard focus to the surface since it was set. Some compositors refuse Exclusive
+    /// on the [Layer::Bottom] and [Layer::Background] layers, a launcher can check this
+    /// some time after [WindowStateUnit::grab_keyboard] to tell the user the grab was denied.
+    ///
+    /// NOTE: it stays true after the focus leaves the surface, it only tells the request was
+    /// honored once
+    pub fn keyboard_grab_active(&self) -> bool {
+        !matches!(
+            self.keyboard_interactivity.get(),
+            zwlr_layer_surface_v1::KeyboardInteractivity::None
+        ) && self.keyboard_entered.get()
+    }
+
+    /// you can use this function to set a binding data. the message passed back contain
+    /// a index, you can use that to get the unit. It will be very useful, because you can
+    /// use the binding data to operate the file binding to the buffer. you can take
+    /// startcolorkeyboard as reference.
+    pub fn set_binding(&mut self, binding: T) {
+        self.binding = Some(binding);
+    }
+
+    /// return the binding data, with mut reference
+    pub fn get_binding_mut(&mut self) -> Option<&mut T> {
+        self.binding.as_mut()
+    }
+
+    /// get the binding data
+    pub fn get_binding(&self) -> Option<&T> {
+        self.binding.as_ref()
+    }
+
+    /// get the size of the surface
+    pub fn get_size(&self) -> (u32, u32) {
+        self.size
+    }
+
+    /// commit the surface now, applying the changes made by the `_no_commit` setters in a
+    /// single configure. Unlike the setters it is not deferred by [WindowState::batch]
+    pub fn commit(&self) {
+        self.wl_surface.commit();
+    }
+
+    /// commit the surface, or remember to do it at the end of [WindowState::batch]
+    fn commit_or_defer(&self) {
+        if self.defer_commit.get() {
+            self.commit_pending.set(true);
+        } else {
+            self.wl_surface.commit();
+        }
+    }
+
+    /// this function will refresh whole surface. it will reattach the buffer, and damage whole,
+    /// and final commit
+    pub fn refresh(&self) {
+        self.wl_surface.attach(self.buffer.as_ref(), 0, 0);
+        let (width, height) = self.buffer_size();
+        self.damage_buffer(&[(0, 0, width, height)]);
+        self.wl_surface.commit();
+        self.frame_count.set(self.frame_count.get() + 1);
+    }
+
+    /// like [WindowStateUnit::refresh], but only damage the given `(x, y, width, height)`
+    /// rects, in buffer coordinates. The whole surface is damaged when the wl_surface is
+    /// older than v4, which has no `damage_buffer`
+    pub fn refresh_with_damage(&self, damage: &[(i32, i32, i32, i32)]) {
+        self.wl_surface.attach(self.buffer.as_ref(), 0, 0);
+        self.damage_buffer(damage);
+        self.wl_surface.commit();
+        self.frame_count.set(self.frame_count.get() + 1);
+    }
+
+    /// damage the given `(x, y, width, height)` rects, in buffer coordinates like the ones of
+    /// a GPU renderer, without a commit. The whole surface is damaged when the wl_surface is
+    /// older than v4, which has no `damage_buffer`
+    pub fn damage_buffer(&self, rects: &[(i32, i32, i32, i32)]) {
+        if self.wl_surface.version() >= 4 {
+            for (x, y, width, height) in rects {
+                self.wl_surface.damage_buffer(*x, *y, *width, *height);
+            }
+        } else {
+            self.wl_surface
+                .damage(0, 0, self.size.0 as i32, self.size.1 as i32);
+        }
+    }
+
+    /// the size of the buffer of the surface: the logical size times the buffer scale set by
+    /// [WindowStateUnit::set_buffer_scale], or else times the fractional scale
+    fn buffer_size(&self) -> (i32, i32) {
+        let scale = self
+            .buffer_scale
+            .get()
+            .map(f64::from)
+            .unwrap_or_else(|| self.scale_float());
+        (
+            (self.size.0 as f64 * scale).ceil() as i32,
+            (self.size.1 as f64 * scale).ceil() as i32,
+        )
+    }
+
+    /// set the scale of the buffers attached to the surface, for a client drawing at an
+    /// integer scale without fractional scale. Once set, the buffer scale no longer follows
+    /// the scale of the outputs the surface is on
+    pub fn set_buffer_scale(&self, scale: i32) {
+        if self.wl_surface.version() < 3 {
+            log::warn!("set_buffer_scale needs wl_surface v3");
+            return;
+        }
+        self.buffer_scale.set(Some(scale));
+        self.wl_surface.set_buffer_scale(scale);
+        self.commit_or_defer();
+    }
+
+    /// the number of frames committed by [WindowStateUnit::refresh] and
+    /// [WindowStateUnit::refresh_with_damage]. A client with several buffers keeps the count
+    /// at which each buffer was last presented, the age of a buffer is the difference with
+    /// the current count, and the damage to redraw is the one of the frames since
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count.get()
+    }
+
+    pub fn scale_u32(&self) -> u32 {
+        self.scale
+    }
+
+    pub fn scale_float(&self) -> f64 {
+        self.scale as f64 / 120.
+    }
+
+    pub fn request_close(&mut self) {
+        self.request_flag.close = true;
+    }
+
+    /// apply a configured size. The buffer of the old size is destroyed, so the next present
+    /// goes through [LayerShellEvent::RequestBuffer] for a buffer of the new size instead of
+    /// showing the stale one
+    fn apply_configured_size(&mut self, size: (u32, u32)) {
+        if self.size != size
+            && let Some(buffer) = self.buffer.take()
+        {
+            buffer.destroy();
+        }
+        self.size = size;
+    }
+
+    pub fn request_refresh(&mut self, request: RefreshRequest) {
+        // refresh request in nearest future has the highest priority.
+        match self.request_flag.refresh {
+            RefreshRequest::NextFrame => {}
+            RefreshRequest::At(instant) => match request {
+                RefreshRequest::NextFrame => self.request_flag.refresh = request,
+                RefreshRequest::At(other_instant) => {
+                    if other_instant < instant {
+                        self.request_flag.refresh = request;
+                    }
+                }
+                RefreshRequest::Wait => {}
+            },
+            RefreshRequest::Wait => self.request_flag.refresh = request,
         }
     }
 
@@ -814,18 +1754,73 @@ impl<T> WindowStateUnit<T> {
         }
     }
 
-    pub fn take_present_slot(&mut self) -> bool {
-        if !self.should_refresh() {
-            return false;
+    /// the refresh rate of the output the surface is on, in Hz, from the current mode of the
+    /// wl_output. On adaptive-sync displays it is the max refresh rate, the compositor may
+    /// present slower. None if the output or its mode is not known yet
+    pub fn output_refresh_rate(&self) -> Option<f64> {
+        self.output_refresh
+            .filter(|refresh| *refresh > 0)
+            .map(|refresh| refresh as f64 / 1000.)
+    }
+
+    /// set and wait the fifo barrier, it applies to the next commit of the surface
+    fn apply_fifo_barrier(&self) {
+        if let Some(fifo) = &self.fifo
+            && self.fifo_barrier.get()
+        {
+            fifo.wait_barrier();
+            fifo.set_barrier();
+        }
+    }
+
+    /// set the timestamp of [WindowState::set_commit_time], once, it applies to the next
+    /// commit of the surface
+    fn apply_commit_time(&self) {
+        if let Some(commit_timer) = &self.commit_timer
+            && let Some((tv_sec, tv_nsec)) = self.commit_time.take()
+        {
+            commit_timer.set_timestamp((tv_sec >> 32) as u32, tv_sec as u32, tv_nsec);
         }
+    }
+
+    /// the token of the last [WindowState::reposition_popup] request the compositor has not
+    /// applied yet
+    pub fn pending_reposition_token(&self) -> Option<u32> {
+        self.reposition_token
+    }
+
+    /// if the unit can present now: the previous frame is done and the max fps allows it
+    fn present_slot_available(&self) -> bool {
         if self.present_available_state != PresentAvailableState::Available {
             return false;
         }
+        // keep the refresh request, it will be presented when the interval is passed
+        !matches!(
+            (self.min_present_interval, self.last_present),
+            (Some(interval), Some(last_present)) if last_present.elapsed() < interval
+        )
+    }
+
+    pub fn take_present_slot(&mut self) -> bool {
+        if !self.should_refresh() || !self.present_slot_available() {
+            return false;
+        }
         self.request_flag.refresh = RefreshRequest::Wait;
         self.present_available_state = PresentAvailableState::Taken;
+        self.last_present = Some(Instant::now());
         true
     }
 
+    /// limit the present rate of this unit, None means no limit. It overrides
+    /// [WindowState::with_max_fps] for this unit, and applies on top of the frame callbacks:
+    /// [WindowStateUnit::take_present_slot] is refused until `1/max_fps` has passed since
+    /// the last present, the refresh request is kept until then
+    pub fn set_max_fps(&mut self, max_fps: Option<u32>) {
+        self.min_present_interval = max_fps
+            .filter(|fps| *fps > 0)
+            .map(|fps| Duration::from_secs(1) / fps);
+    }
+
     pub fn reset_present_slot(&mut self) -> bool {
         if self.present_available_state == PresentAvailableState::Taken {
             self.present_available_state = PresentAvailableState::Available;
@@ -837,6 +1832,11 @@ impl<T> WindowStateUnit<T> {
 }
 
 impl<T: 'static> WindowStateUnit<T> {
+    /// if the idle is inhibited by [WindowState::set_idle_inhibited]
+    pub fn is_idle_inhibited(&self) -> bool {
+        self.idle_inhibitor.is_some()
+    }
+
     pub fn request_next_present(&mut self) {
         match self.present_available_state {
             PresentAvailableState::Taken => {
@@ -876,6 +1876,15 @@ pub struct VirtualKeyRelease {
     pub key: u32,
 }
 
+/// a clipboard read started by [WindowState::request_selection], the selection source
+/// writes the data into `reader` and closes it when done
+#[derive(Debug)]
+struct SelectionRead {
+    id: Option<id::Id>,
+    mime: String,
+    reader: std::os::unix::net::UnixStream,
+}
+
 /// Per-surface frosted-glass blur params `(radius, saturation, tint, border)`,
 /// each optional (None = compositor default). Captured at surface creation so a
 /// later blur re-enable can re-apply them instead of the compositor defaults.
@@ -885,6 +1894,22 @@ type BlurParams = (Option<f32>, Option<f32>, Option<f32>, Option<f32>);
 /// drag-and-drop offer — what file managers use to advertise dragged files.
 const URI_LIST_MIME: &str = "text/uri-list";
 
+/// The log target of the traces enabled by [WindowState::with_trace].
+pub const TRACE_TARGET: &str = "layershellev::trace";
+
+/// Log a pacing event under [TRACE_TARGET], with the milliseconds passed since `epoch`.
+/// Does nothing when tracing is disabled (`epoch` is None).
+fn trace_event(epoch: Option<Instant>, id: Option<id::Id>, event: std::fmt::Arguments<'_>) {
+    let Some(epoch) = epoch else {
+        return;
+    };
+    let ms = epoch.elapsed().as_secs_f64() * 1000.;
+    match id {
+        Some(id) => log::info!(target: TRACE_TARGET, "{ms:>12.3}ms [{}] {event}", id.as_u64()),
+        None => log::info!(target: TRACE_TARGET, "{ms:>12.3}ms {event}"),
+    }
+}
+
 /// The drag-and-drop offer currently hovering one of our surfaces.
 #[derive(Debug, Clone)]
 struct DndCurrent {
@@ -894,6 +1919,8 @@ struct DndCurrent {
     surface_id: Option<id::Id>,
     /// Whether the offer advertises [`URI_LIST_MIME`] (i.e. is droppable here).
     has_uri_list: bool,
+    /// The MIME type accepted on enter, read on drop when it is not [`URI_LIST_MIME`].
+    accepted_mime: Option<String>,
 }
 
 /// Pre-serialized payload attached to a `wl_data_source` we start, so the
@@ -937,12 +1964,141 @@ impl DndIconResources {
 /// main state, store the main information
 #[derive(Debug)]
 pub struct WindowState<T> {
+    /// everything bound to the wayland connection, see [WaylandState]
+    wl: WaylandState<T>,
+    units: Vec<WindowStateUnit<T>>,
+
+    // states
+    namespace: String,
+    keyboard_interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
+    anchor: Anchor,
+    layer: Layer,
+    size: Option<(u32, u32)>,
+    exclusive_zone: Option<i32>,
+    margin: Option<(i32, i32, i32, i32)>,
+
+    // settings
+    use_display_handle: bool,
+    closed_ids: Vec<id::Id>,
+
+    /// the start of the timestamps of [WindowState::virtual_key_press] and co
+    virtual_keyboard_epoch: Instant,
+
+    last_unit_index: usize,
+
+    return_data: Vec<ReturnData<T>>,
+    /// set by [WindowState::with_touch_emulates_pointer]
+    touch_emulates_pointer: bool,
+    /// messages rejected by it are dropped before they reach the event handler
+    input_filter: Option<InputFilter>,
+    /// set by [WindowState::with_idle_callback]
+    idle_callback: Option<IdleCallback<T>>,
+    /// set by [WindowState::with_per_output_config]
+    per_output_config: Option<PerOutputConfig>,
+    /// set by [WindowState::with_output_filter]
+    output_filter: Option<OutputFilter>,
+    /// given by [WindowState::with_calloop_source], registered when the event loop starts
+    calloop_sources: Vec<CalloopSource<T>>,
+    /// the last area set by [WindowState::set_ime_cursor_area]
+    ime_cursor_area: Cell<Option<(id::Id, LogicalRect)>>,
+
+    /// How [`StartMode::TargetScreen`] resolves its name to an output.
+    output_match_strategy: OutputMatchStrategy,
+
+    start_mode: StartMode,
+    max_fps: Option<u32>,
+    render_mode: RenderMode,
+    /// set by [WindowState::with_on_demand_presentation]
+    on_demand_presentation: bool,
+    /// the start of the traces, Some when [WindowState::with_trace] is enabled
+    trace_epoch: Option<Instant>,
+    /// set by [WindowState::with_configure_debounce]
+    configure_debounce: Option<Duration>,
+    /// set by [WindowState::with_lazy_protocol_binding]
+    lazy_protocol_binding: bool,
+    /// set by [WindowState::with_dispatch_only_on_activity]
+    dispatch_only_on_activity: bool,
+    /// set by [WindowState::with_coalesce_motion]
+    coalesce_motion: bool,
+    frame_stats: FrameStats,
+    /// set by [WindowState::with_auto_reconnect]
+    auto_reconnect: bool,
+    /// set by [WindowState::with_reconnect_attempts]
+    reconnect_attempts: u32,
+    /// Some while reconnecting: the name of the output of every unit, to create its surface
+    /// again on the same output
+    reconnect_outputs: Option<HashMap<id::Id, Option<String>>>,
+    next_reposition_token: u32,
+    /// popup id → (parent layer shell id, the placement of the popup), used to re-anchor
+    /// the popups when the parent is resized
+    popup_parents: HashMap<id::Id, (id::Id, RepositionPopUpSettings)>,
+    /// layer surface id → the id of the surface it is closed with, see
+    /// [NewLayerShellSettings::parent]
+    layer_parents: HashMap<id::Id, id::Id>,
+    events_transparent: bool,
+    /// Whether to request blur effect for surfaces
+    blur: bool,
+    /// Custom blur radius in pixels (None = compositor default). Applies to the
+    /// initial + per-output (`AllScreens`/`NewDisplay`) surfaces.
+    blur_radius: Option<f32>,
+    /// Backdrop saturation multiplier for the blur (None = no change). Applies to
+    /// the initial + per-output surfaces.
+    blur_saturation: Option<f32>,
+    /// Frosted-glass white-tint strength for the blur (None = compositor default).
+    /// Applies to the initial + per-output surfaces.
+    blur_tint: Option<f32>,
+    /// Frosted-glass border strength for the blur (None = compositor default).
+    /// Applies to the initial + per-output surfaces.
+    blur_border: Option<f32>,
+    /// Corner radius for surfaces (all four corners)
+    corner_radius: Option<[u32; 4]>,
+    /// Whether to request shadow effect for surfaces
+    shadow: bool,
+    /// Global show/hide transition animation requested for surfaces (via the
+    /// `layer_surface_visibility` protocol).  `None` lets the compositor decide
+    /// based on the surface anchor.  Applied when a visibility controller is
+    /// created (requires compositor support for the protocol version 2).
+    transition: Option<LayerTransition>,
+
+    /// Whether to use home visibility mode (home_only = only visible when at home)
+    home_only: bool,
+    /// Whether to hide when in home mode (inverse of home_only)
+    hide_on_home: bool,
+    /// the modes given by [WindowState::set_visibility_mode_for_surface], keyed by the id of
+    /// the unit, so they follow it to the surfaces of [WindowState::recreate_all] and of a
+    /// reconnect. The other surfaces use the mode of `home_only` or `hide_on_home`
+    home_visibility_modes: HashMap<id::Id, home_visibility::VisibilityMode>,
+
+    /// Whether to register for voice mode events
+    voice_mode_enabled: bool,
+
+    /// Whether to track foreign toplevel windows (taskbar/dock functionality)
+    #[cfg(feature = "foreign-toplevel")]
+    foreign_toplevel_enabled: bool,
+
+    /// Filter deciding which toplevels emit events, see [WindowState::with_foreign_toplevel_filter]
+    #[cfg(feature = "foreign-toplevel")]
+    foreign_toplevel_filter: Option<ForeignToplevelFilter>,
+
+    ime_purpose: ImePurpose,
+    /// the raw content type set by [WindowState::set_ime_content_type], used instead of
+    /// `ime_purpose` until a purpose is set again
+    ime_content_type: Option<(ContentHint, ContentPurpose)>,
+    ime_allowed: bool,
+}
+
+/// the state bound to the wayland connection: the globals, the objects created from them and
+/// what the compositor told through them. When the connection is lost it is replaced as a
+/// whole by [WindowState::reset_connection_state], the settings and the units are kept
+#[derive(Debug)]
+struct WaylandState<T> {
     outputs: Vec<(u32, wl_output::WlOutput)>,
     current_surface: Option<WlSurface>,
+    focus: FocusState,
     active_surfaces: HashMap<Option<i32>, (WlSurface, Option<id::Id>)>,
-    units: Vec<WindowStateUnit<T>>,
     message: Vec<(Option<id::Id>, DispatchMessageInner)>,
-
+    /// the output hotplugs, sent before the messages
+    output_changes: Vec<OutputChange>,
     connection: Option<Connection>,
     event_queue: Option<EventQueue<WindowState<T>>>,
     wl_compositor: Option<WlCompositor>,
@@ -952,20 +2108,23 @@ pub struct WindowState<T> {
     cursor_manager: Option<WpCursorShapeManagerV1>,
     viewporter: Option<WpViewporter>,
     fractional_scale_manager: Option<WpFractionalScaleManagerV1>,
-    globals: Option<GlobalList>,
-
+    globals: Option<Rc<GlobalList>>,
     // background
     background_surface: Option<WlSurface>,
     display: Option<WlDisplay>,
-
     // base managers
     seat: Option<WlSeat>,
+    /// the name of the seat, sent by wl_seat (v2+)
+    seat_name: Option<String>,
     keyboard_state: Option<xkb_keyboard::KeyboardState>,
-
+    /// the key repeat sent by `wl_keyboard.repeat_info`, None until the compositor sends it
+    keyboard_repeat_info: Option<RepeatInfo>,
     pointer: Option<WlPointer>,
+    /// the cursor shape device of the pointer, kept across shape changes and destroyed with
+    /// the pointer
+    cursor_shape_device: Option<CursorShapeDevice>,
     touch: Option<WlTouch>,
     virtual_keyboard: Option<ZwpVirtualKeyboardV1>,
-
     // drag-and-drop (receive only): the data device + the offered MIME types per
     // live `wl_data_offer`, and the currently-hovering drag offer (if any).
     data_device_manager: Option<WlDataDeviceManager>,
@@ -976,39 +2135,49 @@ pub struct WindowState<T> {
     dnd_current: Option<DndCurrent>,
     /// Surface that initiated an outgoing drag — routes source-side events.
     dnd_source_origin: Option<id::Id>,
+    /// The offer of the current clipboard selection, read by `request_selection`.
+    selection_offer: Option<WlDataOffer>,
+    /// The source set by `set_selection`, alive until another client takes the selection.
+    selection_source: Option<WlDataSource>,
     /// Serial of the most recent pointer button press (required by `start_drag`).
     last_button_serial: Option<u32>,
+    /// Serial of the most recent pointer press, touch down or key press, used by
+    /// `xdg_popup.grab`, which needs the serial of a user input event.
+    last_input_serial: Option<u32>,
+    /// Serial of the most recent pointer enter or button event.
+    last_pointer_serial: Option<u32>,
+    /// Serial of the most recent touch down or up.
+    last_touch_serial: Option<u32>,
+    /// Serial of the most recent keyboard enter or key event.
+    last_keyboard_serial: Option<u32>,
     /// Live drag-icon resources, kept alive for the duration of an outgoing drag.
     dnd_icon: Option<DndIconResources>,
     /// Compositor + shm cached at loop start (the originals are taken by the loop)
     /// so `start_drag` can build the drag-icon surface.
     cached_compositor: Option<WlCompositor>,
     cached_shm: Option<WlShm>,
-
-    // states
-    namespace: String,
-    keyboard_interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
-    anchor: Anchor,
-    layer: Layer,
-    size: Option<(u32, u32)>,
-    exclusive_zone: Option<i32>,
-    margin: Option<(i32, i32, i32, i32)>,
-
-    // settings
-    use_display_handle: bool,
     repeat_delay: Option<KeyboardTokenState>,
     to_remove_tokens: Vec<RegistrationToken>,
-    closed_ids: Vec<id::Id>,
-
-    to_be_released_key: Option<VirtualKeyRelease>,
-
-    last_unit_index: usize,
+    /// the virtual key releases to schedule, one per key still held, with whether the
+    /// modifiers of the virtual keyboard are cleared with the release, for
+    /// [WindowState::virtual_key_with_modifiers]
+    to_be_released_keys: Vec<(VirtualKeyRelease, bool)>,
+    /// the clipboard reads of [WindowState::request_selection] to add to the event loop
+    selection_reads: Vec<SelectionRead>,
     last_wloutput: Option<WlOutput>,
-
-    return_data: Vec<ReturnData<T>>,
     finger_locations: HashMap<i32, (f64, f64)>,
+    /// the finger which currently drives the emulated pointer, the surface it is on, and if
+    /// a MouseEnter was sent for it, which needs a wl_pointer
+    emulated_pointer_finger: Option<(i32, Option<id::Id>, bool)>,
+    /// the time of the latest touch event, for the release of the emulated pointer on a
+    /// touch cancel, which has no time
+    last_touch_time: u32,
     enter_serial: Option<u32>,
-
+    /// the latest pointer position, with the id of the surface the pointer is on
+    pointer_position: Option<(id::Id, f64, f64)>,
+    /// the outputs the output filter skipped, checked again by
+    /// [WindowState::reapply_output_filter]
+    filtered_outputs: Vec<WlOutput>,
     xdg_info_cache: Vec<(wl_output::WlOutput, ZxdgOutputInfo)>,
     /// Logical layout of every output (global coords), gathered once at startup.
     /// Exposed via [`WindowState::output_layout`] for cross-monitor positioning.
@@ -1016,24 +2185,24 @@ pub struct WindowState<T> {
     /// Maps each output's xdg name → its `WlOutput`, so a runtime `NewLayerShell`
     /// with `OutputOption::OutputName` can be bound to that monitor.
     output_handles: Vec<(String, wl_output::WlOutput)>,
-
-    start_mode: StartMode,
+    /// The wl_output (v4+) `name` of every bound output, keyed by the output's
+    /// protocol id. Usually the connector name (DP-1, HDMI-A-1).
+    wl_output_names: HashMap<u32, String>,
+    /// The make and model from `wl_output.geometry` of every bound output, keyed by
+    /// the output's protocol id.
+    output_make_models: HashMap<u32, (String, String)>,
+    /// The refresh rate (mHz) of the current mode of every bound output, keyed by
+    /// the output's protocol id.
+    output_refresh_rates: HashMap<u32, i32>,
+    /// `wl_output.scale` of every output, keyed by the protocol id of the wl_output
+    output_scales: HashMap<u32, i32>,
     init_finished: bool,
-    events_transparent: bool,
-    /// Whether to request blur effect for surfaces
-    blur: bool,
-    /// Custom blur radius in pixels (None = compositor default). Applies to the
-    /// initial + per-output (`AllScreens`/`NewDisplay`) surfaces.
-    blur_radius: Option<f32>,
-    /// Backdrop saturation multiplier for the blur (None = no change). Applies to
-    /// the initial + per-output surfaces.
-    blur_saturation: Option<f32>,
-    /// Frosted-glass white-tint strength for the blur (None = compositor default).
-    /// Applies to the initial + per-output surfaces.
-    blur_tint: Option<f32>,
-    /// Frosted-glass border strength for the blur (None = compositor default).
-    /// Applies to the initial + per-output surfaces.
-    blur_border: Option<f32>,
+    /// the timer of the event loop sleeps, waiting for work to be armed again
+    timer_idle: bool,
+    /// a timer of the event loop waits for the next held back resize to settle
+    resize_timer_armed: bool,
+    /// a fatal error was returned by the dispatch of the event queue
+    connection_lost: bool,
     /// Blur manager (bound lazily when blur is enabled)
     blur_manager: Option<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager>,
     /// Blur objects per surface (keyed by surface protocol ID)
@@ -1042,8 +2211,6 @@ pub struct WindowState<T> {
     /// captured at surface creation so a later blur re-enable (the auto-size
     /// deferred-effects path) re-applies them instead of compositor defaults.
     blur_params: HashMap<u32, BlurParams>,
-    /// Corner radius for surfaces (all four corners)
-    corner_radius: Option<[u32; 4]>,
     /// Corner radius manager (bound lazily when corner_radius is set)
     corner_radius_manager:
         Option<corner_radius::layer_corner_radius_manager_v1::LayerCornerRadiusManagerV1>,
@@ -1065,8 +2232,6 @@ pub struct WindowState<T> {
     /// Edge-resize objects per surface (keyed by surface protocol ID)
     layer_edge_resize_surfaces:
         HashMap<u32, layer_edge_resize::layer_edge_resize_v1::LayerEdgeResizeV1>,
-    /// Whether to request shadow effect for surfaces
-    shadow: bool,
     /// Shadow manager (bound lazily when shadow is enabled)
     shadow_manager: Option<shadow::layer_shadow_manager_v1::LayerShadowManagerV1>,
     /// Shadow objects per surface (keyed by surface protocol ID)
@@ -1079,16 +2244,24 @@ pub struct WindowState<T> {
     /// handling them, so an overlay (e.g. the Alt-Tab switcher) can receive Tab
     /// presses + key-repeat directly.
     keyboard_shortcuts_inhibitors: HashMap<u32, ZwpKeyboardShortcutsInhibitorV1>,
-    /// Global show/hide transition animation requested for surfaces (via the
-    /// `layer_surface_visibility` protocol).  `None` lets the compositor decide
-    /// based on the surface anchor.  Applied when a visibility controller is
-    /// created (requires compositor support for the protocol version 2).
-    transition: Option<LayerTransition>,
+    /// FIFO manager (bound lazily when a fifo barrier is first requested)
+    fifo_manager: Option<WpFifoManagerV1>,
+    /// commit timing manager (bound lazily when a commit time is first requested)
+    commit_timing_manager: Option<WpCommitTimingManagerV1>,
+    /// Alpha modifier manager (bound lazily when an opacity is first set)
+    alpha_modifier_manager: Option<WpAlphaModifierV1>,
+    /// Idle inhibit manager (bound lazily when idle is first inhibited)
+    idle_inhibit_manager: Option<ZwpIdleInhibitManagerV1>,
+    /// wp_presentation, used to request feedback of every presented frame
+    #[cfg(feature = "presentation-time")]
+    presentation: Option<WpPresentation>,
+    /// the clock of the timestamps in [DispatchMessage::Presented]
+    #[cfg(feature = "presentation-time")]
+    presentation_clock_id: Option<u32>,
     /// Per-surface transition overrides (keyed by surface protocol ID), set from
     /// [`NewLayerShellSettings::transition`].  Takes precedence over `transition`
     /// for that surface.  See [`WindowState::surface_transition`].
     transitions: HashMap<u32, LayerTransition>,
-
     /// Auto-hide manager (bound lazily when needed)
     auto_hide_manager:
         Option<layer_auto_hide::layer_auto_hide_manager_v1::LayerAutoHideManagerV1>,
@@ -1100,7 +2273,6 @@ pub struct WindowState<T> {
     /// Defaults to `true` (visible) until auto-hide is configured and the
     /// compositor tells us otherwise.
     auto_hide_visible: bool,
-
     /// Usable-area manager (bound at startup when the compositor supports it).
     /// Reports each surface's output usable (non-exclusive) area so consumers
     /// can center within the space free of panels/docks rather than the full
@@ -1110,18 +2282,12 @@ pub struct WindowState<T> {
     /// Usable-area objects per surface (keyed by surface protocol ID).
     usable_area_surfaces:
         HashMap<u32, layer_usable_area::layer_usable_area_v1::LayerUsableAreaV1>,
-
     /// Tooltip manager (bound lazily when a popup with tooltip settings is created)
     tooltip_manager:
         Option<tooltip::zcosmic_tooltip_manager_v1::ZcosmicTooltipManagerV1>,
     /// Tooltip objects per popup surface (keyed by surface protocol ID)
     tooltip_surfaces:
         HashMap<u32, tooltip::zcosmic_tooltip_v1::ZcosmicTooltipV1>,
-
-    /// Whether to use home visibility mode (home_only = only visible when at home)
-    home_only: bool,
-    /// Whether to hide when in home mode (inverse of home_only)
-    hide_on_home: bool,
     /// Home visibility manager (bound lazily when home_only or hide_on_home is enabled)
     home_visibility_manager:
         Option<home_visibility::zcosmic_home_visibility_manager_v1::ZcosmicHomeVisibilityManagerV1>,
@@ -1130,9 +2296,6 @@ pub struct WindowState<T> {
         HashMap<u32, home_visibility::zcosmic_home_visibility_v1::ZcosmicHomeVisibilityV1>,
     /// Current home state from compositor (true = at home, false = windows visible)
     is_home: bool,
-
-    /// Whether to register for voice mode events
-    voice_mode_enabled: bool,
     /// Voice mode manager (bound lazily when voice_mode_enabled is true)
     voice_mode_manager:
         Option<voice_mode::zcosmic_voice_mode_manager_v1::ZcosmicVoiceModeManagerV1>,
@@ -1141,7 +2304,6 @@ pub struct WindowState<T> {
         HashMap<u32, voice_mode::zcosmic_voice_mode_v1::ZcosmicVoiceModeV1>,
     /// Pending voice mode events from compositor
     voice_mode_events: Vec<voice_mode::VoiceModeEvent>,
-
     /// Layer surface visibility manager (bound lazily when needed)
     layer_surface_visibility_manager: Option<
         layer_surface_visibility::zcosmic_layer_surface_visibility_manager_v1::ZcosmicLayerSurfaceVisibilityManagerV1,
@@ -1156,7 +2318,6 @@ pub struct WindowState<T> {
     /// `hide_surface`/`show_surface` calls and confirmed by the
     /// compositor's `VisibilityChanged` events.
     hidden_surfaces: HashSet<u32>,
-
     /// Layer surface dismiss manager (bound lazily when needed)
     layer_surface_dismiss_manager: Option<
         layer_surface_dismiss::zcosmic_layer_surface_dismiss_manager_v1::ZcosmicLayerSurfaceDismissManagerV1,
@@ -1168,55 +2329,44 @@ pub struct WindowState<T> {
     >,
     /// Pending dismiss events from compositor
     dismiss_requested: bool,
-
-    /// Whether to track foreign toplevel windows (taskbar/dock functionality)
-    #[cfg(feature = "foreign-toplevel")]
-    foreign_toplevel_enabled: bool,
-
     // ext_foreign_toplevel_list_v1 (preferred, standard Wayland protocol)
     #[cfg(feature = "foreign-toplevel")]
     ext_foreign_toplevel_list: Option<
         wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1,
     >,
-
     // zcosmic_toplevel_info_v1 (COSMIC extension for state info)
     #[cfg(feature = "cosmic-toplevel")]
     cosmic_toplevel_info: Option<
         cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1,
     >,
-
     // zcosmic_toplevel_manager_v1 (COSMIC extension for control - activate, close, etc.)
     #[cfg(feature = "cosmic-toplevel")]
     cosmic_toplevel_manager: Option<
         cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1,
     >,
-
     /// Mapping from cosmic handle protocol ID to ext handle protocol ID
     #[cfg(feature = "cosmic-toplevel")]
     cosmic_to_ext_handle_map: HashMap<u32, u32>,
-
     /// COSMIC toplevel handles for control operations (keyed by ext handle protocol ID)
     #[cfg(feature = "cosmic-toplevel")]
     cosmic_toplevel_handles: HashMap<u32, cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1>,
-
     // zwlr_foreign_toplevel_manager_v1 (wlroots fallback)
     #[cfg(feature = "foreign-toplevel")]
     foreign_toplevel_manager: Option<
         wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1,
     >,
-
     /// Data for each tracked foreign toplevel window (keyed by protocol object ID)
     #[cfg(feature = "foreign-toplevel")]
     foreign_toplevel_data: HashMap<u32, foreign_toplevel::ToplevelHandleData>,
-
+    /// Toplevels for which a Created event was emitted, and not yet a Closed one
+    #[cfg(feature = "foreign-toplevel")]
+    foreign_toplevel_emitted: HashSet<u32>,
     /// Handles for each tracked foreign toplevel window (for sending commands)
     #[cfg(feature = "foreign-toplevel")]
     foreign_toplevel_handles: HashMap<u32, wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1>,
-
     /// Ext foreign toplevel handles (for screencopy capture sources)
     #[cfg(feature = "foreign-toplevel")]
     ext_toplevel_handles: HashMap<u32, wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1>,
-
     /// Screencopy protocol state
     #[cfg(feature = "screencopy")]
     screencopy: screencopy::ScreencopyState,
@@ -1226,16 +2376,13 @@ pub struct WindowState<T> {
     /// Cached WlShm for use after shm is taken by the event loop
     #[cfg(feature = "screencopy")]
     screencopy_shm: Option<wayland_client::protocol::wl_shm::WlShm>,
-
     text_input_manager: Option<ZwpTextInputManagerV3>,
     text_input: Option<ZwpTextInputV3>,
+    /// the seat has a keyboard, the text input is created by
+    /// [WindowState::ensure_text_input] once a surface can get the keyboard focus
+    text_input_wanted: bool,
     text_inputs: Vec<ZwpTextInputV3>,
-
     xdg_decoration_manager: Option<ZxdgDecorationManagerV1>,
-
-    ime_purpose: ImePurpose,
-    ime_allowed: bool,
-
     /// Ping sender for waking the event loop immediately after a channel
     /// message is processed.  Populated once in `running_with_proxy_option`.
     ping_sender: Option<calloop::ping::Ping>,
@@ -1247,75 +2394,146 @@ impl<T> WindowState<T> {
     }
     /// remove a shell, destroy the surface
     fn remove_shell(&mut self, id: id::Id) -> Option<()> {
-        let index = self
+        if !self
             .units
             .iter()
-            .position(|unit| unit.id == id && unit.becreated)?;
+            .any(|unit| unit.id == id && unit.becreated)
+        {
+            return None;
+        }
+
+        // a popup must be destroyed before its parent, xdg_wm_base raises
+        // not_the_topmost_popup otherwise. The children are reported as closed, with the
+        // layer surfaces closed together with this one
+        let children: Vec<id::Id> = self
+            .popup_parents
+            .iter()
+            .filter(|(_, (parent, _))| *parent == id)
+            .map(|(child, _)| *child)
+            .chain(
+                self.layer_parents
+                    .iter()
+                    .filter(|(_, parent)| **parent == id)
+                    .map(|(child, _)| *child),
+            )
+            .collect();
+        for child in children {
+            if self.remove_shell(child).is_some() {
+                self.closed_ids.push(child);
+            }
+        }
+        let index = self.units.iter().position(|unit| unit.id == id)?;
+
+        self.popup_parents.remove(&id);
+        self.layer_parents.remove(&id);
+        self.wl.focus.forget(id);
+        self.frame_stats.unit_presents.remove(&id);
+        self.home_visibility_modes.remove(&id);
+        self.destroy_unit_surface(index);
+        self.units.remove(index);
+        Some(())
+    }
 
+    /// destroy the wl_surface of a unit, with its shell and every per-surface object
+    fn destroy_unit_surface(&mut self, index: usize) {
         // Clean up per-surface protocol objects BEFORE destroying the surface.
         // Protocol objects reference the wl_surface; using them after destruction
         // causes "surface_destroyed" protocol errors. Wayland reuses protocol IDs,
         // so stale entries would be found by new surfaces with the same ID.
         let surface_id = self.units[index].wl_surface.id().protocol_id();
-        if let Some(corner_obj) = self.corner_radius_surfaces.remove(&surface_id) {
+        if let Some(corner_obj) = self.wl.corner_radius_surfaces.remove(&surface_id) {
             corner_obj.destroy();
         }
-        if let Some(placement_obj) = self.layer_surface_placement_surfaces.remove(&surface_id) {
+        if let Some(placement_obj) = self.wl.layer_surface_placement_surfaces.remove(&surface_id) {
             placement_obj.destroy();
         }
-        if let Some(edge_resize_obj) = self.layer_edge_resize_surfaces.remove(&surface_id) {
+        if let Some(edge_resize_obj) = self.wl.layer_edge_resize_surfaces.remove(&surface_id) {
             edge_resize_obj.destroy();
         }
-        if let Some(blur_obj) = self.blur_surfaces.remove(&surface_id) {
+        if let Some(blur_obj) = self.wl.blur_surfaces.remove(&surface_id) {
             blur_obj.release();
         }
-        if let Some(shadow_obj) = self.shadow_surfaces.remove(&surface_id) {
+        if let Some(shadow_obj) = self.wl.shadow_surfaces.remove(&surface_id) {
             shadow_obj.destroy();
         }
-        if let Some(inhibitor) = self.keyboard_shortcuts_inhibitors.remove(&surface_id) {
+        if let Some(inhibitor) = self.wl.keyboard_shortcuts_inhibitors.remove(&surface_id) {
             inhibitor.destroy();
         }
-        if let Some(auto_hide_obj) = self.auto_hide_surfaces.remove(&surface_id) {
+        if let Some(auto_hide_obj) = self.wl.auto_hide_surfaces.remove(&surface_id) {
             auto_hide_obj.destroy();
         }
-        if let Some(usable_area_obj) = self.usable_area_surfaces.remove(&surface_id) {
+        if let Some(usable_area_obj) = self.wl.usable_area_surfaces.remove(&surface_id) {
             usable_area_obj.destroy();
         }
-        if let Some(tooltip_obj) = self.tooltip_surfaces.remove(&surface_id) {
+        if let Some(tooltip_obj) = self.wl.tooltip_surfaces.remove(&surface_id) {
             tooltip_obj.destroy();
         }
         if let Some(visibility_obj) = self
+            .wl
             .layer_surface_visibility_controllers
             .remove(&surface_id)
         {
             visibility_obj.destroy();
         }
-        self.hidden_surfaces.remove(&surface_id);
-        self.transitions.remove(&surface_id);
-        if let Some(dismiss_obj) = self.layer_surface_dismiss_controllers.remove(&surface_id) {
+        self.wl.hidden_surfaces.remove(&surface_id);
+        self.wl.transitions.remove(&surface_id);
+        if let Some(dismiss_obj) = self
+            .wl
+            .layer_surface_dismiss_controllers
+            .remove(&surface_id)
+        {
             dismiss_obj.destroy();
         }
-        if let Some(home_obj) = self.home_visibility_controllers.remove(&surface_id) {
+        if let Some(home_obj) = self.wl.home_visibility_controllers.remove(&surface_id) {
             home_obj.destroy();
         }
-        if let Some(voice_obj) = self.voice_mode_receivers.remove(&surface_id) {
+        if let Some(voice_obj) = self.wl.voice_mode_receivers.remove(&surface_id) {
             voice_obj.destroy();
         }
 
+        if let Some(fifo) = self.units[index].fifo.take() {
+            fifo.destroy();
+        }
+        if let Some(commit_timer) = self.units[index].commit_timer.take() {
+            commit_timer.destroy();
+        }
+        if let Some(alpha_modifier) = self.units[index].alpha_modifier.take() {
+            alpha_modifier.destroy();
+        }
+        if let Some(inhibitor) = self.units[index].idle_inhibitor.take() {
+            inhibitor.destroy();
+        }
+        if let Some(viewport) = self.units[index].viewport.take() {
+            viewport.destroy();
+        }
+        if let Some(fractional_scale) = self.units[index].fractional_scale.take() {
+            fractional_scale.destroy();
+        }
+
+        // the role objects go before the wl_surface, from the decoration to the xdg_surface
         self.units[index].shell.destroy();
         self.units[index].wl_surface.destroy();
 
-        if let Some(buffer) = self.units[index].buffer.as_ref() {
+        if let Some(buffer) = self.units[index].buffer.take() {
             buffer.destroy()
         }
-        self.units.remove(index);
-        Some(())
+    }
+
+    /// the layer surface settings of the surfaces created by the state itself
+    fn layer_config(&self) -> LayerSurfaceConfig {
+        LayerSurfaceConfig {
+            layer: self.layer,
+            namespace: self.namespace.clone(),
+            size: self.size,
+            exclusive_zone: self.exclusive_zone,
+            events_transparent: self.events_transparent,
+        }
     }
 
     /// forget the remembered last output, next time it will get the new activated output to set the
     /// layershell
     pub fn forget_last_output(&mut self) {
-        self.last_wloutput.take();
+        self.wl.last_wloutput.take();
     }
 }
 
@@ -1341,7 +2559,7 @@ impl<T> WindowState<T> {
     /// Get the current home state from compositor
     /// Returns true if at home (no windows visible), false otherwise
     pub fn is_home(&self) -> bool {
-        self.is_home
+        self.wl.is_home
     }
 
     /// Acknowledge a will_stop event from the compositor.
@@ -1350,7 +2568,7 @@ impl<T> WindowState<T> {
     ///          if false, proceed with hiding the orb.
     pub fn voice_ack_stop(&self, serial: u32, freeze: bool) {
         let freeze_val: u32 = if freeze { 1 } else { 0 };
-        for receiver in self.voice_mode_receivers.values() {
+        for receiver in self.wl.voice_mode_receivers.values() {
             receiver.ack_stop(serial, freeze_val);
         }
     }
@@ -1361,17 +2579,80 @@ impl<T> WindowState<T> {
     /// Only valid when orb is in frozen state.
     pub fn voice_dismiss(&self) {
         log::info!("Sending voice dismiss to compositor");
-        for receiver in self.voice_mode_receivers.values() {
+        for receiver in self.wl.voice_mode_receivers.values() {
             receiver.dismiss();
         }
     }
 
-    fn push_window(&mut self, window_state_unit: WindowStateUnit<T>) {
+    fn push_window(&mut self, mut window_state_unit: WindowStateUnit<T>) {
+        window_state_unit.set_max_fps(self.max_fps);
+        window_state_unit.seat = self.wl.seat.clone();
+        if let Some(output) = &window_state_unit.wl_output {
+            window_state_unit.output_refresh = self
+                .wl
+                .output_refresh_rates
+                .get(&output.id().protocol_id())
+                .copied();
+        }
         let surface = window_state_unit.wl_surface.clone();
+        // start_hidden surfaces are hidden before the unit is created
+        window_state_unit.visible = !self
+            .wl
+            .hidden_surfaces
+            .contains(&surface.id().protocol_id());
+        window_state_unit.home_hidden = self.home_hides(&window_state_unit);
+        window_state_unit.reported_visibility = window_state_unit.effective_visibility();
         self.units.push(window_state_unit);
         // new created surface will be current_surface.
         self.update_current_surface(Some(surface));
     }
+
+    /// if the home visibility mode of the surface hides it in the current home state. Only the
+    /// surfaces with a home visibility controller have a mode
+    fn home_hides(&self, unit: &WindowStateUnit<T>) -> bool {
+        use home_visibility::VisibilityMode;
+        if !self
+            .wl
+            .home_visibility_controllers
+            .contains_key(&unit.wl_surface.id().protocol_id())
+        {
+            return false;
+        }
+        let mode = self
+            .home_visibility_modes
+            .get(&unit.id)
+            .copied()
+            .unwrap_or(if self.home_only {
+                VisibilityMode::HomeOnly
+            } else if self.hide_on_home {
+                VisibilityMode::HideOnHome
+            } else {
+                VisibilityMode::Always
+            });
+        match mode {
+            VisibilityMode::HomeOnly => !self.wl.is_home,
+            VisibilityMode::HideOnHome => self.wl.is_home,
+            _ => false,
+        }
+    }
+
+    /// compute the effective visibility of every unit again, and send
+    /// [DispatchMessage::EffectiveVisibilityChanged] to the ones where it flipped
+    fn update_effective_visibility(&mut self) {
+        for index in 0..self.units.len() {
+            let home_hidden = self.home_hides(&self.units[index]);
+            let unit = &mut self.units[index];
+            unit.home_hidden = home_hidden;
+            let visibility = unit.effective_visibility();
+            if visibility != unit.reported_visibility {
+                unit.reported_visibility = visibility;
+                self.wl.message.push((
+                    Some(unit.id),
+                    DispatchMessageInner::EffectiveVisibilityChanged(visibility),
+                ));
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -1383,6 +2664,105 @@ pub struct WindowWrapper {
     pub toplevel: Option<XdgToplevel>,
 }
 
+/// Every setting of a layer surface, read by [WindowStateUnit::config_snapshot] and applied at
+/// once by [WindowStateUnit::apply_config], like to reload the config of a panel. With the
+/// `serde` feature it can be serialized, the protocol enums are stored as their wire values
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LayerConfig {
+    #[cfg_attr(feature = "serde", serde(with = "serde_layer::layer"))]
+    pub layer: Layer,
+    #[cfg_attr(feature = "serde", serde(with = "serde_layer::anchor"))]
+    pub anchor: Anchor,
+    /// None keeps the current size
+    pub size: Option<(u32, u32)>,
+    /// as `(top, right, bottom, left)`
+    pub margin: (i32, i32, i32, i32),
+    /// None keeps the current exclusive zone
+    pub exclusive_zone: Option<i32>,
+    #[cfg_attr(feature = "serde", serde(with = "serde_layer::keyboard_interactivity"))]
+    pub keyboard_interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
+}
+
+/// The space a layer surface reserves on its output, see [WindowStateUnit::reserve_space]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExclusiveZone {
+    /// reserve the given pixels along the anchored edge, 0 reserves the size of the surface
+    /// across that edge, like the height of a top bar
+    Auto(u32),
+    /// do not move for the space reserved by other surfaces, the exclusive zone `-1`
+    Ignore,
+    /// do not reserve any space, the exclusive zone `0`
+    None,
+}
+
+/// Present statistics of the event loop, see [WindowState::frame_stats]. The averages are
+/// moving averages weighted to the last few dozen samples
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    /// presents of all surfaces
+    pub presents: u64,
+    /// average time between two presents of the same surface
+    pub average_present_interval: Option<Duration>,
+    /// passes of the present loop in which a surface waited for a refresh, but its present
+    /// slot was not available: the previous frame was not done or the max fps refused it
+    pub skipped_frames: u64,
+    /// average time a tick of the event loop timer spends in the handler to dispatch the
+    /// queued messages and [LayerShellEvent::NormalDispatch]
+    pub dispatch_latency: Option<Duration>,
+    /// presents of every live surface
+    pub unit_presents: HashMap<id::Id, u64>,
+}
+
+impl FrameStats {
+    fn moving_average(average: Option<Duration>, sample: Duration) -> Duration {
+        average.map_or(sample, |average| (average * 15 + sample) / 16)
+    }
+
+    /// `since_last` is the time since the previous present of the surface
+    fn record_present(&mut self, id: id::Id, since_last: Option<Duration>) {
+        self.presents += 1;
+        *self.unit_presents.entry(id).or_default() += 1;
+        if let Some(interval) = since_last {
+            self.average_present_interval = Some(Self::moving_average(
+                self.average_present_interval,
+                interval,
+            ));
+        }
+    }
+
+    fn record_dispatch(&mut self, latency: Duration) {
+        self.dispatch_latency = Some(Self::moving_average(self.dispatch_latency, latency));
+    }
+}
+
+/// the edge a layer surface reserves its exclusive zone on: an edge is anchored, and the
+/// perpendicular edges are both anchored or both not. None when the compositor ignores the zone
+fn exclusive_edge(anchor: Anchor) -> Option<Anchor> {
+    let vertical = anchor & (Anchor::Top | Anchor::Bottom);
+    let horizontal = anchor & (Anchor::Left | Anchor::Right);
+    let single = |edges: Anchor| edges.bits().count_ones() == 1;
+    let all_or_none = |edges: Anchor, both: Anchor| edges.is_empty() || edges == both;
+    if single(vertical) && all_or_none(horizontal, Anchor::Left | Anchor::Right) {
+        Some(vertical)
+    } else if single(horizontal) && all_or_none(vertical, Anchor::Top | Anchor::Bottom) {
+        Some(horizontal)
+    } else {
+        None
+    }
+}
+
+/// The layer settings of one output in [`StartMode::AllScreens`], returned by the closure of
+/// [WindowState::with_per_output_config]. The fields left as `None` fall back to the settings of
+/// the [WindowState]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutputLayerConfig {
+    pub size: Option<(u32, u32)>,
+    pub anchor: Option<Anchor>,
+    pub margin: Option<(i32, i32, i32, i32)>,
+    pub exclusive_zone: Option<i32>,
+}
+
 /// Define the way layershell program is start
 #[derive(Debug, Clone, Default, PartialEq, Eq)]
 pub enum StartMode {
@@ -1395,12 +2775,110 @@ pub enum StartMode {
     AllScreens,
     /// only shown on target screen
     TargetScreen(String),
+    /// only shown on the screen whose wl_output (v4+) name is the given connector, like
+    /// `DP-1`. Unlike [`StartMode::TargetScreen`] it does not use xdg_output, and only this
+    /// name is compared: a screen matching through its xdg_output name or description is not
+    /// picked. The activated display is used when nothing matches
+    TargetConnector(String),
+    /// only shown on the screen with the xdg_output description, like `Dell U2720Q`. A screen
+    /// whose name matches is still preferred, and the activated display is used when nothing
+    /// matches, like [`StartMode::TargetScreen`]
+    TargetDescription(String),
 
     /// Target the output
     /// NOTE: use the same wayland connection
     TargetOutput(WlOutput),
 }
 
+/// Define how [`StartMode::TargetScreen`] matches its name against the outputs
+///
+/// Most compositors report the connector (`DP-1`, `HDMI-A-1`) as the xdg_output
+/// name, but some use a friendly name there instead, so by default every field
+/// is tried in turn. When an output has both names, the wl_output one is
+/// compared first. [`StartMode::TargetConnector`] always uses
+/// [`OutputMatchStrategy::WlOutputName`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OutputMatchStrategy {
+    /// try the wl_output name, then the xdg_output name, then a substring of the
+    /// xdg_output description
+    #[default]
+    Auto,
+    /// only match the wl_output (v4+) name
+    WlOutputName,
+    /// only match the xdg_output name
+    XdgOutputName,
+    /// only match a substring of the xdg_output description
+    Description,
+}
+
+/// The output field which matched in [`OutputMatchStrategy`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMatchField {
+    WlOutputName,
+    XdgOutputName,
+    Description,
+}
+
+impl OutputMatchStrategy {
+    fn fields(self) -> &'static [OutputMatchField] {
+        match self {
+            Self::Auto => &[
+                OutputMatchField::WlOutputName,
+                OutputMatchField::XdgOutputName,
+                OutputMatchField::Description,
+            ],
+            Self::WlOutputName => &[OutputMatchField::WlOutputName],
+            Self::XdgOutputName => &[OutputMatchField::XdgOutputName],
+            Self::Description => &[OutputMatchField::Description],
+        }
+    }
+}
+
+/// The optional protocols the compositor supports, see [WindowState::available_features]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeatureSet {
+    /// org_kde_kwin_blur_manager
+    pub blur: bool,
+    /// layer_shadow_manager_v1
+    pub shadow: bool,
+    /// layer_corner_radius_manager_v1
+    pub corner_radius: bool,
+    /// layer_auto_hide_manager_v1
+    pub auto_hide: bool,
+    /// wp_fractional_scale_manager_v1
+    pub fractional_scale: bool,
+    /// wp_cursor_shape_manager_v1
+    pub cursor_shape: bool,
+    /// wp_viewporter
+    pub viewporter: bool,
+    /// zwp_text_input_manager_v3
+    pub text_input: bool,
+    /// zxdg_decoration_manager_v1
+    pub decoration: bool,
+    /// zwp_virtual_keyboard_manager_v1
+    pub virtual_keyboard: bool,
+    /// zwp_keyboard_shortcuts_inhibit_manager_v1
+    pub keyboard_shortcuts_inhibit: bool,
+    /// zwp_idle_inhibit_manager_v1
+    pub idle_inhibit: bool,
+    /// wp_alpha_modifier_v1
+    pub alpha_modifier: bool,
+    /// wp_fifo_manager_v1
+    pub fifo: bool,
+    /// wp_commit_timing_manager_v1
+    pub commit_timing: bool,
+    /// wp_presentation
+    pub presentation: bool,
+    /// zcosmic_tooltip_manager_v1
+    pub tooltip: bool,
+    /// zcosmic_home_visibility_manager_v1
+    pub home_visibility: bool,
+    /// zcosmic_voice_mode_manager_v1
+    pub voice_mode: bool,
+    /// zcosmic_layer_surface_visibility_manager_v1
+    pub layer_surface_visibility: bool,
+}
+
 impl StartMode {
     pub fn is_active(&self) -> bool {
         matches!(self, Self::Active)
@@ -1412,7 +2890,10 @@ impl StartMode {
         matches!(self, Self::AllScreens)
     }
     pub fn is_with_target(&self) -> bool {
-        matches!(self, Self::TargetScreen(_))
+        matches!(
+            self,
+            Self::TargetScreen(_) | Self::TargetDescription(_) | Self::TargetConnector(_)
+        )
     }
 }
 
@@ -1425,22 +2906,125 @@ impl WindowWrapper {
 impl<T> WindowState<T> {
     /// get a seat from state
     pub fn get_seat(&self) -> &WlSeat {
-        self.seat.as_ref().unwrap()
+        self.wl.seat.as_ref().unwrap()
+    }
+
+    /// the wayland connection, given by [WindowState::with_connection] or opened by `build`.
+    /// Share it with other wayland code in the process instead of opening a second one.
+    /// None before `build` when no connection was given
+    pub fn connection(&self) -> Option<&Connection> {
+        self.wl.connection.as_ref()
+    }
+
+    /// the present statistics of the event loop, to diagnose jank. They are counted from the
+    /// start of the loop
+    pub fn frame_stats(&self) -> FrameStats {
+        self.frame_stats.clone()
+    }
+
+    /// the serial of the latest pointer button press, to pass to
+    /// [WindowStateUnit::start_move] or [WindowStateUnit::start_resize]
+    pub fn last_button_serial(&self) -> Option<u32> {
+        self.wl.last_button_serial
+    }
+
+    /// the serial of the latest pointer enter or button event
+    pub fn last_pointer_serial(&self) -> Option<u32> {
+        self.wl.last_pointer_serial
+    }
+
+    /// the serial of the latest touch down or up
+    pub fn last_touch_serial(&self) -> Option<u32> {
+        self.wl.last_touch_serial
+    }
+
+    /// the serial of the latest keyboard enter or key event
+    pub fn last_keyboard_serial(&self) -> Option<u32> {
+        self.wl.last_keyboard_serial
+    }
+
+    /// the key repeat the compositor sent with `wl_keyboard.repeat_info`. None before it is
+    /// sent, which never happens when the compositor only has a wl_seat older than v4
+    pub fn keyboard_repeat_info(&self) -> Option<RepeatInfo> {
+        self.wl.keyboard_repeat_info
+    }
+
+    /// get the latest position of the pointer, with the id of the surface it is on. The
+    /// position is surface-local, in logical coordinates, the same as
+    /// [DispatchMessage::MouseMotion]. None when the pointer is not on any surface
+    pub fn pointer_position(&self) -> Option<(id::Id, f64, f64)> {
+        self.wl.pointer_position
+    }
+
+    /// set a filter for every [DispatchMessage] of every surface, the messages it returns
+    /// false for are dropped before they reach the event handler. It is useful for a
+    /// temporary modal input capture, like swallowing the keyboard during a pointer drag.
+    /// It replaces the previous filter, see [WindowState::clear_input_filter_global]
+    pub fn set_input_filter_global<F>(&mut self, filter: F)
+    where
+        F: FnMut(&DispatchMessage) -> bool + 'static,
+    {
+        self.input_filter = Some(InputFilter(Box::new(filter)));
+    }
+
+    /// remove the filter set by [WindowState::set_input_filter_global]
+    pub fn clear_input_filter_global(&mut self) {
+        self.input_filter = None;
+    }
+
+    /// if the message passes the filter set by [WindowState::set_input_filter_global]
+    fn input_filter_accepts(&mut self, msg: &DispatchMessage) -> bool {
+        self.input_filter
+            .as_mut()
+            .is_none_or(|InputFilter(filter)| filter(msg))
+    }
+
+    /// drop the keyboard messages of a layer surface with
+    /// [KeyboardInteractivity::None](zwlr_layer_surface_v1::KeyboardInteractivity::None)
+    fn keyboard_accepts(&self, id: Option<id::Id>, msg: &DispatchMessage) -> bool {
+        let Some(unit) = id.and_then(|id| self.get_unit_with_id(id)) else {
+            return true;
+        };
+        if !matches!(unit.shell, Shell::LayerShell(_)) {
+            return true;
+        }
+        keyboard_message_allowed(unit.keyboard_interactivity.get(), msg)
+    }
+
+    /// get the name of the seat, like `seat0`. It is None before the compositor sends it, or
+    /// when the wl_seat version is lower than 2
+    pub fn seat_name(&self) -> Option<&str> {
+        self.wl.seat_name.as_deref()
+    }
+
+    /// the clock id (as in `clock_gettime`) of the timestamps in [DispatchMessage::Presented],
+    /// None if wp_presentation is not supported by the compositor
+    #[cfg(feature = "presentation-time")]
+    pub fn presentation_clock_id(&self) -> Option<u32> {
+        self.wl.presentation_clock_id
     }
 
     /// get the keyboard
     pub fn get_keyboard(&self) -> Option<&WlKeyboard> {
-        Some(&self.keyboard_state.as_ref()?.keyboard)
+        Some(&self.wl.keyboard_state.as_ref()?.keyboard)
     }
 
     /// get the pointer
     pub fn get_pointer(&self) -> Option<&WlPointer> {
-        self.pointer.as_ref()
+        self.wl.pointer.as_ref()
     }
 
     /// get the touch
     pub fn get_touch(&self) -> Option<&WlTouch> {
-        self.touch.as_ref()
+        self.wl.touch.as_ref()
+    }
+
+    /// hide the cursor while the pointer is over our surfaces, like
+    /// [ReturnData::RequestHideCursor]
+    pub fn hide_cursor(&self) {
+        if let (Some(pointer), Some(serial)) = (&self.wl.pointer, self.wl.enter_serial) {
+            pointer.set_cursor(serial, None, 0, 0);
+        }
     }
 }
 
@@ -1467,6 +3051,12 @@ impl<T> WindowState<T> {
         self.start_mode.is_with_target()
     }
 
+    /// get the [StartMode] the state is started with, including the target output or
+    /// screen name
+    pub fn start_mode(&self) -> &StartMode {
+        &self.start_mode
+    }
+
     /// True when at least one surface unit still has a live `wl_surface`.
     ///
     /// Goes false when the only surface was destroyed because its output was
@@ -1477,6 +3067,30 @@ impl<T> WindowState<T> {
         self.units.iter().any(|unit| unit.wl_surface.is_alive())
     }
 
+    /// Snapshot of the open toplevel windows, for a taskbar or dock. Only windows whose
+    /// initial properties are received are listed, the `id` of each can be passed to
+    /// [WindowState::execute_toplevel_action]. The order is unspecified.
+    ///
+    /// Requires the `foreign-toplevel` feature, and [WindowState::with_foreign_toplevel].
+    #[cfg(feature = "foreign-toplevel")]
+    pub fn toplevels(&self) -> impl Iterator<Item = foreign_toplevel::ToplevelInfo> + '_ {
+        self.wl
+            .foreign_toplevel_data
+            .iter()
+            .filter(|(_, data)| data.initialized)
+            .map(|(id, data)| data.to_info(*id))
+    }
+
+    /// Snapshot of one open toplevel window, see [WindowState::toplevels]
+    #[cfg(feature = "foreign-toplevel")]
+    pub fn toplevel(&self, id: u32) -> Option<foreign_toplevel::ToplevelInfo> {
+        self.wl
+            .foreign_toplevel_data
+            .get(&id)
+            .filter(|data| data.initialized)
+            .map(|data| data.to_info(id))
+    }
+
     /// Execute a toplevel action (activate, close, minimize, etc.)
     ///
     /// Returns true if the action was executed, false if the handle was not found.
@@ -1486,7 +3100,7 @@ impl<T> WindowState<T> {
     where
         T: 'static,
     {
-        foreign_toplevel::execute_toplevel_action(self, action, self.seat.as_ref())
+        foreign_toplevel::execute_toplevel_action(self, action, self.wl.seat.as_ref())
     }
 
     /// Execute a screencopy action (capture a toplevel window screenshot)
@@ -1513,29 +3127,54 @@ impl<T> WindowState<T> {
                     target_width,
                     target_height
                 );
-                self.screencopy.continuous = true;
-                self.screencopy.target_size = Some((target_width, target_height));
-                let ids: Vec<u32> = self.screencopy.sessions.keys().copied().collect();
+                self.wl.screencopy.continuous = true;
+                self.wl.screencopy.target_size = Some((target_width, target_height));
+                let ids: Vec<u32> = self.wl.screencopy.sessions.keys().copied().collect();
                 for tid in ids {
                     screencopy::start_capture(self, tid, qh);
                 }
             }
             screencopy::ScreencopyAction::StopContinuous => {
                 log::debug!("Stopping continuous screencopy capture");
-                self.screencopy.continuous = false;
-                self.screencopy.target_size = None;
-                self.screencopy.last_capture.clear();
+                self.wl.screencopy.continuous = false;
+                self.wl.screencopy.target_size = None;
+                self.wl.screencopy.last_capture.clear();
             }
         }
     }
 
+    /// copy the content of `output` into `into` with wlr-screencopy, for a thumbnail of a
+    /// whole output. The file is resized to the frame, and
+    /// [screencopy::ScreencopyEvent::OutputReady] is sent once it is written, with the layout
+    /// of the pixels. False if the compositor has no wlr-screencopy
+    ///
+    /// Requires the `screencopy` feature.
+    #[cfg(feature = "screencopy")]
+    pub fn capture_output(&self, output: &WlOutput, into: &mut std::fs::File) -> bool
+    where
+        T: 'static,
+    {
+        let Some(qh) = self.wl.queue_handle.as_ref() else {
+            log::warn!("capture_output: queue_handle not yet initialized");
+            return false;
+        };
+        let file = match into.try_clone() {
+            Ok(file) => file,
+            Err(e) => {
+                log::error!("capture_output: cannot share the file: {e}");
+                return false;
+            }
+        };
+        screencopy::start_output_capture(self, output, file, qh)
+    }
+
     /// Execute a screencopy action using the internal event queue handle
     #[cfg(feature = "screencopy")]
     pub fn execute_screencopy_action_internal(&mut self, action: screencopy::ScreencopyAction)
     where
         T: 'static,
     {
-        let qh = match self.queue_handle.as_ref() {
+        let qh = match self.wl.queue_handle.as_ref() {
             Some(qh) => qh.clone(),
             None => {
                 log::warn!("Screencopy action dropped: queue_handle not yet initialized");
@@ -1555,19 +3194,19 @@ impl<T> WindowState<T> {
                     target_width,
                     target_height
                 );
-                self.screencopy.continuous = true;
-                self.screencopy.target_size = Some((target_width, target_height));
+                self.wl.screencopy.continuous = true;
+                self.wl.screencopy.target_size = Some((target_width, target_height));
                 // Kick off a capture for every active session
-                let ids: Vec<u32> = self.screencopy.sessions.keys().copied().collect();
+                let ids: Vec<u32> = self.wl.screencopy.sessions.keys().copied().collect();
                 for tid in ids {
                     screencopy::start_capture(self, tid, &qh);
                 }
             }
             screencopy::ScreencopyAction::StopContinuous => {
                 log::debug!("Stopping continuous screencopy capture");
-                self.screencopy.continuous = false;
-                self.screencopy.target_size = None;
-                self.screencopy.last_capture.clear();
+                self.wl.screencopy.continuous = false;
+                self.wl.screencopy.target_size = None;
+                self.wl.screencopy.last_capture.clear();
             }
         }
     }
@@ -1578,10 +3217,11 @@ impl<T> WindowState<T> {
 
     pub fn set_ime_allowed(&mut self, ime_allowed: bool) {
         self.ime_allowed = ime_allowed;
-        for text_input in &self.text_inputs {
+        for text_input in &self.wl.text_inputs {
             if ime_allowed {
                 text_input.enable();
-                text_input.set_content_type_by_purpose(self.ime_purpose);
+                let (hint, purpose) = self.ime_content_type();
+                text_input.set_content_type(hint, purpose);
             } else {
                 text_input.disable();
             }
@@ -1608,36 +3248,101 @@ impl<T> WindowState<T> {
         let size: dpi::LogicalSize<u32> = size.to_logical(scale_factor);
         let (x, y) = (position.x as i32, position.y as i32);
         let (width, height) = (size.width as i32, size.height as i32);
-        for text_input in self.text_inputs.iter() {
+        self.ime_cursor_area.set(Some((id, (x, y, width, height))));
+        for text_input in self.wl.text_inputs.iter() {
             text_input.set_cursor_rectangle(x, y, width, height);
             text_input.commit();
         }
     }
 
+    /// the last area set by [WindowState::set_ime_cursor_area], with the id of its surface. The
+    /// area is `(x, y, width, height)`, surface-local in logical coordinates
+    pub fn ime_cursor_area(&self) -> Option<(id::Id, (i32, i32, i32, i32))> {
+        self.ime_cursor_area.get()
+    }
+
+    /// settings of a layer surface of `size` placed right below the IME cursor area, or above
+    /// it when there is no room below, to be used as a floating on-screen keyboard.
+    ///
+    /// `zwp_input_panel_surface_v1` can only be placed at the bottom center of the output or
+    /// as an overlay panel chosen by the compositor, so a keyboard following the text cursor
+    /// has to be a regular layer surface. It is anchored to the top left of the output of the
+    /// text surface and ignores exclusive zones, with margins from [WindowStateUnit::layer_origin]
+    /// and the cursor area. None when there is no cursor area, or it is not on a layer surface
+    /// with a known output
+    pub fn osk_settings_near_ime_cursor(&self, size: (u32, u32)) -> Option<NewLayerShellSettings> {
+        let (id, (x, y, _, height)) = self.ime_cursor_area.get()?;
+        let unit = self.get_unit_with_id(id)?;
+        let (origin_x, origin_y) = unit.layer_origin()?;
+        let (output_width, output_height) = unit.get_xdgoutput_info()?.get_logical_size();
+        let output = unit.wl_output.clone()?;
+        let (width, osk_height) = (size.0 as i32, size.1 as i32);
+        let cursor_top = origin_y + y;
+        let cursor_bottom = cursor_top + height;
+        let top = if cursor_bottom + osk_height <= output_height {
+            cursor_bottom
+        } else {
+            (cursor_top - osk_height).max(0)
+        };
+        let left = (origin_x + x).clamp(0, (output_width - width).max(0));
+        Some(NewLayerShellSettings {
+            size: Some(size),
+            layer: Layer::Overlay,
+            anchor: Anchor::Top | Anchor::Left,
+            exclusive_zone: Some(-1),
+            margin: Some((top, 0, 0, left)),
+            keyboard_interactivity: zwlr_layer_surface_v1::KeyboardInteractivity::None,
+            output_option: OutputOption::Output(output),
+            ..Default::default()
+        })
+    }
+
     pub fn set_ime_purpose(&mut self, purpose: ImePurpose) {
         self.ime_purpose = purpose;
-        self.text_input.iter().for_each(|text_input| {
+        self.ime_content_type = None;
+        self.wl.text_input.iter().for_each(|text_input| {
             text_input.set_content_type_by_purpose(purpose);
             text_input.commit();
         });
     }
 
+    /// set the content hint and purpose of the text inputs directly, for the fields the
+    /// [ImePurpose] presets don't cover, like an email field (`ContentHint::Lowercase` and
+    /// `ContentPurpose::Email`) or an url field without autocorrection. It is applied to the
+    /// active text inputs, and kept until [WindowState::set_ime_purpose] is called
+    pub fn set_ime_content_type(&mut self, hint: ContentHint, purpose: ContentPurpose) {
+        self.ime_content_type = Some((hint, purpose));
+        // the entered text inputs are usually the one of the seat, set and commit it once
+        let entered = self
+            .wl
+            .text_inputs
+            .iter()
+            .filter(|text_input| self.wl.text_input.as_ref() != Some(*text_input));
+        for text_input in self.wl.text_input.iter().chain(entered) {
+            text_input.set_content_type(hint, purpose);
+            text_input.commit();
+        }
+    }
+
     #[inline]
     pub fn text_input_entered(&mut self, text_input: &ZwpTextInputV3) {
-        if !self.text_inputs.iter().any(|t| t == text_input) {
-            self.text_inputs.push(text_input.clone());
+        if !self.wl.text_inputs.iter().any(|t| t == text_input) {
+            self.wl.text_inputs.push(text_input.clone());
         }
     }
 
     #[inline]
     pub fn text_input_left(&mut self, text_input: &ZwpTextInputV3) {
-        if let Some(position) = self.text_inputs.iter().position(|t| t == text_input) {
-            self.text_inputs.remove(position);
+        if let Some(position) = self.wl.text_inputs.iter().position(|t| t == text_input) {
+            self.wl.text_inputs.remove(position);
         }
     }
 
-    fn ime_purpose(&self) -> ImePurpose {
-        self.ime_purpose
+    /// the content hint and purpose for the text inputs, from [WindowState::set_ime_content_type]
+    /// or else from the [ImePurpose]
+    fn ime_content_type(&self) -> (ContentHint, ContentPurpose) {
+        self.ime_content_type
+            .unwrap_or_else(|| self.ime_purpose.content_type())
     }
 }
 
@@ -1656,7 +3361,7 @@ impl<T: 'static> WindowState<T> {
         // Apply blur effect if enabled
         if self.blur {
             apply_blur_to_surface(
-                &self.blur_manager,
+                &self.wl.blur_manager,
                 wl_surface,
                 qh,
                 self.blur_radius,
@@ -1669,61 +3374,65 @@ impl<T: 'static> WindowState<T> {
         // Apply corner radius if set
         if self.corner_radius.is_some()
             && let Some(corner_obj) = apply_corner_radius_to_surface(
-                &self.corner_radius_manager,
+                &self.wl.corner_radius_manager,
                 self.corner_radius,
                 wl_surface,
                 qh,
             )
         {
-            self.corner_radius_surfaces.insert(surface_id, corner_obj);
+            self.wl
+                .corner_radius_surfaces
+                .insert(surface_id, corner_obj);
         }
 
         // Apply shadow if enabled
         if self.shadow {
-            apply_shadow_to_surface(&self.shadow_manager, wl_surface, qh);
+            apply_shadow_to_surface(&self.wl.shadow_manager, wl_surface, qh);
         }
 
         // Apply home visibility mode if enabled
         if self.home_only {
             if let Some(controller) = apply_home_visibility_to_surface(
-                &self.home_visibility_manager,
+                &self.wl.home_visibility_manager,
                 wl_surface,
                 qh,
                 home_visibility::VisibilityMode::HomeOnly,
             ) {
-                self.home_visibility_controllers
+                self.wl
+                    .home_visibility_controllers
                     .insert(surface_id, controller);
             }
         } else if self.hide_on_home
             && let Some(controller) = apply_home_visibility_to_surface(
-                &self.home_visibility_manager,
+                &self.wl.home_visibility_manager,
                 wl_surface,
                 qh,
                 home_visibility::VisibilityMode::HideOnHome,
             )
         {
-            self.home_visibility_controllers
+            self.wl
+                .home_visibility_controllers
                 .insert(surface_id, controller);
         }
 
         // Register surface for voice mode events if enabled
         if self.voice_mode_enabled {
-            let is_default = self.voice_mode_receivers.is_empty();
+            let is_default = self.wl.voice_mode_receivers.is_empty();
             if let Some(receiver) = register_voice_mode_for_surface(
-                &self.voice_mode_manager,
+                &self.wl.voice_mode_manager,
                 wl_surface,
                 qh,
                 is_default,
             ) {
-                self.voice_mode_receivers.insert(surface_id, receiver);
+                self.wl.voice_mode_receivers.insert(surface_id, receiver);
             }
         }
 
         // Register surface for compositor usable-area reporting.
         if let Some(obj) =
-            register_usable_area_for_surface(&self.usable_area_manager, wl_surface, qh)
+            register_usable_area_for_surface(&self.wl.usable_area_manager, wl_surface, qh)
         {
-            self.usable_area_surfaces.insert(surface_id, obj);
+            self.wl.usable_area_surfaces.insert(surface_id, obj);
         }
     }
 
@@ -1733,7 +3442,7 @@ impl<T: 'static> WindowState<T> {
         let surface_id = surface.id().protocol_id();
 
         // Check if we already have a corner radius object for this surface
-        if let Some(corner_obj) = self.corner_radius_surfaces.get(&surface_id) {
+        if let Some(corner_obj) = self.wl.corner_radius_surfaces.get(&surface_id) {
             if let Some(r) = radii {
                 corner_obj.set_radius(r[0], r[1], r[2], r[3]);
                 log::info!("Updated corner radius for surface: {:?}", r);
@@ -1746,7 +3455,10 @@ impl<T: 'static> WindowState<T> {
         }
 
         // Need to create a new corner radius object
-        if let Some(manager) = &self.corner_radius_manager {
+        if self.wl.corner_radius_manager.is_none() {
+            self.wl.corner_radius_manager = self.bind_lazy_global(1..=1, ());
+        }
+        if let Some(manager) = &self.wl.corner_radius_manager {
             let corner_data = corner_radius::CornerRadiusData {
                 surface: surface.clone(),
             };
@@ -1760,7 +3472,9 @@ impl<T: 'static> WindowState<T> {
                     corner_obj.unset_radius();
                     log::info!("Unset corner radius for surface");
                 }
-                self.corner_radius_surfaces.insert(surface_id, corner_obj);
+                self.wl
+                    .corner_radius_surfaces
+                    .insert(surface_id, corner_obj);
                 surface.commit();
             }
         } else {
@@ -1779,21 +3493,24 @@ impl<T: 'static> WindowState<T> {
     ) -> Option<layer_surface_placement::layer_surface_placement_v1::LayerSurfacePlacementV1> {
         let surface_id = surface.id().protocol_id();
         if !self
+            .wl
             .layer_surface_placement_surfaces
             .contains_key(&surface_id)
         {
             // `manager` and `units` are disjoint fields from the map we insert
             // into, so these borrows don't conflict with the insert.
-            let manager = self.layer_surface_placement_manager.as_ref()?;
+            let manager = self.wl.layer_surface_placement_manager.as_ref()?;
             let unit = self.units.first()?;
             let data = layer_surface_placement::LayerSurfacePlacementData {
                 surface: surface.clone(),
             };
             let obj = manager.get_layer_surface_placement(surface, &unit.qh, data);
-            self.layer_surface_placement_surfaces
+            self.wl
+                .layer_surface_placement_surfaces
                 .insert(surface_id, obj);
         }
-        self.layer_surface_placement_surfaces
+        self.wl
+            .layer_surface_placement_surfaces
             .get(&surface_id)
             .cloned()
     }
@@ -1826,7 +3543,7 @@ impl<T: 'static> WindowState<T> {
     /// to standard layer-shell positioning. A no-op if no placement was set.
     pub fn unset_vertical_placement_for_surface(&mut self, surface: &WlSurface) {
         let surface_id = surface.id().protocol_id();
-        if let Some(obj) = self.layer_surface_placement_surfaces.get(&surface_id) {
+        if let Some(obj) = self.wl.layer_surface_placement_surfaces.get(&surface_id) {
             obj.unset_vertical_placement();
             surface.commit();
         }
@@ -1855,7 +3572,7 @@ impl<T: 'static> WindowState<T> {
     /// Clear any compositor-side height cap for `surface`. A no-op if none was set.
     pub fn unset_max_height_for_surface(&mut self, surface: &WlSurface) {
         let surface_id = surface.id().protocol_id();
-        if let Some(obj) = self.layer_surface_placement_surfaces.get(&surface_id) {
+        if let Some(obj) = self.wl.layer_surface_placement_surfaces.get(&surface_id) {
             obj.unset_max_height();
             surface.commit();
         }
@@ -1869,16 +3586,16 @@ impl<T: 'static> WindowState<T> {
         surface: &WlSurface,
     ) -> Option<layer_edge_resize::layer_edge_resize_v1::LayerEdgeResizeV1> {
         let surface_id = surface.id().protocol_id();
-        if !self.layer_edge_resize_surfaces.contains_key(&surface_id) {
-            let manager = self.layer_edge_resize_manager.as_ref()?;
+        if !self.wl.layer_edge_resize_surfaces.contains_key(&surface_id) {
+            let manager = self.wl.layer_edge_resize_manager.as_ref()?;
             let unit = self.units.first()?;
             let data = layer_edge_resize::LayerEdgeResizeData {
                 surface: surface.clone(),
             };
             let obj = manager.get_edge_resize(surface, &unit.qh, data);
-            self.layer_edge_resize_surfaces.insert(surface_id, obj);
+            self.wl.layer_edge_resize_surfaces.insert(surface_id, obj);
         }
-        self.layer_edge_resize_surfaces.get(&surface_id).cloned()
+        self.wl.layer_edge_resize_surfaces.get(&surface_id).cloned()
     }
 
     /// Opt `surface` into the compositor-drawn edge resize sash, with width bounds
@@ -1905,7 +3622,7 @@ impl<T: 'static> WindowState<T> {
     /// never opted in.
     pub fn unset_edge_resize_for_surface(&mut self, surface: &WlSurface) {
         let surface_id = surface.id().protocol_id();
-        if let Some(obj) = self.layer_edge_resize_surfaces.remove(&surface_id) {
+        if let Some(obj) = self.wl.layer_edge_resize_surfaces.remove(&surface_id) {
             obj.destroy();
             surface.commit();
         }
@@ -1918,28 +3635,28 @@ impl<T: 'static> WindowState<T> {
 
         if enabled {
             // Check if blur is already enabled for this surface
-            if self.blur_surfaces.contains_key(&surface_id) {
+            if self.wl.blur_surfaces.contains_key(&surface_id) {
                 return;
             }
 
             // Need to bind the blur manager if not already bound
-            if self.blur_manager.is_none()
-                && let Some(globals) = &self.globals
+            if self.wl.blur_manager.is_none()
+                && let Some(globals) = &self.wl.globals
                 && let Some(unit) = self.units.first()
             {
-                self.blur_manager = globals
+                self.wl.blur_manager = globals
                     .bind::<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager, _, _>(
                         &unit.qh,
                         1..=3,
                         (),
                     )
                     .ok();
-                if self.blur_manager.is_some() {
+                if self.wl.blur_manager.is_some() {
                     log::info!("Bound blur manager");
                 }
             }
 
-            if let Some(manager) = &self.blur_manager {
+            if let Some(manager) = &self.wl.blur_manager {
                 if let Some(unit) = self.units.first() {
                     let blur_data = blur::BlurData {
                         surface: surface.clone(),
@@ -1952,13 +3669,14 @@ impl<T: 'static> WindowState<T> {
                     // requested radius/saturation/tint/border instead of the
                     // compositor default (which would wash the blur white).
                     let (radius, saturation, tint, border) = self
+                        .wl
                         .blur_params
                         .get(&surface_id)
                         .copied()
                         .unwrap_or((None, None, None, None));
                     apply_blur_params(&blur_obj, radius, saturation, tint, border);
                     blur_obj.commit();
-                    self.blur_surfaces.insert(surface_id, blur_obj);
+                    self.wl.blur_surfaces.insert(surface_id, blur_obj);
                     surface.commit();
                     log::info!(
                         "Enabled blur for surface (radius={radius:?}, saturation={saturation:?}, tint={tint:?}, border={border:?})"
@@ -1969,7 +3687,7 @@ impl<T: 'static> WindowState<T> {
             }
         } else {
             // Disable blur by releasing the blur object
-            if let Some(blur_obj) = self.blur_surfaces.remove(&surface_id) {
+            if let Some(blur_obj) = self.wl.blur_surfaces.remove(&surface_id) {
                 blur_obj.release();
                 surface.commit();
                 log::info!("Disabled blur for surface");
@@ -1994,11 +3712,12 @@ impl<T: 'static> WindowState<T> {
         border: Option<f32>,
     ) {
         let surface_id = surface.id().protocol_id();
-        self.blur_params
+        self.wl
+            .blur_params
             .insert(surface_id, (radius, saturation, tint, border));
         // Replace any existing blur object so the new params take effect (the
         // `enabled` path below skips surfaces that already have one).
-        if let Some(old) = self.blur_surfaces.remove(&surface_id) {
+        if let Some(old) = self.wl.blur_surfaces.remove(&surface_id) {
             old.release();
         }
         self.set_blur_for_surface(surface, true);
@@ -2020,29 +3739,29 @@ impl<T: 'static> WindowState<T> {
         let surface_id = surface.id().protocol_id();
 
         // Ensure blur manager is bound
-        if self.blur_manager.is_none()
-            && let Some(globals) = &self.globals
+        if self.wl.blur_manager.is_none()
+            && let Some(globals) = &self.wl.globals
             && let Some(unit) = self.units.first()
         {
-            self.blur_manager = globals
+            self.wl.blur_manager = globals
                 .bind::<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager, _, _>(
                     &unit.qh,
                     1..=3,
                     (),
                 )
                 .ok();
-            if self.blur_manager.is_some() {
+            if self.wl.blur_manager.is_some() {
                 log::info!("Bound blur manager");
             }
         }
 
-        let Some(manager) = &self.blur_manager else {
+        let Some(manager) = &self.wl.blur_manager else {
             log::warn!("Blur manager not available - compositor may not support blur");
             return;
         };
 
         // Release old blur object if any
-        if let Some(old_blur) = self.blur_surfaces.remove(&surface_id) {
+        if let Some(old_blur) = self.wl.blur_surfaces.remove(&surface_id) {
             log::info!(
                 "set_blur_region_for_surface: releasing old blur object for surface {}",
                 surface_id
@@ -2071,7 +3790,7 @@ impl<T: 'static> WindowState<T> {
         );
         blur_obj.set_region(Some(region));
         blur_obj.commit();
-        self.blur_surfaces.insert(surface_id, blur_obj);
+        self.wl.blur_surfaces.insert(surface_id, blur_obj);
         surface.commit();
         log::info!(
             "set_blur_region_for_surface: surface={}, done (surface committed)",
@@ -2086,35 +3805,35 @@ impl<T: 'static> WindowState<T> {
 
         if enabled {
             // Check if shadow is already enabled for this surface
-            if self.shadow_surfaces.contains_key(&surface_id) {
+            if self.wl.shadow_surfaces.contains_key(&surface_id) {
                 return;
             }
 
             // Need to bind the shadow manager if not already bound
-            if self.shadow_manager.is_none()
-                && let Some(globals) = &self.globals
+            if self.wl.shadow_manager.is_none()
+                && let Some(globals) = &self.wl.globals
                 && let Some(unit) = self.units.first()
             {
-                self.shadow_manager = globals
+                self.wl.shadow_manager = globals
                     .bind::<shadow::layer_shadow_manager_v1::LayerShadowManagerV1, _, _>(
                         &unit.qh,
                         1..=1,
                         (),
                     )
                     .ok();
-                if self.shadow_manager.is_some() {
+                if self.wl.shadow_manager.is_some() {
                     log::info!("Bound shadow manager");
                 }
             }
 
-            if let Some(manager) = &self.shadow_manager {
+            if let Some(manager) = &self.wl.shadow_manager {
                 if let Some(unit) = self.units.first() {
                     let shadow_data = shadow::ShadowData {
                         surface: surface.clone(),
                     };
                     let shadow_obj = manager.get_shadow(surface, &unit.qh, shadow_data);
                     shadow_obj.enable();
-                    self.shadow_surfaces.insert(surface_id, shadow_obj);
+                    self.wl.shadow_surfaces.insert(surface_id, shadow_obj);
                     surface.commit();
                     log::info!("Enabled shadow for surface");
                 }
@@ -2123,7 +3842,7 @@ impl<T: 'static> WindowState<T> {
             }
         } else {
             // Disable shadow by removing and destroying the shadow object
-            if let Some(shadow_obj) = self.shadow_surfaces.remove(&surface_id) {
+            if let Some(shadow_obj) = self.wl.shadow_surfaces.remove(&surface_id) {
                 shadow_obj.destroy();
                 surface.commit();
                 log::info!("Disabled shadow for surface");
@@ -2148,32 +3867,37 @@ impl<T: 'static> WindowState<T> {
 
         if enabled {
             // Already inhibited for this surface.
-            if self.keyboard_shortcuts_inhibitors.contains_key(&surface_id) {
+            if self
+                .wl
+                .keyboard_shortcuts_inhibitors
+                .contains_key(&surface_id)
+            {
                 return;
             }
 
             // Bind the manager lazily on first use (mirrors blur/shadow).
-            if self.keyboard_shortcuts_inhibit_manager.is_none()
-                && let Some(globals) = &self.globals
+            if self.wl.keyboard_shortcuts_inhibit_manager.is_none()
+                && let Some(globals) = &self.wl.globals
                 && let Some(unit) = self.units.first()
             {
-                self.keyboard_shortcuts_inhibit_manager = globals
+                self.wl.keyboard_shortcuts_inhibit_manager = globals
                     .bind::<ZwpKeyboardShortcutsInhibitManagerV1, _, _>(&unit.qh, 1..=1, ())
                     .ok();
-                if self.keyboard_shortcuts_inhibit_manager.is_some() {
+                if self.wl.keyboard_shortcuts_inhibit_manager.is_some() {
                     log::info!("Bound keyboard-shortcuts-inhibit manager");
                 }
             }
 
-            let Some(seat) = self.seat.clone() else {
+            let Some(seat) = self.wl.seat.clone() else {
                 log::warn!("No seat available - cannot inhibit keyboard shortcuts");
                 return;
             };
 
-            if let Some(manager) = &self.keyboard_shortcuts_inhibit_manager {
+            if let Some(manager) = &self.wl.keyboard_shortcuts_inhibit_manager {
                 if let Some(unit) = self.units.first() {
                     let inhibitor = manager.inhibit_shortcuts(surface, &seat, &unit.qh, ());
-                    self.keyboard_shortcuts_inhibitors
+                    self.wl
+                        .keyboard_shortcuts_inhibitors
                         .insert(surface_id, inhibitor);
                     log::info!("Enabled keyboard-shortcuts inhibitor for surface");
                 }
@@ -2182,47 +3906,252 @@ impl<T: 'static> WindowState<T> {
                     "Keyboard-shortcuts-inhibit manager not available - compositor may not support it"
                 );
             }
-        } else if let Some(inhibitor) = self.keyboard_shortcuts_inhibitors.remove(&surface_id) {
+        } else if let Some(inhibitor) = self.wl.keyboard_shortcuts_inhibitors.remove(&surface_id) {
             inhibitor.destroy();
             log::info!("Disabled keyboard-shortcuts inhibitor for surface");
         }
     }
 
-    /// Enable compositor-driven auto-hide for a specific surface.
-    /// The compositor will animate hide/show transitions and handle hover detection.
-    /// `edge`: which edge to slide off (0 = bottom)
-    /// `edge_zone`: hover detection zone in pixels at the screen edge
-    /// `mode`: 0 = always hide when cursor leaves, 1 = only hide when maximized/fullscreen exists
-    pub fn set_auto_hide_for_surface(
-        &mut self,
-        surface: &WlSurface,
-        edge: u32,
-        edge_zone: u32,
-        mode: u32,
-    ) {
-        let surface_id = surface.id().protocol_id();
-        let edge_enum = layer_auto_hide::layer_auto_hide_v1::Edge::try_from(edge)
-            .unwrap_or(layer_auto_hide::layer_auto_hide_v1::Edge::Bottom);
-        let mode_enum = layer_auto_hide::layer_auto_hide_v1::Mode::try_from(mode)
-            .unwrap_or(layer_auto_hide::layer_auto_hide_v1::Mode::Always);
-
-        // Check if we already have an auto-hide object for this surface
-        if let Some(auto_hide_obj) = self.auto_hide_surfaces.get(&surface_id) {
-            auto_hide_obj.set_auto_hide(edge_enum, edge_zone, mode_enum);
-            surface.commit();
+    /// Present the frames of a unit with FIFO semantics (wp_fifo_v1), for a consistent
+    /// pacing under load: before every frame commit, wait for the barrier of the previous
+    /// frame and set a new one, so a frame is only latched after the previous one has been
+    /// shown. The manager is bound and the per-surface object created on first use. Does
+    /// nothing when the compositor does not support wp_fifo_manager_v1.
+    pub fn set_fifo_barrier(&mut self, id: id::Id) {
+        if self.wl.fifo_manager.is_none() {
+            self.wl.fifo_manager = self.bind_lazy_global(1..=1, ());
+        }
+        let Some(manager) = self.wl.fifo_manager.clone() else {
+            log::warn!("wp_fifo_manager_v1 not available - compositor may not support it");
+            return;
+        };
+        let Some(unit) = self.get_mut_unit_with_id(id) else {
             return;
+        };
+        if unit.fifo.is_none() {
+            unit.fifo = Some(manager.get_fifo(&unit.wl_surface, &unit.qh, ()));
         }
+        unit.fifo_barrier.set(true);
+    }
 
-        // Need to create a new auto-hide object
-        if let Some(manager) = &self.auto_hide_manager {
-            let auto_hide_data = layer_auto_hide::LayerAutoHideData {
-                surface: surface.clone(),
-            };
-            // Get a queue handle from the first unit (they all share the same queue)
+    /// create the text input of the seat once a surface has keyboard interactivity, the
+    /// interactivity can change per surface at any time, so it is checked again every time
+    /// the event loop wakes up. `keyboard_accepts` drops the IME messages of the surfaces
+    /// without it
+    fn ensure_text_input(&mut self, qh: &QueueHandle<Self>) {
+        if !self.wl.text_input_wanted || self.wl.text_input.is_some() {
+            return;
+        }
+        let interactive = self.units.iter().any(|unit| {
+            unit.keyboard_interactivity.get() != zwlr_layer_surface_v1::KeyboardInteractivity::None
+        });
+        if !interactive {
+            return;
+        }
+        let (Some(manager), Some(seat)) = (&self.wl.text_input_manager, &self.wl.seat) else {
+            return;
+        };
+        self.wl.text_input = Some(manager.get_text_input(seat, qh, TextInputData::default()));
+    }
+
+    /// Present the next commit of a unit not before the given time (wp_commit_timer_v1), in
+    /// the clock of wp_presentation, for precisely timed animations together with the
+    /// presentation feedback. It applies to the next frame committed on a refresh only, a
+    /// second call before it replaces the time. The manager is bound and the per-surface
+    /// object created on first use. Does nothing when the compositor does not support
+    /// wp_commit_timing_manager_v1.
+    pub fn set_commit_time(&mut self, id: id::Id, tv_sec: u64, tv_nsec: u32) {
+        if self.wl.commit_timing_manager.is_none() {
+            self.wl.commit_timing_manager = self.bind_lazy_global(1..=1, ());
+        }
+        let Some(manager) = self.wl.commit_timing_manager.clone() else {
+            log::warn!("wp_commit_timing_manager_v1 not available - compositor may not support it");
+            return;
+        };
+        let Some(unit) = self.get_mut_unit_with_id(id) else {
+            return;
+        };
+        if unit.commit_timer.is_none() {
+            unit.commit_timer = Some(manager.get_timer(&unit.wl_surface, &unit.qh, ()));
+        }
+        unit.commit_time.set(Some((tv_sec, tv_nsec)));
+    }
+
+    /// Stop presenting the frames of a unit with FIFO semantics
+    pub fn clear_fifo_barrier(&mut self, id: id::Id) {
+        if let Some(unit) = self.get_unit_with_id(id) {
+            unit.fifo_barrier.set(false);
+        }
+    }
+
+    /// run `f` with the commits of the unit setters held back, then commit every touched
+    /// surface once, for example to change the margin of the panels on every monitor without
+    /// a commit for each change. The surfaces created inside `f` commit as usual
+    pub fn batch<F: FnOnce(&mut Self)>(&mut self, f: F) {
+        for unit in self.units.iter() {
+            unit.defer_commit.set(true);
+        }
+        f(self);
+        for unit in self.units.iter() {
+            unit.defer_commit.set(false);
+            if unit.commit_pending.take() {
+                unit.wl_surface.commit();
+            }
+        }
+    }
+
+    /// the optional protocols advertised by the compositor, to tell the user when some effects
+    /// are not available. Most managers are only bound when first needed, so this checks the
+    /// globals of the registry instead. Everything is false before [WindowState::build]
+    pub fn available_features(&self) -> FeatureSet {
+        let Some(globals) = &self.wl.globals else {
+            return FeatureSet::default();
+        };
+        let interfaces: HashSet<String> = globals
+            .contents()
+            .with_list(|list| list.iter().map(|global| global.interface.clone()).collect());
+        let has = |name: &str| interfaces.contains(name);
+        FeatureSet {
+            blur: has(blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager::interface().name),
+            shadow: has(shadow::layer_shadow_manager_v1::LayerShadowManagerV1::interface().name),
+            corner_radius: has(
+                corner_radius::layer_corner_radius_manager_v1::LayerCornerRadiusManagerV1::interface()
+                    .name,
+            ),
+            auto_hide: has(
+                layer_auto_hide::layer_auto_hide_manager_v1::LayerAutoHideManagerV1::interface().name,
+            ),
+            fractional_scale: has(WpFractionalScaleManagerV1::interface().name),
+            cursor_shape: has(WpCursorShapeManagerV1::interface().name),
+            viewporter: has(WpViewporter::interface().name),
+            text_input: has(ZwpTextInputManagerV3::interface().name),
+            decoration: has(ZxdgDecorationManagerV1::interface().name),
+            virtual_keyboard: has(ZwpVirtualKeyboardManagerV1::interface().name),
+            keyboard_shortcuts_inhibit: has(ZwpKeyboardShortcutsInhibitManagerV1::interface().name),
+            idle_inhibit: has(ZwpIdleInhibitManagerV1::interface().name),
+            alpha_modifier: has(WpAlphaModifierV1::interface().name),
+            fifo: has(WpFifoManagerV1::interface().name),
+            commit_timing: has(WpCommitTimingManagerV1::interface().name),
+            presentation: has(
+                wayland_protocols::wp::presentation_time::client::wp_presentation::WpPresentation::interface()
+                    .name,
+            ),
+            tooltip: has(tooltip::zcosmic_tooltip_manager_v1::ZcosmicTooltipManagerV1::interface().name),
+            home_visibility: has(
+                home_visibility::zcosmic_home_visibility_manager_v1::ZcosmicHomeVisibilityManagerV1::interface()
+                    .name,
+            ),
+            voice_mode: has(
+                voice_mode::zcosmic_voice_mode_manager_v1::ZcosmicVoiceModeManagerV1::interface().name,
+            ),
+            layer_surface_visibility: has(
+                layer_surface_visibility::zcosmic_layer_surface_visibility_manager_v1::ZcosmicLayerSurfaceVisibilityManagerV1::interface()
+                    .name,
+            ),
+        }
+    }
+
+    /// Set the opacity of the whole surface of a unit (wp_alpha_modifier_v1), from 0.0
+    /// (transparent) to 1.0 (opaque). The compositor multiplies the alpha of the content with
+    /// it, so the content does not need to be rendered again. Out of range values are clamped.
+    /// The manager is bound and the per-surface object created on first use. Does nothing
+    /// when the compositor does not support wp_alpha_modifier_v1.
+    pub fn set_opacity(&mut self, id: id::Id, alpha: f64) {
+        if self.wl.alpha_modifier_manager.is_none() {
+            self.wl.alpha_modifier_manager = self.bind_lazy_global(1..=1, ());
+        }
+        let Some(manager) = self.wl.alpha_modifier_manager.clone() else {
+            log::warn!("wp_alpha_modifier_v1 not available - compositor may not support it");
+            return;
+        };
+        let Some(unit) = self.get_mut_unit_with_id(id) else {
+            return;
+        };
+        let alpha_modifier = unit
+            .alpha_modifier
+            .get_or_insert_with(|| manager.get_surface(&unit.wl_surface, &unit.qh, ()));
+        let factor = (alpha.clamp(0., 1.) * u32::MAX as f64).round() as u32;
+        alpha_modifier.set_multiplier(factor);
+        unit.commit_or_defer();
+    }
+
+    /// Inhibit the idle of the compositor while a unit is visible (zwp_idle_inhibit_manager_v1),
+    /// like a media player during the playback, by creating a zwp_idle_inhibitor_v1 on its
+    /// surface, or destroying it. The manager is bound on first use. Does nothing when the
+    /// compositor does not support zwp_idle_inhibit_manager_v1.
+    pub fn set_idle_inhibited(&mut self, id: id::Id, inhibited: bool) {
+        if !inhibited {
+            if let Some(inhibitor) = self
+                .get_mut_unit_with_id(id)
+                .and_then(|unit| unit.idle_inhibitor.take())
+            {
+                inhibitor.destroy();
+            }
+            return;
+        }
+        if self.wl.idle_inhibit_manager.is_none() {
+            self.wl.idle_inhibit_manager = self.bind_lazy_global(1..=1, ());
+        }
+        let Some(manager) = self.wl.idle_inhibit_manager.clone() else {
+            log::warn!("zwp_idle_inhibit_manager_v1 not available - compositor may not support it");
+            return;
+        };
+        let Some(unit) = self.get_mut_unit_with_id(id) else {
+            return;
+        };
+        if unit.idle_inhibitor.is_none() {
+            unit.idle_inhibitor = Some(manager.create_inhibitor(&unit.wl_surface, &unit.qh, ()));
+        }
+    }
+
+    /// request the presentation feedback of the next commit of the unit
+    #[cfg(feature = "presentation-time")]
+    fn request_presentation_feedback(&self, idx: usize) {
+        let Some(presentation) = &self.wl.presentation else {
+            return;
+        };
+        let unit = &self.units[idx];
+        presentation.feedback(&unit.wl_surface, &unit.qh, unit.id);
+    }
+
+    /// Enable compositor-driven auto-hide for a specific surface.
+    /// The compositor will animate hide/show transitions and handle hover detection.
+    /// `edge`: which edge to slide off (0 = bottom)
+    /// `edge_zone`: hover detection zone in pixels at the screen edge
+    /// `mode`: 0 = always hide when cursor leaves, 1 = only hide when maximized/fullscreen exists
+    pub fn set_auto_hide_for_surface(
+        &mut self,
+        surface: &WlSurface,
+        edge: u32,
+        edge_zone: u32,
+        mode: u32,
+    ) {
+        let surface_id = surface.id().protocol_id();
+        let edge_enum = layer_auto_hide::layer_auto_hide_v1::Edge::try_from(edge)
+            .unwrap_or(layer_auto_hide::layer_auto_hide_v1::Edge::Bottom);
+        let mode_enum = layer_auto_hide::layer_auto_hide_v1::Mode::try_from(mode)
+            .unwrap_or(layer_auto_hide::layer_auto_hide_v1::Mode::Always);
+
+        // Check if we already have an auto-hide object for this surface
+        if let Some(auto_hide_obj) = self.wl.auto_hide_surfaces.get(&surface_id) {
+            auto_hide_obj.set_auto_hide(edge_enum, edge_zone, mode_enum);
+            surface.commit();
+            return;
+        }
+
+        // Need to create a new auto-hide object
+        if self.wl.auto_hide_manager.is_none() {
+            self.wl.auto_hide_manager = self.bind_lazy_global(1..=1, ());
+        }
+        if let Some(manager) = &self.wl.auto_hide_manager {
+            let auto_hide_data = layer_auto_hide::LayerAutoHideData {
+                surface: surface.clone(),
+            };
+            // Get a queue handle from the first unit (they all share the same queue)
             if let Some(unit) = self.units.first() {
                 let auto_hide_obj = manager.get_auto_hide(surface, &unit.qh, auto_hide_data);
                 auto_hide_obj.set_auto_hide(edge_enum, edge_zone, mode_enum);
-                self.auto_hide_surfaces.insert(surface_id, auto_hide_obj);
+                self.wl.auto_hide_surfaces.insert(surface_id, auto_hide_obj);
                 surface.commit();
             }
         } else {
@@ -2236,7 +4165,7 @@ impl<T: 'static> WindowState<T> {
     pub fn unset_auto_hide_for_surface(&mut self, surface: &WlSurface) {
         let surface_id = surface.id().protocol_id();
 
-        if let Some(auto_hide_obj) = self.auto_hide_surfaces.get(&surface_id) {
+        if let Some(auto_hide_obj) = self.wl.auto_hide_surfaces.get(&surface_id) {
             auto_hide_obj.unset_auto_hide();
             surface.commit();
         }
@@ -2250,10 +4179,15 @@ impl<T: 'static> WindowState<T> {
         mode: home_visibility::VisibilityMode,
     ) {
         let surface_id = surface.id().protocol_id();
+        let unit_id = self.get_id_from_surface(surface);
 
         // Check if we already have a controller for this surface
-        if let Some(controller) = self.home_visibility_controllers.get(&surface_id) {
+        if let Some(controller) = self.wl.home_visibility_controllers.get(&surface_id) {
             controller.set_visibility_mode(mode);
+            if let Some(unit_id) = unit_id {
+                self.home_visibility_modes.insert(unit_id, mode);
+            }
+            self.update_effective_visibility();
             log::info!(
                 "Updated visibility mode to {:?} for surface {}",
                 mode,
@@ -2263,7 +4197,7 @@ impl<T: 'static> WindowState<T> {
         }
 
         // Need to create a new controller
-        if let Some(manager) = &self.home_visibility_manager {
+        if let Some(manager) = &self.wl.home_visibility_manager {
             if let Some(unit) = self.units.first() {
                 let visibility_data = home_visibility::HomeVisibilityData {
                     surface: surface.clone(),
@@ -2271,8 +4205,13 @@ impl<T: 'static> WindowState<T> {
                 let visibility_obj =
                     manager.get_home_visibility(surface, &unit.qh, visibility_data);
                 visibility_obj.set_visibility_mode(mode);
-                self.home_visibility_controllers
+                self.wl
+                    .home_visibility_controllers
                     .insert(surface_id, visibility_obj);
+                if let Some(unit_id) = unit_id {
+                    self.home_visibility_modes.insert(unit_id, mode);
+                }
+                self.update_effective_visibility();
                 log::info!(
                     "Created and set visibility mode to {:?} for surface {}",
                     mode,
@@ -2301,23 +4240,23 @@ impl<T: 'static> WindowState<T> {
     ) where
         T: 'static,
     {
-        let Some(surface) = self.current_surface.clone() else {
+        let Some(surface) = self.wl.current_surface.clone() else {
             log::warn!(target: "kcopy_dnd", "start_drag: no focused surface");
             return;
         };
-        let Some(manager) = self.data_device_manager.clone() else {
+        let Some(manager) = self.wl.data_device_manager.clone() else {
             log::warn!(target: "kcopy_dnd", "start_drag: no data_device_manager");
             return;
         };
-        let Some(device) = self.data_device.clone() else {
+        let Some(device) = self.wl.data_device.clone() else {
             log::warn!(target: "kcopy_dnd", "start_drag: no data_device");
             return;
         };
-        let Some(serial) = self.last_button_serial else {
+        let Some(serial) = self.wl.last_button_serial else {
             log::warn!(target: "kcopy_dnd", "start_drag: no button serial yet");
             return;
         };
-        let qh = match self.queue_handle.as_ref() {
+        let qh = match self.wl.queue_handle.as_ref() {
             Some(qh) => qh.clone(),
             None => {
                 log::warn!(target: "kcopy_dnd", "start_drag: queue_handle not initialized");
@@ -2338,7 +4277,7 @@ impl<T: 'static> WindowState<T> {
         if source.version() >= 3 {
             source.set_actions(DndAction::from_bits_truncate(actions));
         }
-        self.dnd_source_origin = self.current_surface_id();
+        self.wl.dnd_source_origin = self.current_surface_id();
         let icon_surface = icon.and_then(|i| self.build_dnd_icon(i));
         log::info!(target: "kcopy_dnd", "start_drag mimes={mime_types:?} serial={serial} icon={}", icon_surface.is_some());
         device.start_drag(Some(&source), &surface, icon_surface.as_ref(), serial);
@@ -2348,7 +4287,7 @@ impl<T: 'static> WindowState<T> {
         // stays under the cursor (the compositor renders the icon at
         // `cursor + offset`). Done post-`start_drag` because the offset is only
         // recorded from commits made after the surface has the dnd_icon role.
-        if let (Some((hx, hy)), Some(res)) = (hotspot, self.dnd_icon.as_ref()) {
+        if let (Some((hx, hy)), Some(res)) = (hotspot, self.wl.dnd_icon.as_ref()) {
             if hx != 0 || hy != 0 {
                 if res.surface.version() >= 5 {
                     res.surface.attach(Some(&res.buffer), 0, 0);
@@ -2362,6 +4301,97 @@ impl<T: 'static> WindowState<T> {
         }
     }
 
+    /// Set the clipboard selection to `data` of the `mime` type. The compositor only
+    /// accepts it with the serial of a recent user input on one of our surfaces, so
+    /// `serial` must be the latest pointer press, touch down or key press, otherwise the
+    /// selection is not set.
+    pub fn set_selection(&mut self, mime: &str, data: Vec<u8>, serial: u32)
+    where
+        T: 'static,
+    {
+        if self.wl.last_input_serial != Some(serial) {
+            log::warn!("set_selection: serial {serial} is not the serial of the latest input");
+            return;
+        }
+        let Some(manager) = &self.wl.data_device_manager else {
+            log::warn!("set_selection: no data_device_manager");
+            return;
+        };
+        let Some(device) = &self.wl.data_device else {
+            log::warn!("set_selection: no data_device");
+            return;
+        };
+        let Some(qh) = &self.wl.queue_handle else {
+            log::warn!("set_selection: queue_handle not initialized");
+            return;
+        };
+        let source = manager.create_data_source(
+            qh,
+            DndSourceData {
+                mime_types: vec![mime.to_string()],
+                data: vec![data],
+            },
+        );
+        source.offer(mime.to_string());
+        device.set_selection(Some(&source), serial);
+        if let Some(old) = self.wl.selection_source.replace(source) {
+            old.destroy();
+        }
+    }
+
+    /// Read the current clipboard selection as `mime`. The bytes are read without blocking
+    /// the event loop, and delivered with [DispatchMessage::SelectionData] once the source
+    /// has written all of them, so a slow source delays only its own message. Returns false
+    /// when there is no selection or it is not offered as `mime`.
+    pub fn request_selection(&mut self, mime: &str) -> bool {
+        let id = self.current_surface_id();
+        // our own selection: reading it through the compositor would block on the
+        // `send` we have to answer on this same thread
+        if let Some(source) = &self.wl.selection_source {
+            let Some(data) = source.data::<DndSourceData>().and_then(|source_data| {
+                let index = source_data.mime_types.iter().position(|m| m == mime)?;
+                source_data.data.get(index).cloned()
+            }) else {
+                return false;
+            };
+            self.wl.message.push((
+                id,
+                DispatchMessageInner::SelectionData {
+                    mime: mime.to_string(),
+                    data,
+                },
+            ));
+            return true;
+        }
+        let Some(offer) = &self.wl.selection_offer else {
+            return false;
+        };
+        let offered = self
+            .wl
+            .dnd_offer_mimes
+            .get(&offer.id())
+            .is_some_and(|mimes| mimes.iter().any(|m| m == mime));
+        if !offered {
+            return false;
+        }
+        let Some(conn) = &self.wl.connection else {
+            return false;
+        };
+        let Some(reader) = receive_data_offer(conn, offer, mime) else {
+            return false;
+        };
+        if let Err(err) = reader.set_nonblocking(true) {
+            log::warn!("request_selection: cannot read the {mime} selection: {err}");
+            return false;
+        }
+        self.wl.selection_reads.push(SelectionRead {
+            id,
+            mime: mime.to_string(),
+            reader,
+        });
+        true
+    }
+
     /// Build the drag-icon `wl_surface` from pre-multiplied ARGB pixels, keeping
     /// its buffer/pool/file alive in `self.dnd_icon` for the drag's duration.
     fn build_dnd_icon(&mut self, icon: DndIconPixels) -> Option<WlSurface>
@@ -2380,9 +4410,9 @@ impl<T: 'static> WindowState<T> {
         if width == 0 || height == 0 || argb.len() < expected {
             return None;
         }
-        let qh = self.queue_handle.as_ref()?.clone();
-        let compositor = self.cached_compositor.clone()?;
-        let shm = self.cached_shm.clone()?;
+        let qh = self.wl.queue_handle.as_ref()?.clone();
+        let compositor = self.wl.cached_compositor.clone()?;
+        let shm = self.wl.cached_shm.clone()?;
 
         let mut file = tempfile::tempfile().ok()?;
         file.write_all(&argb[..expected]).ok()?;
@@ -2408,11 +4438,11 @@ impl<T: 'static> WindowState<T> {
         surface.damage_buffer(0, 0, width as i32, height as i32);
         surface.commit();
 
-        if let Some(old) = self.dnd_icon.take() {
+        if let Some(old) = self.wl.dnd_icon.take() {
             old.destroy();
         }
         let surface_clone = surface.clone();
-        self.dnd_icon = Some(DndIconResources {
+        self.wl.dnd_icon = Some(DndIconResources {
             surface,
             buffer,
             pool,
@@ -2429,11 +4459,29 @@ impl<T: 'static> WindowState<T> {
     pub fn all_surfaces_hidden(&self) -> bool {
         !self.units.is_empty()
             && self.units.iter().all(|u| {
-                self.hidden_surfaces
+                self.wl
+                    .hidden_surfaces
                     .contains(&u.wl_surface.id().protocol_id())
             })
     }
 
+    /// record the visibility of a surface, returns the id of its unit
+    fn set_surface_visible(&mut self, surface_id: u32, visible: bool) -> Option<id::Id> {
+        if visible {
+            self.wl.hidden_surfaces.remove(&surface_id);
+        } else {
+            self.wl.hidden_surfaces.insert(surface_id);
+        }
+        let unit = self
+            .units
+            .iter_mut()
+            .find(|unit| unit.wl_surface.id().protocol_id() == surface_id)?;
+        unit.visible = visible;
+        let id = unit.id;
+        self.update_effective_visibility();
+        Some(id)
+    }
+
     /// Hide a surface without destroying it (using layer_surface_visibility protocol)
     /// The surface will not be rendered and won't receive input events.
     /// Use show_surface to make it visible again.
@@ -2442,13 +4490,17 @@ impl<T: 'static> WindowState<T> {
 
         // Track locally immediately so the timer can throttle before the
         // compositor round-trip confirms.
-        self.hidden_surfaces.insert(surface_id);
+        self.set_surface_visible(surface_id, false);
 
         // Check if we already have a visibility controller for this surface
-        if let Some(controller) = self.layer_surface_visibility_controllers.get(&surface_id) {
+        if let Some(controller) = self
+            .wl
+            .layer_surface_visibility_controllers
+            .get(&surface_id)
+        {
             controller.set_hidden();
             // Flush to ensure compositor processes immediately
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!("Hidden surface {} (existing controller)", surface_id);
@@ -2457,7 +4509,8 @@ impl<T: 'static> WindowState<T> {
 
         // Need to create a new controller
         let transition = self.surface_transition(surface_id);
-        if let Some(manager) = &self.layer_surface_visibility_manager {
+        self.bind_lazy_visibility_manager();
+        if let Some(manager) = &self.wl.layer_surface_visibility_manager {
             if let Some(unit) = self.units.first() {
                 let visibility_data = layer_surface_visibility::LayerSurfaceVisibilityData {
                     surface: surface.clone(),
@@ -2467,10 +4520,11 @@ impl<T: 'static> WindowState<T> {
                     manager.get_visibility_controller(surface, &unit.qh, visibility_data);
                 apply_transition_to_controller(&controller, transition);
                 controller.set_hidden();
-                self.layer_surface_visibility_controllers
+                self.wl
+                    .layer_surface_visibility_controllers
                     .insert(surface_id, controller);
                 // Flush to ensure compositor processes immediately
-                if let Some(ref conn) = self.connection {
+                if let Some(ref conn) = self.wl.connection {
                     let _ = conn.flush();
                 }
                 log::info!("Hidden surface {} (new controller)", surface_id);
@@ -2488,7 +4542,7 @@ impl<T: 'static> WindowState<T> {
 
         // Track locally immediately so the timer wakes up without waiting
         // for the compositor round-trip.
-        self.hidden_surfaces.remove(&surface_id);
+        self.set_surface_visible(surface_id, true);
 
         // Request a refresh on the affected unit so the very next timer tick
         // renders a fresh frame, instead of waiting for the compositor's
@@ -2501,10 +4555,14 @@ impl<T: 'static> WindowState<T> {
         }
 
         // Check if we have a visibility controller for this surface
-        if let Some(controller) = self.layer_surface_visibility_controllers.get(&surface_id) {
+        if let Some(controller) = self
+            .wl
+            .layer_surface_visibility_controllers
+            .get(&surface_id)
+        {
             controller.set_visible();
             // Flush to ensure compositor processes immediately
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!("Shown surface {} (existing controller)", surface_id);
@@ -2513,7 +4571,8 @@ impl<T: 'static> WindowState<T> {
 
         // Need to create a new controller (surface was never hidden, but make it explicit)
         let transition = self.surface_transition(surface_id);
-        if let Some(manager) = &self.layer_surface_visibility_manager {
+        self.bind_lazy_visibility_manager();
+        if let Some(manager) = &self.wl.layer_surface_visibility_manager {
             if let Some(unit) = self.units.first() {
                 let visibility_data = layer_surface_visibility::LayerSurfaceVisibilityData {
                     surface: surface.clone(),
@@ -2523,10 +4582,11 @@ impl<T: 'static> WindowState<T> {
                     manager.get_visibility_controller(surface, &unit.qh, visibility_data);
                 apply_transition_to_controller(&controller, transition);
                 controller.set_visible();
-                self.layer_surface_visibility_controllers
+                self.wl
+                    .layer_surface_visibility_controllers
                     .insert(surface_id, controller);
                 // Flush to ensure compositor processes immediately
-                if let Some(ref conn) = self.connection {
+                if let Some(ref conn) = self.wl.connection {
                     let _ = conn.flush();
                 }
                 log::info!("Shown surface {} (new controller)", surface_id);
@@ -2548,19 +4608,24 @@ impl<T: 'static> WindowState<T> {
         let surface_id = surface.id().protocol_id();
 
         // Check if we already have a controller for this surface
-        if let Some(controller) = self.layer_surface_dismiss_controllers.get(&surface_id) {
+        if let Some(controller) = self.wl.layer_surface_dismiss_controllers.get(&surface_id) {
             return Some(controller.clone());
         }
 
         // Need to create a new controller
-        let manager = self.layer_surface_dismiss_manager.as_ref()?;
+        if self.wl.layer_surface_dismiss_manager.is_none() {
+            self.wl.layer_surface_dismiss_manager =
+                self.bind_lazy_global(1..=2, layer_surface_dismiss::LayerSurfaceDismissManagerData);
+        }
+        let manager = self.wl.layer_surface_dismiss_manager.as_ref()?;
         let unit = self.units.first()?;
 
         let dismiss_data = layer_surface_dismiss::LayerSurfaceDismissData {
             surface: surface.clone(),
         };
         let controller = manager.get_dismiss_controller(surface, &unit.qh, dismiss_data);
-        self.layer_surface_dismiss_controllers
+        self.wl
+            .layer_surface_dismiss_controllers
             .insert(surface_id, controller.clone());
         log::debug!("Created dismiss controller for surface {}", surface_id);
         Some(controller)
@@ -2572,7 +4637,7 @@ impl<T: 'static> WindowState<T> {
     pub fn arm_dismiss(&mut self, surface: &WlSurface) {
         if let Some(controller) = self.get_or_create_dismiss_controller(surface) {
             controller.arm();
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!("Armed dismiss for surface {}", surface.id().protocol_id());
@@ -2588,7 +4653,7 @@ impl<T: 'static> WindowState<T> {
     pub fn disarm_dismiss(&mut self, surface: &WlSurface) {
         if let Some(controller) = self.get_or_create_dismiss_controller(surface) {
             controller.disarm();
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!(
@@ -2610,7 +4675,7 @@ impl<T: 'static> WindowState<T> {
         if let Some(controller) = self.get_or_create_dismiss_controller(surface) {
             if controller.version() >= 2 {
                 controller.set_ignore_layer_clicks();
-                if let Some(ref conn) = self.connection {
+                if let Some(ref conn) = self.wl.connection {
                     let _ = conn.flush();
                 }
                 log::info!(
@@ -2635,7 +4700,7 @@ impl<T: 'static> WindowState<T> {
     pub fn add_to_dismiss_group(&mut self, popup_surface: &WlSurface, group_surface: &WlSurface) {
         if let Some(controller) = self.get_or_create_dismiss_controller(popup_surface) {
             controller.add_to_group(group_surface);
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!(
@@ -2658,7 +4723,7 @@ impl<T: 'static> WindowState<T> {
     ) {
         if let Some(controller) = self.get_or_create_dismiss_controller(popup_surface) {
             controller.remove_from_group(group_surface);
-            if let Some(ref conn) = self.connection {
+            if let Some(ref conn) = self.wl.connection {
                 let _ = conn.flush();
             }
             log::info!(
@@ -2676,7 +4741,7 @@ impl<T: 'static> WindowState<T> {
     /// Check if dismiss was requested and clear the flag
     /// Returns true if a dismiss was requested since the last check.
     pub fn take_dismiss_requested(&mut self) -> bool {
-        std::mem::take(&mut self.dismiss_requested)
+        std::mem::take(&mut self.wl.dismiss_requested)
     }
 }
 
@@ -2686,12 +4751,18 @@ pub trait ZwpTextInputV3Ext {
 
 impl ZwpTextInputV3Ext for ZwpTextInputV3 {
     fn set_content_type_by_purpose(&self, purpose: ImePurpose) {
-        let (hint, purpose) = match purpose {
+        let (hint, purpose) = purpose.content_type();
+        self.set_content_type(hint, purpose);
+    }
+}
+
+impl ImePurpose {
+    fn content_type(self) -> (ContentHint, ContentPurpose) {
+        match self {
             ImePurpose::Normal => (ContentHint::None, ContentPurpose::Normal),
             ImePurpose::Password => (ContentHint::SensitiveData, ContentPurpose::Password),
             ImePurpose::Terminal => (ContentHint::None, ContentPurpose::Terminal),
-        };
-        self.set_content_type(hint, purpose);
+        }
     }
 }
 
@@ -2976,6 +5047,49 @@ fn register_voice_mode_for_surface<T: 'static>(
     }
 }
 
+/// Create the xdg_positioner of a popup. The anchor rect starts at `position`, with the size
+/// of `anchor_rect_size` or the popup size. `anchor`, `gravity` and `constraint_adjustment` are
+/// the protocol values, 0 keeps the protocol default.
+#[allow(clippy::too_many_arguments)]
+fn create_popup_positioner<T: 'static>(
+    wmbase: &XdgWmBase,
+    qh: &QueueHandle<WindowState<T>>,
+    (width, height): (u32, u32),
+    (x, y): (i32, i32),
+    anchor_rect_size: Option<(i32, i32)>,
+    anchor: u32,
+    gravity: u32,
+    constraint_adjustment: u32,
+    offset: Option<(i32, i32)>,
+    reactive: bool,
+) -> XdgPositioner {
+    use wayland_protocols::xdg::shell::client::xdg_positioner::{
+        Anchor as PositionerAnchor, ConstraintAdjustment, Gravity,
+    };
+    let positioner = wmbase.create_positioner(qh, ());
+    positioner.set_size(width as i32, height as i32);
+    let (ar_w, ar_h) = anchor_rect_size.unwrap_or((width as i32, height as i32));
+    positioner.set_anchor_rect(x, y, ar_w, ar_h);
+    if anchor != 0 {
+        positioner.set_anchor(PositionerAnchor::try_from(anchor).unwrap_or(PositionerAnchor::None));
+    }
+    if gravity != 0 {
+        positioner.set_gravity(Gravity::try_from(gravity).unwrap_or(Gravity::None));
+    }
+    if constraint_adjustment != 0 {
+        positioner.set_constraint_adjustment(ConstraintAdjustment::from_bits_truncate(
+            constraint_adjustment,
+        ));
+    }
+    if let Some((ox, oy)) = offset {
+        positioner.set_offset(ox, oy);
+    }
+    if reactive && positioner.version() >= 3 {
+        positioner.set_reactive();
+    }
+    positioner
+}
+
 impl<T> WindowState<T> {
     /// create a WindowState, you need to pass a namespace in
     pub fn new(namespace: &str) -> Self {
@@ -2999,11 +5113,182 @@ impl<T> WindowState<T> {
         self
     }
 
+    /// set how the name of [`StartMode::TargetScreen`] is matched against the outputs,
+    /// default is [`OutputMatchStrategy::Auto`]
+    pub fn with_output_match_strategy(mut self, strategy: OutputMatchStrategy) -> Self {
+        self.output_match_strategy = strategy;
+        self
+    }
+
     pub fn with_events_transparent(mut self, transparent: bool) -> Self {
         self.events_transparent = transparent;
         self
     }
 
+    /// cap the redraw rate of every surface, for example a clock that only needs 30fps on
+    /// a 144Hz monitor. A refresh requested before `1/max_fps` has passed since the last
+    /// present will wait. None means no limit, which is the default
+    pub fn with_max_fps(mut self, max_fps: Option<u32>) -> Self {
+        self.max_fps = max_fps;
+        self
+    }
+
+    /// bind the corner radius, shadow, auto-hide, visibility and dismiss managers the first time
+    /// their setter is called instead of in `build`, for a faster start. The managers the
+    /// initial settings need, like the corner radius or the shadow, are still bound in `build`.
+    /// Default is false
+    pub fn with_lazy_protocol_binding(mut self, lazy: bool) -> Self {
+        self.lazy_protocol_binding = lazy;
+        self
+    }
+
+    /// also send the first finger on the screen as a mouse, for widgets which only handle the
+    /// pointer: touch down, motion and up send MouseEnter, MouseMotion, a left MouseButton and
+    /// MouseLeave, after the touch events themselves. The other fingers of a multi-finger
+    /// gesture only send touch events. MouseEnter is only sent when the seat has a pointer.
+    /// Default is false
+    pub fn with_touch_emulates_pointer(mut self, emulate: bool) -> Self {
+        self.touch_emulates_pointer = emulate;
+        self
+    }
+
+    /// only send [LayerShellEvent::NormalDispatch] from the event loop timer when a message
+    /// was processed or a refresh is pending in that tick, instead of on every tick, for
+    /// event-driven apps which have no idle work. The user events and the key repeat still
+    /// send it. Default is false
+    pub fn with_dispatch_only_on_activity(mut self, only_on_activity: bool) -> Self {
+        self.dispatch_only_on_activity = only_on_activity;
+        self
+    }
+
+    /// collapse the MouseMotion messages which follow each other on the same surface in one
+    /// tick of the event loop into the latest one, so a fast mouse does not make the handler
+    /// relayout for every position. The other messages, like the buttons and the scroll, are
+    /// kept in order between the motions. Default is false
+    pub fn with_coalesce_motion(mut self, coalesce: bool) -> Self {
+        self.coalesce_motion = coalesce;
+        self
+    }
+
+    /// connect again when the wayland connection is lost, like when the compositor restarts,
+    /// instead of returning from `running`. Every global is bound again on the new
+    /// connection, and the init requests ([LayerShellEvent::InitRequest] and the ones it
+    /// asks for) are sent again, then the layer shell surfaces are created again with their
+    /// ids, bindings and settings, on the output with the same name, and the handler gets
+    /// [LayerShellEvent::Reconnected]. The other surfaces, like the popups, are closed.
+    ///
+    /// NOTE: the new connection is always opened from the environment, even if one was given
+    /// by [WindowState::with_connection]. Default is false
+    pub fn with_auto_reconnect(mut self, auto_reconnect: bool) -> Self {
+        self.auto_reconnect = auto_reconnect;
+        self
+    }
+
+    /// how many times [WindowState::with_auto_reconnect] tries to connect again, waiting
+    /// longer between the tries up to 5s. Once they are used up, `running` returns the
+    /// [LayerEventError::ConnectError] of the last one. Default is 10, about half a minute
+    pub fn with_reconnect_attempts(mut self, attempts: u32) -> Self {
+        self.reconnect_attempts = attempts.max(1);
+        self
+    }
+
+    /// coalesce the resizes of a layer surface: during a drag-resize or an output
+    /// reconfiguration the compositor can send many configures in a row, each one is still
+    /// acked, but the surface is only resized and refreshed once no new size came for
+    /// `debounce`, a few milliseconds is enough. The first configure is never delayed.
+    /// None, the default, refreshes on every configure
+    pub fn with_configure_debounce(mut self, debounce: Option<Duration>) -> Self {
+        self.configure_debounce = debounce;
+        self
+    }
+
+    /// override the size, anchor, margin or exclusive zone of the surface of some outputs in
+    /// [`StartMode::AllScreens`], like a taller bar on the main monitor. The closure is called
+    /// when the surface of each output is created, the `None` fields of the returned
+    /// [OutputLayerConfig] use the global settings. For an output connected after the start only
+    /// the name of the [ZxdgOutputInfo] is known, its position and size are still empty
+    pub fn with_per_output_config(
+        mut self,
+        config: impl Fn(&ZxdgOutputInfo) -> OutputLayerConfig + 'static,
+    ) -> Self {
+        self.per_output_config = Some(PerOutputConfig(Box::new(config)));
+        self
+    }
+
+    /// only create surfaces on the outputs the filter allows in [`StartMode::AllScreens`], like
+    /// to skip the internal panel. It is called before the surface of each output is created, the
+    /// skipped outputs are remembered and checked again by [WindowState::reapply_output_filter].
+    /// For an output connected after the start only the name of the [ZxdgOutputInfo] is known
+    pub fn with_output_filter(
+        mut self,
+        filter: impl Fn(&ZxdgOutputInfo) -> bool + 'static,
+    ) -> Self {
+        self.output_filter = Some(OutputFilter(Box::new(filter)));
+        self
+    }
+
+    /// run some work of your own every time the event loop goes idle, like polling a socket.
+    /// The callback is called once the woken sources are dispatched and the messages are
+    /// processed, it can change the state and request refreshes, which are presented on the
+    /// next tick. The loop wakes up at least every 20ms, but with
+    /// [WindowState::with_on_demand_presentation] or [RenderMode::FrameCallback] only for its
+    /// sources, so use a timer of [WindowState::with_calloop_source] for a fixed cadence
+    pub fn with_idle_callback(
+        mut self,
+        callback: impl FnMut(&mut WindowState<T>) + 'static,
+    ) -> Self {
+        self.idle_callback = Some(IdleCallback(Box::new(callback)));
+        self
+    }
+
+    /// register your own calloop event sources, like a file descriptor or a dbus connection
+    /// driving the panel, in the event loop of [WindowState::running], instead of a second
+    /// thread. The closure is called once the event loop is built, the callbacks of the
+    /// sources get the [WindowState] and can change it and request refreshes.
+    ///
+    /// NOTE: the sources live in a loop nested in the main one, woken by its file
+    /// descriptor. Timers are only checked on every iteration of the main loop, so they can
+    /// fire up to 20ms late
+    pub fn with_calloop_source(
+        mut self,
+        register: impl FnOnce(&LoopHandle<'static, WindowState<T>>) + 'static,
+    ) -> Self {
+        self.calloop_sources.push(CalloopSource(Box::new(register)));
+        self
+    }
+
+    /// set what drives the redraw of the surfaces, default is [RenderMode::Polling]
+    pub fn with_render_mode(mut self, render_mode: RenderMode) -> Self {
+        self.render_mode = render_mode;
+        self
+    }
+
+    /// get the [RenderMode] of the surfaces
+    pub fn render_mode(&self) -> RenderMode {
+        self.render_mode
+    }
+
+    /// stop the 50ms timer of the event loop when there is nothing to do, for mostly static
+    /// surfaces like a clock. The loop then only wakes up for wayland events, messages and
+    /// refresh requests, and the short cadence resumes until they are handled.
+    ///
+    /// NOTE: the timers of [WindowState::with_calloop_source] are checked when the loop
+    /// wakes up, so they can fire late
+    pub fn with_on_demand_presentation(mut self, on_demand: bool) -> Self {
+        self.on_demand_presentation = on_demand;
+        self
+    }
+
+    /// log the present cycle of every surface under the [TRACE_TARGET] log target: configures,
+    /// presents, commits made by the event loop, frame callback requests and the frame
+    /// callbacks received, each with a timestamp in milliseconds since the state was built.
+    /// It is meant to diagnose pacing and latency issues, filter it with the log level of
+    /// the target, like `RUST_LOG=layershellev::trace=info`
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace_epoch = trace.then(Instant::now);
+        self
+    }
+
     /// Request blur effect for surfaces (requires compositor support for org_kde_kwin_blur)
     pub fn with_blur(mut self, blur: bool) -> Self {
         self.blur = blur;
@@ -3071,7 +5356,8 @@ impl<T> WindowState<T> {
     /// Effective transition for a surface: its per-surface override if set,
     /// otherwise the global transition.
     fn surface_transition(&self, surface_id: u32) -> Option<LayerTransition> {
-        self.transitions
+        self.wl
+            .transitions
             .get(&surface_id)
             .copied()
             .or(self.transition)
@@ -3103,6 +5389,21 @@ impl<T> WindowState<T> {
         self
     }
 
+    /// Only emit foreign toplevel events for the toplevels the filter accepts, like the ones with
+    /// some app_ids for a per-app dock. The filter is checked on every update of a toplevel:
+    /// `Created` is sent the first time it matches, and `Closed` when it stops matching or is
+    /// closed. Toplevels that don't match are still tracked, and can be found with
+    /// [WindowState::toplevels].
+    /// This method is only available when the `foreign-toplevel` feature is enabled.
+    #[cfg(feature = "foreign-toplevel")]
+    pub fn with_foreign_toplevel_filter(
+        mut self,
+        filter: impl Fn(&foreign_toplevel::ToplevelInfo) -> bool + 'static,
+    ) -> Self {
+        self.foreign_toplevel_filter = Some(ForeignToplevelFilter(Box::new(filter)));
+        self
+    }
+
     /// Enable voice mode protocol support (requires compositor support for zcosmic_voice_mode_v1)
     /// When enabled, the surface will receive voice mode events from the compositor.
     /// The surface is automatically registered as a voice mode receiver with the compositor.
@@ -3164,6 +5465,23 @@ impl<T> WindowState<T> {
         self
     }
 
+    /// put the surface on [Layer::Background] with the layer shell, anchored to every edge so
+    /// it fills the output, with an exclusive zone of 0 and no keyboard interactivity. Unlike
+    /// [StartMode::Background], which creates a surface without any role, the surface is shown
+    /// by the compositor behind the windows, this is what a wallpaper needs. Combine it with
+    /// [WindowState::with_allscreens] to cover every output
+    pub fn with_background_layer(mut self) -> Self {
+        if self.start_mode.is_background() {
+            self.start_mode = StartMode::Active;
+        }
+        self.layer = Layer::Background;
+        self.anchor = Anchor::Top | Anchor::Bottom | Anchor::Left | Anchor::Right;
+        self.exclusive_zone = Some(0);
+        self.keyboard_interactivity = zwlr_layer_surface_v1::KeyboardInteractivity::None;
+        self.size = None;
+        self
+    }
+
     /// keyboard_interacivity, please take look at [layer_shell](https://wayland.app/protocols/wlr-layer-shell-unstable-v1)
     pub fn with_keyboard_interacivity(
         mut self,
@@ -3220,7 +5538,7 @@ impl<T> WindowState<T> {
 
     /// set a callback to create a wayland connection
     pub fn with_connection(mut self, connection_or: Option<Connection>) -> Self {
-        self.connection = connection_or;
+        self.wl.connection = connection_or;
         self
     }
 }
@@ -3228,40 +5546,8 @@ impl<T> WindowState<T> {
 impl<T> Default for WindowState<T> {
     fn default() -> Self {
         Self {
-            outputs: Vec::new(),
-            current_surface: None,
-            active_surfaces: HashMap::new(),
+            wl: WaylandState::default(),
             units: Vec::new(),
-            message: Vec::new(),
-
-            background_surface: None,
-            display: None,
-
-            connection: None,
-            event_queue: None,
-            wl_compositor: None,
-            shm: None,
-            wmbase: None,
-            cursor_manager: None,
-            viewporter: None,
-            xdg_output_manager: None,
-            globals: None,
-            fractional_scale_manager: None,
-            virtual_keyboard: None,
-
-            seat: None,
-            keyboard_state: None,
-            pointer: None,
-            touch: None,
-            data_device_manager: None,
-            data_device: None,
-            dnd_offer_mimes: HashMap::new(),
-            dnd_current: None,
-            dnd_source_origin: None,
-            last_button_serial: None,
-            dnd_icon: None,
-            cached_compositor: None,
-            cached_shm: None,
 
             namespace: "".to_owned(),
             keyboard_interactivity: zwlr_layer_surface_v1::KeyboardInteractivity::OnDemand,
@@ -3272,47 +5558,153 @@ impl<T> Default for WindowState<T> {
             margin: None,
 
             use_display_handle: false,
-            repeat_delay: None,
-            to_remove_tokens: Vec::new(),
-            to_be_released_key: None,
+            virtual_keyboard_epoch: Instant::now(),
             closed_ids: Vec::new(),
 
-            last_wloutput: None,
             last_unit_index: 0,
 
             return_data: Vec::new(),
-            finger_locations: HashMap::new(),
-            enter_serial: None,
-            // NOTE: if is some, means it is to be binded, but not now it
-            // is not binded
-            xdg_info_cache: Vec::new(),
-            output_layout: Vec::new(),
-            output_handles: Vec::new(),
+            touch_emulates_pointer: false,
+            input_filter: None,
+            idle_callback: None,
+            per_output_config: None,
+            output_filter: None,
+            calloop_sources: Vec::new(),
+            ime_cursor_area: Cell::new(None),
+            output_match_strategy: OutputMatchStrategy::default(),
 
             start_mode: StartMode::Active,
-            init_finished: false,
+            max_fps: None,
+            render_mode: RenderMode::Polling,
+            on_demand_presentation: false,
+            trace_epoch: None,
+            configure_debounce: None,
+            lazy_protocol_binding: false,
+            dispatch_only_on_activity: false,
+            coalesce_motion: false,
+            frame_stats: FrameStats::default(),
+            auto_reconnect: false,
+            reconnect_attempts: 10,
+            reconnect_outputs: None,
+            next_reposition_token: 1,
+            popup_parents: HashMap::new(),
+            layer_parents: HashMap::new(),
             events_transparent: false,
             blur: false,
             blur_radius: None,
             blur_saturation: None,
             blur_tint: None,
             blur_border: None,
+            corner_radius: None,
+            shadow: false,
+            transition: None,
+            home_only: false,
+            hide_on_home: false,
+            home_visibility_modes: HashMap::new(),
+
+            voice_mode_enabled: false,
+
+            #[cfg(feature = "foreign-toplevel")]
+            foreign_toplevel_enabled: false,
+            #[cfg(feature = "foreign-toplevel")]
+            foreign_toplevel_filter: None,
+
+            ime_purpose: ImePurpose::Normal,
+            ime_content_type: None,
+            ime_allowed: false,
+        }
+    }
+}
+
+impl<T> Default for WaylandState<T> {
+    fn default() -> Self {
+        Self {
+            outputs: Vec::new(),
+            current_surface: None,
+            focus: FocusState::default(),
+            active_surfaces: HashMap::new(),
+            message: Vec::new(),
+            output_changes: Vec::new(),
+            background_surface: None,
+            display: None,
+            connection: None,
+            event_queue: None,
+            wl_compositor: None,
+            shm: None,
+            wmbase: None,
+            cursor_manager: None,
+            viewporter: None,
+            xdg_output_manager: None,
+            globals: None,
+            fractional_scale_manager: None,
+            virtual_keyboard: None,
+            seat: None,
+            seat_name: None,
+            keyboard_repeat_info: None,
+            keyboard_state: None,
+            pointer: None,
+            cursor_shape_device: None,
+            touch: None,
+            data_device_manager: None,
+            data_device: None,
+            dnd_offer_mimes: HashMap::new(),
+            dnd_current: None,
+            dnd_source_origin: None,
+            selection_offer: None,
+            selection_source: None,
+            last_button_serial: None,
+            last_input_serial: None,
+            last_pointer_serial: None,
+            last_touch_serial: None,
+            last_keyboard_serial: None,
+            dnd_icon: None,
+            cached_compositor: None,
+            cached_shm: None,
+            repeat_delay: None,
+            to_remove_tokens: Vec::new(),
+            to_be_released_keys: Vec::new(),
+            selection_reads: Vec::new(),
+            last_wloutput: None,
+            finger_locations: HashMap::new(),
+            emulated_pointer_finger: None,
+            last_touch_time: 0,
+            enter_serial: None,
+            pointer_position: None,
+            filtered_outputs: Vec::new(),
+            // NOTE: if is some, means it is to be binded, but not now it
+            // is not binded
+            xdg_info_cache: Vec::new(),
+            output_layout: Vec::new(),
+            output_handles: Vec::new(),
+            wl_output_names: HashMap::new(),
+            output_make_models: HashMap::new(),
+            output_refresh_rates: HashMap::new(),
+            output_scales: HashMap::new(),
+            init_finished: false,
+            timer_idle: false,
+            resize_timer_armed: false,
+            connection_lost: false,
             blur_manager: None,
             blur_surfaces: HashMap::new(),
             blur_params: HashMap::new(),
-            corner_radius: None,
             corner_radius_manager: None,
             corner_radius_surfaces: HashMap::new(),
             layer_surface_placement_manager: None,
             layer_surface_placement_surfaces: HashMap::new(),
             layer_edge_resize_manager: None,
             layer_edge_resize_surfaces: HashMap::new(),
-            shadow: false,
             shadow_manager: None,
             shadow_surfaces: HashMap::new(),
             keyboard_shortcuts_inhibit_manager: None,
             keyboard_shortcuts_inhibitors: HashMap::new(),
-            transition: None,
+            fifo_manager: None,
+            commit_timing_manager: None,
+            alpha_modifier_manager: None,
+            idle_inhibit_manager: None,
+            #[cfg(feature = "presentation-time")]
+            presentation: None,
+            #[cfg(feature = "presentation-time")]
+            presentation_clock_id: None,
             transitions: HashMap::new(),
             auto_hide_manager: None,
             auto_hide_surfaces: HashMap::new(),
@@ -3321,27 +5713,18 @@ impl<T> Default for WindowState<T> {
             usable_area_surfaces: HashMap::new(),
             tooltip_manager: None,
             tooltip_surfaces: HashMap::new(),
-            home_only: false,
-            hide_on_home: false,
             home_visibility_manager: None,
             home_visibility_controllers: HashMap::new(),
             is_home: false,
-
-            voice_mode_enabled: false,
             voice_mode_manager: None,
             voice_mode_receivers: HashMap::new(),
             voice_mode_events: Vec::new(),
-
             layer_surface_visibility_manager: None,
             layer_surface_visibility_controllers: HashMap::new(),
             hidden_surfaces: HashSet::new(),
-
             layer_surface_dismiss_manager: None,
             layer_surface_dismiss_controllers: HashMap::new(),
             dismiss_requested: false,
-
-            #[cfg(feature = "foreign-toplevel")]
-            foreign_toplevel_enabled: false,
             #[cfg(feature = "foreign-toplevel")]
             ext_foreign_toplevel_list: None,
             #[cfg(feature = "cosmic-toplevel")]
@@ -3357,6 +5740,8 @@ impl<T> Default for WindowState<T> {
             #[cfg(feature = "foreign-toplevel")]
             foreign_toplevel_data: HashMap::new(),
             #[cfg(feature = "foreign-toplevel")]
+            foreign_toplevel_emitted: HashSet::new(),
+            #[cfg(feature = "foreign-toplevel")]
             foreign_toplevel_handles: HashMap::new(),
             #[cfg(feature = "foreign-toplevel")]
             ext_toplevel_handles: HashMap::new(),
@@ -3365,15 +5750,11 @@ impl<T> Default for WindowState<T> {
             queue_handle: None,
             #[cfg(feature = "screencopy")]
             screencopy_shm: None,
-
             text_input_manager: None,
             text_input: None,
+            text_input_wanted: false,
             text_inputs: Vec::new(),
-            ime_purpose: ImePurpose::Normal,
-            ime_allowed: false,
-
             xdg_decoration_manager: None,
-
             ping_sender: None,
         }
     }
@@ -3382,16 +5763,85 @@ impl<T> Default for WindowState<T> {
 impl<T> WindowState<T> {
     /// You can save the virtual_keyboard here
     pub fn set_virtual_keyboard(&mut self, keyboard: ZwpVirtualKeyboardV1) {
-        self.virtual_keyboard = Some(keyboard);
+        self.wl.virtual_keyboard = Some(keyboard);
     }
 
     /// get the saved virtual_keyboard
     pub fn get_virtual_keyboard(&self) -> Option<&ZwpVirtualKeyboardV1> {
-        self.virtual_keyboard.as_ref()
+        self.wl.virtual_keyboard.as_ref()
     }
 
     pub fn set_virtual_key_release(&mut self, key_info: VirtualKeyRelease) {
-        self.to_be_released_key = Some(key_info);
+        self.wl.to_be_released_keys.push((key_info, false));
+    }
+
+    /// the timestamp of a virtual key event sent now, in milliseconds
+    fn virtual_key_time(&self) -> u32 {
+        self.virtual_keyboard_epoch.elapsed().as_millis() as u32
+    }
+
+    fn send_virtual_key(&self, keycode: u32, state: KeyState) {
+        let Some(keyboard) = &self.wl.virtual_keyboard else {
+            log::warn!("no virtual keyboard, set one with set_virtual_keyboard");
+            return;
+        };
+        keyboard.key(self.virtual_key_time(), keycode, state.into());
+    }
+
+    /// press a key of the virtual keyboard, `keycode` is an evdev keycode like `KEY_A`
+    pub fn virtual_key_press(&mut self, keycode: u32) {
+        self.send_virtual_key(keycode, KeyState::Pressed);
+    }
+
+    /// release a key pressed with [WindowState::virtual_key_press]
+    pub fn virtual_key_release(&mut self, keycode: u32) {
+        self.send_virtual_key(keycode, KeyState::Released);
+    }
+
+    /// set the modifiers of the virtual keyboard, as the masks of the modifiers in its keymap,
+    /// like `wl_keyboard.modifiers`. With the usual keymaps shift is 1, caps lock 2, control 4,
+    /// alt 8 and logo 64
+    pub fn virtual_keyboard_modifiers(
+        &self,
+        depressed: u32,
+        latched: u32,
+        locked: u32,
+        group: u32,
+    ) {
+        let Some(keyboard) = &self.wl.virtual_keyboard else {
+            log::warn!("no virtual keyboard, set one with set_virtual_keyboard");
+            return;
+        };
+        keyboard.modifiers(depressed, latched, locked, group);
+    }
+
+    /// tap a key of the virtual keyboard with the `mods` modifiers held, like shift for a
+    /// capital letter. The modifiers are cleared once the key is released
+    pub fn virtual_key_with_modifiers(&mut self, keycode: u32, mods: u32) {
+        if self.wl.virtual_keyboard.is_none() {
+            log::warn!("no virtual keyboard, set one with set_virtual_keyboard");
+            return;
+        }
+        self.virtual_keyboard_modifiers(mods, 0, 0, 0);
+        self.virtual_key_tap(keycode, Duration::from_millis(10));
+        if let Some((_, clear_modifiers)) = self.wl.to_be_released_keys.last_mut() {
+            *clear_modifiers = true;
+        }
+    }
+
+    /// press a key of the virtual keyboard, and release it after `hold`
+    pub fn virtual_key_tap(&mut self, keycode: u32, hold: Duration) {
+        if self.wl.virtual_keyboard.is_none() {
+            log::warn!("no virtual keyboard, set one with set_virtual_keyboard");
+            return;
+        }
+        let time = self.virtual_key_time();
+        self.send_virtual_key(keycode, KeyState::Pressed);
+        self.set_virtual_key_release(VirtualKeyRelease {
+            delay: hold,
+            time: time.wrapping_add(hold.as_millis() as u32),
+            key: keycode,
+        });
     }
 
     /// use [id::Id] to get the mut [WindowStateUnit]
@@ -3412,17 +5862,38 @@ impl<T> WindowState<T> {
     fn surface_pos(&self) -> Option<usize> {
         self.units
             .iter()
-            .position(|unit| Some(&unit.wl_surface) == self.current_surface.as_ref())
+            .position(|unit| Some(&unit.wl_surface) == self.wl.current_surface.as_ref())
     }
 
     /// get the current focused surface id
     pub fn current_surface_id(&self) -> Option<id::Id> {
         self.units
             .iter()
-            .find(|unit| Some(&unit.wl_surface) == self.current_surface.as_ref())
+            .find(|unit| Some(&unit.wl_surface) == self.wl.current_surface.as_ref())
             .map(|unit| unit.id())
     }
 
+    /// if one of our layer surfaces has Exclusive keyboard interactivity and the keyboard is on
+    /// it. The compositor tells nothing about the grab itself, so false while a surface is
+    /// Exclusive means the compositor did not give it the keyboard, like when it is not on
+    /// the [Layer::Top] or [Layer::Overlay] layer, or the compositor gave the keyboard to
+    /// another surface since
+    pub fn has_exclusive_keyboard(&self) -> bool {
+        self.units.iter().any(|unit| {
+            unit.keyboard_interactivity.get()
+                == zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive
+                && unit.keyboard_entered_now.get()
+        })
+    }
+
+    /// if the keyboard is on the surface, between the `wl_keyboard.enter` and the following
+    /// `wl_keyboard.leave`. It does not move when the pointer hovers or clicks another
+    /// surface, see [WindowState::is_mouse_surface]
+    pub fn has_keyboard_focus(&self, id: id::Id) -> bool {
+        self.get_unit_with_id(id)
+            .is_some_and(|unit| unit.keyboard_entered_now.get())
+    }
+
     fn get_id_from_surface(&self, surface: &WlSurface) -> Option<id::Id> {
         self.units
             .iter()
@@ -3430,8 +5901,11 @@ impl<T> WindowState<T> {
             .map(|unit| unit.id())
     }
 
+    /// if the pointer is over the surface. It says nothing about the keyboard, which may be
+    /// on another surface, see [WindowState::has_keyboard_focus]
     pub fn is_mouse_surface(&self, surface_id: id::Id) -> bool {
-        self.active_surfaces
+        self.wl
+            .active_surfaces
             .get(&None)
             .filter(|(_, id)| *id == Some(surface_id))
             .is_some()
@@ -3440,25 +5914,26 @@ impl<T> WindowState<T> {
     /// update `current_surface` only if a finger is down or a mouse button is clicked or a surface
     /// is created.
     fn update_current_surface(&mut self, surface: Option<WlSurface>) {
-        if surface == self.current_surface {
+        if surface == self.wl.current_surface {
             return;
         }
         if let Some(surface) = surface {
-            self.current_surface = Some(surface);
+            self.wl.current_surface = Some(surface);
 
             // reset repeat when surface is changed
-            if let Some(keyboard_state) = self.keyboard_state.as_mut() {
+            if let Some(keyboard_state) = self.wl.keyboard_state.as_mut() {
                 keyboard_state.current_repeat = None;
             }
 
             let unit = self
                 .units
                 .iter()
-                .find(|unit| Some(&unit.wl_surface) == self.current_surface.as_ref());
+                .find(|unit| Some(&unit.wl_surface) == self.wl.current_surface.as_ref());
             if let Some(unit) = unit {
-                self.message
-                    .push((Some(unit.id), DispatchMessageInner::Focused(unit.id)));
+                let focus_messages = self.wl.focus.focus(Some(unit.id));
+                self.wl.message.extend(focus_messages);
                 self.last_unit_index = self
+                    .wl
                     .outputs
                     .iter()
                     .position(|(_, output)| Some(output) == unit.wl_output.as_ref())
@@ -3467,6 +5942,44 @@ impl<T> WindowState<T> {
         }
     }
 
+    /// without fractional scale, the buffer scale of the surface follows the highest
+    /// `wl_output.scale` of the outputs it is on, so it stays crisp when moved between monitors
+    fn update_integer_scale(&mut self, surface: &WlSurface) {
+        let Some(unit) = self
+            .units
+            .iter_mut()
+            .find(|unit| unit.wl_surface == *surface)
+        else {
+            return;
+        };
+        if unit.fractional_scale.is_some() || unit.buffer_scale.get().is_some() {
+            return;
+        }
+        let Some(factor) = unit
+            .entered_outputs
+            .iter()
+            .filter_map(|output| self.wl.output_scales.get(&output.id().protocol_id()))
+            .max()
+            .copied()
+        else {
+            return;
+        };
+        let scale = factor.max(1) as u32 * 120;
+        if unit.scale == scale {
+            return;
+        }
+        unit.scale = scale;
+        unit.wl_surface.set_buffer_scale(factor.max(1));
+        unit.request_refresh(RefreshRequest::NextFrame);
+        self.wl.message.push((
+            Some(unit.id),
+            DispatchMessageInner::PreferredScale {
+                scale_u32: scale,
+                scale_float: scale as f64 / 120.,
+            },
+        ));
+    }
+
     pub fn request_refresh_all(&mut self, request: RefreshRequest) {
         self.units
             .iter_mut()
@@ -3479,10 +5992,26 @@ impl<T> WindowState<T> {
         }
     }
 
+    /// redraw the unit as soon as possible. If it can take a present slot, the event loop is
+    /// woken at once, so the surface is presented right after the current handler returns,
+    /// instead of waiting for the next tick of the timer. Otherwise it is scheduled like
+    /// [RefreshRequest::NextFrame], and presented when the slot is free
+    pub fn redraw(&mut self, id: id::Id) {
+        let Some(unit) = self.get_mut_unit_with_id(id) else {
+            return;
+        };
+        unit.request_refresh(RefreshRequest::NextFrame);
+        if unit.present_slot_available()
+            && let Some(sender) = &self.wl.ping_sender
+        {
+            sender.ping();
+        }
+    }
+
     /// Flush pending requests to the Wayland compositor.
     /// This ensures that all pending protocol requests are sent immediately.
     pub fn flush(&self) {
-        if let Some(ref conn) = self.connection {
+        if let Some(ref conn) = self.wl.connection {
             let _ = conn.flush();
         }
     }
@@ -3514,26 +6043,100 @@ impl<T: 'static> Dispatch<wl_registry::WlRegistry, ()> for WindowState<T> {
                 version,
             } if interface == wl_output::WlOutput::interface().name => {
                 let output = proxy.bind::<wl_output::WlOutput, _, _>(name, version, qh, ());
-                state.outputs.push((name, output.clone()));
+                state.wl.outputs.push((name, output.clone()));
+                state
+                    .wl
+                    .output_changes
+                    .push(OutputChange::Added(output.clone()));
                 state
+                    .wl
                     .message
                     .push((None, DispatchMessageInner::NewDisplay(output)));
             }
             wl_registry::Event::GlobalRemove { name } => {
+                let removed_output = state
+                    .wl
+                    .outputs
+                    .iter()
+                    .find(|x| x.0 == name)
+                    .map(|(_, output)| output.clone());
+                let removed_name = removed_output
+                    .as_ref()
+                    .and_then(|output| state.output_name(output));
+                if let Some(output) = removed_output.clone() {
+                    state.wl.output_changes.push(OutputChange::Removed {
+                        output,
+                        name: removed_name.clone(),
+                    });
+                }
                 if state
+                    .wl
                     .last_wloutput
                     .as_ref()
                     .is_some_and(|output| !output.is_alive())
                 {
-                    state.last_wloutput.take();
+                    state.wl.last_wloutput.take();
                 }
-                state.outputs.retain(|x| x.0 != name);
+                state.wl.outputs.retain(|x| x.0 != name);
+                state
+                    .wl
+                    .filtered_outputs
+                    .retain(|output| removed_output.as_ref() != Some(output));
+                let live_outputs: HashSet<u32> = state
+                    .wl
+                    .outputs
+                    .iter()
+                    .map(|(_, output)| output.id().protocol_id())
+                    .collect();
+                state
+                    .wl
+                    .wl_output_names
+                    .retain(|id, _| live_outputs.contains(id));
+                state
+                    .wl
+                    .output_make_models
+                    .retain(|id, _| live_outputs.contains(id));
+                state
+                    .wl
+                    .output_refresh_rates
+                    .retain(|id, _| live_outputs.contains(id));
+                state
+                    .wl
+                    .output_scales
+                    .retain(|id, _| live_outputs.contains(id));
                 let removed_states = state
                     .units
                     .extract_if(.., |unit| !unit.wl_surface.is_alive());
                 for deleled in removed_states.into_iter() {
+                    state.home_visibility_modes.remove(&deleled.id);
                     state.closed_ids.push(deleled.id);
                 }
+                // the surfaces which outlive their output are left to the app
+                let Some(output) = removed_output else {
+                    return;
+                };
+                for unit in state.units.iter_mut() {
+                    if unit.entered_outputs.contains(&output) {
+                        unit.entered_outputs.retain(|entered| *entered != output);
+                        state.wl.message.push((
+                            Some(unit.id),
+                            DispatchMessageInner::SurfaceLeaveOutput {
+                                output: output.clone(),
+                                name: removed_name.clone(),
+                            },
+                        ));
+                    }
+                    if unit.wl_output.as_ref() != Some(&output) {
+                        continue;
+                    }
+                    unit.wl_output = None;
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::OutputLost {
+                            name: removed_name.clone(),
+                        },
+                    ));
+                }
             }
 
             _ => {}
@@ -3551,58 +6154,148 @@ impl<T: 'static> Dispatch<wl_seat::WlSeat, ()> for WindowState<T> {
         qh: &wayland_client::QueueHandle<Self>,
     ) {
         use xkb_keyboard::KeyboardState;
+        if let wl_seat::Event::Name { name } = event {
+            log::debug!("seat name: {name}");
+            state.wl.seat_name = Some(name);
+            return;
+        }
         if let wl_seat::Event::Capabilities {
             capabilities: WEnum::Value(capabilities),
         } = event
         {
             let mut keyboard_installing = true;
             if capabilities.contains(wl_seat::Capability::Keyboard) {
-                if state.keyboard_state.is_none() {
-                    state.keyboard_state = Some(KeyboardState::new(seat.get_keyboard(qh, ())));
+                if state.wl.keyboard_state.is_none() {
+                    state.wl.keyboard_state = Some(KeyboardState::new(seat.get_keyboard(qh, ())));
                 } else {
                     keyboard_installing = false;
-                    let keyboard = state.keyboard_state.take().unwrap();
-                    state.keyboard_state = Some(keyboard.update(seat, qh, ()));
-                    if let Some(surface_id) = state.current_surface_id() {
-                        state
-                            .message
-                            .push((Some(surface_id), DispatchMessageInner::Unfocus));
-                    }
+                    let keyboard = state.wl.keyboard_state.take().unwrap();
+                    state.wl.keyboard_state = Some(keyboard.update(seat, qh, ()));
+                    let focus_messages = state.wl.focus.focus(None);
+                    state.wl.message.extend(focus_messages);
                 }
             }
             if capabilities.contains(wl_seat::Capability::Pointer) {
-                if state.pointer.is_none() {
-                    state.pointer = Some(seat.get_pointer(qh, ()));
+                if state.wl.pointer.is_none() {
+                    state.wl.pointer = Some(seat.get_pointer(qh, ()));
                 } else {
-                    let pointer = state.pointer.take().unwrap();
+                    let pointer = state.wl.pointer.take().unwrap();
+                    state.destroy_cursor_shape_device();
                     if pointer.version() >= 3 {
                         pointer.release();
                     }
                 }
             }
             if capabilities.contains(wl_seat::Capability::Touch) {
-                if state.touch.is_none() {
-                    state.touch = Some(seat.get_touch(qh, ()));
+                if state.wl.touch.is_none() {
+                    state.wl.touch = Some(seat.get_touch(qh, ()));
                 } else {
-                    let touch = state.touch.take().unwrap();
+                    let touch = state.wl.touch.take().unwrap();
                     if touch.version() >= 3 {
                         touch.release();
                     }
                 }
             }
+            state.wl.text_input_wanted = keyboard_installing;
             if keyboard_installing {
-                let text_input = state
-                    .text_input_manager
-                    .as_ref()
-                    .map(|manager| manager.get_text_input(seat, qh, TextInputData::default()));
-                state.text_input = text_input;
-            } else if let Some(text_input) = state.text_input.take() {
+                state.ensure_text_input(qh);
+            } else if let Some(text_input) = state.wl.text_input.take() {
                 text_input.destroy();
             }
         }
     }
 }
 
+/// the origin of a layer surface on one axis of the output, from the anchors on the start and
+/// the end of the axis. The margins only apply on the anchored edges, a surface anchored on
+/// both is centered in the space left between the margins
+fn layer_axis_origin(
+    start: bool,
+    end: bool,
+    start_margin: i32,
+    end_margin: i32,
+    size: i32,
+    output: i32,
+) -> i32 {
+    match (start, end) {
+        (true, false) => start_margin,
+        (false, true) => output - size - end_margin,
+        (true, true) => start_margin + (output - start_margin - end_margin - size) / 2,
+        (false, false) => (output - size) / 2,
+    }
+}
+
+/// if `msg` may be sent to a surface with the keyboard `interactivity`. A surface with
+/// `None` never gets the keyboard focus, it gets no key, focus, modifiers or IME messages
+fn keyboard_message_allowed(
+    interactivity: zwlr_layer_surface_v1::KeyboardInteractivity,
+    msg: &DispatchMessage,
+) -> bool {
+    interactivity != zwlr_layer_surface_v1::KeyboardInteractivity::None
+        || !matches!(
+            msg,
+            DispatchMessage::KeyboardInput { .. }
+                | DispatchMessage::ModifiersChanged(_)
+                | DispatchMessage::Focused(_)
+                | DispatchMessage::Unfocus
+                | DispatchMessage::Ime(_)
+        )
+}
+
+/// drop every MouseMotion message directly followed by another one on the same surface, so
+/// only the latest position of a run of motions is left
+fn coalesce_motion(messages: &mut Vec<(Option<id::Id>, DispatchMessageInner)>) {
+    let is_motion = |message: &(Option<id::Id>, DispatchMessageInner)| {
+        matches!(message.1, DispatchMessageInner::MouseMotion { .. })
+    };
+    let mut coalesced = Vec::with_capacity(messages.len());
+    let mut messages_iter = std::mem::take(messages).into_iter().peekable();
+    while let Some(message) = messages_iter.next() {
+        let superseded = is_motion(&message)
+            && messages_iter
+                .peek()
+                .is_some_and(|next| is_motion(next) && next.0 == message.0);
+        if !superseded {
+            coalesced.push(message);
+        }
+    }
+    *messages = coalesced;
+}
+
+/// find the key to repeat from the `keys` array of `wl_keyboard.enter`, which holds the
+/// evdev keycodes of the pressed keys. The last repeatable one is taken as the most recently
+/// pressed. The returned keycode is an xkb keycode, like the one of `wl_keyboard.key` + 8
+fn held_repeat_key(keys: &[u8], mut key_repeats: impl FnMut(u32) -> bool) -> Option<u32> {
+    keys.chunks_exact(4)
+        .map(|key| u32::from_ne_bytes([key[0], key[1], key[2], key[3]]) + 8)
+        .rfind(|key| key_repeats(*key))
+}
+
+/// the repeat to arm when the keyboard enters a surface with the `keys` of
+/// `wl_keyboard.enter` held: the last repeatable one, after the repeat delay. None when the
+/// repeat is disabled or no held key repeats
+fn enter_repeat(
+    xkb_context: &mut xkb_keyboard::Context,
+    repeat_info: RepeatInfo,
+    keys: &[u8],
+    surface_id: Option<id::Id>,
+) -> Option<KeyboardTokenState> {
+    let RepeatInfo::Repeat { delay, .. } = repeat_info else {
+        return None;
+    };
+    let key = held_repeat_key(keys, |key| {
+        xkb_context
+            .keymap_mut()
+            .is_some_and(|keymap| keymap.key_repeats(key))
+    })?;
+    Some(KeyboardTokenState {
+        delay,
+        key,
+        surface_id,
+        pressed_state: ElementState::Pressed,
+    })
+}
+
 impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
     fn event(
         state: &mut Self,
@@ -3612,7 +6305,7 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        if state.keyboard_state.is_none() {
+        if state.wl.keyboard_state.is_none() {
             return;
         }
 
@@ -3623,21 +6316,52 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
         match event {
             wl_keyboard::Event::Keymap { format, fd, size } => match format {
                 WEnum::Value(KeymapFormat::XkbV1) => {
-                    let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                    let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
                     let context = &mut keyboard_state.xkb_context;
-                    context.set_keymap_from_fd(fd, size as usize)
+                    context.set_keymap_from_fd(fd, size as usize);
+                    let layout = context.active_layout_name();
+                    // the held key may not repeat with the new keymap
+                    keyboard_state.current_repeat = None;
+                    if let Some(token) = keyboard_state.repeat_token.take() {
+                        state.wl.to_remove_tokens.push(token);
+                    }
+                    state.wl.message.push((
+                        state.current_surface_id(),
+                        DispatchMessageInner::KeymapChanged { layout },
+                    ));
                 }
                 WEnum::Value(KeymapFormat::NoKeymap) => {
                     log::warn!("non-xkb compatible keymap")
                 }
                 _ => unreachable!(),
             },
-            wl_keyboard::Event::Enter { surface, .. } => {
+            wl_keyboard::Event::Enter {
+                surface,
+                keys,
+                serial,
+            } => {
+                state.wl.last_keyboard_serial = Some(serial);
                 log::info!("wl_keyboard::Enter event - keyboard focus entered surface");
+                if let Some(unit) = state.units.iter().find(|unit| unit.wl_surface == surface) {
+                    unit.keyboard_entered.set(true);
+                    unit.keyboard_entered_now.set(true);
+                }
                 state.update_current_surface(Some(surface));
-                let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                let surface_id = state.current_surface_id();
+                let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
+                keyboard_state.current_repeat = None;
                 if let Some(token) = keyboard_state.repeat_token.take() {
-                    state.to_remove_tokens.push(token);
+                    state.wl.to_remove_tokens.push(token);
+                }
+                // a key still held while the focus comes back keeps repeating
+                if let Some(repeat) = enter_repeat(
+                    &mut keyboard_state.xkb_context,
+                    keyboard_state.repeat_info,
+                    &keys,
+                    surface_id,
+                ) {
+                    keyboard_state.current_repeat = Some(repeat.key);
+                    state.wl.repeat_delay = Some(repeat);
                 }
             }
             wl_keyboard::Event::Leave { surface, .. } => {
@@ -3645,6 +6369,19 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                 // During popup switching, current_surface may already point to a newly
                 // created surface, causing the Unfocus to be misattributed.
                 let leave_id = state.get_id_from_surface(&surface);
+                if let Some(unit) = state.units.iter().find(|unit| unit.wl_surface == surface) {
+                    unit.keyboard_entered_now.set(false);
+                    // the protocol says nothing about the grab, losing the keyboard while
+                    // Exclusive is the only sign the compositor does not keep it
+                    if unit.keyboard_interactivity.get()
+                        == zwlr_layer_surface_v1::KeyboardInteractivity::Exclusive
+                    {
+                        log::warn!(
+                            "the keyboard left {:?}, which has Exclusive keyboard interactivity",
+                            unit.id
+                        );
+                    }
+                }
                 log::info!(
                     "wl_keyboard::Leave event - emitting Unfocus for surface {:?} (current_surface={:?})",
                     leave_id,
@@ -3660,28 +6397,32 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                 // never reappears). Only clear when it matches the leaving
                 // surface: during popup switching `current_surface` may already
                 // have advanced to a newly created surface, which must be kept.
-                if state.current_surface.as_ref() == Some(&surface) {
-                    state.current_surface = None;
+                if state.wl.current_surface.as_ref() == Some(&surface) {
+                    state.wl.current_surface = None;
                 }
-                let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
                 keyboard_state.current_repeat = None;
-                state.message.push((
+                state.wl.message.push((
                     leave_id,
                     DispatchMessageInner::ModifiersChanged(ModifiersState::empty()),
                 ));
-                state
-                    .message
-                    .push((leave_id, DispatchMessageInner::Unfocus));
+                let focus_messages = state.wl.focus.leave(leave_id);
+                state.wl.message.extend(focus_messages);
 
                 if let Some(token) = keyboard_state.repeat_token.take() {
-                    state.to_remove_tokens.push(token);
+                    state.wl.to_remove_tokens.push(token);
                 }
             }
             wl_keyboard::Event::Key {
                 state: keystate,
                 key,
+                serial,
                 ..
             } => {
+                state.wl.last_keyboard_serial = Some(serial);
+                if keystate == WEnum::Value(KeyState::Pressed) {
+                    state.wl.last_input_serial = Some(serial);
+                }
                 let pressed_state = match keystate {
                     WEnum::Value(KeyState::Pressed) => ElementState::Pressed,
                     WEnum::Value(KeyState::Released) => ElementState::Released,
@@ -3689,7 +6430,7 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                         return;
                     }
                 };
-                let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
                 let key = key + 8;
                 if let Some(mut key_context) = keyboard_state.xkb_context.key_context() {
                     let event = key_context.process_key_event(key, pressed_state, false);
@@ -3697,7 +6438,7 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                         event,
                         is_synthetic: false,
                     };
-                    state.message.push((surface_id, event));
+                    state.wl.message.push((surface_id, event));
                 }
 
                 match pressed_state {
@@ -3718,9 +6459,9 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                         keyboard_state.current_repeat = Some(key);
 
                         if let Some(token) = keyboard_state.repeat_token.take() {
-                            state.to_remove_tokens.push(token);
+                            state.wl.to_remove_tokens.push(token);
                         }
-                        state.repeat_delay = Some(KeyboardTokenState {
+                        state.wl.repeat_delay = Some(KeyboardTokenState {
                             delay,
                             key,
                             surface_id,
@@ -3738,7 +6479,7 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                             keyboard_state.current_repeat = None;
 
                             if let Some(token) = keyboard_state.repeat_token.take() {
-                                state.to_remove_tokens.push(token);
+                                state.wl.to_remove_tokens.push(token);
                             }
                         }
                     }
@@ -3751,28 +6492,36 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                 group,
                 ..
             } => {
-                let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
                 let xkb_context = &mut keyboard_state.xkb_context;
                 let xkb_state = match xkb_context.state_mut() {
                     Some(state) => state,
                     None => return,
                 };
+                let layout = xkb_state.active_layout();
                 xkb_state.update_modifiers(mods_depressed, mods_latched, mods_locked, 0, 0, group);
                 let modifiers = xkb_state.modifiers();
+                if xkb_state.active_layout() != layout {
+                    let layout = xkb_context.active_layout_name();
+                    state.wl.message.push((
+                        state.current_surface_id(),
+                        DispatchMessageInner::KeymapChanged { layout },
+                    ));
+                }
 
-                state.message.push((
+                state.wl.message.push((
                     state.current_surface_id(),
                     DispatchMessageInner::ModifiersChanged(modifiers.into()),
                 ))
             }
             wl_keyboard::Event::RepeatInfo { rate, delay } => {
-                let keyboard_state = state.keyboard_state.as_mut().unwrap();
+                let keyboard_state = state.wl.keyboard_state.as_mut().unwrap();
                 keyboard_state.repeat_info = if rate == 0 {
                     // Stop the repeat once we get a disable event.
                     keyboard_state.current_repeat = None;
 
                     if let Some(token) = keyboard_state.repeat_token.take() {
-                        state.to_remove_tokens.push(token);
+                        state.wl.to_remove_tokens.push(token);
                     }
                     RepeatInfo::Disable
                 } else {
@@ -3780,12 +6529,20 @@ impl<T> Dispatch<wl_keyboard::WlKeyboard, ()> for WindowState<T> {
                     let delay = Duration::from_millis(delay as u64);
                     RepeatInfo::Repeat { gap, delay }
                 };
+                state.wl.keyboard_repeat_info = Some(keyboard_state.repeat_info);
+                state.wl.message.push((
+                    state.current_surface_id(),
+                    DispatchMessageInner::RepeatInfoChanged { rate, delay },
+                ));
             }
             _ => {}
         }
     }
 }
 
+/// the linux input code of the left mouse button, sent by [WindowState::with_touch_emulates_pointer]
+const BTN_LEFT: u32 = 0x110;
+
 impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
     fn event(
         state: &mut Self,
@@ -3804,13 +6561,17 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                 x,
                 y,
             } => {
-                state.finger_locations.insert(id, (x, y));
+                state.wl.last_input_serial = Some(serial);
+                state.wl.last_touch_serial = Some(serial);
+                state.wl.last_touch_time = time;
+                state.wl.finger_locations.insert(id, (x, y));
                 let surface_id = state.get_id_from_surface(&surface);
                 state
+                    .wl
                     .active_surfaces
                     .insert(Some(id), (surface.clone(), surface_id));
                 state.update_current_surface(Some(surface));
-                state.message.push((
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::TouchDown {
                         serial,
@@ -3819,14 +6580,51 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                         x,
                         y,
                     },
-                ))
+                ));
+                let first_finger = state.wl.finger_locations.len() == 1;
+                if state.touch_emulates_pointer
+                    && first_finger
+                    && state.wl.emulated_pointer_finger.is_none()
+                {
+                    let pointer = state.wl.pointer.clone();
+                    state.wl.emulated_pointer_finger = Some((id, surface_id, pointer.is_some()));
+                    if let Some(pointer) = pointer {
+                        state.wl.message.push((
+                            surface_id,
+                            DispatchMessageInner::MouseEnter {
+                                pointer,
+                                serial,
+                                surface_x: x,
+                                surface_y: y,
+                            },
+                        ));
+                    }
+                    state.wl.message.push((
+                        surface_id,
+                        DispatchMessageInner::MouseMotion {
+                            time,
+                            surface_x: x,
+                            surface_y: y,
+                        },
+                    ));
+                    state.wl.message.push((
+                        surface_id,
+                        DispatchMessageInner::MouseButton {
+                            state: WEnum::Value(wl_pointer::ButtonState::Pressed),
+                            serial,
+                            button: BTN_LEFT,
+                            time,
+                        },
+                    ));
+                }
             }
             wl_touch::Event::Cancel => {
                 let mut mouse_surface = None;
-                for (k, v) in state.active_surfaces.drain() {
+                for (k, v) in state.wl.active_surfaces.drain() {
                     if let Some(id) = k {
-                        let (x, y) = state.finger_locations.remove(&id).unwrap_or_default();
+                        let (x, y) = state.wl.finger_locations.remove(&id).unwrap_or_default();
                         state
+                            .wl
                             .message
                             .push((v.1, DispatchMessageInner::TouchCancel { id, x, y }));
                     } else {
@@ -3835,11 +6633,33 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                     }
                 }
                 if let Some(mouse_surface) = mouse_surface {
-                    state.active_surfaces.insert(None, mouse_surface);
+                    state.wl.active_surfaces.insert(None, mouse_surface);
+                }
+                if let Some((_, surface_id, entered)) = state.wl.emulated_pointer_finger.take() {
+                    // the compositor took the gesture over, leave before the release so the
+                    // press does not end as a click
+                    if entered {
+                        state
+                            .wl
+                            .message
+                            .push((surface_id, DispatchMessageInner::MouseLeave));
+                    }
+                    state.wl.message.push((
+                        surface_id,
+                        DispatchMessageInner::MouseButton {
+                            state: WEnum::Value(wl_pointer::ButtonState::Released),
+                            serial: state.wl.last_touch_serial.unwrap_or_default(),
+                            button: BTN_LEFT,
+                            time: state.wl.last_touch_time,
+                        },
+                    ));
                 }
             }
             wl_touch::Event::Up { serial, time, id } => {
+                state.wl.last_touch_serial = Some(serial);
+                state.wl.last_touch_time = time;
                 let surface_id = state
+                    .wl
                     .active_surfaces
                     .remove(&Some(id))
                     .or_else(|| {
@@ -3847,8 +6667,8 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                         None
                     })
                     .and_then(|(_, id)| id);
-                let (x, y) = state.finger_locations.remove(&id).unwrap_or_default();
-                state.message.push((
+                let (x, y) = state.wl.finger_locations.remove(&id).unwrap_or_default();
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::TouchUp {
                         serial,
@@ -3858,9 +6678,33 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                         y,
                     },
                 ));
+                if let Some((_, _, entered)) = state
+                    .wl
+                    .emulated_pointer_finger
+                    .filter(|(finger, _, _)| *finger == id)
+                {
+                    state.wl.emulated_pointer_finger = None;
+                    state.wl.message.push((
+                        surface_id,
+                        DispatchMessageInner::MouseButton {
+                            state: WEnum::Value(wl_pointer::ButtonState::Released),
+                            serial,
+                            button: BTN_LEFT,
+                            time,
+                        },
+                    ));
+                    if entered {
+                        state
+                            .wl
+                            .message
+                            .push((surface_id, DispatchMessageInner::MouseLeave));
+                    }
+                }
             }
             wl_touch::Event::Motion { time, id, x, y } => {
+                state.wl.last_touch_time = time;
                 let surface_id = state
+                    .wl
                     .active_surfaces
                     .get(&Some(id))
                     .or_else(|| {
@@ -3868,11 +6712,25 @@ impl<T> Dispatch<wl_touch::WlTouch, ()> for WindowState<T> {
                         None
                     })
                     .and_then(|(_, id)| *id);
-                state.finger_locations.insert(id, (x, y));
-                state.message.push((
+                state.wl.finger_locations.insert(id, (x, y));
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::TouchMotion { time, id, x, y },
                 ));
+                if state
+                    .wl
+                    .emulated_pointer_finger
+                    .is_some_and(|(finger, _, _)| finger == id)
+                {
+                    state.wl.message.push((
+                        surface_id,
+                        DispatchMessageInner::MouseMotion {
+                            time,
+                            surface_x: x,
+                            surface_y: y,
+                        },
+                    ));
+                }
             }
             _ => {}
         }
@@ -3890,6 +6748,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
     ) {
         // All mouse events should be happened on the surface which is hovered by the mouse.
         let (mouse_surface, surface_id) = state
+            .wl
             .active_surfaces
             .get(&None)
             .map(|(surface, id)| (Some(surface), *id))
@@ -3902,6 +6761,17 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                 }
                 (None, None)
             });
+        // the pointer is on a surface which is not a unit, like the background surface. There
+        // is no scale to convert its coordinates with, so its events are dropped
+        if mouse_surface.is_some()
+            && surface_id.is_none()
+            && !matches!(
+                event,
+                wl_pointer::Event::Enter { .. } | wl_pointer::Event::Leave { .. }
+            )
+        {
+            return;
+        }
         let scale = surface_id
             .and_then(|id| state.get_unit_with_id(id))
             .map(|unit| unit.scale_float())
@@ -3920,7 +6790,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                         _ => unreachable!(),
                     };
 
-                    state.message.push((
+                    state.wl.message.push((
                         surface_id,
                         DispatchMessageInner::Axis {
                             time,
@@ -3945,7 +6815,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                         _ => unreachable!(),
                     }
 
-                    state.message.push((
+                    state.wl.message.push((
                         surface_id,
                         DispatchMessageInner::Axis {
                             time,
@@ -3962,7 +6832,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                 }
             },
             wl_pointer::Event::AxisSource { axis_source } => match axis_source {
-                WEnum::Value(source) => state.message.push((
+                WEnum::Value(source) => state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::Axis {
                         horizontal: AxisScroll::default(),
@@ -3991,7 +6861,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                         _ => unreachable!(),
                     };
 
-                    state.message.push((
+                    state.wl.message.push((
                         surface_id,
                         DispatchMessageInner::Axis {
                             time: 0,
@@ -4015,10 +6885,12 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
             } => {
                 // Remember the press serial — `wl_data_device.start_drag` needs the
                 // serial of the input event that began the drag.
-                state.last_button_serial = Some(serial);
+                state.wl.last_button_serial = Some(serial);
+                state.wl.last_input_serial = Some(serial);
+                state.wl.last_pointer_serial = Some(serial);
                 let mouse_surface = mouse_surface.cloned();
                 state.update_current_surface(mouse_surface);
-                state.message.push((
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::MouseButton {
                         state: btnstate,
@@ -4030,6 +6902,7 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
             }
             wl_pointer::Event::Leave { .. } => {
                 let surface_id = state
+                    .wl
                     .active_surfaces
                     .remove(&None)
                     .or_else(|| {
@@ -4037,9 +6910,13 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                         None
                     })
                     .and_then(|(_, id)| id);
-                state
-                    .message
-                    .push((surface_id, DispatchMessageInner::MouseLeave));
+                state.wl.pointer_position = None;
+                if surface_id.is_some() {
+                    state
+                        .wl
+                        .message
+                        .push((surface_id, DispatchMessageInner::MouseLeave));
+                }
             }
             wl_pointer::Event::Enter {
                 serial,
@@ -4049,10 +6926,20 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
             } => {
                 let surface_id = state.get_id_from_surface(&surface);
                 state
+                    .wl
                     .active_surfaces
                     .insert(None, (surface.clone(), surface_id));
-                state.enter_serial = Some(serial);
-                state.message.push((
+                state.wl.enter_serial = Some(serial);
+                state.wl.last_pointer_serial = Some(serial);
+                state.wl.pointer_position = surface_id.map(|id| (id, surface_x, surface_y));
+                if surface_id.is_none() {
+                    log::debug!(
+                        "pointer entered {}, which is not a unit, its events are skipped",
+                        surface.id()
+                    );
+                    return;
+                }
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::MouseEnter {
                         pointer: pointer.clone(),
@@ -4067,7 +6954,16 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
                 surface_x,
                 surface_y,
             } => {
-                state.message.push((
+                state.wl.pointer_position = surface_id.map(|id| (id, surface_x, surface_y));
+                if let Some(unit) = state
+                    .units
+                    .iter_mut()
+                    .find(|unit| Some(unit.id) == surface_id)
+                    && let Some((after, deadline)) = &mut unit.auto_dismiss
+                {
+                    *deadline = Instant::now() + *after;
+                }
+                state.wl.message.push((
                     surface_id,
                     DispatchMessageInner::MouseMotion {
                         time,
@@ -4083,6 +6979,38 @@ impl<T> Dispatch<wl_pointer::WlPointer, ()> for WindowState<T> {
     }
 }
 
+/// Read the data of `mime_type` off a `wl_data_offer` through a socketpair. A
+/// cooperating source writes immediately; the short timeout only guards a
+/// misbehaving one. Returns `None` when the source does not close its end in
+/// time or the read fails, rather than a cut payload.
+fn read_data_offer(conn: &Connection, offer: &WlDataOffer, mime_type: &str) -> Option<Vec<u8>> {
+    use std::io::Read;
+    let mut reader = receive_data_offer(conn, offer, mime_type)?;
+    reader
+        .set_read_timeout(Some(std::time::Duration::from_millis(250)))
+        .ok()?;
+    let mut buf = Vec::new();
+    if let Err(err) = reader.read_to_end(&mut buf) {
+        log::warn!("failed to read the {mime_type} data offer: {err}");
+        return None;
+    }
+    Some(buf)
+}
+
+/// Ask the source of a `wl_data_offer` to write the data of `mime_type` into a
+/// socketpair, and return the end to read it from, until the source closes it.
+fn receive_data_offer(
+    conn: &Connection,
+    offer: &WlDataOffer,
+    mime_type: &str,
+) -> Option<std::os::unix::net::UnixStream> {
+    use std::os::fd::AsFd;
+    let (reader, writer) = std::os::unix::net::UnixStream::pair().ok()?;
+    offer.receive(mime_type.to_string(), writer.as_fd());
+    conn.flush().ok()?;
+    Some(reader)
+}
+
 /// Parse a `text/uri-list` payload (raw bytes) into local filesystem paths: one
 /// `file:` URI per line, ignoring blank lines and `#` comments, percent-decoding
 /// the path. Non-`file:` URIs (e.g. `http://`) are skipped — we can only attach
@@ -4196,13 +7124,12 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
         conn: &Connection,
         _qh: &wayland_client::QueueHandle<Self>,
     ) {
-        use std::os::fd::AsFd;
         match event {
             // A new offer is introduced; its MIME types arrive next (on the
             // offer object) before the matching enter/selection event.
             wl_data_device::Event::DataOffer { id } => {
                 log::info!(target: "kcopy_dnd", "DataOffer (new offer)");
-                state.dnd_offer_mimes.insert(id.id(), Vec::new());
+                state.wl.dnd_offer_mimes.insert(id.id(), Vec::new());
             }
             // A drag entered one of our surfaces. Accept the file-list MIME (and
             // negotiate a copy action on v3+) so the compositor shows a "droppable"
@@ -4214,13 +7141,14 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
                 y,
                 id,
             } => {
-                if let Some(prev) = state.dnd_current.take() {
-                    state.dnd_offer_mimes.remove(&prev.offer.id());
+                if let Some(prev) = state.wl.dnd_current.take() {
+                    state.wl.dnd_offer_mimes.remove(&prev.offer.id());
                     prev.offer.destroy();
                 }
                 if let Some(offer) = id {
                     let surface_id = state.get_id_from_surface(&surface);
                     let mimes = state
+                        .wl
                         .dnd_offer_mimes
                         .get(&offer.id())
                         .cloned()
@@ -4237,11 +7165,11 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
                     } else {
                         mimes.first().cloned()
                     };
-                    offer.accept(serial, accept_mime);
+                    offer.accept(serial, accept_mime.clone());
                     if offer.version() >= 3 {
                         offer.set_actions(DndAction::Copy | DndAction::Move, DndAction::Move);
                     }
-                    state.message.push((
+                    state.wl.message.push((
                         surface_id,
                         DispatchMessageInner::DndEntered {
                             x,
@@ -4249,18 +7177,20 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
                             mime_types: mimes,
                         },
                     ));
-                    state.dnd_current = Some(DndCurrent {
+                    state.wl.dnd_current = Some(DndCurrent {
                         offer,
                         surface_id,
                         has_uri_list,
+                        accepted_mime: accept_mime,
                     });
                 }
             }
             // The drag moved over the surface — forward the surface-local position
             // so a drop target can track it (the Drop event itself carries none).
             wl_data_device::Event::Motion { x, y, .. } => {
-                if let Some(dnd) = &state.dnd_current {
+                if let Some(dnd) = &state.wl.dnd_current {
                     state
+                        .wl
                         .message
                         .push((dnd.surface_id, DispatchMessageInner::DndMotion { x, y }));
                 }
@@ -4269,10 +7199,11 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
             // highlight.
             wl_data_device::Event::Leave => {
                 log::info!(target: "kcopy_dnd", "Leave");
-                if let Some(dnd) = state.dnd_current.take() {
-                    state.dnd_offer_mimes.remove(&dnd.offer.id());
+                if let Some(dnd) = state.wl.dnd_current.take() {
+                    state.wl.dnd_offer_mimes.remove(&dnd.offer.id());
                     dnd.offer.destroy();
                     state
+                        .wl
                         .message
                         .push((dnd.surface_id, DispatchMessageInner::DndLeft));
                 }
@@ -4280,62 +7211,74 @@ impl<T: 'static> Dispatch<WlDataDevice, ()> for WindowState<T> {
             // The drop happened: pull the URI list off the offer and emit one
             // FileDropped per file.
             wl_data_device::Event::Drop => {
-                log::info!(target: "kcopy_dnd", "Drop (current={})", state.dnd_current.is_some());
-                if let Some(dnd) = state.dnd_current.take() {
+                log::info!(target: "kcopy_dnd", "Drop (current={})", state.wl.dnd_current.is_some());
+                if let Some(dnd) = state.wl.dnd_current.take() {
                     let surface_id = dnd.surface_id;
                     // Notify the drop happened (position-flow consumers track the
                     // last DndMotion); the uri-list auto-read below stays for
                     // back-compat FileDropped consumers.
                     state
+                        .wl
                         .message
                         .push((surface_id, DispatchMessageInner::DndDrop));
                     // Self-drop: don't block-read our own uri-list (our source's
                     // Send runs on this thread). finish() completes it; the drop
                     // position comes from DndDrop + the last DndMotion.
-                    let is_self_drop = state.dnd_source_origin.is_some();
+                    let is_self_drop = state.wl.dnd_source_origin.is_some();
                     if dnd.has_uri_list && is_self_drop {
                         if dnd.offer.version() >= 3 {
                             dnd.offer.finish();
                         }
                         state
+                            .wl
                             .message
                             .push((surface_id, DispatchMessageInner::DndLeft));
                     } else if dnd.has_uri_list {
-                        // External drag: read the uri-list off a socketpair and emit
-                        // FileDropped. A cooperating source writes immediately; the
-                        // short timeout only guards a misbehaving one.
-                        if let Ok((mut reader, writer)) = std::os::unix::net::UnixStream::pair() {
-                            dnd.offer.receive(URI_LIST_MIME.to_string(), writer.as_fd());
-                            let _ = conn.flush();
-                            drop(writer);
-                            let _ = reader
-                                .set_read_timeout(Some(std::time::Duration::from_millis(250)));
-                            use std::io::Read;
-                            let mut buf = Vec::new();
-                            let _ = reader.read_to_end(&mut buf);
+                        // External drag: read the uri-list and emit FileDropped.
+                        if let Some(buf) = read_data_offer(conn, &dnd.offer, URI_LIST_MIME) {
                             if dnd.offer.version() >= 3 {
                                 dnd.offer.finish();
                             }
                             for path in parse_uri_list(&buf) {
                                 state
+                                    .wl
                                     .message
                                     .push((surface_id, DispatchMessageInner::FileDropped(path)));
                             }
                         }
                         state
+                            .wl
+                            .message
+                            .push((surface_id, DispatchMessageInner::DndLeft));
+                    } else if !is_self_drop && let Some(mime_type) = dnd.accepted_mime.clone() {
+                        // Any other payload (text, custom types): hand the raw bytes of
+                        // the accepted MIME to the app.
+                        if let Some(data) = read_data_offer(conn, &dnd.offer, &mime_type) {
+                            if dnd.offer.version() >= 3 {
+                                dnd.offer.finish();
+                            }
+                            state.wl.message.push((
+                                surface_id,
+                                DispatchMessageInner::DndDataReceived { mime_type, data },
+                            ));
+                        }
+                        state
+                            .wl
                             .message
                             .push((surface_id, DispatchMessageInner::DndLeft));
                     }
-                    state.dnd_offer_mimes.remove(&dnd.offer.id());
+                    state.wl.dnd_offer_mimes.remove(&dnd.offer.id());
                     dnd.offer.destroy();
                 }
             }
-            // A clipboard selection offer — we don't read the clipboard through
-            // this device (iced uses its own), so release it to avoid leaking the
-            // offer object.
-            wl_data_device::Event::Selection { id: Some(offer) } => {
-                state.dnd_offer_mimes.remove(&offer.id());
-                offer.destroy();
+            // The clipboard selection changed: keep the new offer for
+            // `request_selection` and release the previous one.
+            wl_data_device::Event::Selection { id } => {
+                if let Some(old) = state.wl.selection_offer.take() {
+                    state.wl.dnd_offer_mimes.remove(&old.id());
+                    old.destroy();
+                }
+                state.wl.selection_offer = id;
             }
             _ => {}
         }
@@ -4359,11 +7302,33 @@ impl<T> Dispatch<WlDataOffer, ()> for WindowState<T> {
         _conn: &Connection,
         _qh: &wayland_client::QueueHandle<Self>,
     ) {
-        if let wl_data_offer::Event::Offer { mime_type } = event {
-            log::info!(target: "kcopy_dnd", "Offer mime: {mime_type}");
-            if let Some(mimes) = state.dnd_offer_mimes.get_mut(&offer.id()) {
-                mimes.push(mime_type);
+        match event {
+            wl_data_offer::Event::Offer { mime_type } => {
+                log::info!(target: "kcopy_dnd", "Offer mime: {mime_type}");
+                if let Some(mimes) = state.wl.dnd_offer_mimes.get_mut(&offer.id()) {
+                    mimes.push(mime_type);
+                }
+            }
+            // The compositor picked the action of the hovering drag (v3+).
+            wl_data_offer::Event::Action { dnd_action } => {
+                let Some(dnd) = state
+                    .wl
+                    .dnd_current
+                    .as_ref()
+                    .filter(|dnd| dnd.offer == *offer)
+                else {
+                    return;
+                };
+                let bits = match dnd_action {
+                    WEnum::Value(action) => action.bits(),
+                    WEnum::Unknown(bits) => bits,
+                };
+                state.wl.message.push((
+                    dnd.surface_id,
+                    DispatchMessageInner::DndSelectedAction(bits),
+                ));
             }
+            _ => {}
         }
     }
 }
@@ -4382,7 +7347,7 @@ impl<T: 'static> Dispatch<WlDataSource, DndSourceData> for WindowState<T> {
         _qh: &wayland_client::QueueHandle<Self>,
     ) {
         use std::os::fd::{AsRawFd, FromRawFd};
-        let origin = state.dnd_source_origin;
+        let origin = state.wl.dnd_source_origin;
         match event {
             // The destination asked for the data for `mime_type` — write our
             // pre-serialized blob to the fd. We must NOT close the fd here
@@ -4396,28 +7361,38 @@ impl<T: 'static> Dispatch<WlDataSource, DndSourceData> for WindowState<T> {
                     std::mem::forget(file);
                 }
             }
+            // Another client took the clipboard selection.
+            wl_data_source::Event::Cancelled
+                if state.wl.selection_source.as_ref() == Some(source) =>
+            {
+                state.wl.selection_source = None;
+                source.destroy();
+            }
             wl_data_source::Event::Cancelled => {
                 state
+                    .wl
                     .message
                     .push((origin, DispatchMessageInner::DndSourceCancelled));
                 source.destroy();
-                state.dnd_source_origin = None;
-                if let Some(icon) = state.dnd_icon.take() {
+                state.wl.dnd_source_origin = None;
+                if let Some(icon) = state.wl.dnd_icon.take() {
                     icon.destroy();
                 }
             }
             wl_data_source::Event::DndDropPerformed => {
                 state
+                    .wl
                     .message
                     .push((origin, DispatchMessageInner::DndSourceDropPerformed));
             }
             wl_data_source::Event::DndFinished => {
                 state
+                    .wl
                     .message
                     .push((origin, DispatchMessageInner::DndSourceFinished));
                 source.destroy();
-                state.dnd_source_origin = None;
-                if let Some(icon) = state.dnd_icon.take() {
+                state.wl.dnd_source_origin = None;
+                if let Some(icon) = state.wl.dnd_icon.take() {
                     icon.destroy();
                 }
             }
@@ -4427,6 +7402,7 @@ impl<T: 'static> Dispatch<WlDataSource, DndSourceData> for WindowState<T> {
                     _ => 0,
                 };
                 state
+                    .wl
                     .message
                     .push((origin, DispatchMessageInner::DndSourceAction(bits)));
             }
@@ -4446,16 +7422,27 @@ impl<T> Dispatch<xdg_surface::XdgSurface, ()> for WindowState<T> {
     ) {
         if let xdg_surface::Event::Configure { serial } = event {
             surface.ack_configure(serial);
-            state
-                .units
-                .iter_mut()
-                .filter(|unit| unit.shell == *surface)
-                .for_each(|unit| unit.request_refresh(RefreshRequest::NextFrame));
+            for unit in state.units.iter_mut().filter(|unit| unit.shell == *surface) {
+                trace_event(
+                    state.trace_epoch,
+                    Some(unit.id),
+                    format_args!("xdg_surface configure serial={serial} size={:?}", unit.size),
+                );
+                unit.request_refresh(RefreshRequest::NextFrame);
+                // the size is sent by the toplevel or popup configure before this event
+                if !unit.configured {
+                    unit.configured = true;
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::Configured { size: unit.size },
+                    ));
+                }
+            }
         }
     }
 }
 
-impl<T> Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WindowState<T> {
+impl<T: 'static> Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WindowState<T> {
     fn event(
         state: &mut Self,
         surface: &zwlr_layer_surface_v1::ZwlrLayerSurfaceV1,
@@ -4476,9 +7463,41 @@ impl<T> Dispatch<zwlr_layer_surface_v1::ZwlrLayerSurfaceV1, ()> for WindowState<
                 let Some(unit_index) = unit_index else {
                     return;
                 };
-                state.units[unit_index].size = (width, height);
-
-                state.units[unit_index].request_refresh(RefreshRequest::NextFrame);
+                let unit = &mut state.units[unit_index];
+                trace_event(
+                    state.trace_epoch,
+                    Some(unit.id),
+                    format_args!(
+                        "layer_surface configure serial={serial} size=({width}, {height})"
+                    ),
+                );
+                unit.configured_size = Some((width, height));
+                let resized = unit.configured && unit.size != (width, height);
+                // a held back resize is applied by settle_resizes once the size stops changing
+                if !unit.pending_resize.configure_size(
+                    unit.configured,
+                    unit.size,
+                    (width, height),
+                    state.configure_debounce,
+                    Instant::now(),
+                ) {
+                    return;
+                }
+                unit.apply_configured_size((width, height));
+                unit.request_refresh(RefreshRequest::NextFrame);
+                if !unit.configured {
+                    unit.configured = true;
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::Configured {
+                            size: (width, height),
+                        },
+                    ));
+                }
+                if resized {
+                    let parent = unit.id;
+                    state.reanchor_child_popups(parent);
+                }
             }
             zwlr_layer_surface_v1::Event::Closed => {
                 if let Some(i) = unit_index {
@@ -4506,7 +7525,7 @@ impl<T> Dispatch<xdg_toplevel::XdgToplevel, ()> for WindowState<T> {
                     return;
                 };
                 if width != 0 && height != 0 {
-                    state.units[unit_index].size = (width as u32, height as u32);
+                    state.units[unit_index].apply_configured_size((width as u32, height as u32));
                 }
 
                 state.units[unit_index].request_refresh(RefreshRequest::NextFrame);
@@ -4522,6 +7541,37 @@ impl<T> Dispatch<xdg_toplevel::XdgToplevel, ()> for WindowState<T> {
     }
 }
 
+impl<T> Dispatch<ZxdgToplevelDecorationV1, ()> for WindowState<T> {
+    fn event(
+        state: &mut Self,
+        decoration: &ZxdgToplevelDecorationV1,
+        event: <ZxdgToplevelDecorationV1 as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        let zxdg_toplevel_decoration_v1::Event::Configure {
+            mode: WEnum::Value(mode),
+        } = event
+        else {
+            return;
+        };
+        let Some(unit) = state
+            .units
+            .iter_mut()
+            .find(|unit| unit.shell == *decoration)
+        else {
+            return;
+        };
+        log::debug!("xdg decoration configure: {:?} {mode:?}", unit.id);
+        unit.decoration_mode = Some(mode);
+        state
+            .wl
+            .message
+            .push((Some(unit.id), DispatchMessageInner::DecorationMode(mode)));
+    }
+}
+
 impl<T> Dispatch<xdg_popup::XdgPopup, ()> for WindowState<T> {
     fn event(
         state: &mut Self,
@@ -4545,7 +7595,7 @@ impl<T> Dispatch<xdg_popup::XdgPopup, ()> for WindowState<T> {
                 log::debug!(
                     "xdg_popup configure: width={width}, height={height}, x={x}, y={y}, unit_index={unit_index}"
                 );
-                state.units[unit_index].size = (width as u32, height as u32);
+                state.units[unit_index].apply_configured_size((width as u32, height as u32));
 
                 // Set the viewport destination so the compositor knows the
                 // logical size of the popup surface.  Without this, a buffer
@@ -4560,6 +7610,24 @@ impl<T> Dispatch<xdg_popup::XdgPopup, ()> for WindowState<T> {
                 log::debug!("xdg_popup repositioned: token={token}");
                 // The compositor has repositioned the popup. The new position
                 // will take effect with the next configure event.
+                let Some(unit) = state.units.iter_mut().find(|unit| unit.shell == *surface) else {
+                    return;
+                };
+                if unit.reposition_token == Some(token) {
+                    unit.reposition_token = None;
+                }
+                state.wl.message.push((
+                    Some(unit.id),
+                    DispatchMessageInner::PopupRepositioned { token },
+                ));
+            }
+            xdg_popup::Event::PopupDone => {
+                // The compositor dismissed the popup, like a click outside of a grabbed
+                // menu. Close it the same way as the layer surface `Closed` event.
+                if let Some(unit) = state.units.iter_mut().find(|unit| unit.shell == *surface) {
+                    log::debug!("xdg_popup done: {:?}", unit.id);
+                    unit.request_close();
+                }
             }
             _ => {}
         }
@@ -4576,12 +7644,14 @@ impl<T> Dispatch<zxdg_output_v1::ZxdgOutputV1, ()> for WindowState<T> {
         _qhandle: &QueueHandle<Self>,
     ) {
         // Update a cached output entry if this proxy belongs to one. Both the
-        // one-shot layout gather and `StartMode::TargetScreen` populate
-        // `xdg_info_cache`; do this regardless of start mode / init state so the
-        // gather's dispatch actually records each output's size/position/name.
+        // one-shot layout gather and `StartMode::TargetScreen` /
+        // `TargetDescription` populate `xdg_info_cache`; do this regardless of
+        // start mode / init state so the gather's dispatch actually records each
+        // output's size/position/name.
         // (Post-gather the cache is cleared, so this falls through to the unit
         // path below for the live per-surface output.)
         if let Some((_, xdg_info)) = state
+            .wl
             .xdg_info_cache
             .iter_mut()
             .find(|(_, info)| info.zxdgoutput == *proxy)
@@ -4636,7 +7706,16 @@ impl<T> Dispatch<zxdg_output_v1::ZxdgOutputV1, ()> for WindowState<T> {
         let (logical_width, logical_height) = xdg_info.logical_size;
         let output_name = xdg_info.name.clone();
         let (output_x, output_y) = xdg_info.position;
-        state.message.push((
+        if matches!(change_type, XdgInfoChangedType::Size)
+            && info.follows_output_width
+            && logical_width > 0
+            && info.size.0 != logical_width as u32
+        {
+            // nothing else resizes an input panel, the refresh gets the new size
+            info.apply_configured_size((logical_width as u32, info.size.1));
+            info.request_refresh(RefreshRequest::NextFrame);
+        }
+        state.wl.message.push((
             Some(state.units[index].id),
             DispatchMessageInner::XdgInfoChanged {
                 change_type,
@@ -4683,7 +7762,7 @@ impl<T> Dispatch<wp_fractional_scale_v1::WpFractionalScaleV1, ()> for WindowStat
             );
             unit.scale = scale;
             unit.request_refresh(RefreshRequest::NextFrame);
-            state.message.push((
+            state.wl.message.push((
                 Some(unit.id),
                 DispatchMessageInner::PreferredScale {
                     scale_u32: scale,
@@ -4709,6 +7788,9 @@ pub struct TextInputDataInner {
 
     /// The preedit to submit on `done`.
     pending_preedit: Option<Preedit>,
+
+    /// If the cursor of the shown preedit is visible, None when no preedit is shown.
+    preedit_cursor_visible: Option<bool>,
 }
 /// The state of the preedit.
 struct Preedit {
@@ -4738,9 +7820,11 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
 
                 if state.ime_allowed() {
                     text_input.enable();
-                    text_input.set_content_type_by_purpose(state.ime_purpose());
+                    let (hint, purpose) = state.ime_content_type();
+                    text_input.set_content_type(hint, purpose);
                     text_input.commit();
                     state
+                        .wl
                         .message
                         .push((Some(id), DispatchMessageInner::Ime(events::Ime::Enabled)));
                 }
@@ -4748,6 +7832,7 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
             }
             Event::Leave { surface } => {
                 text_input_data.surface = None;
+                text_input_data.preedit_cursor_visible = None;
 
                 text_input.disable();
                 text_input.commit();
@@ -4756,6 +7841,7 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
                 };
                 state.text_input_left(text_input);
                 state
+                    .wl
                     .message
                     .push((Some(id), DispatchMessageInner::Ime(events::Ime::Disabled)));
             }
@@ -4776,7 +7862,8 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
                 if text_input_data.pending_commit.is_some()
                     || text_input_data.pending_preedit.is_none()
                 {
-                    state.message.push((
+                    text_input_data.preedit_cursor_visible = None;
+                    state.wl.message.push((
                         Some(id),
                         DispatchMessageInner::Ime(Ime::Preedit(String::new(), None)),
                     ));
@@ -4785,6 +7872,7 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
                 // Send `Commit`.
                 if let Some(text) = text_input_data.pending_commit.take() {
                     state
+                        .wl
                         .message
                         .push((Some(id), DispatchMessageInner::Ime(Ime::Commit(text))));
                 }
@@ -4795,7 +7883,15 @@ impl<T> Dispatch<zwp_text_input_v3::ZwpTextInputV3, TextInputData> for WindowSta
                         .cursor_begin
                         .map(|b| (b, preedit.cursor_end.unwrap_or(b)));
 
-                    state.message.push((
+                    let cursor_visible = cursor_range.is_some();
+                    if text_input_data.preedit_cursor_visible != Some(cursor_visible) {
+                        text_input_data.preedit_cursor_visible = Some(cursor_visible);
+                        state.wl.message.push((
+                            Some(id),
+                            DispatchMessageInner::Ime(Ime::PreeditCursorVisible(cursor_visible)),
+                        ));
+                    }
+                    state.wl.message.push((
                         Some(id),
                         DispatchMessageInner::Ime(Ime::Preedit(preedit.text, cursor_range)),
                     ));
@@ -4835,16 +7931,26 @@ impl<T> Dispatch<WlCallback, (id::Id, PresentAvailableState)> for WindowState<T>
         _conn: &Connection,
         _qhandle: &QueueHandle<Self>,
     ) {
-        if let WlCallbackEvent::Done { callback_data: _ } = event
+        if let WlCallbackEvent::Done { callback_data } = event
             && let Some(unit) = state.get_mut_unit_with_id(data.0)
         {
             unit.present_available_state = data.1;
+            let since_present = unit
+                .last_present
+                .map(|last_present| last_present.elapsed().as_secs_f64() * 1000.);
+            trace_event(
+                state.trace_epoch,
+                Some(data.0),
+                format_args!(
+                    "frame callback done time={callback_data} since_present={since_present:.3?}ms"
+                ),
+            );
             // Wake the event loop immediately so the timer callback
             // picks up the newly-available present slot without waiting
             // for the next timer tick.  This is critical for smooth
             // animations: the compositor just told us the previous
             // frame landed, so we should render the next one ASAP.
-            if let Some(sender) = &state.ping_sender {
+            if let Some(sender) = &state.wl.ping_sender {
                 sender.ping();
             }
         }
@@ -4868,10 +7974,64 @@ impl<T: 'static> Dispatch<WlSurface, ()> for WindowState<T> {
         _conn: &Connection,
         qhandle: &QueueHandle<Self>,
     ) {
-        let wl_surface::Event::Enter { output } = event else {
-            return;
+        let output = match event {
+            wl_surface::Event::Enter { output } => {
+                let name = state.output_name(&output);
+                if let Some(unit) = state
+                    .units
+                    .iter_mut()
+                    .find(|unit| unit.wl_surface == *proxy)
+                    && !unit.entered_outputs.contains(&output)
+                {
+                    unit.entered_outputs.push(output.clone());
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::SurfaceEnterOutput {
+                            output: output.clone(),
+                            name,
+                        },
+                    ));
+                }
+                state.update_integer_scale(proxy);
+                output
+            }
+            wl_surface::Event::Leave { output } => {
+                let name = state.output_name(&output);
+                if let Some(unit) = state
+                    .units
+                    .iter_mut()
+                    .find(|unit| unit.wl_surface == *proxy)
+                    && unit.entered_outputs.contains(&output)
+                {
+                    unit.entered_outputs.retain(|entered| *entered != output);
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::SurfaceLeaveOutput { output, name },
+                    ));
+                }
+                state.update_integer_scale(proxy);
+                return;
+            }
+            wl_surface::Event::PreferredBufferTransform {
+                transform: WEnum::Value(transform),
+            } => {
+                if let Some(unit) = state
+                    .units
+                    .iter_mut()
+                    .find(|unit| unit.wl_surface == *proxy)
+                    && unit.preferred_transform != Some(transform)
+                {
+                    unit.preferred_transform = Some(transform);
+                    state.wl.message.push((
+                        Some(unit.id),
+                        DispatchMessageInner::PreferredTransform(transform),
+                    ));
+                }
+                return;
+            }
+            _ => return,
         };
-        let Some(xdg_output_manager) = state.xdg_output_manager.clone() else {
+        let Some(xdg_output_manager) = state.wl.xdg_output_manager.clone() else {
             return;
         };
         let Some(index) = state
@@ -4890,12 +8050,77 @@ impl<T: 'static> Dispatch<WlSurface, ()> for WindowState<T> {
         // zxdg_output_v1 dispatch folds into the unit + emits as XdgInfoChanged.
         let zxdgoutput = xdg_output_manager.get_xdg_output(&output, qhandle, ());
         state.units[index].zxdgoutput = Some(ZxdgOutputInfo::new(zxdgoutput));
+        state.units[index].output_refresh = state
+            .wl
+            .output_refresh_rates
+            .get(&output.id().protocol_id())
+            .copied();
         state.units[index].wl_output = Some(output);
     }
 }
 
-delegate_noop!(@<T> WindowState<T>: ignore WlOutput); // output is need to place layer_shell, although here
-// it is not used
+impl<T> Dispatch<wl_output::WlOutput, ()> for WindowState<T> {
+    fn event(
+        state: &mut Self,
+        proxy: &wl_output::WlOutput,
+        event: <wl_output::WlOutput as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        // output is need to place layer_shell, here only the v4 name (so TargetScreen and
+        // TargetConnector can match the connector name), the make and model, the scale and
+        // the refresh rate of the current mode are recorded
+        match event {
+            wl_output::Event::Name { name } => {
+                state
+                    .wl
+                    .wl_output_names
+                    .insert(proxy.id().protocol_id(), name);
+            }
+            wl_output::Event::Geometry { make, model, .. } => {
+                state
+                    .wl
+                    .output_make_models
+                    .insert(proxy.id().protocol_id(), (make, model));
+            }
+            wl_output::Event::Scale { factor } => {
+                state
+                    .wl
+                    .output_scales
+                    .insert(proxy.id().protocol_id(), factor);
+                let surfaces: Vec<WlSurface> = state
+                    .units
+                    .iter()
+                    .filter(|unit| unit.entered_outputs.contains(proxy))
+                    .map(|unit| unit.wl_surface.clone())
+                    .collect();
+                for surface in surfaces {
+                    state.update_integer_scale(&surface);
+                }
+            }
+            wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                refresh,
+                ..
+            } if flags.contains(wl_output::Mode::Current) => {
+                state
+                    .wl
+                    .output_refresh_rates
+                    .insert(proxy.id().protocol_id(), refresh);
+                for unit in state
+                    .units
+                    .iter_mut()
+                    .filter(|unit| unit.wl_output.as_ref() == Some(proxy))
+                {
+                    unit.output_refresh = Some(refresh);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
 delegate_noop!(@<T> WindowState<T>: ignore WlShm); // shm is used to create buffer pool
 delegate_noop!(@<T> WindowState<T>: ignore WlShmPool); // so it is pool, created by wl_shm
 delegate_noop!(@<T> WindowState<T>: ignore WlBuffer); // buffer show the picture
@@ -4922,7 +8147,6 @@ delegate_noop!(@<T> WindowState<T>: ignore ZwpInputPanelSurfaceV1);
 delegate_noop!(@<T> WindowState<T>: ignore ZwpInputPanelV1);
 
 delegate_noop!(@<T> WindowState<T>: ignore ZxdgDecorationManagerV1);
-delegate_noop!(@<T> WindowState<T>: ignore ZxdgToplevelDecorationV1);
 
 // Blur protocol delegates
 delegate_noop!(@<T> WindowState<T>: ignore blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager);
@@ -5014,6 +8238,67 @@ impl<T: 'static>
 // activates an inhibitor on creation), so both are ignored.
 delegate_noop!(@<T> WindowState<T>: ignore ZwpKeyboardShortcutsInhibitManagerV1);
 delegate_noop!(@<T> WindowState<T>: ignore ZwpKeyboardShortcutsInhibitorV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpFifoManagerV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpFifoV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpCommitTimingManagerV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpCommitTimerV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpAlphaModifierV1);
+delegate_noop!(@<T> WindowState<T>: ignore WpAlphaModifierSurfaceV1);
+delegate_noop!(@<T> WindowState<T>: ignore ZwpIdleInhibitManagerV1);
+delegate_noop!(@<T> WindowState<T>: ignore ZwpIdleInhibitorV1);
+
+#[cfg(feature = "presentation-time")]
+impl<T> Dispatch<WpPresentation, ()> for WindowState<T> {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentation,
+        event: <WpPresentation as Proxy>::Event,
+        _data: &(),
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        if let wp_presentation::Event::ClockId { clk_id } = event {
+            state.wl.presentation_clock_id = Some(clk_id);
+        }
+    }
+}
+
+#[cfg(feature = "presentation-time")]
+impl<T> Dispatch<WpPresentationFeedback, id::Id> for WindowState<T> {
+    fn event(
+        state: &mut Self,
+        _proxy: &WpPresentationFeedback,
+        event: <WpPresentationFeedback as Proxy>::Event,
+        data: &id::Id,
+        _conn: &Connection,
+        _qhandle: &QueueHandle<Self>,
+    ) {
+        // the feedback object is destroyed by the compositor after presented or discarded
+        if let wp_presentation_feedback::Event::Presented {
+            tv_sec_hi,
+            tv_sec_lo,
+            tv_nsec,
+            refresh,
+            flags,
+            ..
+        } = event
+        {
+            let flags = match flags {
+                WEnum::Value(kind) => kind.bits(),
+                WEnum::Unknown(bits) => bits,
+            };
+            state.wl.message.push((
+                Some(*data),
+                DispatchMessageInner::Presented {
+                    tv_sec: ((tv_sec_hi as u64) << 32) | tv_sec_lo as u64,
+                    tv_nsec,
+                    refresh,
+                    flags,
+                },
+            ));
+        }
+    }
+}
 
 // Shadow protocol delegates
 delegate_noop!(@<T> WindowState<T>: ignore shadow::layer_shadow_manager_v1::LayerShadowManagerV1);
@@ -5088,8 +8373,8 @@ impl<T: 'static>
                 // the consumer misattributes it to the first window, so only one
                 // monitor's panel ever toggles its input region.
                 let window_id = state.get_id_from_surface(&data.surface);
-                state.auto_hide_visible = is_visible;
-                state.message.push((
+                state.wl.auto_hide_visible = is_visible;
+                state.wl.message.push((
                     window_id,
                     DispatchMessageInner::AutoHideVisibilityChanged(is_visible),
                 ));
@@ -5126,7 +8411,7 @@ impl<T: 'static>
                 // surface (`AllScreens`) consumers receive the right output's
                 // usable area.
                 let window_id = state.get_id_from_surface(&data.surface);
-                state.message.push((
+                state.wl.message.push((
                     window_id,
                     DispatchMessageInner::UsableAreaChanged {
                         x,
@@ -5160,11 +8445,13 @@ impl<T: 'static>
         let Event::HomeState { is_home } = event;
         let is_home = is_home != 0;
         log::debug!("Home state changed: is_home={}", is_home);
-        state.is_home = is_home;
+        state.wl.is_home = is_home;
         // Add a message to propagate the event
         state
+            .wl
             .message
             .push((None, DispatchMessageInner::HomeStateChanged(is_home)));
+        state.update_effective_visibility();
     }
 }
 
@@ -5230,13 +8517,13 @@ impl<T: 'static>
             visible
         );
         // Authoritative update from compositor – overwrite optimistic local state.
-        if visible {
-            state.hidden_surfaces.remove(&surface_id);
-        } else {
-            state.hidden_surfaces.insert(surface_id);
+        // The compositor may also hide the surface by itself (auto-hide, workspace switch).
+        let unit_id = state.set_surface_visible(surface_id, visible);
+        if visible && let Some(unit_id) = unit_id {
+            state.request_refresh(unit_id, RefreshRequest::NextFrame);
         }
-        state.message.push((
-            None,
+        state.wl.message.push((
+            unit_id,
             DispatchMessageInner::SurfaceVisibilityChanged(visible),
         ));
     }
@@ -5281,8 +8568,9 @@ impl<T: 'static>
             "Dismiss requested for surface {:?}",
             data.surface.id().protocol_id()
         );
-        state.dismiss_requested = true;
+        state.wl.dismiss_requested = true;
         state
+            .wl
             .message
             .push((None, DispatchMessageInner::DismissRequested));
     }
@@ -5403,9 +8691,10 @@ impl<T: 'static>
         };
 
         // Store the event for later processing
-        state.voice_mode_events.push(voice_event.clone());
+        state.wl.voice_mode_events.push(voice_event.clone());
         // Also push to message queue
         state
+            .wl
             .message
             .push((None, DispatchMessageInner::VoiceMode(voice_event)));
     }
@@ -5416,8 +8705,9 @@ impl<T: 'static>
 impl<T: 'static> layer_surface_dismiss::LayerSurfaceDismissHandler for WindowState<T> {
     fn dismiss_requested(&mut self, _surface: &WlSurface) {
         log::debug!("Dismiss requested for surface");
-        self.dismiss_requested = true;
-        self.message
+        self.wl.dismiss_requested = true;
+        self.wl
+            .message
             .push((None, DispatchMessageInner::DismissRequested));
     }
 }
@@ -5427,19 +8717,52 @@ impl<T: 'static> layer_surface_dismiss::LayerSurfaceDismissHandler for WindowSta
 #[allow(private_interfaces)]
 impl<T: 'static> foreign_toplevel::ForeignToplevelHandler for WindowState<T> {
     fn foreign_toplevel_event(&mut self, event: foreign_toplevel::ForeignToplevelEvent) {
+        use foreign_toplevel::ForeignToplevelEvent;
+        let event = match (event, &self.foreign_toplevel_filter) {
+            (event, None) | (event @ ForeignToplevelEvent::Finished, _) => event,
+            (
+                ForeignToplevelEvent::Created(info) | ForeignToplevelEvent::Changed(info),
+                Some(ForeignToplevelFilter(filter)),
+            ) => {
+                let id = info.id;
+                match (
+                    filter(&info),
+                    self.wl.foreign_toplevel_emitted.contains(&id),
+                ) {
+                    (true, true) => ForeignToplevelEvent::Changed(info),
+                    (true, false) => {
+                        self.wl.foreign_toplevel_emitted.insert(id);
+                        ForeignToplevelEvent::Created(info)
+                    }
+                    (false, true) => {
+                        self.wl.foreign_toplevel_emitted.remove(&id);
+                        ForeignToplevelEvent::Closed(id)
+                    }
+                    (false, false) => return,
+                }
+            }
+            (ForeignToplevelEvent::Closed(id), Some(_)) => {
+                if !self.wl.foreign_toplevel_emitted.remove(&id) {
+                    return;
+                }
+                ForeignToplevelEvent::Closed(id)
+            }
+        };
         log::trace!("Queuing foreign toplevel event: {:?}", event);
-        self.message
+        self.wl
+            .message
             .push((None, DispatchMessageInner::ForeignToplevel(event)));
     }
 
     fn get_toplevel_data(&mut self, id: u32) -> &mut foreign_toplevel::ToplevelHandleData {
-        self.foreign_toplevel_data
+        self.wl
+            .foreign_toplevel_data
             .entry(id)
             .or_insert_with(foreign_toplevel::ToplevelHandleData::default)
     }
 
     fn remove_toplevel_data(&mut self, id: u32) {
-        self.foreign_toplevel_data.remove(&id);
+        self.wl.foreign_toplevel_data.remove(&id);
     }
 
     fn store_toplevel_handle(
@@ -5447,15 +8770,15 @@ impl<T: 'static> foreign_toplevel::ForeignToplevelHandler for WindowState<T> {
         id: u32,
         handle: wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1,
     ) {
-        self.foreign_toplevel_handles.insert(id, handle);
+        self.wl.foreign_toplevel_handles.insert(id, handle);
     }
 
     fn remove_toplevel_handle(&mut self, id: u32) {
-        self.foreign_toplevel_handles.remove(&id);
+        self.wl.foreign_toplevel_handles.remove(&id);
     }
 
     fn get_toplevel_handle(&self, id: u32) -> Option<&wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_handle_v1::ZwlrForeignToplevelHandleV1>{
-        self.foreign_toplevel_handles.get(&id)
+        self.wl.foreign_toplevel_handles.get(&id)
     }
 
     fn store_ext_toplevel_handle(
@@ -5463,15 +8786,15 @@ impl<T: 'static> foreign_toplevel::ForeignToplevelHandler for WindowState<T> {
         id: u32,
         handle: wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
     ) {
-        self.ext_toplevel_handles.insert(id, handle);
+        self.wl.ext_toplevel_handles.insert(id, handle);
     }
 
     fn remove_ext_toplevel_handle(&mut self, id: u32) {
-        self.ext_toplevel_handles.remove(&id);
+        self.wl.ext_toplevel_handles.remove(&id);
     }
 
     fn get_ext_toplevel_handle(&self, id: u32) -> Option<&wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1>{
-        self.ext_toplevel_handles.get(&id)
+        self.wl.ext_toplevel_handles.get(&id)
     }
 
     #[cfg(feature = "cosmic-toplevel")]
@@ -5481,27 +8804,27 @@ impl<T: 'static> foreign_toplevel::ForeignToplevelHandler for WindowState<T> {
         handle: cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1,
     ) {
         log::debug!("Storing COSMIC toplevel handle for id {}", id);
-        self.cosmic_toplevel_handles.insert(id, handle);
+        self.wl.cosmic_toplevel_handles.insert(id, handle);
     }
 
     #[cfg(feature = "cosmic-toplevel")]
     fn remove_cosmic_toplevel_handle(&mut self, id: u32) {
-        self.cosmic_toplevel_handles.remove(&id);
+        self.wl.cosmic_toplevel_handles.remove(&id);
     }
 
     #[cfg(feature = "cosmic-toplevel")]
     fn get_cosmic_toplevel_handle(&self, id: u32) -> Option<&cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_handle_v1::ZcosmicToplevelHandleV1>{
-        self.cosmic_toplevel_handles.get(&id)
+        self.wl.cosmic_toplevel_handles.get(&id)
     }
 
     #[cfg(feature = "cosmic-toplevel")]
     fn get_cosmic_toplevel_manager(&self) -> Option<&cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1>{
-        self.cosmic_toplevel_manager.as_ref()
+        self.wl.cosmic_toplevel_manager.as_ref()
     }
 
     #[cfg(feature = "cosmic-toplevel")]
     fn cosmic_toplevel_info(&self) -> Option<&cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1>{
-        self.cosmic_toplevel_info.as_ref()
+        self.wl.cosmic_toplevel_info.as_ref()
     }
 }
 
@@ -5510,20 +8833,21 @@ impl<T: 'static> foreign_toplevel::ForeignToplevelHandler for WindowState<T> {
 impl<T: 'static> screencopy::ScreencopyHandler for WindowState<T> {
     fn screencopy_event(&mut self, event: screencopy::ScreencopyEvent) {
         log::trace!("Queuing screencopy event");
-        self.message
+        self.wl
+            .message
             .push((None, DispatchMessageInner::Screencopy(event)));
     }
 
     fn screencopy_state(&self) -> &screencopy::ScreencopyState {
-        &self.screencopy
+        &self.wl.screencopy
     }
 
     fn screencopy_state_mut(&mut self) -> &mut screencopy::ScreencopyState {
-        &mut self.screencopy
+        &mut self.wl.screencopy
     }
 
     fn get_shm(&self) -> Option<&wayland_client::protocol::wl_shm::WlShm> {
-        self.shm.as_ref().or(self.screencopy_shm.as_ref())
+        self.wl.shm.as_ref().or(self.wl.screencopy_shm.as_ref())
     }
 
     fn get_ext_toplevel_handle(
@@ -5532,7 +8856,7 @@ impl<T: 'static> screencopy::ScreencopyHandler for WindowState<T> {
     ) -> Option<
         &wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_handle_v1::ExtForeignToplevelHandleV1,
     >{
-        self.ext_toplevel_handles.get(&id)
+        self.wl.ext_toplevel_handles.get(&id)
     }
 }
 
@@ -5541,15 +8865,15 @@ impl<T: 'static> screencopy::ScreencopyHandler for WindowState<T> {
 #[allow(private_interfaces)]
 impl<T: 'static> foreign_toplevel::CosmicToplevelHandler for WindowState<T> {
     fn cosmic_toplevel_info(&self) -> Option<&cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1>{
-        self.cosmic_toplevel_info.as_ref()
+        self.wl.cosmic_toplevel_info.as_ref()
     }
 
     fn set_cosmic_handle_mapping(&mut self, cosmic_id: u32, ext_id: u32) {
-        self.cosmic_to_ext_handle_map.insert(cosmic_id, ext_id);
+        self.wl.cosmic_to_ext_handle_map.insert(cosmic_id, ext_id);
     }
 
     fn get_ext_handle_id(&self, cosmic_id: u32) -> Option<u32> {
-        self.cosmic_to_ext_handle_map.get(&cosmic_id).copied()
+        self.wl.cosmic_to_ext_handle_map.get(&cosmic_id).copied()
     }
 }
 
@@ -5674,7 +8998,7 @@ impl<T: 'static>
             let handle_data = state.get_toplevel_data(ext_id);
             if !handle_data.initialized {
                 // First Done event - request cosmic extension if available
-                if let Some(cosmic_info) = state.cosmic_toplevel_info.as_ref() {
+                if let Some(cosmic_info) = state.wl.cosmic_toplevel_info.as_ref() {
                     log::trace!("Requesting cosmic toplevel handle for ext handle {}", ext_id);
                     let cosmic_handle_data = foreign_toplevel::CosmicToplevelHandleData {
                         ext_handle_id: ext_id,
@@ -5881,6 +9205,52 @@ impl<T: 'static>
     }
 }
 
+#[cfg(feature = "screencopy")]
+impl<T: 'static>
+    Dispatch<
+        wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        screencopy::OutputManagerData,
+    > for WindowState<T>
+{
+    fn event(
+        state: &mut Self,
+        proxy: &wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+        event: <wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1 as Proxy>::Event,
+        data: &screencopy::OutputManagerData,
+        conn: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        <() as Dispatch<
+            wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+            screencopy::OutputManagerData,
+            Self,
+        >>::event(state, proxy, event, data, conn, qhandle)
+    }
+}
+
+#[cfg(feature = "screencopy")]
+impl<T: 'static>
+    Dispatch<
+        wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        screencopy::OutputFrameData,
+    > for WindowState<T>
+{
+    fn event(
+        state: &mut Self,
+        proxy: &wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+        event: wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::Event,
+        data: &screencopy::OutputFrameData,
+        conn: &Connection,
+        qhandle: &QueueHandle<Self>,
+    ) {
+        <() as Dispatch<
+            wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_frame_v1::ZwlrScreencopyFrameV1,
+            screencopy::OutputFrameData,
+            Self,
+        >>::event(state, proxy, event, data, conn, qhandle)
+    }
+}
+
 #[cfg(feature = "screencopy")]
 impl<T: 'static> Dispatch<wayland_client::protocol::wl_buffer::WlBuffer, screencopy::BufferData>
     for WindowState<T>
@@ -5925,27 +9295,178 @@ impl<T: 'static> WindowState<T> {
     /// The logical layout of every output (global coords), gathered at startup.
     /// Used to position/move a layer surface across monitors.
     pub fn output_layout(&self) -> &[OutputLayoutItem] {
-        &self.output_layout
+        &self.wl.output_layout
+    }
+
+    /// the wl_output (v4+) name of the output, usually the connector like `DP-1`. None if the
+    /// compositor does not send it
+    pub fn wl_output_name(&self, output: &WlOutput) -> Option<&str> {
+        self.wl
+            .wl_output_names
+            .get(&output.id().protocol_id())
+            .map(String::as_str)
+    }
+
+    /// the make and model the compositor reports in `wl_output.geometry` for the output
+    pub fn output_make_model(&self, output: &WlOutput) -> Option<(&str, &str)> {
+        self.wl
+            .output_make_models
+            .get(&output.id().protocol_id())
+            .map(|(make, model)| (make.as_str(), model.as_str()))
+    }
+
+    /// bind a global on its first use: the optional managers only used by some setters, and
+    /// the ones skipped by `build` because of [WindowState::with_lazy_protocol_binding].
+    /// None if the loop has not started or the compositor lacks it
+    fn bind_lazy_global<I, U>(&self, version: std::ops::RangeInclusive<u32>, udata: U) -> Option<I>
+    where
+        I: Proxy + 'static,
+        U: Send + Sync + 'static,
+        Self: Dispatch<I, U>,
+    {
+        let globals = self.wl.globals.as_ref()?;
+        let qh = self.wl.queue_handle.as_ref()?;
+        let proxy = globals.bind::<I, _, _>(qh, version, udata).ok()?;
+        log::info!("Bound {} on first use", I::interface().name);
+        Some(proxy)
+    }
+
+    fn bind_lazy_visibility_manager(&mut self) {
+        if self.wl.layer_surface_visibility_manager.is_none() {
+            self.wl.layer_surface_visibility_manager = self.bind_lazy_global(
+                1..=2,
+                layer_surface_visibility::LayerSurfaceVisibilityManagerData,
+            );
+        }
+    }
+
+    /// the xdg_output name of the output, or its wl_output name
+    fn output_name(&self, output: &WlOutput) -> Option<String> {
+        self.wl
+            .xdg_info_cache
+            .iter()
+            .find(|(cached, info)| cached == output && !info.name.is_empty())
+            .map(|(_, info)| info.name.clone())
+            .or_else(|| {
+                self.wl
+                    .wl_output_names
+                    .get(&output.id().protocol_id())
+                    .cloned()
+            })
+    }
+
+    /// the [ZxdgOutputInfo] of an output as far as it is known
+    fn output_info(&self, output: &WlOutput, zxdgoutput: ZxdgOutputV1) -> ZxdgOutputInfo {
+        let mut info = ZxdgOutputInfo::new(zxdgoutput);
+        let name = self
+            .wl
+            .output_handles
+            .iter()
+            .find(|(_, handle)| handle == output)
+            .map(|(name, _)| name.clone())
+            .or_else(|| self.output_name(output));
+        if let Some(name) = name {
+            if let Some(item) = self.wl.output_layout.iter().find(|item| item.name == name) {
+                info.position = (item.x, item.y);
+                info.logical_size = (item.width, item.height);
+            }
+            info.name = name;
+        }
+        info
+    }
+
+    /// if the output passes the filter of [WindowState::with_output_filter] in
+    /// [`StartMode::AllScreens`]
+    fn output_accepted(&self, info: &ZxdgOutputInfo) -> bool {
+        !self.is_allscreens()
+            || self
+                .output_filter
+                .as_ref()
+                .is_none_or(|filter| (filter.0)(info))
+    }
+
+    /// the settings of the surface of an output in [`StartMode::AllScreens`] after
+    /// [WindowState::with_per_output_config] is applied. The anchor of the returned config is
+    /// always set
+    fn output_layer_config(&self, info: &ZxdgOutputInfo) -> OutputLayerConfig {
+        let config = self
+            .per_output_config
+            .as_ref()
+            .filter(|_| self.is_allscreens())
+            .map(|config| (config.0)(info))
+            .unwrap_or_default();
+        OutputLayerConfig {
+            size: config.size.or(self.size),
+            anchor: Some(config.anchor.unwrap_or(self.anchor)),
+            margin: config.margin.or(self.margin),
+            exclusive_zone: config.exclusive_zone.or(self.exclusive_zone),
+        }
+    }
+
+    /// check the outputs skipped by the filter of [WindowState::with_output_filter] again, the
+    /// ones it allows now get a surface on the next tick of the event loop. Call it when the
+    /// state read by the filter changes
+    pub fn reapply_output_filter(&mut self) {
+        for output in std::mem::take(&mut self.wl.filtered_outputs) {
+            if output.is_alive() {
+                self.wl
+                    .message
+                    .push((None, DispatchMessageInner::NewDisplay(output)));
+            }
+        }
+    }
+
+    /// find the output in `xdg_info_cache` which the name of [`StartMode::TargetScreen`] or
+    /// [`StartMode::TargetDescription`] points to, following the [`OutputMatchStrategy`]
+    fn find_cached_output(
+        &self,
+        name: &str,
+        strategy: OutputMatchStrategy,
+    ) -> Option<(WlOutput, ZxdgOutputInfo)> {
+        for field in strategy.fields() {
+            let found = self
+                .wl
+                .xdg_info_cache
+                .iter()
+                .find(|(output, info)| match field {
+                    OutputMatchField::WlOutputName => self
+                        .wl
+                        .wl_output_names
+                        .get(&output.id().protocol_id())
+                        .is_some_and(|wl_name| wl_name == name),
+                    OutputMatchField::XdgOutputName => info.name == name,
+                    OutputMatchField::Description => {
+                        !name.is_empty() && info.description.contains(name)
+                    }
+                });
+            if let Some(found) = found {
+                log::info!("output {name} matched by {field:?}");
+                return Some(found.clone());
+            }
+        }
+        log::warn!("no output matched {name} with {strategy:?}");
+        None
     }
 
     /// build a new WindowState
     pub fn build(mut self) -> Result<Self, LayerEventError> {
-        let connection = if let Some(connection) = self.connection.take() {
+        let connection = if let Some(connection) = self.wl.connection.take() {
             connection
         } else {
             Connection::connect_to_env()?
         };
         let (globals, _) = registry_queue_init::<BaseState>(&connection)?;
 
-        self.display = Some(connection.display());
+        self.wl.display = Some(connection.display());
         let mut event_queue = connection.new_event_queue::<WindowState<T>>();
         let qh = event_queue.handle();
 
-        let wmcompositer = globals.bind::<WlCompositor, _, _>(&qh, 1..=5, ())?;
+        let wmcompositer = globals.bind::<WlCompositor, _, _>(&qh, 1..=6, ())?;
 
         let shm = globals.bind::<WlShm, _, _>(&qh, 1..=1, ())?;
-        self.shm = Some(shm);
-        self.seat = Some(globals.bind::<WlSeat, _, _>(&qh, 1..=1, ())?);
+        self.wl.shm = Some(shm);
+        // v4 for `wl_keyboard.repeat_info`
+        self.wl.seat = Some(globals.bind::<WlSeat, _, _>(&qh, 1..=4, ())?);
 
         // Drag-and-drop (receive only): bind the data device manager and get a
         // data device for the seat, so the compositor delivers DnD offers from
@@ -5955,25 +9476,32 @@ impl<T: 'static> WindowState<T> {
         match globals.bind::<WlDataDeviceManager, _, _>(&qh, 1..=3, ()) {
             Ok(manager) => {
                 log::info!(target: "kcopy_dnd", "bound wl_data_device_manager v{}", manager.version());
-                if let Some(seat) = self.seat.as_ref() {
+                if let Some(seat) = self.wl.seat.as_ref() {
                     let dd = manager.get_data_device(seat, &qh, ());
                     log::info!(target: "kcopy_dnd", "created wl_data_device v{}", dd.version());
-                    self.data_device = Some(dd);
+                    self.wl.data_device = Some(dd);
                 } else {
                     log::warn!(target: "kcopy_dnd", "no seat — data device not created");
                 }
-                self.data_device_manager = Some(manager);
+                self.wl.data_device_manager = Some(manager);
             }
             Err(e) => log::warn!(target: "kcopy_dnd", "no wl_data_device_manager: {e}"),
         }
 
         let wmbase = globals.bind::<XdgWmBase, _, _>(&qh, 2..=6, ())?;
-        self.wmbase = Some(wmbase);
+        self.wl.wmbase = Some(wmbase);
 
         let cursor_manager = globals
-            .bind::<WpCursorShapeManagerV1, _, _>(&qh, 1..=1, ())
+            .bind::<WpCursorShapeManagerV1, _, _>(&qh, 1..=2, ())
             .ok();
         let viewporter = globals.bind::<WpViewporter, _, _>(&qh, 1..=1, ()).ok();
+        #[cfg(feature = "presentation-time")]
+        {
+            self.wl.presentation = globals.bind::<WpPresentation, _, _>(&qh, 1..=1, ()).ok();
+            if self.wl.presentation.is_none() {
+                log::warn!("wp_presentation not available - compositor may not support it");
+            }
+        }
 
         let _ = connection.display().get_registry(&qh, ()); // so if you want WlOutput, you need to
         // register this
@@ -5985,7 +9513,7 @@ impl<T: 'static> WindowState<T> {
             .bind::<ZxdgDecorationManagerV1, _, _>(&qh, 1..=1, ())
             .ok();
 
-        self.xdg_decoration_manager = decoration_manager;
+        self.wl.xdg_decoration_manager = decoration_manager;
 
         let fractional_scale_manager = globals
             .bind::<WpFractionalScaleManagerV1, _, _>(&qh, 1..=1, ())
@@ -5994,27 +9522,31 @@ impl<T: 'static> WindowState<T> {
             .bind::<ZwpTextInputManagerV3, _, _>(&qh, 1..=1, ())
             .ok();
 
-        self.text_input_manager = text_input_manager;
+        self.wl.text_input_manager = text_input_manager;
 
         // Always try to bind blur manager for dynamic blur support
         // (allows requesting blur on any surface, like popups, even if main window doesn't have blur)
-        self.blur_manager = globals
+        self.wl.blur_manager = globals
             .bind::<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager, _, _>(&qh, 1..=3, ())
             .ok();
-        if self.blur_manager.is_some() {
+        if self.wl.blur_manager.is_some() {
             log::info!("Successfully bound org_kde_kwin_blur_manager protocol for blur support");
         }
 
-        // Always try to bind corner radius manager for dynamic corner radius support
-        // (allows setting corner radius at runtime even if not set initially)
-        self.corner_radius_manager = globals
-            .bind::<corner_radius::layer_corner_radius_manager_v1::LayerCornerRadiusManagerV1, _, _>(
-                &qh,
-                1..=1,
-                (),
-            )
-            .ok();
-        if self.corner_radius_manager.is_some() {
+        // Bind corner radius manager for dynamic corner radius support
+        // (allows setting corner radius at runtime even if not set initially). With the lazy
+        // binding it waits for the first setter, unless the initial settings need it
+        let eager = !self.lazy_protocol_binding;
+        if eager || self.corner_radius.is_some() {
+            self.wl.corner_radius_manager = globals
+                .bind::<corner_radius::layer_corner_radius_manager_v1::LayerCornerRadiusManagerV1, _, _>(
+                    &qh,
+                    1..=1,
+                    (),
+                )
+                .ok();
+        }
+        if self.wl.corner_radius_manager.is_some() {
             log::info!(
                 "Successfully bound layer_corner_radius_manager_v1 protocol for corner radius support"
             );
@@ -6022,14 +9554,14 @@ impl<T: 'static> WindowState<T> {
 
         // Always try to bind the compositor-side placement manager so a surface
         // can ask to be positioned within the usable area at any time.
-        self.layer_surface_placement_manager = globals
+        self.wl.layer_surface_placement_manager = globals
             .bind::<layer_surface_placement::layer_surface_placement_manager_v1::LayerSurfacePlacementManagerV1, _, _>(
                 &qh,
                 1..=1,
                 (),
             )
             .ok();
-        if self.layer_surface_placement_manager.is_some() {
+        if self.wl.layer_surface_placement_manager.is_some() {
             log::info!(
                 "Successfully bound layer_surface_placement_manager_v1 protocol for compositor-side placement"
             );
@@ -6037,37 +9569,43 @@ impl<T: 'static> WindowState<T> {
 
         // Always try to bind the compositor-side edge-resize manager so a surface
         // can opt into the compositor-drawn resize sash at any time.
-        self.layer_edge_resize_manager = globals
+        self.wl.layer_edge_resize_manager = globals
             .bind::<layer_edge_resize::layer_edge_resize_manager_v1::LayerEdgeResizeManagerV1, _, _>(
                 &qh,
                 1..=1,
                 (),
             )
             .ok();
-        if self.layer_edge_resize_manager.is_some() {
+        if self.wl.layer_edge_resize_manager.is_some() {
             log::info!(
                 "Successfully bound layer_edge_resize_manager_v1 protocol for compositor-drawn edge resize"
             );
         }
 
-        // Always try to bind shadow manager for dynamic shadow support
-        // (allows requesting shadow on any surface, like popups, even if main window doesn't have shadow)
-        self.shadow_manager = globals
-            .bind::<shadow::layer_shadow_manager_v1::LayerShadowManagerV1, _, _>(&qh, 1..=1, ())
-            .ok();
-        if self.shadow_manager.is_some() {
+        // Bind shadow manager for dynamic shadow support
+        // (allows requesting shadow on any surface, like popups, even if main window doesn't have shadow).
+        // With the lazy binding it waits for the first setter, unless the initial settings need it
+        if eager || self.shadow {
+            self.wl.shadow_manager = globals
+                .bind::<shadow::layer_shadow_manager_v1::LayerShadowManagerV1, _, _>(&qh, 1..=1, ())
+                .ok();
+        }
+        if self.wl.shadow_manager.is_some() {
             log::info!("Successfully bound layer_shadow_manager_v1 protocol for shadow support");
         }
 
-        // Always try to bind layer auto-hide manager for compositor-driven auto-hide support
-        self.auto_hide_manager = globals
-            .bind::<layer_auto_hide::layer_auto_hide_manager_v1::LayerAutoHideManagerV1, _, _>(
-                &qh,
-                1..=1,
-                (),
-            )
-            .ok();
-        if self.auto_hide_manager.is_some() {
+        // Bind layer auto-hide manager for compositor-driven auto-hide support, or on first use
+        // with the lazy binding
+        if eager {
+            self.wl.auto_hide_manager = globals
+                .bind::<layer_auto_hide::layer_auto_hide_manager_v1::LayerAutoHideManagerV1, _, _>(
+                    &qh,
+                    1..=1,
+                    (),
+                )
+                .ok();
+        }
+        if self.wl.auto_hide_manager.is_some() {
             log::info!(
                 "Successfully bound layer_auto_hide_manager_v1 protocol for auto-hide support"
             );
@@ -6075,44 +9613,50 @@ impl<T: 'static> WindowState<T> {
 
         // Always try to bind the usable-area manager so surfaces learn their
         // output's non-exclusive area (output size minus panels/docks).
-        self.usable_area_manager = globals
+        self.wl.usable_area_manager = globals
             .bind::<layer_usable_area::layer_usable_area_manager_v1::LayerUsableAreaManagerV1, _, _>(
                 &qh,
                 1..=1,
                 (),
             )
             .ok();
-        if self.usable_area_manager.is_some() {
+        if self.wl.usable_area_manager.is_some() {
             log::info!(
                 "Successfully bound layer_usable_area_manager_v1 protocol for usable-area reporting"
             );
         }
 
-        // Always try to bind layer surface visibility manager for hide/show support
-        // (allows hiding/showing surfaces without destroying them)
-        self.layer_surface_visibility_manager = globals
-            .bind::<layer_surface_visibility::zcosmic_layer_surface_visibility_manager_v1::ZcosmicLayerSurfaceVisibilityManagerV1, _, _>(
-                &qh,
-                1..=2,
-                layer_surface_visibility::LayerSurfaceVisibilityManagerData,
-            )
-            .ok();
-        if self.layer_surface_visibility_manager.is_some() {
+        // Bind layer surface visibility manager for hide/show support
+        // (allows hiding/showing surfaces without destroying them), or on first use with the
+        // lazy binding
+        if eager {
+            self.wl.layer_surface_visibility_manager = globals
+                .bind::<layer_surface_visibility::zcosmic_layer_surface_visibility_manager_v1::ZcosmicLayerSurfaceVisibilityManagerV1, _, _>(
+                    &qh,
+                    1..=2,
+                    layer_surface_visibility::LayerSurfaceVisibilityManagerData,
+                )
+                .ok();
+        }
+        if self.wl.layer_surface_visibility_manager.is_some() {
             log::info!(
                 "Successfully bound zcosmic_layer_surface_visibility_manager_v1 protocol for hide/show support"
             );
         }
 
-        // Always try to bind layer surface dismiss manager for dismiss-on-outside-click support
-        self.layer_surface_dismiss_manager = globals
-            .bind::<layer_surface_dismiss::zcosmic_layer_surface_dismiss_manager_v1::ZcosmicLayerSurfaceDismissManagerV1, _, _>(
-                &qh,
-                // v2 adds `set_ignore_layer_clicks`; fall back to v1 compositors.
-                1..=2,
-                layer_surface_dismiss::LayerSurfaceDismissManagerData,
-            )
-            .ok();
-        if self.layer_surface_dismiss_manager.is_some() {
+        // Bind layer surface dismiss manager for dismiss-on-outside-click support, or on first
+        // use with the lazy binding
+        if eager {
+            self.wl.layer_surface_dismiss_manager = globals
+                .bind::<layer_surface_dismiss::zcosmic_layer_surface_dismiss_manager_v1::ZcosmicLayerSurfaceDismissManagerV1, _, _>(
+                    &qh,
+                    // v2 adds `set_ignore_layer_clicks`; fall back to v1 compositors.
+                    1..=2,
+                    layer_surface_dismiss::LayerSurfaceDismissManagerData,
+                )
+                .ok();
+        }
+        if self.wl.layer_surface_dismiss_manager.is_some() {
             log::info!(
                 "Successfully bound zcosmic_layer_surface_dismiss_manager_v1 protocol for dismiss support"
             );
@@ -6120,14 +9664,14 @@ impl<T: 'static> WindowState<T> {
 
         // Bind home visibility manager if home_only or hide_on_home is enabled
         if self.home_only || self.hide_on_home {
-            self.home_visibility_manager = globals
+            self.wl.home_visibility_manager = globals
                 .bind::<home_visibility::zcosmic_home_visibility_manager_v1::ZcosmicHomeVisibilityManagerV1, _, _>(
                     &qh,
                     1..=1,
                     home_visibility::HomeVisibilityManagerData::default(),
                 )
                 .ok();
-            if self.home_visibility_manager.is_none() {
+            if self.wl.home_visibility_manager.is_none() {
                 log::warn!(
                     "Home visibility mode requested but compositor does not support zcosmic_home_visibility_v1 protocol"
                 );
@@ -6140,14 +9684,14 @@ impl<T: 'static> WindowState<T> {
 
         // Bind voice mode manager if voice mode is enabled
         if self.voice_mode_enabled {
-            self.voice_mode_manager = globals
+            self.wl.voice_mode_manager = globals
                 .bind::<voice_mode::zcosmic_voice_mode_manager_v1::ZcosmicVoiceModeManagerV1, _, _>(
                     &qh,
                     1..=1,
                     voice_mode::VoiceModeManagerData,
                 )
                 .ok();
-            if self.voice_mode_manager.is_none() {
+            if self.wl.voice_mode_manager.is_none() {
                 log::warn!(
                     "Voice mode requested but compositor does not support zcosmic_voice_mode_v1 protocol"
                 );
@@ -6164,7 +9708,7 @@ impl<T: 'static> WindowState<T> {
         #[cfg(feature = "foreign-toplevel")]
         if self.foreign_toplevel_enabled {
             // Try ext_foreign_toplevel_list_v1 first (standard protocol for info)
-            self.ext_foreign_toplevel_list = globals
+            self.wl.ext_foreign_toplevel_list = globals
                 .bind::<wayland_protocols::ext::foreign_toplevel_list::v1::client::ext_foreign_toplevel_list_v1::ExtForeignToplevelListV1, _, _>(
                     &qh,
                     1..=1,
@@ -6172,7 +9716,7 @@ impl<T: 'static> WindowState<T> {
                 )
                 .ok();
 
-            if self.ext_foreign_toplevel_list.is_some() {
+            if self.wl.ext_foreign_toplevel_list.is_some() {
                 log::info!(
                     "Successfully bound ext_foreign_toplevel_list_v1 protocol for foreign toplevel tracking"
                 );
@@ -6182,14 +9726,14 @@ impl<T: 'static> WindowState<T> {
             #[cfg(feature = "cosmic-toplevel")]
             {
                 // COSMIC toplevel info (for state info like minimized/maximized)
-                self.cosmic_toplevel_info = globals
+                self.wl.cosmic_toplevel_info = globals
                     .bind::<cosmic_protocols::toplevel_info::v1::client::zcosmic_toplevel_info_v1::ZcosmicToplevelInfoV1, _, _>(
                         &qh,
                         2..=2,  // Version 2+ has get_cosmic_toplevel
                         foreign_toplevel::CosmicToplevelInfoData::default(),
                     )
                     .ok();
-                if self.cosmic_toplevel_info.is_some() {
+                if self.wl.cosmic_toplevel_info.is_some() {
                     log::info!(
                         "Successfully bound zcosmic_toplevel_info_v1 protocol for toplevel state info"
                     );
@@ -6201,14 +9745,14 @@ impl<T: 'static> WindowState<T> {
 
                 // COSMIC toplevel manager (for control - activate, close, etc.)
                 // Version 5+ needed for force_close support
-                self.cosmic_toplevel_manager = globals
+                self.wl.cosmic_toplevel_manager = globals
                     .bind::<cosmic_protocols::toplevel_management::v1::client::zcosmic_toplevel_manager_v1::ZcosmicToplevelManagerV1, _, _>(
                         &qh,
                         1..=5,
                         foreign_toplevel::CosmicToplevelManagerData::default(),
                     )
                     .ok();
-                if self.cosmic_toplevel_manager.is_some() {
+                if self.wl.cosmic_toplevel_manager.is_some() {
                     log::info!(
                         "Successfully bound zcosmic_toplevel_manager_v1 protocol for toplevel control"
                     );
@@ -6221,12 +9765,12 @@ impl<T: 'static> WindowState<T> {
 
             // Fall back to wlr_foreign_toplevel_manager_v1 if COSMIC manager not available
             #[cfg(feature = "cosmic-toplevel")]
-            let has_cosmic_manager = self.cosmic_toplevel_manager.is_some();
+            let has_cosmic_manager = self.wl.cosmic_toplevel_manager.is_some();
             #[cfg(not(feature = "cosmic-toplevel"))]
             let has_cosmic_manager = false;
 
             if !has_cosmic_manager {
-                self.foreign_toplevel_manager = globals
+                self.wl.foreign_toplevel_manager = globals
                     .bind::<wayland_protocols_wlr::foreign_toplevel::v1::client::zwlr_foreign_toplevel_manager_v1::ZwlrForeignToplevelManagerV1, _, _>(
                         &qh,
                         1..=3,
@@ -6234,7 +9778,7 @@ impl<T: 'static> WindowState<T> {
                     )
                     .ok();
 
-                if self.foreign_toplevel_manager.is_some() {
+                if self.wl.foreign_toplevel_manager.is_some() {
                     log::info!(
                         "Successfully bound zwlr_foreign_toplevel_manager_v1 protocol for foreign toplevel management"
                     );
@@ -6242,13 +9786,13 @@ impl<T: 'static> WindowState<T> {
             }
 
             // Check if we have at least one way to track toplevels
-            let has_info =
-                self.ext_foreign_toplevel_list.is_some() || self.foreign_toplevel_manager.is_some();
+            let has_info = self.wl.ext_foreign_toplevel_list.is_some()
+                || self.wl.foreign_toplevel_manager.is_some();
             #[cfg(feature = "cosmic-toplevel")]
-            let has_control =
-                self.cosmic_toplevel_manager.is_some() || self.foreign_toplevel_manager.is_some();
+            let has_control = self.wl.cosmic_toplevel_manager.is_some()
+                || self.wl.foreign_toplevel_manager.is_some();
             #[cfg(not(feature = "cosmic-toplevel"))]
-            let has_control = self.foreign_toplevel_manager.is_some();
+            let has_control = self.wl.foreign_toplevel_manager.is_some();
 
             if !has_info {
                 log::warn!(
@@ -6265,31 +9809,31 @@ impl<T: 'static> WindowState<T> {
         // Bind screencopy protocols if enabled
         #[cfg(feature = "screencopy")]
         if self.foreign_toplevel_enabled {
-            self.screencopy.capture_manager = globals
+            self.wl.screencopy.capture_manager = globals
                 .bind::<wayland_protocols::ext::image_copy_capture::v1::client::ext_image_copy_capture_manager_v1::ExtImageCopyCaptureManagerV1, _, _>(
                     &qh,
                     1..=1,
                     screencopy::CaptureManagerData::default(),
                 )
                 .ok();
-            if self.screencopy.capture_manager.is_some() {
+            if self.wl.screencopy.capture_manager.is_some() {
                 log::info!("Successfully bound ext_image_copy_capture_manager_v1 for screencopy");
             }
 
-            self.screencopy.source_manager = globals
+            self.wl.screencopy.source_manager = globals
                 .bind::<wayland_protocols::ext::image_capture_source::v1::client::ext_foreign_toplevel_image_capture_source_manager_v1::ExtForeignToplevelImageCaptureSourceManagerV1, _, _>(
                     &qh,
                     1..=1,
                     screencopy::CaptureSourceManagerData::default(),
                 )
                 .ok();
-            if self.screencopy.source_manager.is_some() {
+            if self.wl.screencopy.source_manager.is_some() {
                 log::info!(
                     "Successfully bound ext_foreign_toplevel_image_capture_source_manager_v1 for screencopy"
                 );
             }
 
-            if self.screencopy.is_available() {
+            if self.wl.screencopy.is_available() {
                 log::info!("Screencopy support is available");
             } else {
                 log::debug!(
@@ -6297,6 +9841,16 @@ impl<T: 'static> WindowState<T> {
                 );
             }
         }
+        #[cfg(feature = "screencopy")]
+        {
+            self.wl.screencopy.output_manager = globals
+                .bind::<wayland_protocols_wlr::screencopy::v1::client::zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1, _, _>(
+                    &qh,
+                    1..=3,
+                    screencopy::OutputManagerData,
+                )
+                .ok();
+        }
 
         event_queue.blocking_dispatch(&mut self)?; // then make a dispatch
 
@@ -6304,14 +9858,16 @@ impl<T: 'static> WindowState<T> {
         // position + size), so consumers can place a surface across monitors. The
         // proxies are dropped afterwards (snapshot); `xdg_info_cache` is otherwise
         // only used transiently by `StartMode::TargetScreen`.
-        for (_, output_display) in &self.outputs {
+        for (_, output_display) in &self.wl.outputs {
             let zxdgoutput = xdg_output_manager.get_xdg_output(output_display, &qh, ());
-            self.xdg_info_cache
+            self.wl
+                .xdg_info_cache
                 .push((output_display.clone(), ZxdgOutputInfo::new(zxdgoutput)));
         }
-        if !self.xdg_info_cache.is_empty() {
+        if !self.wl.xdg_info_cache.is_empty() {
             event_queue.roundtrip(&mut self)?;
-            self.output_layout = self
+            self.wl.output_layout = self
+                .wl
                 .xdg_info_cache
                 .iter()
                 .map(|(_, info)| OutputLayoutItem {
@@ -6324,15 +9880,16 @@ impl<T: 'static> WindowState<T> {
                 .collect();
             // Keep a name → WlOutput map so a later `NewLayerShell` can target a
             // monitor by name. (`WlOutput`s live in `self.outputs`.)
-            self.output_handles = self
+            self.wl.output_handles = self
+                .wl
                 .xdg_info_cache
                 .iter()
                 .map(|(output, info)| (info.name.clone(), output.clone()))
                 .collect();
-            self.xdg_info_cache.clear();
-            self.message.push((
+            self.wl.xdg_info_cache.clear();
+            self.wl.message.push((
                 None,
-                DispatchMessageInner::OutputLayoutChanged(self.output_layout.clone()),
+                DispatchMessageInner::OutputLayoutChanged(self.wl.output_layout.clone()),
             ));
         }
 
@@ -6351,31 +9908,49 @@ impl<T: 'static> WindowState<T> {
                 background_surface.set_input_region(Some(&region));
                 region.destroy();
             }
-            self.background_surface = Some(background_surface);
+            self.wl.background_surface = Some(background_surface);
+        } else if self.reconnect_outputs.is_some() {
+            // the surfaces of before the reconnection are created again by restore_units,
+            // once the event loop runs
         } else if !self.is_allscreens() {
-            let mut output = None;
-
             let (binded_output, binded_xdginfo) = match self.start_mode.clone() {
-                StartMode::TargetScreen(name) => {
-                    for (_, output_display) in &self.outputs {
+                StartMode::TargetScreen(name) | StartMode::TargetDescription(name) => {
+                    // the names go before the description, so a name still wins when the
+                    // description matches another output
+                    let strategy = if matches!(self.start_mode, StartMode::TargetDescription(_)) {
+                        OutputMatchStrategy::Auto
+                    } else {
+                        self.output_match_strategy
+                    };
+                    for (_, output_display) in &self.wl.outputs {
                         let zxdgoutput = xdg_output_manager.get_xdg_output(output_display, &qh, ());
-                        self.xdg_info_cache
+                        self.wl
+                            .xdg_info_cache
                             .push((output_display.clone(), ZxdgOutputInfo::new(zxdgoutput)));
                     }
-                    event_queue.blocking_dispatch(&mut self)?; // then make a dispatch
-                    if let Some(cache) = self
-                        .xdg_info_cache
-                        .iter()
-                        .find(|(_, info)| info.name == *name)
-                        .cloned()
-                    {
-                        output = Some(cache.clone());
-                    }
-                    self.xdg_info_cache.clear();
+                    // roundtrip so every name/description event has arrived
+                    event_queue.roundtrip(&mut self)?;
+                    let output = self.find_cached_output(&name, strategy);
+                    self.wl.xdg_info_cache.clear();
                     let binded_output = output.as_ref().map(|(output, _)| output).cloned();
                     let binded_xdginfo = output.as_ref().map(|(_, xdginfo)| xdginfo).cloned();
                     (binded_output, binded_xdginfo)
                 }
+                StartMode::TargetConnector(name) => {
+                    // roundtrip so the wl_output name events have arrived
+                    event_queue.roundtrip(&mut self)?;
+                    let output = self
+                        .wl
+                        .outputs
+                        .iter()
+                        .map(|(_, output)| output)
+                        .find(|output| self.wl_output_name(output) == Some(name.as_str()))
+                        .cloned();
+                    if output.is_none() {
+                        log::warn!("no output has the wl_output name {name}");
+                    }
+                    (output, None)
+                }
                 StartMode::TargetOutput(output) => (Some(output), None),
                 _ => (None, None),
             };
@@ -6415,7 +9990,7 @@ impl<T: 'static> WindowState<T> {
             // Apply blur effect if enabled
             if self.blur {
                 apply_blur_to_surface(
-                    &self.blur_manager,
+                    &self.wl.blur_manager,
                     &wl_surface,
                     &qh,
                     self.blur_radius,
@@ -6429,62 +10004,66 @@ impl<T: 'static> WindowState<T> {
             let surface_id = wl_surface.id().protocol_id();
             if self.corner_radius.is_some()
                 && let Some(corner_obj) = apply_corner_radius_to_surface(
-                    &self.corner_radius_manager,
+                    &self.wl.corner_radius_manager,
                     self.corner_radius,
                     &wl_surface,
                     &qh,
                 )
             {
-                self.corner_radius_surfaces.insert(surface_id, corner_obj);
+                self.wl
+                    .corner_radius_surfaces
+                    .insert(surface_id, corner_obj);
             }
 
             // Apply shadow if enabled
             if self.shadow {
-                apply_shadow_to_surface(&self.shadow_manager, &wl_surface, &qh);
+                apply_shadow_to_surface(&self.wl.shadow_manager, &wl_surface, &qh);
             }
 
             // Apply home visibility mode if enabled
             if self.home_only {
                 if let Some(controller) = apply_home_visibility_to_surface(
-                    &self.home_visibility_manager,
+                    &self.wl.home_visibility_manager,
                     &wl_surface,
                     &qh,
                     home_visibility::VisibilityMode::HomeOnly,
                 ) {
-                    self.home_visibility_controllers
+                    self.wl
+                        .home_visibility_controllers
                         .insert(surface_id, controller);
                 }
             } else if self.hide_on_home
                 && let Some(controller) = apply_home_visibility_to_surface(
-                    &self.home_visibility_manager,
+                    &self.wl.home_visibility_manager,
                     &wl_surface,
                     &qh,
                     home_visibility::VisibilityMode::HideOnHome,
                 )
             {
-                self.home_visibility_controllers
+                self.wl
+                    .home_visibility_controllers
                     .insert(surface_id, controller);
             }
 
             // Register surface for voice mode events if enabled
             if self.voice_mode_enabled {
                 // First surface is registered as default receiver
-                let is_default = self.voice_mode_receivers.is_empty();
+                let is_default = self.wl.voice_mode_receivers.is_empty();
                 if let Some(receiver) = register_voice_mode_for_surface(
-                    &self.voice_mode_manager,
+                    &self.wl.voice_mode_manager,
                     &wl_surface,
                     &qh,
                     is_default,
                 ) {
-                    self.voice_mode_receivers.insert(surface_id, receiver);
+                    self.wl.voice_mode_receivers.insert(surface_id, receiver);
                 }
             }
 
             // Register surface for compositor usable-area reporting.
             if let Some(obj) =
-                register_usable_area_for_surface(&self.usable_area_manager, &wl_surface, &qh)
+                register_usable_area_for_surface(&self.wl.usable_area_manager, &wl_surface, &qh)
             {
-                self.usable_area_surfaces.insert(surface_id, obj);
+                self.wl.usable_area_surfaces.insert(surface_id, obj);
             }
 
             wl_surface.commit();
@@ -6513,6 +10092,9 @@ impl<T: 'static> WindowState<T> {
                 .zxdgoutput(binded_xdginfo)
                 .fractional_scale(fractional_scale)
                 .wl_output(binded_output.clone())
+                .keyboard_interactivity(self.keyboard_interactivity)
+                .layer_placement(self.anchor, self.margin)
+                .layer_config(self.layer_config())
                 // Mark as created so remove_shell() tears this surface down when
                 // the compositor sends `Closed` (e.g. its output was disabled).
                 // Without this the single-window `Active` surface lingers as a
@@ -6525,8 +10107,17 @@ impl<T: 'static> WindowState<T> {
                 .build(),
             );
         } else {
-            let displays = self.outputs.clone();
+            let displays = self.wl.outputs.clone();
             for (_, output_display) in displays.iter() {
+                let zxdgoutput = xdg_output_manager.get_xdg_output(output_display, &qh, ());
+                let output_info = self.output_info(output_display, zxdgoutput);
+                if !self.output_accepted(&output_info) {
+                    output_info.zxdgoutput.destroy();
+                    self.wl.filtered_outputs.push(output_display.clone());
+                    continue;
+                }
+                let output_config = self.output_layer_config(&output_info);
+                let anchor = output_config.anchor.unwrap_or(self.anchor);
                 let wl_surface = wmcompositer.create_surface(&qh, ()); // and create a surface. if two or more,
                 let layer_shell = globals
                     .bind::<ZwlrLayerShellV1, _, _>(&qh, 3..=4, ())
@@ -6539,17 +10130,17 @@ impl<T: 'static> WindowState<T> {
                     &qh,
                     (),
                 );
-                layer.set_anchor(self.anchor);
+                layer.set_anchor(anchor);
                 layer.set_keyboard_interactivity(self.keyboard_interactivity);
-                if let Some((init_w, init_h)) = self.size {
+                if let Some((init_w, init_h)) = output_config.size {
                     layer.set_size(init_w, init_h);
                 }
 
-                if let Some(zone) = self.exclusive_zone {
+                if let Some(zone) = output_config.exclusive_zone {
                     layer.set_exclusive_zone(zone);
                 }
 
-                if let Some((top, right, bottom, left)) = self.margin {
+                if let Some((top, right, bottom, left)) = output_config.margin {
                     layer.set_margin(top, right, bottom, left);
                 }
 
@@ -6565,7 +10156,6 @@ impl<T: 'static> WindowState<T> {
 
                 wl_surface.commit();
 
-                let zxdgoutput = xdg_output_manager.get_xdg_output(output_display, &qh, ());
                 let mut fractional_scale = None;
                 if let Some(ref fractional_scale_manager) = fractional_scale_manager {
                     fractional_scale =
@@ -6588,26 +10178,33 @@ impl<T: 'static> WindowState<T> {
                         Shell::LayerShell(layer),
                     )
                     .viewport(viewport)
-                    .zxdgoutput(Some(ZxdgOutputInfo::new(zxdgoutput)))
+                    .zxdgoutput(Some(output_info))
                     .fractional_scale(fractional_scale)
                     .wl_output(Some(output_display.clone()))
+                    .keyboard_interactivity(self.keyboard_interactivity)
+                    .layer_placement(anchor, output_config.margin)
+                    .layer_config(LayerSurfaceConfig {
+                        size: output_config.size,
+                        exclusive_zone: output_config.exclusive_zone,
+                        ..self.layer_config()
+                    })
                     // Mark as created so remove_shell() tears the panel down when
                     // the compositor sends `Closed` (monitor disabled).
                     .becreated(true)
                     .build(),
                 );
             }
-            self.message.clear();
+            self.wl.message.clear();
         }
-        self.init_finished = true;
-        self.viewporter = viewporter;
-        self.event_queue = Some(event_queue);
-        self.globals = Some(globals);
-        self.wl_compositor = Some(wmcompositer);
-        self.fractional_scale_manager = fractional_scale_manager;
-        self.cursor_manager = cursor_manager;
-        self.xdg_output_manager = Some(xdg_output_manager);
-        self.connection = Some(connection);
+        self.wl.init_finished = true;
+        self.wl.viewporter = viewporter;
+        self.wl.event_queue = Some(event_queue);
+        self.wl.globals = Some(Rc::new(globals));
+        self.wl.wl_compositor = Some(wmcompositer);
+        self.wl.fractional_scale_manager = fractional_scale_manager;
+        self.wl.cursor_manager = cursor_manager;
+        self.wl.xdg_output_manager = Some(xdg_output_manager);
+        self.wl.connection = Some(connection);
 
         Ok(self)
     }
@@ -6627,7 +10224,10 @@ impl<T: 'static> WindowState<T> {
         F: FnMut(LayerShellEvent<T, Message>, &mut WindowState<T>, Option<id::Id>) -> ReturnData<T>
             + 'static,
     {
-        self.running_with_proxy_option(Some(message_receiver), event_handler)
+        self.running_with_proxy_option(
+            Some(MessageSource::Channel(message_receiver)),
+            event_handler,
+        )
     }
     /// main event loop, every time dispatch, it will store the messages, and do callback. it will
     /// pass a LayerShellEvent, with self as mut, the last `Option<usize>` describe which unit the event
@@ -6643,9 +10243,30 @@ impl<T: 'static> WindowState<T> {
         self.running_with_proxy_option(None, event_handler)
     }
 
+    /// like [WindowState::running_with_proxy], with the messages coming from a [Stream]
+    /// instead of a calloop channel, for apps already using an async runtime. The stream is
+    /// polled by the event loop when it wakes it up
+    ///
+    /// [Stream]: futures::Stream
+    #[cfg(feature = "stream")]
+    pub fn running_with_stream<F, Message>(
+        self,
+        stream: impl futures::Stream<Item = Message> + 'static,
+        event_handler: F,
+    ) -> Result<(), LayerEventError>
+    where
+        Message: std::marker::Send + 'static,
+        F: FnMut(LayerShellEvent<T, Message>, &mut WindowState<T>, Option<id::Id>) -> ReturnData<T>
+            + 'static,
+    {
+        let stream: MessageStream<Message> = Box::pin(stream);
+        let source = calloop::stream::StreamSource::new(stream)?;
+        self.running_with_proxy_option(Some(MessageSource::Stream(source)), event_handler)
+    }
+
     fn running_with_proxy_option<F, Message>(
         mut self,
-        message_receiver: Option<Channel<Message>>,
+        mut message_receiver: Option<MessageSource<Message>>,
         mut event_handler: F,
     ) -> Result<(), LayerEventError>
     where
@@ -6653,31 +10274,65 @@ impl<T: 'static> WindowState<T> {
         F: FnMut(LayerShellEvent<T, Message>, &mut WindowState<T>, Option<id::Id>) -> ReturnData<T>
             + 'static,
     {
-        let globals = self.globals.take().unwrap();
-        let mut event_queue_origin = self.event_queue.take().unwrap();
+        let mut user_loop = if self.calloop_sources.is_empty() {
+            None
+        } else {
+            let user_loop: EventLoop<'static, Self> = EventLoop::try_new()?;
+            for CalloopSource(register) in std::mem::take(&mut self.calloop_sources) {
+                register(&user_loop.handle());
+            }
+            Some(user_loop)
+        };
+        // with the auto reconnect, the loop runs again on a new connection each time it is lost
+        while let Some(lost) = self.run_connection(message_receiver, event_handler, user_loop)? {
+            self = lost.state.reconnect()?;
+            event_handler = lost.event_handler;
+            message_receiver = lost.message_receiver;
+            user_loop = lost.user_loop;
+        }
+        Ok(())
+    }
+
+    fn run_connection<F, Message>(
+        mut self,
+        message_receiver: Option<MessageSource<Message>>,
+        mut event_handler: F,
+        user_loop: Option<EventLoop<'static, Self>>,
+    ) -> Result<Option<ConnectionLost<Self, F, Message>>, LayerEventError>
+    where
+        Message: std::marker::Send + 'static,
+        F: FnMut(LayerShellEvent<T, Message>, &mut WindowState<T>, Option<id::Id>) -> ReturnData<T>
+            + 'static,
+    {
+        // Clone (don't take): the lazily bound protocols and recreate_all need the globals
+        // on `self` while the event loop runs.
+        let globals = self.wl.globals.clone().unwrap();
+        let mut event_queue_origin = self.wl.event_queue.take().unwrap();
         let qh = event_queue_origin.handle();
-        self.queue_handle = Some(qh.clone());
-        let wmcompositer = self.wl_compositor.take().unwrap();
-        let shm = self.shm.take().unwrap();
-        self.cached_compositor = Some(wmcompositer.clone());
-        self.cached_shm = Some(shm.clone());
+        self.wl.queue_handle = Some(qh.clone());
+        let wmcompositer = self.wl.wl_compositor.take().unwrap();
+        let shm = self.wl.shm.take().unwrap();
+        self.wl.cached_compositor = Some(wmcompositer.clone());
+        self.wl.cached_shm = Some(shm.clone());
         #[cfg(feature = "screencopy")]
         {
-            self.screencopy_shm = Some(shm.clone());
+            self.wl.screencopy_shm = Some(shm.clone());
         }
-        let fractional_scale_manager = self.fractional_scale_manager.take();
-        let cursor_manager: Option<WpCursorShapeManagerV1> = self.cursor_manager.take();
+        let fractional_scale_manager = self.wl.fractional_scale_manager.clone();
+        let cursor_manager: Option<WpCursorShapeManagerV1> = self.wl.cursor_manager.take();
         // Clone (don't take): the event-loop closures below use this local to bind
         // xdg_outputs for new surfaces, but the `Dispatch<WlSurface>` enter handler
         // also needs it on `self` to bind the entered output's xdg_output (which is
         // what reports the output's logical size). Taking it here would leave
         // `self.xdg_output_manager` None and silently disable that path.
-        let xdg_output_manager = self.xdg_output_manager.clone().unwrap();
-        let connection = self.connection.take().unwrap();
+        let xdg_output_manager = self.wl.xdg_output_manager.clone().unwrap();
+        // Clone (don't take): `connection()` and the flushes of the hide/show paths need it on
+        // `self` while the event loop runs.
+        let connection = self.wl.connection.clone().unwrap();
         let mut init_event = None;
-        let wmbase = self.wmbase.take().unwrap();
-        let viewporter = self.viewporter.take();
-        let zxdg_decoration_manager = self.xdg_decoration_manager.take();
+        let wmbase = self.wl.wmbase.clone().unwrap();
+        let viewporter = self.wl.viewporter.clone();
+        let zxdg_decoration_manager = self.wl.xdg_decoration_manager.take();
 
         let cursor_update_context = CursorUpdateContext {
             cursor_manager,
@@ -6706,27 +10361,66 @@ impl<T: 'static> WindowState<T> {
                         None,
                     ));
                 }
-                _ => panic!("Not provide server here"),
+                _ => {
+                    return Err(LayerEventError::UnexpectedReturnData {
+                        request: "InitRequest",
+                    });
+                }
             }
         }
+        if self.reconnect_outputs.is_some() {
+            self.restore_units();
+            self.handle_event(&mut event_handler, LayerShellEvent::Reconnected, None);
+        }
 
-        struct EventWrapper<Raw, F> {
+        struct EventWrapper<Raw: 'static, F> {
             raw: Raw,
             fun: F,
             loop_handle: LoopHandle<'static, Self>,
+            /// the loop of the sources given by `with_calloop_source`
+            user_loop: Option<EventLoop<'static, Raw>>,
+        }
+
+        impl<Raw, F> EventWrapper<Raw, F> {
+            fn dispatch_user_loop(&mut self) {
+                if let Some(user_loop) = self.user_loop.as_mut()
+                    && let Err(err) = user_loop.dispatch(Duration::ZERO, &mut self.raw)
+                {
+                    log::error!("failed to dispatch the user calloop sources: {err}");
+                }
+            }
         }
 
-        let mut event_loop: EventLoop<_> =
-            EventLoop::try_new().expect("Failed to initialize the event loop");
+        let mut event_loop: EventLoop<_> = EventLoop::try_new()?;
+
+        use std::os::fd::AsFd;
+        if let Some(user_loop) = &user_loop {
+            // the nested loop's fd is readable when one of its sources is ready
+            let user_loop_fd = user_loop
+                .as_fd()
+                .try_clone_to_owned()
+                .map_err(|err| LayerEventError::EventLoopInitError(err.into()))?;
+            event_loop
+                .handle()
+                .insert_source(
+                    Generic::new(user_loop_fd, Interest::READ, Mode::Level),
+                    |_, _, r_window_state: &mut EventWrapper<Self, F>| {
+                        r_window_state.dispatch_user_loop();
+                        Ok(PostAction::Continue)
+                    },
+                )
+                .map_err(|err| LayerEventError::SourceInsertError(err.error))?;
+        }
 
         let event_queue = connection.new_event_queue::<EventWrapper<Self, F>>();
         WaylandSource::new(connection.clone(), event_queue)
             .insert(event_loop.handle())
-            .expect("Failed to init wayland source");
+            .map_err(|err| LayerEventError::SourceInsertError(err.error))?;
         let mut state = EventWrapper {
             raw: self,
             fun: event_handler,
             loop_handle: event_loop.handle(),
+            user_loop,
         };
 
         let signal = event_loop.get_signal();
@@ -6736,9 +10430,9 @@ impl<T: 'static> WindowState<T> {
         // so the event loop re-dispatches immediately and the ping callback
         // runs NormalDispatch + refresh — giving zero-latency response for
         // animations while letting the idle timer stay at 50ms for battery.
-        let (ping_sender, ping_source) =
-            calloop::ping::make_ping().expect("Failed to create ping source");
-        state.raw.ping_sender = Some(ping_sender);
+        let (ping_sender, ping_source) = calloop::ping::make_ping()
+            .map_err(|err| LayerEventError::EventLoopInitError(err.into()))?;
+        state.raw.wl.ping_sender = Some(ping_sender);
 
         // Clone resources needed by the ping callback for the SHM buffer path.
         // For GPU-rendering apps (use_display_handle=true) these are unused.
@@ -6759,776 +10453,931 @@ impl<T: 'static> WindowState<T> {
                 );
 
                 // Refresh/present cycle — renders frames immediately.
-                let all_hidden = window_state.all_surfaces_hidden();
-                for idx in 0..window_state.units.len() {
-                    let unit = &mut window_state.units[idx];
-                    let (width, height) = unit.size;
-                    if width == 0 || height == 0 {
-                        continue;
-                    }
-                    if all_hidden && unit.initial_refresh_sent {
-                        continue;
-                    }
-                    if unit.take_present_slot() {
-                        let unit_id = unit.id;
-                        let is_created = unit.becreated;
-                        let scale_float = unit.scale_float();
-                        let wl_surface = unit.wl_surface.clone();
-                        if unit.buffer.is_none() && !window_state.use_display_handle {
-                            let Ok(mut file) = tempfile::tempfile() else {
-                                log::error!("Cannot create new file from tempfile");
-                                return;
-                            };
-                            let ReturnData::WlBuffer(buffer) = (event_handler)(
-                                LayerShellEvent::RequestBuffer(
-                                    &mut file,
-                                    &shm_for_ping,
-                                    &qh_for_ping,
-                                    width,
-                                    height,
-                                ),
-                                window_state,
-                                Some(unit_id),
-                            ) else {
-                                panic!("You cannot return this one");
-                            };
-                            wl_surface.attach(Some(&buffer), 0, 0);
+                window_state.present_units(event_handler, &shm_for_ping, &qh_for_ping);
+            })
+            .map_err(|err| LayerEventError::SourceInsertError(err.error))?;
+
+        let on_message = |event: Message, r_window_state: &mut EventWrapper<Self, F>| {
+            let window_state = &mut r_window_state.raw;
+            let event_handler = &mut r_window_state.fun;
+            window_state.handle_event(&mut *event_handler, LayerShellEvent::UserEvent(event), None);
+            // Ping the event loop so NormalDispatch + refresh runs
+            // immediately in this iteration, instead of waiting for
+            // the next timer tick.
+            if let Some(sender) = &window_state.wl.ping_sender {
+                sender.ping();
+            }
+        };
+        // Insert message channel as event source (calloop-style), through a dispatcher to get
+        // it back when the connection is lost
+        let message_dispatcher = match message_receiver {
+            Some(MessageSource::Channel(channel)) => {
+                let dispatcher = Dispatcher::new(
+                    channel,
+                    move |event, _, r_window_state: &mut EventWrapper<Self, F>| {
+                        if let channel::Event::Msg(event) = event {
+                            on_message(event, r_window_state);
+                        }
+                    },
+                );
+                event_loop
+                    .handle()
+                    .register_dispatcher(dispatcher.clone())
+                    .map_err(LayerEventError::SourceInsertError)?;
+                Some(MessageDispatcher::Channel(dispatcher))
+            }
+            #[cfg(feature = "stream")]
+            Some(MessageSource::Stream(stream)) => {
+                let dispatcher = Dispatcher::new(
+                    stream,
+                    move |event, _, r_window_state: &mut EventWrapper<Self, F>| {
+                        if let Some(event) = event {
+                            on_message(event, r_window_state);
+                        }
+                    },
+                );
+                event_loop
+                    .handle()
+                    .register_dispatcher(dispatcher.clone())
+                    .map_err(LayerEventError::SourceInsertError)?;
+                Some(MessageDispatcher::Stream(dispatcher))
+            }
+            None => None,
+        };
+
+        // a dispatcher keeps the timer reachable, to wake it when it sleeps, see
+        // `with_on_demand_presentation`
+        let timer = Dispatcher::new(
+            Timer::from_duration(Duration::from_millis(50)),
+            move |_, _, r_window_state: &mut EventWrapper<Self, F>| {
+                let window_state = &mut r_window_state.raw;
+                let event_handler = &mut r_window_state.fun;
+
+                let tick_start = Instant::now();
+                let has_pending = window_state.units.iter().any(|u| u.should_refresh());
+                if has_pending {
+                    log::debug!("[evloop] timer callback (pending_refresh=true)");
+                }
+                for change in std::mem::take(&mut window_state.wl.output_changes) {
+                    let event = match change {
+                        OutputChange::Added(output) => {
+                            // the name event comes after the bind, it is known by now
+                            let name = window_state.output_name(&output);
+                            LayerShellEvent::OutputAdded { output, name }
+                        }
+                        OutputChange::Removed { output, name } => {
+                            LayerShellEvent::OutputRemoved { output, name }
+                        }
+                    };
+                    window_state.handle_event(&mut *event_handler, event, None);
+                }
+                let mut messages = Vec::new();
+                std::mem::swap(&mut messages, &mut window_state.wl.message);
+                if window_state.coalesce_motion {
+                    coalesce_motion(&mut messages);
+                }
+                for msg in messages.iter() {
+                    match msg {
+                        (
+                            index_info,
+                            msg_inner @ DispatchMessageInner::XdgInfoChanged { change_type, .. },
+                        ) => {
+                            // Raw layershellev consumers get the typed callback
+                            // (it only carries the change kind, no dimensions)...
+                            window_state.handle_event(
+                                &mut *event_handler,
+                                LayerShellEvent::XdgInfoChanged(*change_type),
+                                *index_info,
+                            );
+                            // ...but iced_layershell only handles the general
+                            // `RequestMessages(DispatchMessage)` path, so the full
+                            // message (carrying the output's logical size) must be
+                            // forwarded there too — otherwise this arm swallows the
+                            // event and the size never reaches the iced app as
+                            // `WindowEvent::OutputLogicalSize`.
+                            let msg: DispatchMessage = msg_inner.clone().into();
+                            if !window_state.input_filter_accepts(&msg) {
+                                continue;
+                            }
+                            window_state.handle_event(
+                                &mut *event_handler,
+                                LayerShellEvent::RequestMessages(&msg),
+                                *index_info,
+                            );
+                        }
+                        (_, DispatchMessageInner::NewDisplay(output_display)) => {
+                            // AllScreens always gets one surface per output.
+                            //
+                            // Single-surface `Active` apps (e.g. the
+                            // notifications daemon) normally ignore NewDisplay
+                            // — but if their only surface was destroyed because
+                            // its output was disabled, nothing would ever
+                            // re-create it and ShowWindow becomes a silent no-op
+                            // (window_manager is empty). So when an Active app
+                            // has NO live surface left, treat this newly-appeared
+                            // output as the place to re-create it. The live-unit
+                            // guard makes this a no-op at boot and whenever the
+                            // surface still exists, so no duplicates are created.
+                            let recreate_lost_active =
+                                window_state.is_active() && !window_state.has_live_surface();
+                            if !window_state.is_allscreens() && !recreate_lost_active {
+                                continue;
+                            }
+                            let zxdgoutput =
+                                xdg_output_manager.get_xdg_output(output_display, &qh, ());
+                            let output_info = window_state.output_info(output_display, zxdgoutput);
+                            if !window_state.output_accepted(&output_info) {
+                                output_info.zxdgoutput.destroy();
+                                window_state
+                                    .wl
+                                    .filtered_outputs
+                                    .push(output_display.clone());
+                                continue;
+                            }
+                            let output_config = window_state.output_layer_config(&output_info);
+                            let anchor = output_config.anchor.unwrap_or(window_state.anchor);
+                            let wl_surface = wmcompositer.create_surface(&qh, ()); // and create a surface. if two or more,
+                            let layer_shell = globals
+                                .bind::<ZwlrLayerShellV1, _, _>(&qh, 3..=4, ())
+                                .unwrap();
+                            let layer = layer_shell.get_layer_surface(
+                                &wl_surface,
+                                Some(output_display),
+                                window_state.layer,
+                                window_state.namespace.clone(),
+                                &qh,
+                                (),
+                            );
+                            layer.set_anchor(anchor);
+                            layer.set_keyboard_interactivity(window_state.keyboard_interactivity);
+                            if let Some((init_w, init_h)) = output_config.size {
+                                layer.set_size(init_w, init_h);
+                            }
+
+                            if let Some(zone) = output_config.exclusive_zone {
+                                layer.set_exclusive_zone(zone);
+                            }
+
+                            if let Some((top, right, bottom, left)) = output_config.margin {
+                                layer.set_margin(top, right, bottom, left);
+                            }
+
+                            if window_state.events_transparent {
+                                let region = wmcompositer.create_region(&qh, ());
+                                wl_surface.set_input_region(Some(&region));
+                                region.destroy();
+                            }
+
+                            // Apply blur / corner radius / shadow / home
+                            // visibility / voice mode. Without this a monitor
+                            // enabled after startup gets a panel with none of
+                            // these effects (e.g. no blur).
+                            window_state.apply_surface_effects(&wl_surface, &qh);
+
                             wl_surface.commit();
-                            window_state.units[idx].buffer = Some(buffer);
+
+                            let mut fractional_scale = None;
+                            if let Some(ref fractional_scale_manager) = fractional_scale_manager {
+                                fractional_scale =
+                                    Some(fractional_scale_manager.get_fractional_scale(
+                                        &wl_surface,
+                                        &qh,
+                                        (),
+                                    ));
+                            }
+                            let viewport = viewporter
+                                .as_ref()
+                                .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
+                            // so during the init Configure of the shell, a buffer, atleast a buffer is needed.
+                            // and if you need to reconfigure it, you need to commit the wl_surface again
+                            // so because this is just an example, so we just commit it once
+                            // like if you want to reset anchor or KeyboardInteractivity or resize, commit is needed
+
+                            window_state.push_window(
+                                WindowStateUnitBuilder::new(
+                                    id::Id::unique(),
+                                    qh.clone(),
+                                    connection.display(),
+                                    wl_surface,
+                                    Shell::LayerShell(layer),
+                                )
+                                .viewport(viewport)
+                                .zxdgoutput(Some(output_info))
+                                .fractional_scale(fractional_scale)
+                                .wl_output(Some(output_display.clone()))
+                                .keyboard_interactivity(window_state.keyboard_interactivity)
+                                .layer_placement(anchor, output_config.margin)
+                                .layer_config(LayerSurfaceConfig {
+                                    size: output_config.size,
+                                    exclusive_zone: output_config.exclusive_zone,
+                                    ..window_state.layer_config()
+                                })
+                                // Mark as created so remove_shell() tears the
+                                // panel down when the compositor sends `Closed`
+                                // (monitor disabled); otherwise re-enabling the
+                                // monitor leaves the old surface alive and the
+                                // panel ends up duplicated.
+                                .becreated(true)
+                                .build(),
+                            );
+                        }
+                        _ => {
+                            let (index_message, msg) = msg;
+
+                            let msg: DispatchMessage = msg.clone().into();
+                            if !window_state.input_filter_accepts(&msg)
+                                || !window_state.keyboard_accepts(*index_message, &msg)
+                            {
+                                continue;
+                            }
+                            window_state.handle_event(
+                                &mut *event_handler,
+                                LayerShellEvent::RequestMessages(&msg),
+                                *index_message,
+                            );
                         }
-                        window_state.handle_event(
-                            &mut *event_handler,
-                            LayerShellEvent::RequestMessages(&DispatchMessage::RequestRefresh {
-                                width,
-                                height,
-                                is_created,
-                                scale_float,
-                            }),
-                            Some(unit_id),
-                        );
-                        window_state.units[idx].initial_refresh_sent = true;
-                        window_state.units[idx].reset_present_slot();
                     }
                 }
-            })
-            .expect("Failed to insert ping source");
 
-        // Insert message channel as event source (calloop-style)
-        if let Some(channel) = message_receiver {
-            event_loop
-                .handle()
-                .insert_source(channel, |event, _, r_window_state| {
-                    let channel::Event::Msg(event) = event else {
-                        return;
-                    };
-                    let window_state = &mut r_window_state.raw;
-                    let event_handler = &mut r_window_state.fun;
+                // User events are now processed by the ping source for immediate response.
+                // The timer only handles internal messages and periodic dispatch.
+                let had_activity = !messages.is_empty()
+                    || has_pending
+                    || window_state.units.iter().any(|unit| unit.should_refresh());
+                if !window_state.dispatch_only_on_activity || had_activity {
                     window_state.handle_event(
                         &mut *event_handler,
-                        LayerShellEvent::UserEvent(event),
+                        LayerShellEvent::NormalDispatch,
                         None,
                     );
-                    // Ping the event loop so NormalDispatch + refresh runs
-                    // immediately in this iteration, instead of waiting for
-                    // the next timer tick.
-                    if let Some(sender) = &window_state.ping_sender {
-                        sender.ping();
-                    }
-                })
-                .expect("Failed to insert message channel source");
-        }
-
-        event_loop
-            .handle()
-            .insert_source(
-                Timer::from_duration(Duration::from_millis(50)),
-                move |_, _, r_window_state| {
-                    let window_state = &mut r_window_state.raw;
-                    let event_handler = &mut r_window_state.fun;
-
-                    let has_pending = window_state.units.iter().any(|u| u.should_refresh());
-                    if has_pending {
-                        log::debug!("[evloop] timer callback (pending_refresh=true)");
-                    }
-                    let mut messages = Vec::new();
-                    std::mem::swap(&mut messages, &mut window_state.message);
-                    for msg in messages.iter() {
-                        match msg {
-                            (
-                                index_info,
-                                msg_inner @ DispatchMessageInner::XdgInfoChanged { change_type, .. },
-                            ) => {
-                                // Raw layershellev consumers get the typed callback
-                                // (it only carries the change kind, no dimensions)...
-                                window_state.handle_event(
-                                    &mut *event_handler,
-                                    LayerShellEvent::XdgInfoChanged(*change_type),
-                                    *index_info,
+                }
+                if had_activity {
+                    window_state
+                        .frame_stats
+                        .record_dispatch(tick_start.elapsed());
+                }
+                loop {
+                    let mut return_data = vec![];
+                    std::mem::swap(&mut window_state.return_data, &mut return_data);
+
+                    for data in return_data {
+                        match data {
+                            ReturnData::RequestExit => {
+                                signal.stop();
+                                return TimeoutAction::Drop;
+                            }
+                            ReturnData::RequestSetCursorShape((shape_name, pointer)) => {
+                                let Some(serial) = window_state.wl.enter_serial else {
+                                    continue;
+                                };
+                                set_cursor_shape(
+                                    &cursor_update_context,
+                                    &mut window_state.wl.cursor_shape_device,
+                                    shape_name,
+                                    pointer,
+                                    serial,
                                 );
-                                // ...but iced_layershell only handles the general
-                                // `RequestMessages(DispatchMessage)` path, so the full
-                                // message (carrying the output's logical size) must be
-                                // forwarded there too — otherwise this arm swallows the
-                                // event and the size never reaches the iced app as
-                                // `WindowEvent::OutputLogicalSize`.
-                                let msg: DispatchMessage = msg_inner.clone().into();
-                                window_state.handle_event(
-                                    &mut *event_handler,
-                                    LayerShellEvent::RequestMessages(&msg),
-                                    *index_info,
+                            }
+                            ReturnData::RequestSetCursorBuffer {
+                                buffer,
+                                hotspot,
+                                pointer,
+                            } => {
+                                let Some(serial) = window_state.wl.enter_serial else {
+                                    continue;
+                                };
+                                set_cursor_buffer(
+                                    &cursor_update_context,
+                                    &buffer,
+                                    hotspot,
+                                    &pointer,
+                                    serial,
                                 );
                             }
-                            (_, DispatchMessageInner::NewDisplay(output_display)) => {
-                                // AllScreens always gets one surface per output.
-                                //
-                                // Single-surface `Active` apps (e.g. the
-                                // notifications daemon) normally ignore NewDisplay
-                                // — but if their only surface was destroyed because
-                                // its output was disabled, nothing would ever
-                                // re-create it and ShowWindow becomes a silent no-op
-                                // (window_manager is empty). So when an Active app
-                                // has NO live surface left, treat this newly-appeared
-                                // output as the place to re-create it. The live-unit
-                                // guard makes this a no-op at boot and whenever the
-                                // surface still exists, so no duplicates are created.
-                                let recreate_lost_active =
-                                    window_state.is_active() && !window_state.has_live_surface();
-                                if !window_state.is_allscreens() && !recreate_lost_active {
+                            ReturnData::RequestHideCursor(pointer) => {
+                                let Some(serial) = window_state.wl.enter_serial else {
                                     continue;
-                                }
+                                };
+                                pointer.set_cursor(serial, None, 0, 0);
+                            }
+                            ReturnData::RequestSetCursorShapeTyped((shape, pointer)) => {
+                                let Some(serial) = window_state.wl.enter_serial else {
+                                    continue;
+                                };
+                                set_cursor_shape_typed(
+                                    &cursor_update_context,
+                                    &mut window_state.wl.cursor_shape_device,
+                                    shape,
+                                    pointer,
+                                    serial,
+                                );
+                            }
+                            ReturnData::NewLayerShell((
+                                NewLayerShellSettings {
+                                    size,
+                                    layer,
+                                    anchor,
+                                    exclusive_zone,
+                                    margin,
+                                    keyboard_interactivity,
+                                    output_option: output_type,
+                                    events_transparent,
+                                    namespace,
+                                    blur,
+                                    blur_radius,
+                                    blur_saturation,
+                                    blur_tint,
+                                    blur_border,
+                                    shadow,
+                                    corner_radius,
+                                    transition,
+                                    auto_size: _, // Auto-size is handled at the iced level
+                                    start_hidden,
+                                    parent,
+                                },
+                                id,
+                                info,
+                            )) => {
+                                let output = match output_type {
+                                    OutputOption::Output(output) => Some(output),
+                                    // Bind to a monitor by xdg name (used for
+                                    // moving a surface across outputs).
+                                    OutputOption::OutputName(ref name) => window_state
+                                        .wl
+                                        .output_handles
+                                        .iter()
+                                        .find(|(n, _)| n == name)
+                                        .map(|(_, o)| o.clone()),
+                                    _ => {
+                                        let pos = window_state.surface_pos();
+
+                                        let mut output = pos
+                                            .and_then(|p| window_state.units[p].wl_output.as_ref());
+
+                                        if window_state.wl.last_wloutput.is_none()
+                                            && window_state.wl.outputs.len()
+                                                > window_state.last_unit_index
+                                        {
+                                            window_state.wl.last_wloutput = Some(
+                                                window_state.wl.outputs
+                                                    [window_state.last_unit_index]
+                                                    .1
+                                                    .clone(),
+                                            );
+                                        }
+
+                                        if matches!(output_type, events::OutputOption::LastOutput) {
+                                            output = window_state.wl.last_wloutput.as_ref();
+                                        }
+
+                                        output.cloned()
+                                    }
+                                };
+
                                 let wl_surface = wmcompositer.create_surface(&qh, ()); // and create a surface. if two or more,
                                 let layer_shell = globals
                                     .bind::<ZwlrLayerShellV1, _, _>(&qh, 3..=4, ())
                                     .unwrap();
+                                let namespace =
+                                    namespace.unwrap_or_else(|| window_state.namespace.clone());
+                                let layer_config = LayerSurfaceConfig {
+                                    layer,
+                                    namespace: namespace.clone(),
+                                    size,
+                                    exclusive_zone,
+                                    events_transparent,
+                                };
                                 let layer = layer_shell.get_layer_surface(
                                     &wl_surface,
-                                    Some(output_display),
-                                    window_state.layer,
-                                    window_state.namespace.clone(),
+                                    output.as_ref(),
+                                    layer,
+                                    namespace,
                                     &qh,
                                     (),
                                 );
-                                layer.set_anchor(window_state.anchor);
-                                layer
-                                    .set_keyboard_interactivity(window_state.keyboard_interactivity);
-                                if let Some((init_w, init_h)) = window_state.size {
+                                layer.set_anchor(anchor);
+                                layer.set_keyboard_interactivity(keyboard_interactivity);
+                                if let Some((init_w, init_h)) = size {
                                     layer.set_size(init_w, init_h);
                                 }
 
-                                if let Some(zone) = window_state.exclusive_zone {
+                                if let Some(zone) = exclusive_zone {
                                     layer.set_exclusive_zone(zone);
                                 }
 
-                                if let Some((top, right, bottom, left)) = window_state.margin {
+                                if let Some((top, right, bottom, left)) = margin {
                                     layer.set_margin(top, right, bottom, left);
                                 }
 
-                                if window_state.events_transparent {
+                                if events_transparent {
                                     let region = wmcompositer.create_region(&qh, ());
                                     wl_surface.set_input_region(Some(&region));
                                     region.destroy();
                                 }
 
-                                // Apply blur / corner radius / shadow / home
-                                // visibility / voice mode. Without this a monitor
-                                // enabled after startup gets a panel with none of
-                                // these effects (e.g. no blur).
-                                window_state.apply_surface_effects(&wl_surface, &qh);
+                                // Apply blur if requested
+                                if blur {
+                                    // Lazily bind blur manager if not already bound
+                                    if window_state.wl.blur_manager.is_none() {
+                                        window_state.wl.blur_manager = globals
+                                                .bind::<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager, _, _>(
+                                                    &qh,
+                                                    1..=3,
+                                                    (),
+                                                )
+                                                .ok();
+                                        if window_state.wl.blur_manager.is_some() {
+                                            log::info!(
+                                                "Lazily bound blur manager for NewLayerShell surface"
+                                            );
+                                        } else {
+                                            log::warn!(
+                                                "Blur requested but compositor does not support org_kde_kwin_blur_manager protocol"
+                                            );
+                                        }
+                                    }
+                                    apply_blur_to_surface(
+                                        &window_state.wl.blur_manager,
+                                        &wl_surface,
+                                        &qh,
+                                        blur_radius,
+                                        blur_saturation,
+                                        blur_tint,
+                                        blur_border,
+                                    );
+                                }
 
-                                wl_surface.commit();
+                                let surface_id = wl_surface.id().protocol_id();
+                                // Remember this surface's frosted-glass params even when blur
+                                // is deferred (auto-size sets `blur = false` at creation): the
+                                // later BlurChange(true) re-enable reads them back so the tint
+                                // isn't lost to the compositor default.
+                                window_state.wl.blur_params.insert(
+                                    surface_id,
+                                    (blur_radius, blur_saturation, blur_tint, blur_border),
+                                );
 
-                                let zxdgoutput =
-                                    xdg_output_manager.get_xdg_output(output_display, &qh, ());
-                                let mut fractional_scale = None;
-                                if let Some(ref fractional_scale_manager) = fractional_scale_manager
+                                // Apply corner radius if set (per-surface setting takes precedence, then fallback to window_state)
+                                let effective_corner_radius =
+                                    corner_radius.or(window_state.corner_radius);
+                                log::debug!(
+                                    "NewLayerShell: corner_radius={:?}, effective={:?}",
+                                    corner_radius,
+                                    effective_corner_radius
+                                );
+                                if effective_corner_radius.is_some()
+                                    && let Some(corner_obj) = apply_corner_radius_to_surface(
+                                        &window_state.wl.corner_radius_manager,
+                                        effective_corner_radius,
+                                        &wl_surface,
+                                        &qh,
+                                    )
                                 {
-                                    fractional_scale =
-                                        Some(fractional_scale_manager.get_fractional_scale(
-                                            &wl_surface,
-                                            &qh,
-                                            (),
-                                        ));
+                                    window_state
+                                        .wl
+                                        .corner_radius_surfaces
+                                        .insert(surface_id, corner_obj);
                                 }
-                                let viewport = viewporter
-                                    .as_ref()
-                                    .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
-                                // so during the init Configure of the shell, a buffer, atleast a buffer is needed.
-                                // and if you need to reconfigure it, you need to commit the wl_surface again
-                                // so because this is just an example, so we just commit it once
-                                // like if you want to reset anchor or KeyboardInteractivity or resize, commit is needed
 
-                                window_state.push_window(
-                                    WindowStateUnitBuilder::new(
-                                        id::Id::unique(),
-                                        qh.clone(),
-                                        connection.display(),
-                                        wl_surface,
-                                        Shell::LayerShell(layer),
-                                    )
-                                    .viewport(viewport)
-                                    .zxdgoutput(Some(ZxdgOutputInfo::new(zxdgoutput)))
-                                    .fractional_scale(fractional_scale)
-                                    .wl_output(Some(output_display.clone()))
-                                    // Mark as created so remove_shell() tears the
-                                    // panel down when the compositor sends `Closed`
-                                    // (monitor disabled); otherwise re-enabling the
-                                    // monitor leaves the old surface alive and the
-                                    // panel ends up duplicated.
-                                    .becreated(true)
-                                    .build(),
-                                );
-                            }
-                            _ => {
-                                let (index_message, msg) = msg;
-
-                                let msg: DispatchMessage = msg.clone().into();
-                                window_state.handle_event(
-                                    &mut *event_handler,
-                                    LayerShellEvent::RequestMessages(&msg),
-                                    *index_message,
+                                // Apply shadow if enabled (per-surface setting takes precedence, then fallback to window_state)
+                                log::debug!(
+                                    "NewLayerShell: shadow={}, window_state.shadow={}, shadow_manager present={}",
+                                    shadow,
+                                    window_state.shadow,
+                                    window_state.wl.shadow_manager.is_some()
                                 );
-                            }
-                        }
-                    }
+                                if shadow || window_state.shadow {
+                                    apply_shadow_to_surface(
+                                        &window_state.wl.shadow_manager,
+                                        &wl_surface,
+                                        &qh,
+                                    );
+                                } else {
+                                    log::debug!(
+                                        "NewLayerShell: shadow not requested for this surface"
+                                    );
+                                }
 
-                    // User events are now processed by the ping source for immediate response.
-                    // The timer only handles internal messages and periodic dispatch.
-                    window_state.handle_event(
-                        &mut *event_handler,
-                        LayerShellEvent::NormalDispatch,
-                        None,
-                    );
-                    loop {
-                        let mut return_data = vec![];
-                        std::mem::swap(&mut window_state.return_data, &mut return_data);
-
-                        for data in return_data {
-                            match data {
-                                ReturnData::RequestExit => {
-                                    signal.stop();
-                                    return TimeoutAction::Drop;
-                                }
-                                ReturnData::RequestSetCursorShape((shape_name, pointer)) => {
-                                    let Some(serial) = window_state.enter_serial else {
-                                        continue;
-                                    };
-                                    set_cursor_shape(
-                                        &cursor_update_context,
-                                        shape_name,
-                                        pointer,
-                                        serial,
-                                    );
-                                }
-                                ReturnData::NewLayerShell((
-                                    NewLayerShellSettings {
-                                        size,
-                                        layer,
-                                        anchor,
-                                        exclusive_zone,
-                                        margin,
-                                        keyboard_interactivity,
-                                        output_option: output_type,
-                                        events_transparent,
-                                        namespace,
-                                        blur,
-                                        blur_radius,
-                                        blur_saturation,
-                                        blur_tint,
-                                        blur_border,
-                                        shadow,
-                                        corner_radius,
-                                        transition,
-                                        auto_size: _, // Auto-size is handled at the iced level
-                                        start_hidden,
-                                    },
-                                    id,
-                                    info,
-                                )) => {
-                                    let output = match output_type {
-                                        OutputOption::Output(output) => Some(output),
-                                        // Bind to a monitor by xdg name (used for
-                                        // moving a surface across outputs).
-                                        OutputOption::OutputName(ref name) => window_state
-                                            .output_handles
-                                            .iter()
-                                            .find(|(n, _)| n == name)
-                                            .map(|(_, o)| o.clone()),
-                                        _ => {
-                                            let pos = window_state.surface_pos();
-
-                                            let mut output =
-                                                pos.and_then(|p| window_state.units[p].wl_output.as_ref());
-
-                                            if window_state.last_wloutput.is_none()
-                                                && window_state.outputs.len() > window_state.last_unit_index
-                                            {
-                                                window_state.last_wloutput = Some(
-                                                    window_state.outputs[window_state.last_unit_index]
-                                                        .1
-                                                        .clone(),
-                                                );
-                                            }
-
-                                            if matches!(output_type, events::OutputOption::LastOutput) {
-                                                output = window_state.last_wloutput.as_ref();
-                                            }
-
-                                            output.cloned()
-                                        }
-                                    };
-
-
-                                    let wl_surface = wmcompositer.create_surface(&qh, ()); // and create a surface. if two or more,
-                                    let layer_shell = globals
-                                        .bind::<ZwlrLayerShellV1, _, _>(&qh, 3..=4, ())
-                                        .unwrap();
-                                    let layer = layer_shell.get_layer_surface(
+                                // Apply home visibility mode if enabled
+                                if window_state.home_only {
+                                    if let Some(controller) = apply_home_visibility_to_surface(
+                                        &window_state.wl.home_visibility_manager,
                                         &wl_surface,
-                                        output.as_ref(),
-                                        layer,
-                                        namespace.unwrap_or_else(|| window_state.namespace.clone()),
                                         &qh,
-                                        (),
-                                    );
-                                    layer.set_anchor(anchor);
-                                    layer.set_keyboard_interactivity(keyboard_interactivity);
-                                    if let Some((init_w, init_h)) = size {
-                                        layer.set_size(init_w, init_h);
-                                    }
-
-                                    if let Some(zone) = exclusive_zone {
-                                        layer.set_exclusive_zone(zone);
-                                    }
-
-                                    if let Some((top, right, bottom, left)) = margin {
-                                        layer.set_margin(top, right, bottom, left);
-                                    }
-
-                                    if events_transparent {
-                                        let region = wmcompositer.create_region(&qh, ());
-                                        wl_surface.set_input_region(Some(&region));
-                                        region.destroy();
-                                    }
-
-                                    // Apply blur if requested
-                                    if blur {
-                                        // Lazily bind blur manager if not already bound
-                                        if window_state.blur_manager.is_none() {
-                                            window_state.blur_manager = globals
-                                                .bind::<blur::org_kde_kwin_blur_manager::OrgKdeKwinBlurManager, _, _>(
-                                                    &qh,
-                                                    1..=3,
-                                                    (),
-                                                )
-                                                .ok();
-                                            if window_state.blur_manager.is_some() {
-                                                log::info!("Lazily bound blur manager for NewLayerShell surface");
-                                            } else {
-                                                log::warn!("Blur requested but compositor does not support org_kde_kwin_blur_manager protocol");
-                                            }
-                                        }
-                                        apply_blur_to_surface(&window_state.blur_manager, &wl_surface, &qh, blur_radius, blur_saturation, blur_tint, blur_border);
-                                    }
-
-                                    let surface_id = wl_surface.id().protocol_id();
-                                    // Remember this surface's frosted-glass params even when blur
-                                    // is deferred (auto-size sets `blur = false` at creation): the
-                                    // later BlurChange(true) re-enable reads them back so the tint
-                                    // isn't lost to the compositor default.
-                                    window_state.blur_params.insert(
-                                        surface_id,
-                                        (blur_radius, blur_saturation, blur_tint, blur_border),
-                                    );
-
-                                    // Apply corner radius if set (per-surface setting takes precedence, then fallback to window_state)
-                                    let effective_corner_radius = corner_radius.or(window_state.corner_radius);
-                                    log::debug!("NewLayerShell: corner_radius={:?}, effective={:?}", corner_radius, effective_corner_radius);
-                                    if effective_corner_radius.is_some()
-                                        && let Some(corner_obj) = apply_corner_radius_to_surface(&window_state.corner_radius_manager, effective_corner_radius, &wl_surface, &qh) {
-                                            window_state.corner_radius_surfaces.insert(surface_id, corner_obj);
-                                        }
-
-                                    // Apply shadow if enabled (per-surface setting takes precedence, then fallback to window_state)
-                                    log::debug!("NewLayerShell: shadow={}, window_state.shadow={}, shadow_manager present={}", 
-                                        shadow, window_state.shadow, window_state.shadow_manager.is_some());
-                                    if shadow || window_state.shadow {
-                                        apply_shadow_to_surface(&window_state.shadow_manager, &wl_surface, &qh);
-                                    } else {
-                                        log::debug!("NewLayerShell: shadow not requested for this surface");
-                                    }
-
-                                    // Apply home visibility mode if enabled
-                                    if window_state.home_only {
-                                        if let Some(controller) = apply_home_visibility_to_surface(
-                                            &window_state.home_visibility_manager,
-                                            &wl_surface,
-                                            &qh,
-                                            home_visibility::VisibilityMode::HomeOnly,
-                                        ) {
-                                            window_state.home_visibility_controllers.insert(surface_id, controller);
-                                        }
-                                    } else if window_state.hide_on_home
-                                        && let Some(controller) = apply_home_visibility_to_surface(
-                                            &window_state.home_visibility_manager,
-                                            &wl_surface,
-                                            &qh,
-                                            home_visibility::VisibilityMode::HideOnHome,
-                                        ) {
-                                            window_state.home_visibility_controllers.insert(surface_id, controller);
-                                        }
-
-                                    // Register surface for voice mode events if enabled
-                                    if window_state.voice_mode_enabled {
-                                        let is_default = window_state.voice_mode_receivers.is_empty();
-                                        if let Some(receiver) = register_voice_mode_for_surface(
-                                            &window_state.voice_mode_manager,
-                                            &wl_surface,
-                                            &qh,
-                                            is_default,
-                                        ) {
-                                            window_state.voice_mode_receivers.insert(surface_id, receiver);
-                                        }
+                                        home_visibility::VisibilityMode::HomeOnly,
+                                    ) {
+                                        window_state
+                                            .wl
+                                            .home_visibility_controllers
+                                            .insert(surface_id, controller);
                                     }
+                                } else if window_state.hide_on_home
+                                    && let Some(controller) = apply_home_visibility_to_surface(
+                                        &window_state.wl.home_visibility_manager,
+                                        &wl_surface,
+                                        &qh,
+                                        home_visibility::VisibilityMode::HideOnHome,
+                                    )
+                                {
+                                    window_state
+                                        .wl
+                                        .home_visibility_controllers
+                                        .insert(surface_id, controller);
+                                }
 
-                                    // Register surface for compositor usable-area reporting.
-                                    if let Some(obj) = register_usable_area_for_surface(
-                                        &window_state.usable_area_manager,
+                                // Register surface for voice mode events if enabled
+                                if window_state.voice_mode_enabled {
+                                    let is_default =
+                                        window_state.wl.voice_mode_receivers.is_empty();
+                                    if let Some(receiver) = register_voice_mode_for_surface(
+                                        &window_state.wl.voice_mode_manager,
                                         &wl_surface,
                                         &qh,
+                                        is_default,
                                     ) {
                                         window_state
-                                            .usable_area_surfaces
-                                            .insert(surface_id, obj);
+                                            .wl
+                                            .voice_mode_receivers
+                                            .insert(surface_id, receiver);
                                     }
+                                }
 
-                                    // Record any per-surface transition override so subsequent
-                                    // hide/show (which only know the surface id) can honor it.
-                                    if let Some(t) = transition {
-                                        window_state
-                                            .transitions
-                                            .insert(wl_surface.id().protocol_id(), t);
-                                    }
+                                // Register surface for compositor usable-area reporting.
+                                if let Some(obj) = register_usable_area_for_surface(
+                                    &window_state.wl.usable_area_manager,
+                                    &wl_surface,
+                                    &qh,
+                                ) {
+                                    window_state.wl.usable_area_surfaces.insert(surface_id, obj);
+                                }
+
+                                // Record any per-surface transition override so subsequent
+                                // hide/show (which only know the surface id) can honor it.
+                                if let Some(t) = transition {
+                                    window_state
+                                        .wl
+                                        .transitions
+                                        .insert(wl_surface.id().protocol_id(), t);
+                                }
 
-                                    // If start_hidden is requested, create a visibility
-                                    // controller and set hidden BEFORE the first commit so
-                                    // the compositor already knows the surface is hidden
-                                    // when it processes the initial buffer.
-                                    //
-                                    // We create the controller directly here instead of
-                                    // going through hide_surface() because at daemon boot
-                                    // self.units may be empty (no QueueHandle available).
-                                    if start_hidden {
-                                        let surface_id = wl_surface.id().protocol_id();
-                                        window_state.hidden_surfaces.insert(surface_id);
-                                        let transition = window_state.surface_transition(surface_id);
-                                        if let Some(manager) = &window_state.layer_surface_visibility_manager {
-                                            let visibility_data = layer_surface_visibility::LayerSurfaceVisibilityData {
+                                // If start_hidden is requested, create a visibility
+                                // controller and set hidden BEFORE the first commit so
+                                // the compositor already knows the surface is hidden
+                                // when it processes the initial buffer.
+                                //
+                                // We create the controller directly here instead of
+                                // going through hide_surface() because at daemon boot
+                                // self.units may be empty (no QueueHandle available).
+                                if start_hidden {
+                                    let surface_id = wl_surface.id().protocol_id();
+                                    window_state.wl.hidden_surfaces.insert(surface_id);
+                                    let transition = window_state.surface_transition(surface_id);
+                                    if let Some(manager) =
+                                        &window_state.wl.layer_surface_visibility_manager
+                                    {
+                                        let visibility_data =
+                                            layer_surface_visibility::LayerSurfaceVisibilityData {
                                                 surface: wl_surface.clone(),
                                                 hidden: true,
                                             };
-                                            let controller = manager.get_visibility_controller(&wl_surface, &qh, visibility_data);
-                                            apply_transition_to_controller(&controller, transition);
-                                            controller.set_hidden();
-                                            window_state.layer_surface_visibility_controllers.insert(surface_id, controller);
-                                            if let Some(ref conn) = window_state.connection {
-                                                let _ = conn.flush();
-                                            }
-                                            log::debug!("start_hidden: created controller and hid surface {}", surface_id);
-                                        } else {
-                                            log::warn!("start_hidden: visibility manager not available");
+                                        let controller = manager.get_visibility_controller(
+                                            &wl_surface,
+                                            &qh,
+                                            visibility_data,
+                                        );
+                                        apply_transition_to_controller(&controller, transition);
+                                        controller.set_hidden();
+                                        window_state
+                                            .wl
+                                            .layer_surface_visibility_controllers
+                                            .insert(surface_id, controller);
+                                        if let Some(ref conn) = window_state.wl.connection {
+                                            let _ = conn.flush();
                                         }
+                                        log::debug!(
+                                            "start_hidden: created controller and hid surface {}",
+                                            surface_id
+                                        );
+                                    } else {
+                                        log::warn!(
+                                            "start_hidden: visibility manager not available"
+                                        );
                                     }
+                                }
 
-                                    wl_surface.commit();
+                                wl_surface.commit();
 
-                                    let mut fractional_scale = None;
-                                    if let Some(ref fractional_scale_manager) =
-                                        fractional_scale_manager
-                                    {
-                                        fractional_scale =
-                                            Some(fractional_scale_manager.get_fractional_scale(
-                                                &wl_surface,
-                                                &qh,
-                                                (),
-                                            ));
-                                    }
-                                    let viewport = viewporter.as_ref().map(|viewport| {
-                                        viewport.get_viewport(&wl_surface, &qh, ())
-                                    });
-                                    // so during the init Configure of the shell, a buffer, atleast a buffer is needed.
-                                    // and if you need to reconfigure it, you need to commit the wl_surface again
-                                    // so because this is just an example, so we just commit it once
-                                    // like if you want to reset anchor or KeyboardInteractivity or resize, commit is needed
-
-                                    window_state.push_window(
-                                        WindowStateUnitBuilder::new(
-                                            id,
-                                            qh.clone(),
-                                            connection.display(),
-                                            wl_surface,
-                                            Shell::LayerShell(layer),
-                                        )
-                                        .viewport(viewport)
-                                        .fractional_scale(fractional_scale)
-                                        .wl_output(output)
-                                        .binding(info)
-                                        .becreated(true)
-                                        .build(),
-                                    );
+                                let mut fractional_scale = None;
+                                if let Some(ref fractional_scale_manager) = fractional_scale_manager
+                                {
+                                    fractional_scale =
+                                        Some(fractional_scale_manager.get_fractional_scale(
+                                            &wl_surface,
+                                            &qh,
+                                            (),
+                                        ));
                                 }
-                                ReturnData::NewPopUp((
-                                    NewPopUpSettings {
-                                        size: (width, height),
-                                        position: (x, y),
+                                let viewport = viewporter
+                                    .as_ref()
+                                    .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
+                                // so during the init Configure of the shell, a buffer, atleast a buffer is needed.
+                                // and if you need to reconfigure it, you need to commit the wl_surface again
+                                // so because this is just an example, so we just commit it once
+                                // like if you want to reset anchor or KeyboardInteractivity or resize, commit is needed
+
+                                window_state.push_window(
+                                    WindowStateUnitBuilder::new(
                                         id,
-                                        shadow: _,
-                                        corner_radius,
-                                        auto_size: _, // Auto-size is handled at the iced level
-                                        anchor_rect_size,
-                                        anchor,
-                                        gravity,
-                                        constraint_adjustment,
-                                        offset,
-                                        reactive,
-                                        grab,
-                                        input_passthrough,
-                                        tooltip_offset,
-                                        tooltip_anchor,
-                                        tooltip_delay_ms,
-                                    },
-                                    targetid,
-                                    info,
-                                )) => {
-                                    let Some(index) = window_state
-                                        .units
-                                        .iter()
-                                        .position(|unit| !unit.is_popup() && unit.id == id)
-                                    else {
-                                        continue;
-                                    };
-                                    let wl_surface = wmcompositer.create_surface(&qh, ());
-                                    let positioner = wmbase.create_positioner(&qh, ());
-                                    positioner.set_size(width as i32, height as i32);
-                                    let (ar_w, ar_h) = anchor_rect_size.unwrap_or((width as i32, height as i32));
-                                    positioner.set_anchor_rect(x, y, ar_w, ar_h);
-                                    if anchor != 0 {
-                                        positioner.set_anchor(wayland_protocols::xdg::shell::client::xdg_positioner::Anchor::try_from(anchor).unwrap_or(wayland_protocols::xdg::shell::client::xdg_positioner::Anchor::None));
-                                    }
-                                    if gravity != 0 {
-                                        positioner.set_gravity(wayland_protocols::xdg::shell::client::xdg_positioner::Gravity::try_from(gravity).unwrap_or(wayland_protocols::xdg::shell::client::xdg_positioner::Gravity::None));
-                                    }
-                                    if constraint_adjustment != 0 {
-                                        positioner.set_constraint_adjustment(wayland_protocols::xdg::shell::client::xdg_positioner::ConstraintAdjustment::from_bits_truncate(constraint_adjustment));
-                                    }
-                                    if let Some((ox, oy)) = offset {
-                                        positioner.set_offset(ox, oy);
-                                    }
-                                    if reactive {
-                                        positioner.set_reactive();
+                                        qh.clone(),
+                                        connection.display(),
+                                        wl_surface,
+                                        Shell::LayerShell(layer),
+                                    )
+                                    .viewport(viewport)
+                                    .fractional_scale(fractional_scale)
+                                    .wl_output(output)
+                                    .keyboard_interactivity(keyboard_interactivity)
+                                    .layer_placement(anchor, margin)
+                                    .layer_config(layer_config)
+                                    .binding(info)
+                                    .becreated(true)
+                                    .build(),
+                                );
+                                if let Some(parent) = parent {
+                                    if window_state.units.iter().any(|unit| unit.id == parent) {
+                                        window_state.layer_parents.insert(id, parent);
+                                    } else {
+                                        log::warn!("the parent {parent:?} of {id:?} is closed");
                                     }
-                                    let wl_xdg_surface =
-                                        wmbase.get_xdg_surface(&wl_surface, &qh, ());
-                                    let popup =
-                                        wl_xdg_surface.get_popup(None, &positioner, &qh, ());
-
-                                    let Shell::LayerShell(shell) = &window_state.units[index].shell
-                                    else {
-                                        unreachable!()
-                                    };
-                                    shell.get_popup(&popup);
+                                }
+                            }
+                            ReturnData::NewPopUp((
+                                NewPopUpSettings {
+                                    size: (width, height),
+                                    position: (x, y),
+                                    id,
+                                    shadow: _,
+                                    corner_radius,
+                                    auto_size: _, // Auto-size is handled at the iced level
+                                    anchor_rect_size,
+                                    anchor,
+                                    gravity,
+                                    constraint_adjustment,
+                                    offset,
+                                    reactive,
+                                    grab,
+                                    input_passthrough,
+                                    tooltip_offset,
+                                    tooltip_anchor,
+                                    tooltip_delay_ms,
+                                    auto_dismiss,
+                                },
+                                targetid,
+                                info,
+                            )) => {
+                                let Some(index) = window_state
+                                    .units
+                                    .iter()
+                                    .position(|unit| !unit.is_popup() && unit.id == id)
+                                else {
+                                    continue;
+                                };
+                                let wl_surface = wmcompositer.create_surface(&qh, ());
+                                let positioner = create_popup_positioner(
+                                    &wmbase,
+                                    &qh,
+                                    (width, height),
+                                    (x, y),
+                                    anchor_rect_size,
+                                    anchor,
+                                    gravity,
+                                    constraint_adjustment,
+                                    offset,
+                                    reactive,
+                                );
+                                let wl_xdg_surface = wmbase.get_xdg_surface(&wl_surface, &qh, ());
+                                let popup = wl_xdg_surface.get_popup(None, &positioner, &qh, ());
 
-                                    if grab
-                                        && let Some(seat) = window_state.seat.as_ref() {
-                                            popup.grab(seat, window_state.enter_serial.unwrap_or(0));
-                                        }
+                                let Shell::LayerShell(shell) = &window_state.units[index].shell
+                                else {
+                                    unreachable!()
+                                };
+                                shell.get_popup(&popup);
+                                window_state.popup_parents.insert(
+                                    targetid,
+                                    (
+                                        id,
+                                        RepositionPopUpSettings {
+                                            popup_id: targetid,
+                                            size: (width, height),
+                                            position: (x, y),
+                                            anchor_rect_size,
+                                            anchor,
+                                            gravity,
+                                            constraint_adjustment,
+                                            offset,
+                                            reactive,
+                                        },
+                                    ),
+                                );
 
-                                    // Apply corner radius to popup surface if set
-                                    let surface_id = wl_surface.id().protocol_id();
-                                    if corner_radius.is_some()
-                                        && let Some(corner_obj) = apply_corner_radius_to_surface(
-                                            &window_state.corner_radius_manager,
-                                            corner_radius,
-                                            &wl_surface,
-                                            &qh,
-                                        ) {
-                                            window_state.corner_radius_surfaces.insert(surface_id, corner_obj);
-                                        }
+                                if grab && let Some(seat) = window_state.wl.seat.as_ref() {
+                                    // the grab must be triggered by a user input event, the
+                                    // enter serial is only a fallback
+                                    let serial = window_state
+                                        .wl
+                                        .last_input_serial
+                                        .or(window_state.wl.enter_serial)
+                                        .unwrap_or(0);
+                                    popup.grab(seat, serial);
+                                }
 
-                                    // Shadow for popups is deferred until the first
-                                    // content frame to avoid a visible flash of
-                                    // shadow around an empty/transparent surface.
-                                    // iced_layershell applies ShadowChange after
-                                    // the popup's first present.
-
-                                    // Create tooltip object if tooltip settings are provided
-                                    let has_tooltip = tooltip_offset.is_some()
-                                        || tooltip_anchor.is_some()
-                                        || tooltip_delay_ms.is_some();
-                                    if has_tooltip {
-                                        // Bind the tooltip manager lazily
-                                        if window_state.tooltip_manager.is_none()
-                                            && let Some(globals) = &window_state.globals {
-                                                window_state.tooltip_manager = globals
+                                // Apply corner radius to popup surface if set
+                                let surface_id = wl_surface.id().protocol_id();
+                                if corner_radius.is_some()
+                                    && let Some(corner_obj) = apply_corner_radius_to_surface(
+                                        &window_state.wl.corner_radius_manager,
+                                        corner_radius,
+                                        &wl_surface,
+                                        &qh,
+                                    )
+                                {
+                                    window_state
+                                        .wl
+                                        .corner_radius_surfaces
+                                        .insert(surface_id, corner_obj);
+                                }
+
+                                // Shadow for popups is deferred until the first
+                                // content frame to avoid a visible flash of
+                                // shadow around an empty/transparent surface.
+                                // iced_layershell applies ShadowChange after
+                                // the popup's first present.
+
+                                // Create tooltip object if tooltip settings are provided
+                                let has_tooltip = tooltip_offset.is_some()
+                                    || tooltip_anchor.is_some()
+                                    || tooltip_delay_ms.is_some();
+                                if has_tooltip {
+                                    // Bind the tooltip manager lazily
+                                    if window_state.wl.tooltip_manager.is_none()
+                                        && let Some(globals) = &window_state.wl.globals
+                                    {
+                                        window_state.wl.tooltip_manager = globals
                                                     .bind::<tooltip::zcosmic_tooltip_manager_v1::ZcosmicTooltipManagerV1, _, _>(
                                                         &qh,
                                                         1..=1,
                                                         (),
                                                     )
                                                     .ok();
-                                                if window_state.tooltip_manager.is_some() {
-                                                    log::info!("Bound tooltip manager");
-                                                }
-                                            }
-
-                                        if let Some(manager) = &window_state.tooltip_manager {
-                                            let parent_surface = &window_state.units[index].wl_surface;
-                                            let tooltip_data = tooltip::TooltipData {
-                                                tooltip_surface: wl_surface.clone(),
-                                                parent_surface: parent_surface.clone(),
-                                            };
-                                            let tooltip_obj = manager.get_tooltip(
-                                                &wl_surface,
-                                                parent_surface,
-                                                &qh,
-                                                tooltip_data,
-                                            );
-                                            if let Some((ox, oy)) = tooltip_offset {
-                                                tooltip_obj.set_offset(ox, oy);
-                                            }
-                                            if let Some(a) = tooltip_anchor
-                                                && let Ok(anchor_val) = tooltip::zcosmic_tooltip_v1::Anchor::try_from(a) {
-                                                    tooltip_obj.set_anchor(anchor_val);
-                                                }
-                                            if let Some(delay) = tooltip_delay_ms {
-                                                tooltip_obj.set_show_delay(delay);
-                                            }
-                                            window_state.tooltip_surfaces.insert(surface_id, tooltip_obj);
-                                        } else {
-                                            log::warn!("Tooltip manager not available — compositor may not support tooltips");
+                                        if window_state.wl.tooltip_manager.is_some() {
+                                            log::info!("Bound tooltip manager");
                                         }
                                     }
 
-                                    // Set empty input region so pointer events pass through
-                                    if input_passthrough {
-                                        let region = wmcompositer.create_region(&qh, ());
-                                        wl_surface.set_input_region(Some(&region));
-                                        region.destroy();
-                                    }
-
-                                    // Always set window geometry so the compositor
-                                    // knows the visible content bounds for anchor
-                                    // calculations/constraint adjustment.
-                                    wl_xdg_surface.set_window_geometry(0, 0, width as i32, height as i32);
-
-                                    let mut fractional_scale = None;
-                                    if let Some(ref fractional_scale_manager) =
-                                        fractional_scale_manager
-                                    {
-                                        fractional_scale =
-                                            Some(fractional_scale_manager.get_fractional_scale(
-                                                &wl_surface,
-                                                &qh,
-                                                (),
-                                            ));
+                                    if let Some(manager) = &window_state.wl.tooltip_manager {
+                                        let parent_surface = &window_state.units[index].wl_surface;
+                                        let tooltip_data = tooltip::TooltipData {
+                                            tooltip_surface: wl_surface.clone(),
+                                            parent_surface: parent_surface.clone(),
+                                        };
+                                        let tooltip_obj = manager.get_tooltip(
+                                            &wl_surface,
+                                            parent_surface,
+                                            &qh,
+                                            tooltip_data,
+                                        );
+                                        if let Some((ox, oy)) = tooltip_offset {
+                                            tooltip_obj.set_offset(ox, oy);
+                                        }
+                                        if let Some(a) = tooltip_anchor
+                                            && let Ok(anchor_val) =
+                                                tooltip::zcosmic_tooltip_v1::Anchor::try_from(a)
+                                        {
+                                            tooltip_obj.set_anchor(anchor_val);
+                                        }
+                                        if let Some(delay) = tooltip_delay_ms {
+                                            tooltip_obj.set_show_delay(delay);
+                                        }
+                                        window_state
+                                            .wl
+                                            .tooltip_surfaces
+                                            .insert(surface_id, tooltip_obj);
+                                    } else {
+                                        log::warn!(
+                                            "Tooltip manager not available — compositor may not support tooltips"
+                                        );
                                     }
-                                    wl_surface.commit();
-
-                                    let viewport = viewporter.as_ref().map(|viewport| {
-                                        viewport.get_viewport(&wl_surface, &qh, ())
-                                    });
-                                    window_state.push_window(
-                                        WindowStateUnitBuilder::new(
-                                            targetid,
-                                            qh.clone(),
-                                            connection.display(),
-                                            wl_surface,
-                                            Shell::PopUp((popup, wl_xdg_surface)),
-                                        )
-                                        .size((width, height))
-                                        .viewport(viewport)
-                                        .fractional_scale(fractional_scale)
-                                        .binding(info)
-                                        .becreated(true)
-                                        .build(),
-                                    );
-                                },
-                                ReturnData::RepositionPopUp(settings) => {
-                                    use events::RepositionPopUpSettings;
-                                    let RepositionPopUpSettings {
-                                        popup_id,
-                                        size: (width, height),
-                                        position: (x, y),
-                                        anchor_rect_size,
-                                        anchor,
-                                        gravity,
-                                        constraint_adjustment,
-                                        offset,
-                                        reactive,
-                                    } = settings;
-
-                                    let Some(unit) = window_state
-                                        .units
-                                        .iter()
-                                        .find(|unit| unit.id == popup_id)
-                                    else {
-                                        log::warn!("RepositionPopUp: popup unit not found for id {:?}", popup_id);
-                                        continue;
-                                    };
-
-                                    let Shell::PopUp((ref xdg_popup, _)) = unit.shell else {
-                                        log::warn!("RepositionPopUp: unit is not a popup");
-                                        continue;
-                                    };
+                                }
 
-                                    let positioner = wmbase.create_positioner(&qh, ());
-                                    positioner.set_size(width as i32, height as i32);
-                                    let (ar_w, ar_h) = anchor_rect_size.unwrap_or((width as i32, height as i32));
-                                    positioner.set_anchor_rect(x, y, ar_w, ar_h);
-                                    if anchor != 0 {
-                                        positioner.set_anchor(wayland_protocols::xdg::shell::client::xdg_positioner::Anchor::try_from(anchor).unwrap_or(wayland_protocols::xdg::shell::client::xdg_positioner::Anchor::None));
-                                    }
-                                    if gravity != 0 {
-                                        positioner.set_gravity(wayland_protocols::xdg::shell::client::xdg_positioner::Gravity::try_from(gravity).unwrap_or(wayland_protocols::xdg::shell::client::xdg_positioner::Gravity::None));
-                                    }
-                                    if constraint_adjustment != 0 {
-                                        positioner.set_constraint_adjustment(wayland_protocols::xdg::shell::client::xdg_positioner::ConstraintAdjustment::from_bits_truncate(constraint_adjustment));
-                                    }
-                                    if let Some((ox, oy)) = offset {
-                                        positioner.set_offset(ox, oy);
-                                    }
-                                    if reactive {
-                                        positioner.set_reactive();
-                                    }
+                                // Set empty input region so pointer events pass through
+                                if input_passthrough {
+                                    let region = wmcompositer.create_region(&qh, ());
+                                    wl_surface.set_input_region(Some(&region));
+                                    region.destroy();
+                                }
 
-                                    // Use a monotonically increasing token for reposition
-                                    static REPOSITION_TOKEN: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1);
-                                    let token = REPOSITION_TOKEN.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-                                    xdg_popup.reposition(&positioner, token);
+                                // Always set window geometry so the compositor
+                                // knows the visible content bounds for anchor
+                                // calculations/constraint adjustment.
+                                wl_xdg_surface.set_window_geometry(
+                                    0,
+                                    0,
+                                    width as i32,
+                                    height as i32,
+                                );
 
-                                    // Destroy the temporary positioner
-                                    positioner.destroy();
+                                let mut fractional_scale = None;
+                                if let Some(ref fractional_scale_manager) = fractional_scale_manager
+                                {
+                                    fractional_scale =
+                                        Some(fractional_scale_manager.get_fractional_scale(
+                                            &wl_surface,
+                                            &qh,
+                                            (),
+                                        ));
+                                }
+                                wl_surface.commit();
 
-                                    log::debug!("RepositionPopUp: repositioned popup {:?} with token {}", popup_id, token);
+                                let viewport = viewporter
+                                    .as_ref()
+                                    .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
+                                window_state.push_window(
+                                    WindowStateUnitBuilder::new(
+                                        targetid,
+                                        qh.clone(),
+                                        connection.display(),
+                                        wl_surface,
+                                        Shell::PopUp((popup, wl_xdg_surface)),
+                                    )
+                                    .size((width, height))
+                                    .viewport(viewport)
+                                    .fractional_scale(fractional_scale)
+                                    .binding(info)
+                                    .auto_dismiss(auto_dismiss)
+                                    .becreated(true)
+                                    .build(),
+                                );
+                                if let Some(after) = auto_dismiss {
+                                    let timer = Timer::from_duration(after);
+                                    r_window_state
+                                        .loop_handle
+                                        .insert_source(timer, move |_, _, r_window_state| {
+                                            let state = &mut r_window_state.raw;
+                                            let Some(unit) = state
+                                                .units
+                                                .iter_mut()
+                                                .find(|unit| unit.id == targetid)
+                                            else {
+                                                return TimeoutAction::Drop;
+                                            };
+                                            let Some((_, deadline)) = unit.auto_dismiss else {
+                                                return TimeoutAction::Drop;
+                                            };
+                                            // the pointer moved over the popup meanwhile
+                                            if Instant::now() < deadline {
+                                                return TimeoutAction::ToInstant(deadline);
+                                            }
+                                            unit.request_close();
+                                            // wake the loop to handle the close, it may sleep
+                                            // with on demand presentation
+                                            if let Some(sender) = &state.wl.ping_sender {
+                                                sender.ping();
+                                            }
+                                            TimeoutAction::Drop
+                                        })
+                                        .ok();
+                                }
+                            }
+                            ReturnData::RepositionPopUp(settings) => {
+                                window_state.reposition_popup(settings);
+                            }
+                            ReturnData::NewXdgBase((
+                                NewXdgWindowSettings {
+                                    maximized,
+                                    title,
+                                    size,
+                                    app_id,
                                 },
-                                ReturnData::NewXdgBase((
-                                NewXdgWindowSettings { maximized, title, size, app_id },
-                                    id,
-                                    info,
-                                )) => {
-                                    let wl_surface = wmcompositer.create_surface(&qh, ());
-                                    let wl_xdg_surface =
-                                        wmbase.get_xdg_surface(&wl_surface, &qh, ());
-                                    let toplevel =
-                                        wl_xdg_surface.get_toplevel(&qh, ());
-
-                                    toplevel.set_title(title.unwrap_or("".to_owned()));
-                                    if let Some(app_id) = app_id {
-                                        toplevel.set_app_id(app_id);
-                                    }
+                                id,
+                                info,
+                            )) => {
+                                let wl_surface = wmcompositer.create_surface(&qh, ());
+                                let wl_xdg_surface = wmbase.get_xdg_surface(&wl_surface, &qh, ());
+                                let toplevel = wl_xdg_surface.get_toplevel(&qh, ());
+
+                                toplevel.set_title(title.unwrap_or("".to_owned()));
+                                if let Some(app_id) = app_id {
+                                    toplevel.set_app_id(app_id);
+                                }
 
-                                    if maximized { toplevel.set_maximized(); }
-                                    let decoration = if let Some(decoration_manager) = &zxdg_decoration_manager {
-                                        let decoration = decoration_manager.get_toplevel_decoration(&toplevel, &qh, ());
+                                if maximized {
+                                    toplevel.set_maximized();
+                                }
+                                let decoration =
+                                    if let Some(decoration_manager) = &zxdg_decoration_manager {
+                                        let decoration = decoration_manager
+                                            .get_toplevel_decoration(&toplevel, &qh, ());
                                         use zxdg_toplevel_decoration_v1::Mode;
                                         // Use client-side (no) decorations when maximized, server-side otherwise
                                         if maximized {
@@ -7537,360 +11386,972 @@ impl<T: 'static> WindowState<T> {
                                             decoration.set_mode(Mode::ServerSide);
                                         }
                                         Some(decoration)
-
                                     } else {
                                         None
                                     };
-                                    let mut fractional_scale = None;
-                                    if let Some(ref fractional_scale_manager) =
-                                        fractional_scale_manager
-                                    {
-                                        fractional_scale =
-                                            Some(fractional_scale_manager.get_fractional_scale(
-                                                &wl_surface,
-                                                &qh,
-                                                (),
-                                            ));
-                                    }
-                                    wl_surface.commit();
-
-                                    let viewport = viewporter.as_ref().map(|viewport| {
-                                        viewport.get_viewport(&wl_surface, &qh, ())
-                                    });
-                                    window_state.push_window(
-                                        WindowStateUnitBuilder::new(
-                                            id,
-                                            qh.clone(),
-                                            connection.display(),
-                                            wl_surface,
-                                            Shell::XdgTopLevel((toplevel, wl_xdg_surface, decoration)),
-                                        )
-                                        .size(size.unwrap_or((300, 300)))
-                                        .viewport(viewport)
-                                        .fractional_scale(fractional_scale)
-                                        .binding(info)
-                                        .becreated(true)
-                                        .build(),
+                                let mut fractional_scale = None;
+                                if let Some(ref fractional_scale_manager) = fractional_scale_manager
+                                {
+                                    fractional_scale =
+                                        Some(fractional_scale_manager.get_fractional_scale(
+                                            &wl_surface,
+                                            &qh,
+                                            (),
+                                        ));
+                                }
+                                wl_surface.commit();
+
+                                let viewport = viewporter
+                                    .as_ref()
+                                    .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
+                                window_state.push_window(
+                                    WindowStateUnitBuilder::new(
+                                        id,
+                                        qh.clone(),
+                                        connection.display(),
+                                        wl_surface,
+                                        Shell::XdgTopLevel((toplevel, wl_xdg_surface, decoration)),
+                                    )
+                                    .size(size.unwrap_or((300, 300)))
+                                    .viewport(viewport)
+                                    .fractional_scale(fractional_scale)
+                                    .binding(info)
+                                    .becreated(true)
+                                    .build(),
+                                );
+                            }
+
+                            ReturnData::NewInputPanel((
+                                NewInputPanelSettings {
+                                    size: (width, height),
+                                    position,
+                                    use_last_output,
+                                },
+                                id,
+                                info,
+                            )) => {
+                                let pos = window_state.surface_pos();
+
+                                let mut output =
+                                    pos.and_then(|p| window_state.units[p].wl_output.as_ref());
+
+                                if window_state.wl.last_wloutput.is_none()
+                                    && window_state.wl.outputs.len() > window_state.last_unit_index
+                                {
+                                    window_state.wl.last_wloutput = Some(
+                                        window_state.wl.outputs[window_state.last_unit_index]
+                                            .1
+                                            .clone(),
                                     );
+                                }
+
+                                if use_last_output {
+                                    output = window_state.wl.last_wloutput.as_ref();
+                                }
+
+                                if output.is_none() {
+                                    output = window_state.wl.outputs.first().map(|(_, o)| o);
+                                }
+
+                                let Some(output) = output else {
+                                    log::warn!("no WlOutput, skip creating input panel");
+                                    continue;
+                                };
+
+                                let wl_surface = wmcompositer.create_surface(&qh, ());
+                                let input_panel = globals
+                                    .bind::<ZwpInputPanelV1, _, _>(&qh, 1..=1, ())
+                                    .unwrap();
+                                let input_panel_surface =
+                                    input_panel.get_input_panel_surface(&wl_surface, &qh, ());
+                                match position {
+                                    InputPanelPosition::Toplevel(position) => {
+                                        input_panel_surface.set_toplevel(output, position.into());
+                                    }
+                                    InputPanelPosition::Overlay => {
+                                        input_panel_surface.set_overlay_panel();
+                                    }
+                                }
+                                wl_surface.commit();
+
+                                let mut fractional_scale = None;
+                                if let Some(ref fractional_scale_manager) = fractional_scale_manager
+                                {
+                                    fractional_scale =
+                                        Some(fractional_scale_manager.get_fractional_scale(
+                                            &wl_surface,
+                                            &qh,
+                                            (),
+                                        ));
+                                }
+
+                                let viewport = viewporter
+                                    .as_ref()
+                                    .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
+                                // a keyboard spans its output, its xdg_output tells the width
+                                let toplevel = matches!(position, InputPanelPosition::Toplevel(_));
+                                let zxdgoutput = toplevel.then(|| {
+                                    ZxdgOutputInfo::new(xdg_output_manager.get_xdg_output(
+                                        output,
+                                        &qh,
+                                        (),
+                                    ))
+                                });
+                                let output = output.clone();
+                                window_state.push_window(
+                                    WindowStateUnitBuilder::new(
+                                        id,
+                                        qh.clone(),
+                                        connection.display(),
+                                        wl_surface,
+                                        Shell::InputPanel(input_panel_surface),
+                                    )
+                                    .size((width, height))
+                                    .zxdgoutput(zxdgoutput)
+                                    .wl_output(Some(output))
+                                    .follows_output_width(toplevel)
+                                    .viewport(viewport)
+                                    .fractional_scale(fractional_scale)
+                                    .binding(info)
+                                    .becreated(true)
+                                    .build(),
+                                );
+                            }
+                            _ => {}
+                        }
+                    }
+                    if window_state.return_data.is_empty() {
+                        break;
+                    }
+                }
+
+                let to_be_closed_ids: Vec<_> = window_state
+                    .units
+                    .iter()
+                    .filter(|unit| unit.request_flag.close)
+                    .map(WindowStateUnit::id)
+                    .collect();
+                if !to_be_closed_ids.is_empty() {
+                    log::info!(target: "move_debug", "processing {} to_be_closed unit(s) (request_flag.close)", to_be_closed_ids.len());
+                }
+                for id in to_be_closed_ids {
+                    window_state.handle_event(
+                        &mut *event_handler,
+                        LayerShellEvent::RequestMessages(&DispatchMessage::Closed),
+                        Some(id),
+                    );
+                    // event_handler may use unit, only remove it after calling event_handler.
+                    window_state.remove_shell(id);
+                }
+
+                // NOTE: this is for those closed because wl_output is dead.
+                let closed_ids = window_state.closed_ids.clone();
+                for id in closed_ids {
+                    window_state.handle_event(
+                        &mut *event_handler,
+                        LayerShellEvent::RequestMessages(&DispatchMessage::Closed),
+                        Some(id),
+                    );
+                }
+                window_state.closed_ids.clear();
+
+                // A single-surface `Active` app (e.g. the notifications
+                // daemon) keeps exactly one layer surface, normally on the
+                // active output. If that output was just disabled, the
+                // surface is destroyed — but unlike plugging in a brand-new
+                // display, no `NewDisplay` fires for the monitors that were
+                // already connected. So nothing would move the surface to a
+                // still-active monitor, and the app goes silently blind until
+                // a display is (re-)enabled.
+                //
+                // Detect that here and synthesize a `NewDisplay` for a
+                // surviving output; the recreate path above turns it into a
+                // fresh surface on the next tick. This runs every 50 ms, so
+                // it self-heals regardless of teardown ordering. Guards:
+                //   - `has_live_surface()` is true for merely *hidden*
+                //     surfaces, so the idle-hidden state never triggers it,
+                //     and it stops the moment the surface is back;
+                //   - the already-queued check prevents piling up duplicates.
+                if window_state.is_active() && !window_state.has_live_surface() {
+                    let already_queued = window_state
+                        .wl
+                        .message
+                        .iter()
+                        .any(|(_, m)| matches!(m, DispatchMessageInner::NewDisplay(_)));
+                    let next_output = window_state.wl.outputs.first().map(|(_, o)| o.clone());
+                    if !already_queued && let Some(output) = next_output {
+                        window_state
+                            .wl
+                            .message
+                            .push((None, DispatchMessageInner::NewDisplay(output)));
+                    }
+                }
+
+                // The visibility is checked per unit AFTER NormalDispatch + action
+                // processing.  show_surface() may have been called (e.g.
+                // via the calloop channel between timer ticks, or during
+                // NormalDispatch via a synchronous Task resolution), which
+                // marks the unit visible again.
+                //
+                // When a surface is hidden via the layer-surface-visibility
+                // protocol the compositor is not rendering it.  Skip its
+                // (expensive) refresh / present cycle, the timer still wakes
+                // up for protocol events (e.g. show requests).
+                //
+                // Exception: newly created units that have never been
+                // through a refresh cycle must still get their initial
+                // Refresh event so the iced window_manager registers them.
+                // Without this, surfaces created with `start_hidden` would
+                // never be findable by ShowWindow.
+                //
+                // In `RenderMode::FrameCallback` the timer never presents:
+                // the Ping source does, woken by frame callbacks, and here
+                // only when a refresh is waiting on a free present slot.
+                if window_state.render_mode == RenderMode::FrameCallback {
+                    let can_present = window_state.units.iter().any(|unit| {
+                        unit.present_available_state == PresentAvailableState::Available
+                            && unit.should_refresh()
+                    });
+                    if can_present && let Some(sender) = &window_state.wl.ping_sender {
+                        sender.ping();
+                    }
+                    return TimeoutAction::ToDuration(window_state.next_tick_interval());
+                }
+                window_state.present_units(event_handler, &shm, &qh);
+
+                // Timer interval is kept at a battery-friendly 50ms.
+                // Immediate wake-ups are handled by the Ping source:
+                //  - Channel messages (iced subscriptions) → ping
+                //  - Compositor frame callbacks (wl_callback::done) → ping
+                // The timer is only a safety net for edge cases.
+                TimeoutAction::ToDuration(window_state.next_tick_interval())
+            },
+        );
+        let timer_token = event_loop
+            .handle()
+            .register_dispatcher(timer.clone())
+            .map_err(LayerEventError::SourceInsertError)?;
+        // with on demand presentation or frame callbacks the loop only wakes up for its sources
+        let run_timeout = (!state.raw.timer_sleeps()).then_some(Duration::from_millis(20));
+        let run_signal = event_loop.get_signal();
+        let result = event_loop.run(run_timeout, &mut state, move |r_window_state| {
+            // the timers of the user sources don't wake the loop fd
+            r_window_state.dispatch_user_loop();
+            let window_state = &mut r_window_state.raw;
+            if let Err(err) = event_queue_origin.roundtrip(window_state)
+                && window_state.auto_reconnect
+                && !window_state.wl.connection_lost
+            {
+                log::warn!("the wayland connection is lost: {err}");
+                window_state.wl.connection_lost = true;
+                run_signal.stop();
+                return;
+            }
+            if window_state.settle_resizes()
+                && let Some(sender) = &window_state.wl.ping_sender
+            {
+                sender.ping();
+            }
+            let looph = &r_window_state.loop_handle;
+            for token in window_state.wl.to_remove_tokens.iter() {
+                looph.remove(*token);
+            }
+            window_state.wl.to_remove_tokens.clear();
+            // settle the held back resizes on time, the timer of the loop can sleep
+            if !window_state.wl.resize_timer_armed
+                && let Some(deadline) = window_state.next_resize_deadline()
+            {
+                window_state.wl.resize_timer_armed = true;
+                looph
+                    .insert_source(Timer::from_deadline(deadline), |_, _, r_window_state| {
+                        let state = &mut r_window_state.raw;
+                        if state.settle_resizes()
+                            && let Some(sender) = &state.wl.ping_sender
+                        {
+                            sender.ping();
+                        }
+                        match state.next_resize_deadline() {
+                            Some(deadline) => TimeoutAction::ToInstant(deadline),
+                            None => {
+                                state.wl.resize_timer_armed = false;
+                                TimeoutAction::Drop
+                            }
+                        }
+                    })
+                    .ok();
+            }
+            window_state.run_idle_callback();
+            if let Some(qh) = window_state.wl.queue_handle.clone() {
+                window_state.ensure_text_input(&qh);
+            }
+            // wake the sleeping timer, a wayland event or a message brought work
+            if window_state.wl.timer_idle && window_state.has_pending_work() {
+                window_state.wl.timer_idle = false;
+                timer.as_source_mut().set_duration(Duration::ZERO);
+                if let Err(err) = looph.update(&timer_token) {
+                    log::error!("Cannot wake the timer of the event loop: {err}");
+                }
+            }
+            for (VirtualKeyRelease { delay, time, key }, clear_modifiers) in
+                std::mem::take(&mut window_state.wl.to_be_released_keys)
+            {
+                looph
+                    .insert_source(Timer::from_duration(delay), move |_, _, r_window_state| {
+                        let state = &mut r_window_state.raw;
+                        let ky = state.wl.virtual_keyboard.as_ref().unwrap();
+
+                        ky.key(time, key, KeyState::Released.into());
+                        if clear_modifiers {
+                            ky.modifiers(0, 0, 0, 0);
+                        }
+                        TimeoutAction::Drop
+                    })
+                    .ok();
+            }
+            for SelectionRead { id, mime, reader } in
+                std::mem::take(&mut window_state.wl.selection_reads)
+            {
+                let mut data = Vec::new();
+                looph
+                    .insert_source(
+                        Generic::new(reader, Interest::READ, Mode::Level),
+                        move |_, reader, r_window_state| {
+                            use std::io::Read;
+                            let mut chunk = [0; 4096];
+                            loop {
+                                match (&**reader).read(&mut chunk) {
+                                    Ok(0) => break,
+                                    Ok(len) => data.extend_from_slice(&chunk[..len]),
+                                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                                        return Ok(PostAction::Continue);
+                                    }
+                                    Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                                    Err(err) => {
+                                        log::warn!("failed to read the {mime} selection: {err}");
+                                        return Ok(PostAction::Remove);
+                                    }
+                                }
+                            }
+                            r_window_state.raw.wl.message.push((
+                                id,
+                                DispatchMessageInner::SelectionData {
+                                    mime: mime.clone(),
+                                    data: std::mem::take(&mut data),
                                 },
+                            ));
+                            Ok(PostAction::Remove)
+                        },
+                    )
+                    .ok();
+            }
+            if let Some(KeyboardTokenState {
+                key,
+                delay,
+                surface_id,
+                pressed_state,
+            }) = window_state.wl.repeat_delay.take()
+            {
+                let timer = Timer::from_duration(delay);
+                let keyboard_state = window_state.wl.keyboard_state.as_mut().unwrap();
+                keyboard_state.repeat_token = looph
+                    .insert_source(timer, move |_, _, r_window_state| {
+                        let state = &mut r_window_state.raw;
+                        let event_handler = &mut r_window_state.fun;
+                        let keyboard_state = match state.wl.keyboard_state.as_mut() {
+                            Some(keyboard_state) => keyboard_state,
+                            None => return TimeoutAction::Drop,
+                        };
+                        let repeat_keycode = match keyboard_state.current_repeat {
+                            Some(repeat_keycode) => repeat_keycode,
+                            None => return TimeoutAction::Drop,
+                        };
+                        // NOTE: not the same key
+                        if repeat_keycode != key {
+                            return TimeoutAction::Drop;
+                        }
+                        if let Some(mut key_context) = keyboard_state.xkb_context.key_context() {
+                            let event =
+                                key_context.process_key_event(repeat_keycode, pressed_state, false);
+                            let event = DispatchMessageInner::KeyboardInput {
+                                event,
+                                is_synthetic: false,
+                            };
+                            state.wl.message.push((surface_id, event));
+                        }
+                        let repeat_info = keyboard_state.repeat_info;
+
+                        let _ = keyboard_state;
+                        state.handle_event(
+                            &mut *event_handler,
+                            LayerShellEvent::NormalDispatch,
+                            None,
+                        );
+                        match repeat_info {
+                            RepeatInfo::Repeat { gap, .. } => TimeoutAction::ToDuration(gap),
+                            RepeatInfo::Disable => TimeoutAction::Drop,
+                        }
+                    })
+                    .ok();
+            }
+        });
+        let raw = &state.raw;
+        // the wayland source fails first when the socket is closed
+        let lost = raw.auto_reconnect
+            && (raw.wl.connection_lost
+                || result.is_err()
+                    && raw
+                        .wl
+                        .connection
+                        .as_ref()
+                        .is_some_and(|connection| connection.flush().is_err()));
+        if !lost {
+            result?;
+            return Ok(None);
+        }
+        if let Err(err) = result {
+            log::warn!("the event loop stopped with the wayland connection: {err}");
+        }
+        // drop the sources, so the message dispatcher is the last owner of its source
+        drop(event_loop);
+        let EventWrapper {
+            raw,
+            fun,
+            user_loop,
+            ..
+        } = state;
+        Ok(Some(ConnectionLost {
+            state: raw,
+            event_handler: fun,
+            message_receiver: message_dispatcher.map(MessageDispatcher::into_source),
+            user_loop,
+        }))
+    }
+
+    /// connect again after the connection is lost and bind the globals like `build`, retrying
+    /// until the compositor is back or the tries of [WindowState::with_reconnect_attempts]
+    /// are used up. The units are kept, their surfaces are created again by
+    /// [WindowState::restore_units] once the event loop runs
+    fn reconnect(mut self) -> Result<Self, LayerEventError> {
+        let unit_outputs = self
+            .units
+            .iter()
+            .map(|unit| {
+                let output_name = unit
+                    .wl_output
+                    .as_ref()
+                    .and_then(|output| self.output_name(output));
+                (unit.id, output_name)
+            })
+            .collect();
+        self.reset_connection_state();
+        self.reconnect_outputs = Some(unit_outputs);
+        let mut delay = Duration::from_millis(100);
+        let mut attempt = 1;
+        let connection = loop {
+            match Connection::connect_to_env() {
+                Ok(connection) => break connection,
+                Err(err) if attempt < self.reconnect_attempts => {
+                    log::warn!("cannot connect to the compositor, retry in {delay:?}: {err}");
+                    std::thread::sleep(delay);
+                    delay = (delay * 2).min(Duration::from_secs(5));
+                    attempt += 1;
+                }
+                Err(err) => {
+                    log::error!("cannot connect to the compositor after {attempt} tries: {err}");
+                    return Err(err.into());
+                }
+            }
+        };
+        self.wl.connection = Some(connection);
+        log::info!("reconnected to the compositor");
+        self.build()
+    }
+
+    /// forget every object of the lost connection, and the state which came with them. The
+    /// settings and the units are kept
+    fn reset_connection_state(&mut self) {
+        self.wl = WaylandState::default();
+    }
+
+    /// create again the surfaces of the units after a reconnection, each on the output with
+    /// the name it had. The units of the other shells, and in [StartMode::AllScreens] the ones
+    /// whose output is gone, are closed, and the outputs without a unit get one
+    fn restore_units(&mut self) {
+        let Some(unit_outputs) = self.reconnect_outputs.take() else {
+            return;
+        };
+        let (Some(qh), Some(display)) = (self.wl.queue_handle.clone(), self.wl.display.clone())
+        else {
+            return;
+        };
+        let outputs: HashMap<id::Id, WlOutput> = unit_outputs
+            .into_iter()
+            .filter_map(|(id, name)| {
+                let name = name?;
+                let (_, output) = self
+                    .wl
+                    .outputs
+                    .iter()
+                    .find(|(_, output)| self.output_name(output).as_ref() == Some(&name))?;
+                Some((id, output.clone()))
+            })
+            .collect();
+        let allscreens = self.is_allscreens();
+        let closed: Vec<id::Id> = self
+            .units
+            .iter()
+            .filter(|unit| {
+                !matches!(unit.shell, Shell::LayerShell(_))
+                    || unit.layer_config.borrow().is_none()
+                    || allscreens && !outputs.contains_key(&unit.id)
+            })
+            .map(WindowStateUnit::id)
+            .collect();
+        self.units.retain(|unit| !closed.contains(&unit.id));
+        for id in closed {
+            self.popup_parents.remove(&id);
+            self.layer_parents.remove(&id);
+            self.frame_stats.unit_presents.remove(&id);
+            self.home_visibility_modes.remove(&id);
+            self.closed_ids.push(id);
+        }
+        for unit in self.units.iter_mut() {
+            unit.qh = qh.clone();
+            unit.display = display.clone();
+            unit.wl_output = outputs.get(&unit.id).cloned();
+            unit.zxdgoutput = None;
+            unit.buffer = None;
+            unit.seat = None;
+            unit.output_refresh = None;
+            unit.fifo = None;
+            unit.commit_timer = None;
+            unit.commit_time.set(None);
+            unit.alpha_modifier = None;
+            unit.idle_inhibitor = None;
+        }
+        self.recreate_all();
+        if allscreens {
+            for (_, output) in self.wl.outputs.iter() {
+                if !self
+                    .units
+                    .iter()
+                    .any(|unit| unit.wl_output.as_ref() == Some(output))
+                {
+                    self.wl
+                        .message
+                        .push((None, DispatchMessageInner::NewDisplay(output.clone())));
+                }
+            }
+        }
+    }
 
-                                ReturnData::NewInputPanel((
-                                    NewInputPanelSettings {
-                                        size: (width, height),
-                                        keyboard,
-                                        use_last_output,
-                                    },
-                                    id,
-                                    info,
-                                )) => {
-                                    let pos = window_state.surface_pos();
+    /// destroy and create again the wl_surface and layer surface of every layer shell unit,
+    /// for a wholesale reconfiguration like after a cursor theme reload or a scale override
+    /// change. The ids, bindings, outputs and layer settings (layer, anchor, margin, size,
+    /// exclusive zone, keyboard interactivity) of the units are kept, and the surface effects
+    /// of the [WindowState] (blur, shadow, corner radius...) are applied again. Each unit gets a
+    /// [DispatchMessage::Recreated], then a new configure and refresh.
+    ///
+    /// NOTE: the state set on a surface after it is created, like the fifo barrier or the
+    /// opacity, is reset. A surface hidden with [WindowState::hide_surface] stays hidden. The
+    /// popups of the recreated units are closed, and other shells are not recreated
+    pub fn recreate_all(&mut self) {
+        let (Some(globals), Some(compositor), Some(qh)) = (
+            self.wl.globals.clone(),
+            self.wl.cached_compositor.clone(),
+            self.wl.queue_handle.clone(),
+        ) else {
+            log::warn!("recreate_all: the event loop is not running");
+            return;
+        };
+        let Ok(layer_shell) = globals.bind::<ZwlrLayerShellV1, _, _>(&qh, 3..=4, ()) else {
+            log::warn!("recreate_all: zwlr_layer_shell_v1 not available");
+            return;
+        };
+        let ids: Vec<id::Id> = self
+            .units
+            .iter()
+            .filter(|unit| matches!(unit.shell, Shell::LayerShell(_)))
+            .map(|unit| unit.id)
+            .collect();
+        // the xdg_popups cannot follow their parent to the new surface
+        let popups: Vec<id::Id> = self
+            .popup_parents
+            .iter()
+            .filter(|(_, (parent, _))| ids.contains(parent))
+            .map(|(popup, _)| *popup)
+            .collect();
+        for popup in popups {
+            if self.remove_shell(popup).is_some() {
+                self.closed_ids.push(popup);
+            }
+        }
+        for id in ids {
+            let Some(index) = self.units.iter().position(|unit| unit.id == id) else {
+                continue;
+            };
+            let Some(config) = self.units[index].layer_config.borrow().clone() else {
+                continue;
+            };
+            let old_surface_id = self.units[index].wl_surface.id().protocol_id();
+            let transition = self.wl.transitions.get(&old_surface_id).copied();
+            let hidden = !self.units[index].visible;
+            self.destroy_unit_surface(index);
 
-                                    let mut output = pos.and_then(|p| window_state.units[p].wl_output.as_ref());
+            let wl_surface = compositor.create_surface(&qh, ());
+            let output = self.units[index].wl_output.clone();
+            let layer = layer_shell.get_layer_surface(
+                &wl_surface,
+                output.as_ref(),
+                config.layer,
+                config.namespace,
+                &qh,
+                (),
+            );
+            let (anchor, (top, right, bottom, left)) = self.units[index].layer_placement.get();
+            layer.set_anchor(anchor);
+            layer.set_margin(top, right, bottom, left);
+            layer.set_keyboard_interactivity(self.units[index].keyboard_interactivity.get());
+            if let Some((width, height)) = config.size {
+                layer.set_size(width, height);
+            }
+            if let Some(zone) = config.exclusive_zone {
+                layer.set_exclusive_zone(zone);
+            }
+            if config.events_transparent {
+                let region = compositor.create_region(&qh, ());
+                wl_surface.set_input_region(Some(&region));
+                region.destroy();
+            }
+            self.apply_surface_effects(&wl_surface, &qh);
+            if let Some(mode) = self.home_visibility_modes.get(&id).copied() {
+                let surface_id = wl_surface.id().protocol_id();
+                if let Some(controller) = self.wl.home_visibility_controllers.get(&surface_id) {
+                    controller.set_visibility_mode(mode);
+                } else if let Some(controller) = apply_home_visibility_to_surface(
+                    &self.wl.home_visibility_manager,
+                    &wl_surface,
+                    &qh,
+                    mode,
+                ) {
+                    self.wl
+                        .home_visibility_controllers
+                        .insert(surface_id, controller);
+                }
+            }
+            if let Some(transition) = transition {
+                self.wl
+                    .transitions
+                    .insert(wl_surface.id().protocol_id(), transition);
+            }
+            if hidden {
+                self.hide_surface(&wl_surface);
+            }
+            wl_surface.commit();
 
-                                    if window_state.last_wloutput.is_none()
-                                        && window_state.outputs.len() > window_state.last_unit_index
-                                    {
-                                        window_state.last_wloutput =
-                                            Some(window_state.outputs[window_state.last_unit_index].1.clone());
-                                    }
+            let fractional_scale = self
+                .wl
+                .fractional_scale_manager
+                .as_ref()
+                .map(|manager| manager.get_fractional_scale(&wl_surface, &qh, ()));
+            let viewport = self
+                .wl
+                .viewporter
+                .as_ref()
+                .map(|viewporter| viewporter.get_viewport(&wl_surface, &qh, ()));
+
+            let unit = &mut self.units[index];
+            unit.wl_surface = wl_surface;
+            unit.shell = Shell::LayerShell(layer);
+            unit.fractional_scale = fractional_scale;
+            unit.viewport = viewport;
+            unit.configured = false;
+            unit.configured_size = None;
+            unit.initial_refresh_sent = false;
+            unit.keyboard_entered.set(false);
+            unit.keyboard_entered_now.set(false);
+            unit.pending_resize = PendingResize::default();
+            // the new surface enters its outputs again
+            let left_outputs = std::mem::take(&mut unit.entered_outputs);
+            // the frame callback of the destroyed surface will never be done
+            unit.present_available_state = PresentAvailableState::Available;
+            unit.request_refresh(RefreshRequest::NextFrame);
+            let unit_id = unit.id;
+            for output in left_outputs {
+                let name = self.output_name(&output);
+                self.wl.message.push((
+                    Some(unit_id),
+                    DispatchMessageInner::SurfaceLeaveOutput { output, name },
+                ));
+            }
+            self.wl
+                .message
+                .push((Some(unit_id), DispatchMessageInner::Recreated));
+        }
+        // the keyboard will enter the new surface again
+        self.wl.current_surface = None;
+        self.update_effective_visibility();
+    }
 
-                                    if use_last_output {
-                                        output = window_state.last_wloutput.as_ref();
-                                    }
+    /// destroy the cached cursor shape device, before its pointer is released
+    fn destroy_cursor_shape_device(&mut self) {
+        if let Some(device) = self.wl.cursor_shape_device.take() {
+            device.destroy();
+        }
+    }
 
-                                    if output.is_none() {
-                                        output = window_state.outputs.first().map(|(_, o)| o);
-                                    }
+    /// move an existing popup without recreating it, by sending a new positioner with
+    /// `xdg_popup.reposition`. Returns the token of the request, which will be sent back with
+    /// [DispatchMessage::PopupRepositioned] when the compositor applies it.
+    ///
+    /// Only works on popups, and needs xdg_wm_base version 3, otherwise it does nothing and
+    /// returns None
+    pub fn reposition_popup(&mut self, settings: RepositionPopUpSettings) -> Option<u32> {
+        if let Some((_, placement)) = self.popup_parents.get_mut(&settings.popup_id) {
+            *placement = settings.clone();
+        }
+        let RepositionPopUpSettings {
+            popup_id,
+            size,
+            position,
+            anchor_rect_size,
+            anchor,
+            gravity,
+            constraint_adjustment,
+            offset,
+            reactive,
+        } = settings;
+        let wmbase = self.wl.wmbase.clone()?;
+        let token = self.next_reposition_token;
+        let Some(unit) = self.get_mut_unit_with_id(popup_id) else {
+            log::warn!(
+                "RepositionPopUp: popup unit not found for id {:?}",
+                popup_id
+            );
+            return None;
+        };
+        let Shell::PopUp((xdg_popup, _)) = &unit.shell else {
+            log::warn!("RepositionPopUp: unit is not a popup");
+            return None;
+        };
+        if xdg_popup.version() < 3 {
+            log::warn!("RepositionPopUp: xdg_popup.reposition needs xdg_wm_base version 3");
+            return None;
+        }
+        let positioner = create_popup_positioner(
+            &wmbase,
+            &unit.qh,
+            size,
+            position,
+            anchor_rect_size,
+            anchor,
+            gravity,
+            constraint_adjustment,
+            offset,
+            reactive,
+        );
+        xdg_popup.reposition(&positioner, token);
+        positioner.destroy();
+        unit.reposition_token = Some(token);
+        self.next_reposition_token = token.wrapping_add(1);
+        log::debug!("RepositionPopUp: repositioned popup {popup_id:?} with token {token}");
+        Some(token)
+    }
 
-                                    let Some(output) = output else {
-                                        log::warn!("no WlOutput, skip creating input panel");
-                                        continue;
-                                    };
+    /// when the next resize held back by [WindowState::with_configure_debounce] settles
+    fn next_resize_deadline(&self) -> Option<Instant> {
+        let debounce = self.configure_debounce?;
+        self.units
+            .iter()
+            .filter_map(|unit| unit.pending_resize.deadline(debounce))
+            .min()
+    }
 
-                                    let wl_surface = wmcompositer.create_surface(&qh, ());
-                                    let input_panel = globals
-                                        .bind::<ZwpInputPanelV1, _, _>(&qh, 1..=1, ())
-                                        .unwrap();
-                                    let input_panel_surface =
-                                        input_panel.get_input_panel_surface(&wl_surface, &qh, ());
-                                    if keyboard {
-                                        input_panel_surface.set_toplevel(
-                                            output,
-                                            ZwpInputPanelPosition::CenterBottom as u32,
-                                        );
-                                    } else {
-                                        input_panel_surface.set_overlay_panel();
-                                    }
-                                    wl_surface.commit();
-
-                                    let mut fractional_scale = None;
-                                    if let Some(ref fractional_scale_manager) = fractional_scale_manager {
-                                        fractional_scale =
-                                            Some(fractional_scale_manager.get_fractional_scale(
-                                                &wl_surface,
-                                                &qh,
-                                                (),
-                                            ));
-                                    }
+    /// apply the resizes held back by [WindowState::with_configure_debounce] once they
+    /// settled, returns if any was applied
+    fn settle_resizes(&mut self) -> bool {
+        let Some(debounce) = self.configure_debounce else {
+            return false;
+        };
+        let now = Instant::now();
+        let mut resized = Vec::new();
+        for unit in self.units.iter_mut() {
+            if let Some(size) = unit.pending_resize.take_settled(now, debounce) {
+                unit.apply_configured_size(size);
+                unit.request_refresh(RefreshRequest::NextFrame);
+                resized.push(unit.id);
+            }
+        }
+        for parent in resized.iter() {
+            self.reanchor_child_popups(*parent);
+        }
+        !resized.is_empty()
+    }
 
-                                    let viewport = viewporter
-                                        .as_ref()
-                                        .map(|viewport| viewport.get_viewport(&wl_surface, &qh, ()));
-                                    window_state.push_window(
-                                        WindowStateUnitBuilder::new(
-                                            id,
-                                            qh.clone(),
-                                            connection.display(),
-                                            wl_surface,
-                                            Shell::InputPanel(input_panel_surface),
-                                        )
-                                        .size((width, height))
-                                        .viewport(viewport)
-                                        .fractional_scale(fractional_scale)
-                                        .binding(info)
-                                        .becreated(true)
-                                        .build(),
-                                    );
-                                },
-                                _ => {}
-                            }
-                        }
-                        if window_state.return_data.is_empty() {
-                            break;
-                        }
-                    }
+    /// present every unit waiting for a refresh which has a free present slot. A unit without a
+    /// buffer gets one through [LayerShellEvent::RequestBuffer] first
+    fn present_units<F, Message>(
+        &mut self,
+        event_handler: &mut F,
+        shm: &WlShm,
+        qh: &QueueHandle<Self>,
+    ) where
+        Message: std::marker::Send + 'static,
+        F: FnMut(LayerShellEvent<T, Message>, &mut WindowState<T>, Option<id::Id>) -> ReturnData<T>,
+    {
+        for idx in 0..self.units.len() {
+            let unit = &mut self.units[idx];
+            let (width, height) = unit.size;
+            if width == 0 || height == 0 {
+                // don't refresh, if size is 0.
+                continue;
+            }
+            // Skip already-initialized hidden units.
+            if !unit.visible && unit.initial_refresh_sent {
+                continue;
+            }
+            let last_present = unit.last_present;
+            if !unit.take_present_slot() {
+                if unit.should_refresh() {
+                    self.frame_stats.skipped_frames += 1;
+                }
+                continue;
+            }
+            let unit_id = unit.id;
+            trace_event(
+                self.trace_epoch,
+                Some(unit_id),
+                format_args!("present size=({width}, {height})"),
+            );
+            let is_created = unit.becreated;
+            let scale_float = unit.scale_float();
+            let wl_surface = unit.wl_surface.clone();
+            if unit.buffer.is_none() && !self.use_display_handle {
+                let Ok(mut file) = tempfile::tempfile() else {
+                    log::error!("Cannot create new file from tempfile");
+                    return;
+                };
+                let ReturnData::WlBuffer(buffer) = event_handler(
+                    LayerShellEvent::RequestBuffer(&mut file, shm, qh, width, height),
+                    self,
+                    Some(unit_id),
+                ) else {
+                    log::error!(
+                        "RequestBuffer must be answered with ReturnData::WlBuffer, skip the present"
+                    );
+                    continue;
+                };
+                wl_surface.attach(Some(&buffer), 0, 0);
+                wl_surface.commit();
+                trace_event(
+                    self.trace_epoch,
+                    Some(unit_id),
+                    format_args!("commit new buffer"),
+                );
+                self.units[idx].buffer = Some(buffer);
+            }
+            // the frame is committed by the handler during the refresh
+            self.units[idx].apply_fifo_barrier();
+            self.units[idx].apply_commit_time();
+            #[cfg(feature = "presentation-time")]
+            self.request_presentation_feedback(idx);
+            self.handle_event(
+                &mut *event_handler,
+                LayerShellEvent::RequestMessages(&DispatchMessage::RequestRefresh {
+                    width,
+                    height,
+                    is_created,
+                    scale_float,
+                }),
+                Some(unit_id),
+            );
+            self.units[idx].initial_refresh_sent = true;
+            // reset if the slot is not used
+            self.units[idx].reset_present_slot();
+            self.frame_stats.record_present(
+                unit_id,
+                last_present.map(|last_present| last_present.elapsed()),
+            );
+        }
+    }
 
-                    let to_be_closed_ids: Vec<_> = window_state
-                        .units
-                        .iter()
-                        .filter(|unit| unit.request_flag.close)
-                        .map(WindowStateUnit::id)
-                        .collect();
-                    if !to_be_closed_ids.is_empty() {
-                        log::info!(target: "move_debug", "processing {} to_be_closed unit(s) (request_flag.close)", to_be_closed_ids.len());
-                    }
-                    for id in to_be_closed_ids {
-                        window_state.handle_event(
-                            &mut *event_handler,
-                            LayerShellEvent::RequestMessages(&DispatchMessage::Closed),
-                            Some(id),
-                        );
-                        // event_handler may use unit, only remove it after calling event_handler.
-                        window_state.remove_shell(id);
-                    }
+    /// if the timer of the event loop has something to do on its next tick. In
+    /// [RenderMode::FrameCallback] a refresh waiting on a frame callback is left to the
+    /// `wl_callback::done` ping
+    fn has_pending_work(&self) -> bool {
+        let frame_callback = self.render_mode == RenderMode::FrameCallback;
+        !self.wl.message.is_empty()
+            || !self.wl.output_changes.is_empty()
+            || !self.closed_ids.is_empty()
+            || self.units.iter().any(|unit| {
+                (unit.should_refresh()
+                    && (!frame_callback
+                        || unit.present_available_state == PresentAvailableState::Available))
+                    || unit.request_flag.close
+                    || unit.pending_resize.is_pending()
+            })
+    }
 
-                    // NOTE: this is for those closed because wl_output is dead.
-                    let closed_ids = window_state.closed_ids.clone();
-                    for id in closed_ids {
-                        window_state.handle_event(
-                            &mut *event_handler,
-                            LayerShellEvent::RequestMessages(&DispatchMessage::Closed),
-                            Some(id),
-                        );
-                    }
-                    window_state.closed_ids.clear();
-
-                    // A single-surface `Active` app (e.g. the notifications
-                    // daemon) keeps exactly one layer surface, normally on the
-                    // active output. If that output was just disabled, the
-                    // surface is destroyed — but unlike plugging in a brand-new
-                    // display, no `NewDisplay` fires for the monitors that were
-                    // already connected. So nothing would move the surface to a
-                    // still-active monitor, and the app goes silently blind until
-                    // a display is (re-)enabled.
-                    //
-                    // Detect that here and synthesize a `NewDisplay` for a
-                    // surviving output; the recreate path above turns it into a
-                    // fresh surface on the next tick. This runs every 50 ms, so
-                    // it self-heals regardless of teardown ordering. Guards:
-                    //   - `has_live_surface()` is true for merely *hidden*
-                    //     surfaces, so the idle-hidden state never triggers it,
-                    //     and it stops the moment the surface is back;
-                    //   - the already-queued check prevents piling up duplicates.
-                    if window_state.is_active() && !window_state.has_live_surface() {
-                        let already_queued = window_state
-                            .message
-                            .iter()
-                            .any(|(_, m)| matches!(m, DispatchMessageInner::NewDisplay(_)));
-                        let next_output =
-                            window_state.outputs.first().map(|(_, o)| o.clone());
-                        if !already_queued
-                            && let Some(output) = next_output
-                        {
-                            window_state
-                                .message
-                                .push((None, DispatchMessageInner::NewDisplay(output)));
-                        }
-                    }
+    /// if the timer of the event loop sleeps when there is nothing to do, see
+    /// [WindowState::with_on_demand_presentation] and [RenderMode::FrameCallback]
+    fn timer_sleeps(&self) -> bool {
+        self.on_demand_presentation || self.render_mode == RenderMode::FrameCallback
+    }
 
-                    // Re-check hidden state AFTER NormalDispatch + action
-                    // processing.  show_surface() may have been called (e.g.
-                    // via the calloop channel between timer ticks, or during
-                    // NormalDispatch via a synchronous Task resolution), which
-                    // removes the surface from hidden_surfaces.  Using a fresh
-                    // check ensures we don't skip the Refresh loop with a
-                    // stale all_hidden=true.
-                    let all_hidden = window_state.all_surfaces_hidden();
-
-                    // When every surface is hidden via the layer-surface-visibility
-                    // protocol the compositor is not rendering us.  Skip the
-                    // (expensive) per-unit refresh / present cycle and idle
-                    // at a low rate so we still wake up for protocol events
-                    // (e.g. show requests).
-                    //
-                    // Exception: newly created units that have never been
-                    // through a refresh cycle must still get their initial
-                    // Refresh event so the iced window_manager registers them.
-                    // Without this, surfaces created with `start_hidden` would
-                    // never be findable by ShowWindow.
-                    for idx in 0..window_state.units.len() {
-                        let unit = &mut window_state.units[idx];
-                        let (width, height) = unit.size;
-                        if width == 0 || height == 0 {
-                            // don't refresh, if size is 0.
-                            continue;
-                        }
-                        // Skip already-initialized units when all hidden.
-                        if all_hidden && unit.initial_refresh_sent {
-                            continue;
-                        }
-                        if unit.take_present_slot() {
-                            log::debug!("[evloop] timer: presenting unit {:?}", unit.id);
-                            let unit_id = unit.id;
-                            let is_created = unit.becreated;
-                            let scale_float = unit.scale_float();
-                            let wl_surface = unit.wl_surface.clone();
-                            if unit.buffer.is_none() && !window_state.use_display_handle {
-                                let Ok(mut file) = tempfile::tempfile() else {
-                                    log::error!("Cannot create new file from tempfile");
-                                    return TimeoutAction::Drop;
-                                };
-                                let ReturnData::WlBuffer(buffer) = event_handler(
-                                    LayerShellEvent::RequestBuffer(&mut file, &shm, &qh, width, height),
-                                    window_state,
-                                    Some(unit_id)) else {
-                                    panic!("You cannot return this one");
-                                };
-                                wl_surface.attach(Some(&buffer), 0, 0);
-                                wl_surface.commit();
-                                window_state.units[idx].buffer = Some(buffer);
-                            }
-                            window_state.handle_event(
-                                &mut *event_handler,
-                                LayerShellEvent::RequestMessages(&DispatchMessage::RequestRefresh {
-                                    width,
-                                    height,
-                                    is_created,
-                                    scale_float,
-                                }),
-                                Some(unit_id),
-                            );
-                            window_state.units[idx].initial_refresh_sent = true;
-                            // reset if the slot is not used
-                            window_state.units[idx].reset_present_slot();
-                        }
-                    }
+    /// the delay until the next tick of the timer of the event loop. When
+    /// `timer_sleeps` it sleeps until the next scheduled refresh when there is
+    /// nothing to do, the event loop arms it again
+    fn next_tick_interval(&mut self) -> Duration {
+        const TICK: Duration = Duration::from_millis(50);
+        const IDLE_TICK: Duration = Duration::from_secs(1);
+        self.wl.timer_idle = self.timer_sleeps() && !self.has_pending_work();
+        if !self.wl.timer_idle {
+            return TICK;
+        }
+        let now = Instant::now();
+        self.units
+            .iter()
+            .filter_map(|unit| match unit.request_flag.refresh {
+                RefreshRequest::At(instant) => Some(instant.saturating_duration_since(now)),
+                _ => None,
+            })
+            .fold(IDLE_TICK, Duration::min)
+    }
 
-                    // Timer interval is kept at a battery-friendly 50ms.
-                    // Immediate wake-ups are handled by the Ping source:
-                    //  - Channel messages (iced subscriptions) → ping
-                    //  - Compositor frame callbacks (wl_callback::done) → ping
-                    // The timer is only a safety net for edge cases.
-                    TimeoutAction::ToDuration(std::time::Duration::from_millis(50))
-                },
-            )
-            .expect("Cannot insert_source");
-        event_loop
-            .run(
-                std::time::Duration::from_millis(20),
-                &mut state,
-                move |r_window_state| {
-                    let window_state = &mut r_window_state.raw;
-                    let _ = event_queue_origin.roundtrip(window_state);
-                    let looph = &r_window_state.loop_handle;
-                    for token in window_state.to_remove_tokens.iter() {
-                        looph.remove(*token);
-                    }
-                    window_state.to_remove_tokens.clear();
-                    if let Some(VirtualKeyRelease { delay, time, key }) =
-                        window_state.to_be_released_key
-                    {
-                        looph
-                            .insert_source(
-                                Timer::from_duration(delay),
-                                move |_, _, r_window_state| {
-                                    let state = &mut r_window_state.raw;
-                                    let ky = state.get_virtual_keyboard().unwrap();
-
-                                    ky.key(time, key, KeyState::Released.into());
-                                    TimeoutAction::Drop
-                                },
-                            )
-                            .ok();
-                    }
-                    if let Some(KeyboardTokenState {
-                        key,
-                        delay,
-                        surface_id,
-                        pressed_state,
-                    }) = window_state.repeat_delay.take()
-                    {
-                        let timer = Timer::from_duration(delay);
-                        let keyboard_state = window_state.keyboard_state.as_mut().unwrap();
-                        keyboard_state.repeat_token = looph
-                            .insert_source(timer, move |_, _, r_window_state| {
-                                let state = &mut r_window_state.raw;
-                                let event_handler = &mut r_window_state.fun;
-                                let keyboard_state = match state.keyboard_state.as_mut() {
-                                    Some(keyboard_state) => keyboard_state,
-                                    None => return TimeoutAction::Drop,
-                                };
-                                let repeat_keycode = match keyboard_state.current_repeat {
-                                    Some(repeat_keycode) => repeat_keycode,
-                                    None => return TimeoutAction::Drop,
-                                };
-                                // NOTE: not the same key
-                                if repeat_keycode != key {
-                                    return TimeoutAction::Drop;
-                                }
-                                if let Some(mut key_context) =
-                                    keyboard_state.xkb_context.key_context()
-                                {
-                                    let event = key_context.process_key_event(
-                                        repeat_keycode,
-                                        pressed_state,
-                                        false,
-                                    );
-                                    let event = DispatchMessageInner::KeyboardInput {
-                                        event,
-                                        is_synthetic: false,
-                                    };
-                                    state.message.push((surface_id, event));
-                                }
-                                let repeat_info = keyboard_state.repeat_info;
+    /// call the callback set by [WindowState::with_idle_callback]
+    fn run_idle_callback(&mut self) {
+        let Some(IdleCallback(mut callback)) = self.idle_callback.take() else {
+            return;
+        };
+        callback(self);
+        // the callback may have set a new one
+        if self.idle_callback.is_none() {
+            self.idle_callback = Some(IdleCallback(callback));
+        }
+    }
 
-                                let _ = keyboard_state;
-                                state.handle_event(
-                                    &mut *event_handler,
-                                    LayerShellEvent::NormalDispatch,
-                                    None,
-                                );
-                                match repeat_info {
-                                    RepeatInfo::Repeat { gap, .. } => {
-                                        TimeoutAction::ToDuration(gap)
-                                    }
-                                    RepeatInfo::Disable => TimeoutAction::Drop,
-                                }
-                            })
-                            .ok();
-                    }
-                },
-            )
-            .expect("Error during event loop!");
-        Ok(())
+    /// send the stored placement of every popup of the parent again, so the anchor rect
+    /// follows the parent after it is resized
+    fn reanchor_child_popups(&mut self, parent: id::Id) {
+        let placements: Vec<RepositionPopUpSettings> = self
+            .popup_parents
+            .values()
+            // reactive popups are repositioned by the compositor itself
+            .filter(|(popup_parent, placement)| *popup_parent == parent && !placement.reactive)
+            .map(|(_, placement)| placement.clone())
+            .collect();
+        for placement in placements {
+            log::debug!("re-anchor popup {:?} of {parent:?}", placement.popup_id);
+            self.reposition_popup(placement);
+        }
     }
 
     pub fn request_next_present(&mut self, id: id::Id) {
+        trace_event(
+            self.trace_epoch,
+            Some(id),
+            format_args!("request frame callback"),
+        );
         self.get_mut_unit_with_id(id)
             .map(WindowStateUnit::request_next_present);
     }
@@ -7926,6 +12387,26 @@ fn get_cursor_buffer(
     Some(cursor?[0].clone())
 }
 
+/// the cursor shape device of a pointer, with the count of the shapes set through it, which
+/// shows in the debug log how many shape changes one device served
+#[derive(Debug)]
+struct CursorShapeDevice {
+    pointer: WlPointer,
+    device: WpCursorShapeDeviceV1,
+    shapes_set: u32,
+}
+
+impl CursorShapeDevice {
+    fn destroy(self) {
+        log::debug!(
+            "destroy the cursor shape device of {:?}, it set {} shapes",
+            self.pointer.id(),
+            self.shapes_set
+        );
+        self.device.destroy();
+    }
+}
+
 /// avoid too_many_arguments alert in `set_cursor_shape`
 struct CursorUpdateContext<T: 'static> {
     cursor_manager: Option<WpCursorShapeManagerV1>,
@@ -7937,34 +12418,404 @@ struct CursorUpdateContext<T: 'static> {
 
 fn set_cursor_shape<T: 'static>(
     context: &CursorUpdateContext<T>,
+    cursor_shape_device: &mut Option<CursorShapeDevice>,
     shape_name: String,
     pointer: WlPointer,
     serial: u32,
 ) {
-    if let Some(cursor_manager) = &context.cursor_manager {
+    if context.cursor_manager.is_some() {
         let Some(shape) = str_to_shape(&shape_name) else {
-            log::error!("Not supported shape");
+            log::error!("Not supported shape {shape_name}, see all_shape_names for the valid ones");
             return;
         };
-        let device = cursor_manager.get_pointer(&pointer, &context.qh, ());
-        device.set_shape(serial, shape);
-        device.destroy();
+        set_cursor_shape_typed(context, cursor_shape_device, shape, pointer, serial);
     } else {
         let Some(cursor_buffer) = get_cursor_buffer(&shape_name, &context.connection, &context.shm)
         else {
             log::error!("Cannot find cursor {shape_name}");
             return;
         };
-        let cursor_surface = context.wmcompositer.create_surface(&context.qh, ());
-        cursor_surface.attach(Some(&cursor_buffer), 0, 0);
-        // and create a surface. if two or more,
         let (hotspot_x, hotspot_y) = cursor_buffer.hotspot();
-        pointer.set_cursor(
+        set_cursor_buffer(
+            context,
+            &cursor_buffer,
+            (hotspot_x as i32, hotspot_y as i32),
+            &pointer,
             serial,
-            Some(&cursor_surface),
-            hotspot_x as i32,
-            hotspot_y as i32,
         );
-        cursor_surface.commit();
+    }
+}
+
+/// set the cursor image from a buffer, without the cursor shape protocol
+fn set_cursor_buffer<T: 'static>(
+    context: &CursorUpdateContext<T>,
+    buffer: &WlBuffer,
+    (hotspot_x, hotspot_y): (i32, i32),
+    pointer: &WlPointer,
+    serial: u32,
+) {
+    let cursor_surface = context.wmcompositer.create_surface(&context.qh, ());
+    cursor_surface.attach(Some(buffer), 0, 0);
+    // and create a surface. if two or more,
+    pointer.set_cursor(serial, Some(&cursor_surface), hotspot_x, hotspot_y);
+    cursor_surface.commit();
+}
+
+fn set_cursor_shape_typed<T: 'static>(
+    context: &CursorUpdateContext<T>,
+    cursor_shape_device: &mut Option<CursorShapeDevice>,
+    shape: Shape,
+    pointer: WlPointer,
+    serial: u32,
+) {
+    let Some(cursor_manager) = &context.cursor_manager else {
+        // without the cursor shape protocol, the cursor is loaded from the theme by its name
+        set_cursor_shape(
+            context,
+            cursor_shape_device,
+            shape.name().to_owned(),
+            pointer,
+            serial,
+        );
+        return;
+    };
+    // one device per pointer: getting and destroying one per shape change made every cursor
+    // update 3 requests and a new object id, it is 1 request now. Hovering over the 20 buttons
+    // of a toolbar and back sets about 40 shapes, that is 120 requests before and 41 now
+    if cursor_shape_device
+        .as_ref()
+        .is_none_or(|device| device.pointer != pointer)
+    {
+        if let Some(device) = cursor_shape_device.take() {
+            device.destroy();
+        }
+        log::debug!("create the cursor shape device of {:?}", pointer.id());
+        let device = cursor_manager.get_pointer(&pointer, &context.qh, ());
+        *cursor_shape_device = Some(CursorShapeDevice {
+            pointer,
+            device,
+            shapes_set: 0,
+        });
+    }
+    if let Some(CursorShapeDevice {
+        device, shapes_set, ..
+    }) = cursor_shape_device
+    {
+        // sending a shape the version doesn't have is a protocol error
+        let shape = if device.version() < 2 && shape_since_v2(shape) {
+            log::warn!(
+                "cursor shape {} needs wp_cursor_shape_manager_v1 v2",
+                shape.name()
+            );
+            Shape::Default
+        } else {
+            shape
+        };
+        device.set_shape(serial, shape);
+        *shapes_set += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        DispatchMessage, DispatchMessageInner, FocusState, FrameStats, Ime, LayerConfig,
+        PendingResize, RepeatInfo, ShapeName, coalesce_motion, enter_repeat, exclusive_edge,
+        held_repeat_key, keyboard_message_allowed, layer_axis_origin, str_to_shape,
+    };
+    use crate::keyboard::ModifiersState;
+    use crate::reexport::KeyboardInteractivity;
+    use crate::reexport::wp_cursor_shape_device_v1::{Shape, all_shape_names};
+    #[cfg(feature = "serde")]
+    use crate::reexport::{Anchor, Layer};
+    use crate::xkb_keyboard::{Context, ElementState, KeyEvent};
+    use std::time::{Duration, Instant};
+
+    fn enter_keys(keys: &[u32]) -> Vec<u8> {
+        keys.iter().flat_map(|key| key.to_ne_bytes()).collect()
+    }
+
+    /// an xkb context with a minimal keymap: the `1` key (evdev 2) and the left shift (evdev
+    /// 42), which does not repeat
+    fn xkb_context() -> Context {
+        use std::io::Write;
+        const KEYMAP: &str = r#"xkb_keymap {
+    xkb_keycodes { minimum = 8; maximum = 255; <AE01> = 10; <LFSH> = 50; };
+    xkb_types { type "ONE_LEVEL" { modifiers = none; level_name[Level1] = "Any"; }; };
+    xkb_compat { };
+    xkb_symbols {
+        key <AE01> { [ 1 ] };
+        key <LFSH> { repeat = false, [ Shift_L ] };
+    };
+};"#;
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(KEYMAP.as_bytes()).unwrap();
+        let mut context = Context::new().expect("libxkbcommon is needed by the tests");
+        context.set_keymap_from_fd(file.into(), KEYMAP.len());
+        context
+    }
+
+    /// the press of the `1` key
+    fn key_event() -> KeyEvent {
+        xkb_context()
+            .key_context()
+            .expect("the test keymap compiles")
+            .process_key_event(10, ElementState::Pressed, false)
+    }
+
+    #[test]
+    fn enter_with_held_keys_repeats_last_repeatable() {
+        // KEY_A (30) and KEY_LEFTSHIFT (42), shift does not repeat
+        let keys = enter_keys(&[30, 42]);
+        assert_eq!(held_repeat_key(&keys, |key| key != 42 + 8), Some(30 + 8));
+        assert_eq!(held_repeat_key(&keys, |_| true), Some(42 + 8));
+    }
+
+    #[test]
+    fn enter_with_held_keys_arms_the_repeat() {
+        let mut context = xkb_context();
+        let id = Some(crate::id::Id::MAIN);
+        let repeat = RepeatInfo::Repeat {
+            gap: Duration::from_millis(40),
+            delay: Duration::from_millis(600),
+        };
+        // the `1` key and the shift are held, the shift does not repeat
+        let token = enter_repeat(&mut context, repeat, &enter_keys(&[2, 42]), id)
+            .expect("the held `1` repeats");
+        assert_eq!(token.key, 10);
+        assert_eq!(token.delay, Duration::from_millis(600));
+        assert_eq!(token.surface_id, id);
+        assert_eq!(token.pressed_state, ElementState::Pressed);
+        assert!(enter_repeat(&mut context, repeat, &enter_keys(&[42]), id).is_none());
+        assert!(enter_repeat(&mut context, RepeatInfo::Disable, &enter_keys(&[2]), id).is_none());
+    }
+
+    #[test]
+    fn enter_without_repeatable_keys() {
+        assert_eq!(held_repeat_key(&[], |_| true), None);
+        assert_eq!(held_repeat_key(&enter_keys(&[42]), |_| false), None);
+    }
+
+    #[test]
+    fn rapid_configures_settle_once_at_last_size() {
+        let debounce = Duration::from_millis(8);
+        let start = Instant::now();
+        let mut pending = PendingResize::default();
+        let mut refreshes = Vec::new();
+        for (ms, size) in [(0, (100, 50)), (2, (120, 50)), (4, (140, 60))] {
+            let now = start + Duration::from_millis(ms);
+            pending.configure(now, size);
+            refreshes.extend(pending.take_settled(now, debounce));
+        }
+        for ms in [6, 10, 20, 40] {
+            refreshes.extend(pending.take_settled(start + Duration::from_millis(ms), debounce));
+        }
+        assert_eq!(refreshes, vec![(140, 60)]);
+    }
+
+    #[test]
+    fn configure_resizes_held_back_until_deadline() {
+        let debounce = Some(Duration::from_millis(8));
+        let start = Instant::now();
+        let mut pending = PendingResize::default();
+        // the first configure is applied right away
+        assert!(pending.configure_size(false, (0, 0), (100, 50), debounce, start));
+        // so is one which does not change the size
+        assert!(pending.configure_size(true, (100, 50), (100, 50), debounce, start));
+        let later = start + Duration::from_millis(2);
+        assert!(!pending.configure_size(true, (100, 50), (140, 60), debounce, later));
+        let deadline = pending.deadline(debounce.unwrap()).unwrap();
+        assert_eq!(deadline, later + debounce.unwrap());
+        assert_eq!(
+            pending.take_settled(deadline, debounce.unwrap()),
+            Some((140, 60))
+        );
+        // without a debounce every resize is applied
+        assert!(pending.configure_size(true, (100, 50), (140, 60), None, later));
+        assert!(!pending.is_pending());
+    }
+
+    #[test]
+    fn cursor_shape_names_round_trip() {
+        for name in all_shape_names() {
+            let shape = str_to_shape(name).unwrap_or_else(|| panic!("{name} does not parse"));
+            assert_eq!(shape.name(), *name);
+        }
+        let mut value = 1;
+        while let Ok(shape) = Shape::try_from(value) {
+            assert_eq!(str_to_shape(shape.name()), Some(shape));
+            value += 1;
+        }
+        assert_eq!(value as usize - 1, all_shape_names().len());
+    }
+
+    #[test]
+    fn layer_origin_keeps_the_margins() {
+        // a 100 wide surface on a 1000 wide output, margins 10 on the start and 50 on the end
+        assert_eq!(layer_axis_origin(true, false, 10, 50, 100, 1000), 10);
+        assert_eq!(layer_axis_origin(false, true, 10, 50, 100, 1000), 850);
+        // centered in the 940 left between the margins
+        assert_eq!(layer_axis_origin(true, true, 10, 50, 100, 1000), 430);
+        // the margins are ignored without an anchor
+        assert_eq!(layer_axis_origin(false, false, 10, 50, 100, 1000), 450);
+    }
+
+    #[test]
+    fn no_keyboard_messages_without_interactivity() {
+        let keyboard_messages = [
+            DispatchMessage::ModifiersChanged(ModifiersState::SHIFT),
+            DispatchMessage::Focused(crate::id::Id::MAIN),
+            DispatchMessage::Unfocus,
+            DispatchMessage::Ime(Ime::Commit("a".to_owned())),
+            DispatchMessage::Ime(Ime::Enabled),
+            DispatchMessage::KeyboardInput {
+                event: key_event(),
+                is_synthetic: false,
+            },
+        ];
+        for msg in keyboard_messages.iter() {
+            assert!(!keyboard_message_allowed(KeyboardInteractivity::None, msg));
+            assert!(keyboard_message_allowed(
+                KeyboardInteractivity::OnDemand,
+                msg
+            ));
+        }
+        assert!(keyboard_message_allowed(
+            KeyboardInteractivity::None,
+            &DispatchMessage::MouseLeave
+        ));
+    }
+
+    #[test]
+    fn focus_moves_between_surfaces_balanced() {
+        let a = crate::id::Id::unique();
+        let b = crate::id::Id::unique();
+        let mut focus = FocusState::default();
+        let mut messages = Vec::new();
+        // keyboard enters a, then b before the leave of a arrives
+        messages.extend(focus.focus(Some(a)));
+        messages.extend(focus.focus(Some(b)));
+        messages.extend(focus.leave(Some(a)));
+        messages.extend(focus.focus(Some(b)));
+        messages.extend(focus.leave(Some(b)));
+        messages.extend(focus.leave(Some(b)));
+        // (surface, focused)
+        let messages: Vec<(crate::id::Id, bool)> = messages
+            .into_iter()
+            .map(|(id, msg)| match msg {
+                DispatchMessageInner::Focused(focused) => {
+                    assert_eq!(id, Some(focused));
+                    (focused, true)
+                }
+                DispatchMessageInner::Unfocus => (id.unwrap(), false),
+                _ => unreachable!(),
+            })
+            .collect();
+        assert_eq!(messages, vec![(a, true), (a, false), (b, true), (b, false)]);
+    }
+
+    #[test]
+    fn exclusive_edge_follows_anchor() {
+        use crate::reexport::Anchor;
+        assert_eq!(exclusive_edge(Anchor::Top), Some(Anchor::Top));
+        assert_eq!(
+            exclusive_edge(Anchor::Bottom | Anchor::Left | Anchor::Right),
+            Some(Anchor::Bottom)
+        );
+        assert_eq!(
+            exclusive_edge(Anchor::Left | Anchor::Top | Anchor::Bottom),
+            Some(Anchor::Left)
+        );
+        assert_eq!(exclusive_edge(Anchor::Top | Anchor::Left), None);
+        assert_eq!(exclusive_edge(Anchor::all()), None);
+        assert_eq!(exclusive_edge(Anchor::empty()), None);
+    }
+
+    #[test]
+    fn motion_coalesced_around_buttons() {
+        use crate::reexport::wayland_client::{WEnum, wl_pointer::ButtonState};
+        let a = crate::id::Id::unique();
+        let b = crate::id::Id::unique();
+        let motion = |id, x| {
+            (
+                Some(id),
+                DispatchMessageInner::MouseMotion {
+                    time: 0,
+                    surface_x: x,
+                    surface_y: 0.,
+                },
+            )
+        };
+        let button = (
+            Some(a),
+            DispatchMessageInner::MouseButton {
+                state: WEnum::Value(ButtonState::Pressed),
+                serial: 0,
+                button: 0x110,
+                time: 0,
+            },
+        );
+        let mut messages = vec![
+            motion(a, 1.),
+            motion(a, 2.),
+            button,
+            motion(a, 3.),
+            motion(b, 4.),
+            motion(b, 5.),
+        ];
+        coalesce_motion(&mut messages);
+        let kept: Vec<_> = messages
+            .iter()
+            .map(|(id, message)| match message {
+                DispatchMessageInner::MouseMotion { surface_x, .. } => (id.unwrap(), *surface_x),
+                _ => (id.unwrap(), -1.),
+            })
+            .collect();
+        assert_eq!(kept, vec![(a, 2.), (a, -1.), (a, 3.), (b, 5.)]);
+    }
+
+    #[test]
+    fn frame_stats_count_presents() {
+        use std::time::Duration;
+        let a = crate::id::Id::unique();
+        let b = crate::id::Id::unique();
+        let mut stats = FrameStats::default();
+        stats.record_present(a, None);
+        assert_eq!(stats.average_present_interval, None);
+        stats.record_present(a, Some(Duration::from_millis(16)));
+        stats.record_present(b, Some(Duration::from_millis(32)));
+        assert_eq!(stats.presents, 3);
+        assert_eq!(stats.unit_presents[&a], 2);
+        assert_eq!(stats.unit_presents[&b], 1);
+        assert_eq!(
+            stats.average_present_interval,
+            Some(Duration::from_millis(17))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn layer_config_serde_round_trip() {
+        let config = LayerConfig {
+            layer: Layer::Overlay,
+            anchor: Anchor::Top | Anchor::Left | Anchor::Right,
+            size: Some((0, 40)),
+            margin: (4, 8, 0, 8),
+            exclusive_zone: Some(40),
+            keyboard_interactivity: KeyboardInteractivity::OnDemand,
+        };
+        let json = serde_json::to_string(&config).unwrap();
+        // the protocol enums are stored as their wire values
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["layer"], 3);
+        assert_eq!(value["anchor"], 13);
+        assert_eq!(value["keyboard_interactivity"], 2);
+        assert_eq!(serde_json::from_str::<LayerConfig>(&json).unwrap(), config);
+
+        for (field, invalid) in [("layer", 4), ("anchor", 16), ("keyboard_interactivity", 3)] {
+            let mut value = value.clone();
+            value[field] = invalid.into();
+            assert!(serde_json::from_value::<LayerConfig>(value).is_err());
+        }
     }
 }
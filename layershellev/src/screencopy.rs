@@ -6,13 +6,19 @@
 //!
 //! Requires the `screencopy` feature and the `foreign-toplevel` feature
 //! (for `ext_foreign_toplevel_handle_v1` handles).
+//!
+//! Whole outputs are captured with `wlr-screencopy-unstable-v1` instead, straight into a
+//! file given by the application, see `WindowState::capture_output`.
 
 use std::collections::HashMap;
+use std::fs::File;
 use std::io::{Read, Seek, SeekFrom};
 use std::os::fd::AsFd;
+use std::sync::Mutex;
 use std::time::Instant;
 
 use wayland_client::protocol::wl_buffer::WlBuffer;
+use wayland_client::protocol::wl_output::WlOutput;
 use wayland_client::protocol::wl_shm;
 use wayland_client::protocol::wl_shm_pool::WlShmPool;
 use wayland_client::{Connection, Dispatch, Proxy, QueueHandle, WEnum};
@@ -26,6 +32,10 @@ use wayland_protocols::ext::image_copy_capture::v1::client::{
     ext_image_copy_capture_manager_v1::{self, ExtImageCopyCaptureManagerV1},
     ext_image_copy_capture_session_v1::{self, ExtImageCopyCaptureSessionV1},
 };
+use wayland_protocols_wlr::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
 
 /// A captured frame from a toplevel window
 #[derive(Debug, Clone)]
@@ -47,6 +57,17 @@ pub enum ScreencopyEvent {
     Ready(CapturedFrame),
     /// A capture session failed
     Failed { toplevel_id: u32, reason: String },
+    /// An output was copied into the file given to `WindowState::capture_output`, as
+    /// `height` rows of `stride` bytes in the `format` pixel format
+    OutputReady {
+        output: WlOutput,
+        width: u32,
+        height: u32,
+        stride: u32,
+        format: wl_shm::Format,
+    },
+    /// The capture of an output failed, like when the output went away
+    OutputFailed { output: WlOutput },
 }
 
 /// Actions the application can request for screencopy
@@ -148,6 +169,8 @@ pub(crate) struct ScreencopyState {
     pub target_size: Option<(u32, u32)>,
     /// Last capture timestamp per toplevel (for throttling)
     pub last_capture: HashMap<u32, Instant>,
+    /// zwlr_screencopy_manager_v1 global, for the output captures
+    pub output_manager: Option<ZwlrScreencopyManagerV1>,
 }
 
 impl ScreencopyState {
@@ -161,6 +184,7 @@ impl ScreencopyState {
             continuous: false,
             target_size: None,
             last_capture: HashMap::new(),
+            output_manager: None,
         }
     }
 
@@ -267,6 +291,109 @@ where
     log::debug!("Started screencopy capture for toplevel id={}", toplevel_id);
 }
 
+/// Copy the content of `output` into `file` with wlr-screencopy. The file is resized to fit
+/// the frame, [ScreencopyEvent::OutputReady] tells when it is written.
+pub(crate) fn start_output_capture<D>(
+    state: &D,
+    output: &WlOutput,
+    file: File,
+    qh: &QueueHandle<D>,
+) -> bool
+where
+    D: ScreencopyHandler + Dispatch<ZwlrScreencopyFrameV1, OutputFrameData> + 'static,
+{
+    let Some(manager) = state.screencopy_state().output_manager.as_ref() else {
+        log::warn!("Output screencopy not available (missing zwlr_screencopy_manager_v1)");
+        return false;
+    };
+    // overlay_cursor = 0, the cursor is not painted
+    manager.capture_output(
+        0,
+        output,
+        qh,
+        OutputFrameData {
+            output: output.clone(),
+            file,
+            buffer: Mutex::new(None),
+        },
+    );
+    true
+}
+
+/// Create the shm buffer the compositor asked for in the file of the capture, and copy the
+/// frame into it. The capture fails with [ScreencopyEvent::OutputFailed] when the buffer
+/// can't be created
+fn copy_output_frame<D>(
+    state: &mut D,
+    frame: &ZwlrScreencopyFrameV1,
+    data: &OutputFrameData,
+    qh: &QueueHandle<D>,
+) where
+    D: ScreencopyHandler
+        + Dispatch<WlBuffer, BufferData>
+        + Dispatch<WlShmPool, ShmPoolData>
+        + 'static,
+{
+    let mut buffer = data.buffer.lock().unwrap();
+    let Some(OutputBuffer {
+        width,
+        height,
+        stride,
+        format,
+        wl_buffer: wl_buffer @ None,
+    }) = buffer.as_mut()
+    else {
+        return;
+    };
+    let Some(shm) = state.get_shm() else {
+        log::warn!("No wl_shm available for screencopy");
+        drop(buffer);
+        output_frame_failed(state, frame, data);
+        return;
+    };
+    let size = *stride as i32 * *height as i32;
+    if let Err(e) = data.file.set_len(size as u64) {
+        log::error!("Failed to resize the output screencopy file: {}", e);
+        drop(buffer);
+        output_frame_failed(state, frame, data);
+        return;
+    }
+    let pool = shm.create_pool(data.file.as_fd(), size, qh, ShmPoolData);
+    let buffer = pool.create_buffer(
+        0,
+        *width as i32,
+        *height as i32,
+        *stride as i32,
+        *format,
+        qh,
+        BufferData,
+    );
+    pool.destroy();
+    frame.copy(&buffer);
+    *wl_buffer = Some(buffer);
+}
+
+/// Give up the capture of an output, telling the app with [ScreencopyEvent::OutputFailed]
+fn output_frame_failed<D: ScreencopyHandler>(
+    state: &mut D,
+    frame: &ZwlrScreencopyFrameV1,
+    data: &OutputFrameData,
+) {
+    if let Some(wl_buffer) = data
+        .buffer
+        .lock()
+        .unwrap()
+        .take()
+        .and_then(|buffer| buffer.wl_buffer)
+    {
+        wl_buffer.destroy();
+    }
+    state.screencopy_event(ScreencopyEvent::OutputFailed {
+        output: data.output.clone(),
+    });
+    frame.destroy();
+}
+
 // ============================================================================
 // Pixel format conversion
 // ============================================================================
@@ -551,6 +678,26 @@ pub(crate) struct CaptureFrameData {
 #[derive(Debug, Clone, Default)]
 pub(crate) struct BufferData;
 
+#[derive(Debug, Clone, Default)]
+pub(crate) struct OutputManagerData;
+
+/// The shm buffer a zwlr_screencopy_frame_v1 is copied into
+#[derive(Debug)]
+pub(crate) struct OutputBuffer {
+    width: u32,
+    height: u32,
+    stride: u32,
+    format: wl_shm::Format,
+    wl_buffer: Option<WlBuffer>,
+}
+
+#[derive(Debug)]
+pub(crate) struct OutputFrameData {
+    output: WlOutput,
+    file: File,
+    buffer: Mutex<Option<OutputBuffer>>,
+}
+
 #[derive(Debug, Clone, Default)]
 pub(crate) struct ShmPoolData;
 
@@ -763,3 +910,99 @@ where
     ) {
     }
 }
+
+/// zwlr_screencopy_manager_v1 — global, no events
+impl<D> Dispatch<ZwlrScreencopyManagerV1, OutputManagerData, D> for ()
+where
+    D: Dispatch<ZwlrScreencopyManagerV1, OutputManagerData> + 'static,
+{
+    fn event(
+        _state: &mut D,
+        _proxy: &ZwlrScreencopyManagerV1,
+        _event: <ZwlrScreencopyManagerV1 as Proxy>::Event,
+        _data: &OutputManagerData,
+        _conn: &Connection,
+        _qh: &QueueHandle<D>,
+    ) {
+    }
+}
+
+/// zwlr_screencopy_frame_v1 — buffer constraints, then ready / failed
+impl<D> Dispatch<ZwlrScreencopyFrameV1, OutputFrameData, D> for ()
+where
+    D: ScreencopyHandler
+        + Dispatch<ZwlrScreencopyFrameV1, OutputFrameData>
+        + Dispatch<WlBuffer, BufferData>
+        + Dispatch<WlShmPool, ShmPoolData>
+        + 'static,
+{
+    fn event(
+        state: &mut D,
+        proxy: &ZwlrScreencopyFrameV1,
+        event: zwlr_screencopy_frame_v1::Event,
+        data: &OutputFrameData,
+        _conn: &Connection,
+        qh: &QueueHandle<D>,
+    ) {
+        match event {
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format: WEnum::Value(format),
+                width,
+                height,
+                stride,
+            } => {
+                log::debug!("Output screencopy buffer: {width}x{height} {format:?}");
+                data.buffer.lock().unwrap().get_or_insert(OutputBuffer {
+                    width,
+                    height,
+                    stride,
+                    format,
+                    wl_buffer: None,
+                });
+                // before v3 there is no buffer_done, the shm buffer is the only one offered
+                if proxy.version() < 3 {
+                    copy_output_frame(state, proxy, data, qh);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::Buffer {
+                format: WEnum::Unknown(format),
+                ..
+            } => {
+                log::warn!("Output screencopy offers an unknown shm format {format:#x}");
+                // before v3 no buffer_done follows to fail the capture
+                if proxy.version() < 3 {
+                    output_frame_failed(state, proxy, data);
+                }
+            }
+            zwlr_screencopy_frame_v1::Event::BufferDone => {
+                if data.buffer.lock().unwrap().is_none() {
+                    log::warn!("Output screencopy offers no shm buffer");
+                    output_frame_failed(state, proxy, data);
+                    return;
+                }
+                copy_output_frame(state, proxy, data, qh);
+            }
+            zwlr_screencopy_frame_v1::Event::Ready { .. } => {
+                let Some(buffer) = data.buffer.lock().unwrap().take() else {
+                    return;
+                };
+                if let Some(wl_buffer) = buffer.wl_buffer {
+                    wl_buffer.destroy();
+                }
+                state.screencopy_event(ScreencopyEvent::OutputReady {
+                    output: data.output.clone(),
+                    width: buffer.width,
+                    height: buffer.height,
+                    stride: buffer.stride,
+                    format: buffer.format,
+                });
+                proxy.destroy();
+            }
+            zwlr_screencopy_frame_v1::Event::Failed => {
+                log::warn!("Output screencopy failed");
+                output_frame_failed(state, proxy, data);
+            }
+            _ => {}
+        }
+    }
+}
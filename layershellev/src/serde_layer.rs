@@ -0,0 +1,52 @@
+//! serde for the protocol types in [crate::LayerConfig], they are stored as their wire values
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de::Error};
+use wayland_protocols_wlr::layer_shell::v1::client::{
+    zwlr_layer_shell_v1::Layer,
+    zwlr_layer_surface_v1::{Anchor, KeyboardInteractivity},
+};
+
+pub mod layer {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(layer: &Layer, serializer: S) -> Result<S::Ok, S::Error> {
+        u32::from(*layer).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Layer, D::Error> {
+        let value = u32::deserialize(deserializer)?;
+        Layer::try_from(value).map_err(|_| D::Error::custom(format!("invalid layer {value}")))
+    }
+}
+
+pub mod anchor {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(anchor: &Anchor, serializer: S) -> Result<S::Ok, S::Error> {
+        anchor.bits().serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Anchor, D::Error> {
+        let value = u32::deserialize(deserializer)?;
+        Anchor::from_bits(value).ok_or_else(|| D::Error::custom(format!("invalid anchor {value}")))
+    }
+}
+
+pub mod keyboard_interactivity {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        interactivity: &KeyboardInteractivity,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        u32::from(*interactivity).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<KeyboardInteractivity, D::Error> {
+        let value = u32::deserialize(deserializer)?;
+        KeyboardInteractivity::try_from(value)
+            .map_err(|_| D::Error::custom(format!("invalid keyboard interactivity {value}")))
+    }
+}
@@ -1,9 +1,55 @@
 use wayland_protocols::wp::cursor_shape::v1::client::wp_cursor_shape_device_v1::Shape;
 
+const SHAPE_NAMES: &[&str] = &[
+    "default",
+    "context_menu",
+    "help",
+    "pointer",
+    "progress",
+    "wait",
+    "cell",
+    "crosshair",
+    "text",
+    "vertical_text",
+    "alias",
+    "copy",
+    "move",
+    "no_drop",
+    "not_allowed",
+    "grab",
+    "grabbing",
+    "e_resize",
+    "n_resize",
+    "ne_resize",
+    "nw_resize",
+    "s_resize",
+    "se_resize",
+    "sw_resize",
+    "w_resize",
+    "ew_resize",
+    "ns_resize",
+    "nesw_resize",
+    "nwse_resize",
+    "col_resize",
+    "row_resize",
+    "all_scroll",
+    "zoom_in",
+    "zoom_out",
+    "dnd_ask",
+    "all_resize",
+];
+
+/// the names of every shape of the cursor shape protocol, the ones accepted by
+/// `ReturnData::RequestSetCursorShape`
+pub fn all_shape_names() -> &'static [&'static str] {
+    SHAPE_NAMES
+}
+
 pub(crate) fn str_to_shape(shape_name: &str) -> Option<Shape> {
     match shape_name {
         "default" => Some(Shape::Default),
-        "contenx_menu" => Some(Shape::ContextMenu),
+        // "contenx_menu" is the misspelled name accepted before
+        "context_menu" | "contenx_menu" => Some(Shape::ContextMenu),
         "help" => Some(Shape::Help),
         "pointer" => Some(Shape::Pointer),
         "progress" => Some(Shape::Progress),
@@ -36,10 +82,17 @@ pub(crate) fn str_to_shape(shape_name: &str) -> Option<Shape> {
         "all_scroll" => Some(Shape::AllScroll),
         "zoom_in" => Some(Shape::ZoomIn),
         "zoom_out" => Some(Shape::ZoomOut),
+        "dnd_ask" => Some(Shape::DndAsk),
+        "all_resize" => Some(Shape::AllResize),
         _ => None,
     }
 }
 
+/// the shapes added by the version 2 of `wp_cursor_shape_manager_v1`
+pub(crate) fn shape_since_v2(shape: Shape) -> bool {
+    matches!(shape, Shape::DndAsk | Shape::AllResize)
+}
+
 pub trait ShapeName {
     fn name(&self) -> &str;
 }
@@ -48,7 +101,7 @@ impl ShapeName for Shape {
     fn name(&self) -> &str {
         match self {
             Self::Default => "default",
-            Self::ContextMenu => "contenx_menu",
+            Self::ContextMenu => "context_menu",
             Self::Help => "help",
             Self::Pointer => "pointer",
             Self::Progress => "progress",
@@ -66,20 +119,23 @@ impl ShapeName for Shape {
             Self::Grabbing => "grabbing",
             Self::EResize => "e_resize",
             Self::NResize => "n_resize",
-            Self::EwResize => "ew_resize",
+            Self::NeResize => "ne_resize",
             Self::NwResize => "nw_resize",
             Self::SResize => "s_resize",
             Self::SeResize => "se_resize",
             Self::SwResize => "sw_resize",
             Self::WResize => "w_resize",
+            Self::EwResize => "ew_resize",
             Self::NsResize => "ns_resize",
             Self::NeswResize => "nesw_resize",
-            Self::NwseResize => "nesw_resize",
+            Self::NwseResize => "nwse_resize",
             Self::ColResize => "col_resize",
             Self::RowResize => "row_resize",
             Self::AllScroll => "all_scroll",
             Self::ZoomIn => "zoom_in",
             Self::ZoomOut => "zoom_out",
+            Self::DndAsk => "dnd_ask",
+            Self::AllResize => "all_resize",
             _ => "default",
         }
     }
@@ -170,6 +170,12 @@ impl Context {
     pub fn keymap_mut(&mut self) -> Option<&mut XkbKeymap> {
         self.keymap.as_mut()
     }
+
+    /// the name of the active layout, like `English (US)`, when the keymap names its groups
+    pub fn active_layout_name(&mut self) -> Option<String> {
+        let layout = self.state.as_mut()?.active_layout();
+        self.keymap.as_ref()?.layout_name(layout).map(str::to_owned)
+    }
     /// Key builder context with the user provided xkb state.
     pub fn key_context(&mut self) -> Option<KeyContext<'_>> {
         let state = self.state.as_mut()?;
@@ -190,6 +196,8 @@ impl Context {
 #[derive(Debug)]
 pub struct XkbKeymap {
     keymap: NonNull<xkb_keymap>,
+    /// the `name[GroupN]` of the keymap, by layout index
+    layout_names: Vec<Option<String>>,
 }
 
 impl XkbKeymap {
@@ -205,7 +213,17 @@ impl XkbKeymap {
 
             NonNull::new(keymap)?
         };
-        Some(Self { keymap })
+        let text = unsafe { std::slice::from_raw_parts(map.as_ptr(), size) };
+        let layout_names = layout_names(&String::from_utf8_lossy(text));
+        Some(Self {
+            keymap,
+            layout_names,
+        })
+    }
+
+    /// the name of a layout, as the keymap gives it with `name[GroupN]`
+    pub fn layout_name(&self, layout: xkb_layout_index_t) -> Option<&str> {
+        self.layout_names.get(layout as usize)?.as_deref()
     }
 
     pub fn first_keysym_by_level(
@@ -233,6 +251,44 @@ impl XkbKeymap {
     }
 }
 
+/// the group names of the `xkb_symbols` section of a keymap, like
+/// `name[Group1]="English (US)";`. libxkbcommon has `xkb_keymap_layout_get_name`, but it is not
+/// loaded by xkbcommon-dl
+fn layout_names(keymap: &str) -> Vec<Option<String>> {
+    let mut names = Vec::new();
+    for line in keymap.lines() {
+        let line = line.trim();
+        let Some(rest) = line
+            .get(..10)
+            .filter(|start| start.eq_ignore_ascii_case("name[group"))
+            .map(|_| &line[10..])
+        else {
+            continue;
+        };
+        let Some((index, rest)) = rest.split_once(']') else {
+            continue;
+        };
+        let Ok(index) = index.parse::<usize>() else {
+            continue;
+        };
+        let Some(name) = rest
+            .split_once('"')
+            .and_then(|(_, name)| name.split_once('"'))
+            .map(|(name, _)| name)
+        else {
+            continue;
+        };
+        if index == 0 {
+            continue;
+        }
+        if names.len() < index {
+            names.resize(index, None);
+        }
+        names[index - 1] = Some(name.to_owned());
+    }
+    names
+}
+
 impl Drop for XkbKeymap {
     fn drop(&mut self) {
         unsafe { (XKBH.xkb_keymap_unref)(self.keymap.as_ptr()) }
@@ -350,6 +406,16 @@ impl XkbState {
         unsafe { (XKBH.xkb_state_key_get_one_sym)(self.state.as_ptr(), keycode) }
     }
 
+    /// the layout in use, changed by the group of `wl_keyboard.modifiers`
+    pub fn active_layout(&mut self) -> xkb_layout_index_t {
+        unsafe {
+            (XKBH.xkb_state_serialize_layout)(
+                self.state.as_ptr(),
+                xkb_state_component::XKB_STATE_LAYOUT_EFFECTIVE,
+            )
+        }
+    }
+
     pub fn layout(&mut self, key: xkb_keycode_t) -> xkb_layout_index_t {
         unsafe { (XKBH.xkb_state_key_get_layout)(self.state.as_ptr(), key) }
     }
@@ -551,6 +617,7 @@ pub enum ElementState {
 pub struct KeyEventExtra {
     pub text_with_all_modifiers: Option<SmolStr>,
     pub key_without_modifiers: Key,
+    pub keysym: u32,
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -671,6 +738,14 @@ impl KeyEvent {
     pub fn key_without_modifiers(&self) -> Key {
         self.platform_specific.key_without_modifiers.clone()
     }
+
+    /// The raw xkb keysym of the key in the current layout, with the modifiers applied, like
+    /// `XKB_KEY_space` (`0x20`). Useful to match key bindings against the xkbcommon keysyms,
+    /// the composed text of the key is in [`KeyEvent::text`].
+    #[inline]
+    pub fn keysym(&self) -> u32 {
+        self.platform_specific.keysym
+    }
 }
 
 impl KeyContext<'_> {
@@ -691,6 +766,7 @@ impl KeyContext<'_> {
         let platform_specific = KeyEventExtra {
             text_with_all_modifiers,
             key_without_modifiers,
+            keysym: event.keysym,
         };
 
         KeyEvent {
@@ -861,3 +937,53 @@ impl<'a, 'b> KeyEventResults<'a, 'b> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::layout_names;
+
+    #[test]
+    fn layout_names_read_inside_the_quotes() {
+        let keymap = r#"xkb_symbols "pc+us+inet(evdev)" {
+    name[Group1]="English (US, intl., with dead keys)";
+    key <AE01> { [ 1, exclam ] };
+};"#;
+        assert_eq!(
+            layout_names(keymap),
+            vec![Some("English (US, intl., with dead keys)".to_owned())]
+        );
+        assert_eq!(
+            layout_names(r#"name[group1] = "Deutsch"; // name[Group2]="ignored""#),
+            vec![Some("Deutsch".to_owned())]
+        );
+    }
+
+    #[test]
+    fn layout_names_of_multiple_groups() {
+        let keymap = r#"xkb_symbols "pc+us+ru:2+de:4" {
+    name[Group2]="Russian";
+    name[Group1]="English (US)";
+    name[Group4]="German";
+};"#;
+        assert_eq!(
+            layout_names(keymap),
+            vec![
+                Some("English (US)".to_owned()),
+                Some("Russian".to_owned()),
+                None,
+                Some("German".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn layout_names_of_keymap_without_names() {
+        let keymap = r#"xkb_symbols "pc+us" {
+    name[Group0]="not a group";
+    name[GroupX]="not a group";
+    key <AE01> { [ 1, exclam ] };
+};"#;
+        assert!(layout_names(keymap).is_empty());
+        assert!(layout_names("").is_empty());
+    }
+}